@@ -0,0 +1,123 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput, BatchSize};
+use lmfu::HashSet;
+use rustgit::{Repository, FileType, Mode, Hash, Codec, Miniz};
+use rustgit::internals::{
+    ObjectStore, Directory, PacketLine, PackfileReader, SyntheticParams, encode_lines, decode_line,
+};
+
+fn synthetic() -> (Repository, Hash) {
+    let repo = Repository::synthetic(&SyntheticParams::default()).unwrap();
+    let head = repo.head();
+    (repo, head)
+}
+
+fn stage(c: &mut Criterion) {
+    let files = 1000;
+    let data = vec![b'x'; 1024];
+
+    let mut group = c.benchmark_group("stage");
+    group.throughput(Throughput::Elements(files));
+    group.bench_function("1000 files", |b| b.iter_batched(Repository::new, |mut repo| {
+        for i in 0..files {
+            let path = format!("dir{}/file{}", i % 10, i);
+            repo.stage(&path, Some((data.clone(), FileType::RegularFile))).unwrap();
+        }
+    }, BatchSize::SmallInput));
+    group.finish();
+}
+
+fn serialize_tree(c: &mut Criterion) {
+    let mut dir = Directory::new();
+    for i in 0..1000u32 {
+        let mut bytes = [0; 20];
+        bytes[..4].copy_from_slice(&i.to_be_bytes());
+        dir.insert(format!("entry{}", i).as_str().into(), (Hash::new(bytes), Mode::RegularFile));
+    }
+
+    c.bench_function("serialize tree (1000 entries)", |b| b.iter_batched(ObjectStore::new, |mut store| {
+        store.serialize_directory(&dir, None)
+    }, BatchSize::SmallInput));
+}
+
+fn pack(c: &mut Criterion) {
+    let (repo, head) = synthetic();
+
+    let mut packfile = Vec::new();
+    repo.pack(HashSet::new(), &[("main", head)], &mut packfile, |_, _| ()).unwrap();
+
+    let mut group = c.benchmark_group("pack");
+    group.throughput(Throughput::Bytes(packfile.len() as u64));
+
+    group.bench_function("encode", |b| b.iter(|| {
+        let mut dst = Vec::with_capacity(packfile.len());
+        repo.pack(HashSet::new(), &[("main", head)], &mut dst, |_, _| ()).unwrap();
+        dst
+    }));
+
+    group.bench_function("ingest", |b| b.iter_batched(|| packfile.clone(), |packfile| {
+        Repository::new().import_packfile(packfile, Some(head)).unwrap()
+    }, BatchSize::SmallInput));
+
+    group.finish();
+}
+
+fn framing(c: &mut Criterion) {
+    let want = format!("want {}\n", Hash::zero());
+    let lines: Vec<_> = (0..1000).map(|_| PacketLine::String(&want)).collect();
+
+    let mut encoded = Vec::new();
+    encode_lines(&lines, &mut encoded);
+
+    let mut group = c.benchmark_group("pkt-line");
+    group.throughput(Throughput::Elements(lines.len() as u64));
+
+    group.bench_function("encode", |b| b.iter(|| {
+        let mut dst = Vec::with_capacity(encoded.len());
+        encode_lines(&lines, &mut dst);
+        dst
+    }));
+
+    group.bench_function("decode", |b| b.iter(|| {
+        let mut buffer = encoded.as_slice();
+        while let Some((_line, len)) = decode_line(buffer).unwrap() {
+            buffer = &buffer[len..];
+        }
+    }));
+
+    group.finish();
+}
+
+fn compression(c: &mut Criterion) {
+    let (repo, head) = synthetic();
+
+    let mut packfile = Vec::new();
+    repo.pack(HashSet::new(), &[("main", head)], &mut packfile, |_, _| ()).unwrap();
+
+    let mut store = ObjectStore::new();
+    PackfileReader::from_file(packfile).unwrap().read_all_objects(&mut store).unwrap();
+
+    // uncompressed objects, like the content of snapshots & packfiles
+    let objects: Vec<u8> = store.iter().flat_map(|(_, object)| object.content().to_vec()).collect();
+
+    let mut codecs: Vec<(&str, Box<dyn Codec>)> = vec![("miniz", Box::new(Miniz))];
+    #[cfg(feature = "zstd")]
+    codecs.push(("zstd", Box::new(rustgit::Zstd)));
+
+    let mut group = c.benchmark_group("compression");
+    group.throughput(Throughput::Bytes(objects.len() as u64));
+
+    for (name, codec) in &codecs {
+        let compressed = codec.compress(&objects, 1);
+        println!("{}: {} -> {} bytes", name, objects.len(), compressed.len());
+
+        group.bench_function(format!("{} compress", name), |b| b.iter(|| codec.compress(&objects, 1)));
+        group.bench_function(format!("{} decompress", name), |b| b.iter(|| {
+            codec.decompress(&compressed, objects.len()).unwrap()
+        }));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, stage, serialize_tree, pack, framing, compression);
+criterion_main!(benches);
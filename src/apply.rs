@@ -0,0 +1,297 @@
+use super::internals::{Result, Error, FileType, Repository};
+use super::diff::split_lines;
+
+/// Hunk of a unified diff
+struct Hunk {
+    /// First line in the original file, starting at 1
+    old_start: usize,
+    /// `(kind, line)` where kind is `b' '`, `b'-'` or `b'+'`
+    lines: Vec<(u8, Vec<u8>)>,
+    /// Position in the patch, for error messages
+    number: usize,
+}
+
+/// Changes of a file in a unified diff
+struct FilePatch<'a> {
+    /// `None` for `/dev/null`
+    old_path: Option<&'a str>,
+    new_path: Option<&'a str>,
+    new_mode: Option<FileType>,
+    hunks: Vec<Hunk>,
+}
+
+fn invalid(line: &str) -> Error {
+    log::error!("Invalid patch line: {:?}", line);
+    Error::InvalidPatch
+}
+
+/// `a/src/main.rs\t2024-01-01...` => `src/main.rs`
+fn parse_path(value: &str) -> Option<&str> {
+    let path = value.split('\t').next().unwrap_or(value).trim_end();
+    match path {
+        "/dev/null" => None,
+        path => Some(path.strip_prefix("a/").or(path.strip_prefix("b/")).unwrap_or(path)),
+    }
+}
+
+fn parse_mode(value: &str) -> Option<FileType> {
+    match value.trim() {
+        "100755" => Some(FileType::ExecutableFile),
+        "100664" => Some(FileType::GroupWriteableFile),
+        "120000" => Some(FileType::SymbolicLink),
+        _ => Some(FileType::RegularFile),
+    }
+}
+
+/// `-12,3` => `(12, 3)`; the count defaults to 1
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+    Some((start.parse().ok()?, count.parse().ok()?))
+}
+
+fn parse_patch(patch: &str) -> Result<Vec<FilePatch<'_>>> {
+    let mut files = Vec::new();
+    let mut new_mode = None;
+    let mut hunk_number = 0;
+    let mut lines = patch.split_inclusive('\n').peekable();
+
+    while let Some(line) = lines.next() {
+        let text = line.trim_end_matches('\n');
+
+        if let Some(mode) = text.strip_prefix("new file mode ").or(text.strip_prefix("new mode ")) {
+            new_mode = parse_mode(mode);
+        } else if text.starts_with("diff ") {
+            new_mode = None;
+        } else if let Some(old) = text.strip_prefix("--- ") {
+            let new = lines.next().and_then(|l| l.trim_end_matches('\n').strip_prefix("+++ "));
+            let new = new.ok_or_else(|| invalid(text))?;
+
+            files.push(FilePatch {
+                old_path: parse_path(old),
+                new_path: parse_path(new),
+                new_mode: new_mode.take(),
+                hunks: Vec::new(),
+            });
+        } else if let Some(header) = text.strip_prefix("@@ -") {
+            let file = files.last_mut().ok_or_else(|| invalid(text))?;
+            let (old_range, rest) = header.split_once(" +").ok_or_else(|| invalid(text))?;
+            let (new_range, _) = rest.split_once(" @@").ok_or_else(|| invalid(text))?;
+
+            let (old_start, mut old_left) = parse_range(old_range).ok_or_else(|| invalid(text))?;
+            let (_, mut new_left) = parse_range(new_range).ok_or_else(|| invalid(text))?;
+
+            hunk_number += 1;
+            let mut hunk = Hunk {
+                old_start,
+                lines: Vec::new(),
+                number: hunk_number,
+            };
+
+            while old_left > 0 || new_left > 0 || lines.peek().is_some_and(|l| l.starts_with('\\')) {
+                let line = lines.next().ok_or(Error::InvalidPatch)?;
+                let (kind, content) = match line.as_bytes().split_first() {
+                    // some editors strip the space of empty context lines
+                    Some((b'\n', _)) => (b' ', &b"\n"[..]),
+                    Some((kind, content)) => (*kind, content),
+                    None => return Err(Error::InvalidPatch),
+                };
+
+                match kind {
+                    b' ' if old_left > 0 && new_left > 0 => {
+                        old_left -= 1;
+                        new_left -= 1;
+                    },
+                    b'-' if old_left > 0 => old_left -= 1,
+                    b'+' if new_left > 0 => new_left -= 1,
+                    // `\ No newline at end of file`
+                    b'\\' => {
+                        let (_, last) = hunk.lines.last_mut().ok_or_else(|| invalid(line))?;
+                        last.pop();
+                        continue;
+                    },
+                    _ => return Err(invalid(line)),
+                }
+
+                hunk.lines.push((kind, content.to_vec()));
+            }
+
+            file.hunks.push(hunk);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Finds where `expected` lines are in `lines`, closest to `guess` first
+fn find_hunk(lines: &[&[u8]], expected: &[&[u8]], min: usize, guess: usize) -> Option<usize> {
+    let max = lines.len().checked_sub(expected.len())?;
+    let matches = |pos: usize| lines[pos..pos + expected.len()] == *expected;
+    let guess = guess.clamp(min, max.max(min));
+
+    (0..=lines.len()).find_map(|offset| {
+        let after = guess.checked_add(offset).filter(|pos| *pos <= max && matches(*pos));
+        let before = guess.checked_sub(offset).filter(|pos| *pos >= min && *pos <= max && matches(*pos));
+        after.or(before)
+    })
+}
+
+fn apply_hunks(original: &[u8], hunks: &[Hunk], path: &str) -> Result<Vec<u8>> {
+    let lines = split_lines(original);
+    let mut output = Vec::new();
+    let mut cursor = 0;
+    let mut shift = 0isize;
+
+    for hunk in hunks {
+        let old = hunk.lines.iter().filter(|(k, _)| *k != b'+').map(|(_, l)| l.as_slice()).collect::<Vec<_>>();
+        let new = hunk.lines.iter().filter(|(k, _)| *k != b'-').map(|(_, l)| l.as_slice());
+
+        // empty ranges point to the line before
+        let expected_pos = match old.is_empty() {
+            true => hunk.old_start,
+            false => hunk.old_start.saturating_sub(1),
+        };
+
+        // previous hunks may have moved too
+        let guess = expected_pos.saturating_add_signed(shift);
+        let Some(pos) = find_hunk(&lines, &old, cursor, guess) else {
+            log::error!("Hunk #{} doesn't match {}", hunk.number, path);
+            return Err(Error::PatchMismatch);
+        };
+
+        shift = pos as isize - expected_pos as isize;
+        lines[cursor..pos].iter().for_each(|line| output.extend_from_slice(line));
+        new.for_each(|line| output.extend_from_slice(line));
+        cursor = pos + old.len();
+    }
+
+    lines[cursor..].iter().for_each(|line| output.extend_from_slice(line));
+    Ok(output)
+}
+
+impl Repository {
+    /// Applies a unified diff to the workspace, like `git apply`
+    ///
+    /// Files can be created (`--- /dev/null`), deleted (`+++ /dev/null`),
+    /// modified or renamed; `a/` & `b/` path prefixes are stripped.
+    /// Hunks may have moved since the diff was made, but their context
+    /// must match exactly. Resulting files are staged.
+    ///
+    /// Returns `InvalidPatch` if the diff cannot be parsed,
+    /// `PatchMismatch` if a hunk doesn't match its file and `PathError`
+    /// if a modified file doesn't exist; nothing is staged in these
+    /// cases.
+    pub fn apply_patch(&mut self, unified_diff: &str) -> Result<()> {
+        let mut staged = Vec::new();
+
+        for file in parse_patch(unified_diff)? {
+            let (original, old_type) = match file.old_path {
+                Some(path) => {
+                    let (_, mode) = self.find_file(path)?;
+                    (self.read_file(path)?, mode.file_type())
+                },
+                None => (&[][..], None),
+            };
+
+            let path = file.new_path.or(file.old_path).ok_or(Error::InvalidPatch)?;
+            let result = apply_hunks(original, &file.hunks, path)?;
+
+            if file.old_path != file.new_path {
+                if let Some(old_path) = file.old_path {
+                    staged.push((old_path, None));
+                }
+            }
+
+            if let Some(new_path) = file.new_path {
+                let file_type = file.new_mode.or(old_type).unwrap_or(FileType::RegularFile);
+                staged.push((new_path, Some((result, file_type))));
+            }
+        }
+
+        for (path, data) in staged {
+            self.stage(path, data)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Repository, FileType, Error};
+    use crate::diff::unified_diff;
+
+    fn numbered(lines: std::ops::Range<usize>) -> String {
+        lines.map(|i| format!("line {}\n", i)).collect()
+    }
+
+    fn repo_with(files: &[(&str, &str)]) -> Repository {
+        let mut repo = Repository::new();
+        for (path, content) in files {
+            repo.stage(path, Some((content.as_bytes().to_vec(), FileType::RegularFile))).unwrap();
+        }
+
+        repo
+    }
+
+    #[test]
+    fn hunks_with_offsets() {
+        let old = numbered(0..20);
+        let new = old.replace("line 5\n", "line five\n").replace("line 15\n", "line fifteen\n");
+        let patch = format!("--- a/file.txt\n+++ b/file.txt\n{}", unified_diff(old.as_bytes(), new.as_bytes(), 3));
+
+        // lines were added at the top and removed between hunks
+        let moved = format!("new 0\nnew 1\nnew 2\n{}", old.replace("line 10\n", ""));
+        let mut repo = repo_with(&[("file.txt", &moved)]);
+        repo.apply_patch(&patch).unwrap();
+
+        let expected = format!("new 0\nnew 1\nnew 2\n{}", new.replace("line 10\n", ""));
+        assert_eq!(repo.read_file("file.txt").unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn context_mismatch() {
+        let old = numbered(0..10);
+        let new = old.replace("line 5\n", "line five\n");
+        let patch = format!(
+            "--- a/other.txt\n+++ b/other.txt\n@@ -1 +1 @@\n-other\n+changed\n--- a/file.txt\n+++ b/file.txt\n{}",
+            unified_diff(old.as_bytes(), new.as_bytes(), 3),
+        );
+
+        let edited = old.replace("line 4\n", "line four\n");
+        let mut repo = repo_with(&[("file.txt", &edited), ("other.txt", "other\n")]);
+        let result = repo.apply_patch(&patch);
+
+        assert!(matches!(result, Err(Error::PatchMismatch)));
+        assert_eq!(repo.read_file("file.txt").unwrap(), edited.as_bytes());
+        assert_eq!(repo.read_file("other.txt").unwrap(), b"other\n");
+    }
+
+    #[test]
+    fn create_and_delete_files() {
+        let mut repo = repo_with(&[("old.txt", "old\n"), ("kept.txt", "kept\n")]);
+        let patch = concat!(
+            "diff --git a/new.sh b/new.sh\n",
+            "new file mode 100755\n",
+            "--- /dev/null\n",
+            "+++ b/new.sh\n",
+            "@@ -0,0 +1,2 @@\n",
+            "+#!/bin/sh\n",
+            "+echo new\n",
+            "diff --git a/old.txt b/old.txt\n",
+            "deleted file mode 100644\n",
+            "--- a/old.txt\n",
+            "+++ /dev/null\n",
+            "@@ -1 +0,0 @@\n",
+            "-old\n",
+        );
+
+        repo.apply_patch(patch).unwrap();
+        assert_eq!(repo.read_file("new.sh").unwrap(), b"#!/bin/sh\necho new\n");
+        assert!(matches!(repo.find_file("new.sh").unwrap().1.file_type(), Some(FileType::ExecutableFile)));
+        assert!(matches!(repo.read_file("old.txt"), Err(Error::PathError)));
+        assert_eq!(repo.read_file("kept.txt").unwrap(), b"kept\n");
+
+        // the deleted file is gone, so the patch doesn't apply twice
+        assert!(matches!(repo.apply_patch(patch), Err(Error::PathError)));
+    }
+}
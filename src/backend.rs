@@ -0,0 +1,617 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write as _, BufReader};
+use std::path::Path as FsPath;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::collections::HashMap;
+
+use super::internals::{Result, Error, Hash, Object, ObjectStore, ObjectType};
+
+/// Blob which is being written in chunks, see [`ObjectBackend::begin_blob`]
+pub enum PendingBlob {
+    /// Chunks kept in memory until the hash is known
+    Buffer(Vec<u8>),
+    /// Chunks already stored by the backend, from `position`
+    Stored { position: u64, size: usize, written: usize },
+}
+
+/// Upper bound of the memory reserved for a [`PendingBlob::Buffer`]
+///
+/// Declared sizes aren't trusted: bigger blobs grow as chunks come.
+pub(crate) const BLOB_RESERVE_LIMIT: usize = 1 << 20;
+
+/// Storage of an [`ObjectStore`], see [`ObjectStore::with_backend`]
+///
+/// Objects are immutable and keyed by their hash,
+/// which is computed by the store.
+pub trait ObjectBackend: Send + Sync {
+    /// Borrows an object
+    ///
+    /// Backends which don't keep objects in memory have to cache
+    /// the ones returned here until [`Self::release_memory`]; walks
+    /// over many objects use [`Self::load`] instead.
+    fn get(&self, hash: Hash) -> Option<&Object>;
+
+    /// Reads an object without keeping it in memory
+    ///
+    /// Contents are reference-counted, so this is cheap for
+    /// objects which are already in memory.
+    fn load(&self, hash: Hash) -> Option<Object> {
+        self.get(hash).cloned()
+    }
+
+    /// Stores an object
+    ///
+    /// Must return `IoError` if the object couldn't be stored.
+    fn insert(&mut self, hash: Hash, object: Object) -> Result<()>;
+
+    fn has(&self, hash: Hash) -> bool {
+        self.get(hash).is_some()
+    }
+
+    /// Removes an object and returns it, `None` if it's missing
+    fn remove(&mut self, hash: Hash) -> Option<Object>;
+
+    /// Hashes of all objects
+    fn hashes(&self) -> Box<dyn Iterator<Item = Hash> + '_>;
+
+    /// Number of objects
+    fn len(&self) -> usize {
+        self.hashes().count()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hashes which may share their first `nibbles` hex digits with `hash`
+    ///
+    /// This speeds up prefix lookups; all hashes by default.
+    fn neighbours(&self, hash: Hash, nibbles: usize) -> Box<dyn Iterator<Item = Hash> + '_> {
+        let _ = (hash, nibbles);
+        self.hashes()
+    }
+
+    /// Hints that these objects will be read soon
+    ///
+    /// Remote or slow backends can load them in a batch,
+    /// see [`RevWalk::prefetch`](crate::RevWalk::prefetch).
+    /// Unknown hashes must be ignored.
+    fn prefetch(&self, hashes: &[Hash]) {
+        let _ = hashes;
+    }
+
+    /// Copy of the backend, used when the store is cloned
+    fn duplicate(&self) -> Box<dyn ObjectBackend>;
+
+    /// Drops objects cached in memory, if any
+    fn release_memory(&mut self) {}
+
+    /// Changes the delta base hint of a stored object,
+    /// see [`ObjectStore::set_delta_hint`]
+    ///
+    /// Must return `MissingObject` if the object isn't stored.
+    /// By default, the object is removed and inserted again;
+    /// backends should update the hint in place instead.
+    fn set_delta_hint(&mut self, hash: Hash, base: Option<Hash>) -> Result<()> {
+        let object = self.remove(hash).ok_or(Error::MissingObject)?;
+        self.insert(hash, object.with_delta_hint(base))
+    }
+
+    /// Starts a blob of `size` bytes which is written in chunks
+    ///
+    /// Backends which keep contents out of memory can store chunks
+    /// as they come, see [`PendingBlob::Stored`]; by default, they
+    /// are buffered until [`Self::end_blob`].
+    fn begin_blob(&mut self, size: usize) -> Result<PendingBlob> {
+        Ok(PendingBlob::Buffer(Vec::with_capacity(size.min(BLOB_RESERVE_LIMIT))))
+    }
+
+    /// Appends a chunk to a blob started by [`Self::begin_blob`]
+    fn write_blob(&mut self, blob: &mut PendingBlob, chunk: &[u8]) -> Result<()> {
+        match blob {
+            PendingBlob::Buffer(content) => {
+                content.extend_from_slice(chunk);
+                Ok(())
+            },
+            PendingBlob::Stored { .. } => Err(Error::InvalidObject),
+        }
+    }
+
+    /// Stores a blob started by [`Self::begin_blob`], once its
+    /// hash is known
+    fn end_blob(&mut self, hash: Hash, blob: PendingBlob) -> Result<()> {
+        match blob {
+            PendingBlob::Buffer(content) => self.insert(hash, Object::new(ObjectType::Blob, content, None)),
+            PendingBlob::Stored { .. } => Err(Error::InvalidObject),
+        }
+    }
+
+    /// Gives up a blob started by [`Self::begin_blob`]
+    ///
+    /// Backends should release what they reserved for it.
+    fn abort_blob(&mut self, blob: PendingBlob) {
+        drop(blob);
+    }
+}
+
+impl ObjectBackend for ObjectStore {
+    fn get(&self, hash: Hash) -> Option<&Object> {
+        ObjectStore::get(self, hash)
+    }
+
+    fn load(&self, hash: Hash) -> Option<Object> {
+        ObjectStore::load(self, hash)
+    }
+
+    fn insert(&mut self, _hash: Hash, object: Object) -> Result<()> {
+        self.insert_entry(object).map(drop)
+    }
+
+    fn has(&self, hash: Hash) -> bool {
+        ObjectStore::has(self, hash)
+    }
+
+    fn remove(&mut self, hash: Hash) -> Option<Object> {
+        ObjectStore::remove(self, hash)
+    }
+
+    fn hashes(&self) -> Box<dyn Iterator<Item = Hash> + '_> {
+        ObjectStore::hashes(self)
+    }
+
+    fn len(&self) -> usize {
+        ObjectStore::len(self)
+    }
+
+    fn neighbours(&self, hash: Hash, nibbles: usize) -> Box<dyn Iterator<Item = Hash> + '_> {
+        ObjectStore::neighbours(self, hash, nibbles)
+    }
+
+    fn duplicate(&self) -> Box<dyn ObjectBackend> {
+        Box::new(self.clone())
+    }
+
+    fn release_memory(&mut self) {
+        ObjectStore::release_memory(self)
+    }
+
+    fn set_delta_hint(&mut self, hash: Hash, base: Option<Hash>) -> Result<()> {
+        ObjectStore::set_delta_hint(self, hash, base)
+    }
+}
+
+/// `<hash> <type> <delta hint> <content length>`
+const RECORD_HEADER_LEN: usize = 20 + 1 + 20 + 8;
+
+/// Type of empty records which remove an object
+const REMOVAL_CODE: u8 = 0;
+
+/// Type of empty records which change the delta hint of an object
+const HINT_CODE: u8 = 0xff;
+
+pub(crate) fn type_code(obj_type: ObjectType) -> u8 {
+    match obj_type {
+        ObjectType::Commit => 1,
+        ObjectType::Tree => 2,
+        ObjectType::Blob => 3,
+        ObjectType::Tag => 4,
+    }
+}
+
+pub(crate) fn code_type(code: u8) -> Option<ObjectType> {
+    match code {
+        1 => Some(ObjectType::Commit),
+        2 => Some(ObjectType::Tree),
+        3 => Some(ObjectType::Blob),
+        4 => Some(ObjectType::Tag),
+        _ => None,
+    }
+}
+
+struct Slot {
+    /// Offset of the content in the file
+    offset: u64,
+    len: usize,
+    obj_type: ObjectType,
+    delta_hint: Hash,
+    cached: OnceLock<Object>,
+}
+
+/// Object backend which keeps object contents in a file
+///
+/// Only an index of the objects stays in memory, plus the objects
+/// which were borrowed (see [`ObjectBackend::get`]) since the last
+/// [`ObjectStore::release_memory`] (see
+/// [`Repository::release_memory`](crate::Repository::release_memory)).
+/// Walks such as packing, gc & bitmaps read objects without caching them.
+///
+/// The file is append-only: removals and delta hint changes are
+/// appended as empty records, which are applied in order when the
+/// file is opened again. Clones of the store share the file, so it
+/// holds the changes of all of them.
+pub struct DiskBackend {
+    file: Arc<Mutex<File>>,
+    index: HashMap<Hash, Slot>,
+}
+
+impl DiskBackend {
+    /// Opens or creates an object file
+    ///
+    /// Objects already in the file are indexed. Returns `IoError`
+    /// if the file cannot be opened or read; an incomplete last
+    /// object (interrupted write) is dropped.
+    pub fn open<P: AsRef<FsPath>>(path: P) -> Result<Self> {
+        let mut file = OpenOptions::new().create(true).truncate(false).read(true).write(true).open(path)?;
+        let mut index = HashMap::<Hash, Slot>::new();
+
+        let file_len = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut reader = BufReader::new(&mut file);
+        let mut offset = 0;
+        loop {
+            let mut header = [0; RECORD_HEADER_LEN];
+            match reader.read_exact(&mut header) {
+                Ok(()) => (),
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error.into()),
+            }
+
+            let hash = Hash::new(header[..20].try_into().unwrap());
+            let delta_hint = Hash::new(header[21..41].try_into().unwrap());
+            let len = u64::from_le_bytes(header[41..].try_into().unwrap());
+            let content_offset = offset + RECORD_HEADER_LEN as u64;
+
+            if content_offset + len > file_len {
+                break;
+            }
+
+            let obj_type = match (header[20], code_type(header[20])) {
+                (_, Some(obj_type)) => obj_type,
+                (REMOVAL_CODE, None) if len == 0 => {
+                    index.remove(&hash);
+                    offset = content_offset;
+                    continue;
+                },
+                (HINT_CODE, None) if len == 0 => {
+                    if let Some(slot) = index.get_mut(&hash) {
+                        slot.delta_hint = delta_hint;
+                    }
+
+                    offset = content_offset;
+                    continue;
+                },
+                _ => break,
+            };
+
+            reader.seek_relative(len as i64)?;
+            offset = content_offset + len;
+
+            // blobs which were abandoned while being written
+            if hash.is_zero() {
+                continue;
+            }
+
+            index.insert(hash, Slot {
+                offset: content_offset,
+                len: len as usize,
+                obj_type,
+                delta_hint,
+                cached: OnceLock::new(),
+            });
+        }
+
+        drop(reader);
+        if offset < file_len {
+            log::warn!("Dropping an incomplete object at offset {}", offset);
+            file.set_len(offset)?;
+        }
+
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            index,
+        })
+    }
+
+    fn read(&self, slot: &Slot) -> io::Result<Object> {
+        let mut content = vec![0; slot.len];
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(slot.offset))?;
+        file.read_exact(&mut content)?;
+
+        Ok(Object::new(slot.obj_type, content, Some(slot.delta_hint).filter(|hint| !hint.is_zero())))
+    }
+
+    fn write(&self, hash: Hash, object: &Object) -> io::Result<u64> {
+        let content = object.content();
+        let mut record = Vec::with_capacity(RECORD_HEADER_LEN + content.len());
+        record.extend_from_slice(&hash.to_bytes());
+        record.push(type_code(object.obj_type()));
+        record.extend_from_slice(&object.delta_hint().unwrap_or(Hash::zero()).to_bytes());
+        record.extend_from_slice(&(content.len() as u64).to_le_bytes());
+        record.extend_from_slice(content);
+
+        let mut file = self.file.lock().unwrap();
+        let offset = file.seek(SeekFrom::End(0))?;
+        file.write_all(&record)?;
+
+        Ok(offset + RECORD_HEADER_LEN as u64)
+    }
+
+    /// Appends an empty record, see [`REMOVAL_CODE`] & [`HINT_CODE`]
+    fn write_marker(&self, hash: Hash, code: u8, delta_hint: Hash) -> io::Result<()> {
+        let mut record = [0; RECORD_HEADER_LEN];
+        record[..20].copy_from_slice(&hash.to_bytes());
+        record[20] = code;
+        record[21..41].copy_from_slice(&delta_hint.to_bytes());
+
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(&record)
+    }
+}
+
+impl ObjectBackend for DiskBackend {
+    fn get(&self, hash: Hash) -> Option<&Object> {
+        let slot = self.index.get(&hash)?;
+        if slot.cached.get().is_none() {
+            match self.read(slot) {
+                Ok(object) => drop(slot.cached.set(object)),
+                Err(error) => {
+                    log::error!("Couldn't read object {}: {}", hash, error);
+                    return None;
+                },
+            }
+        }
+
+        slot.cached.get()
+    }
+
+    fn load(&self, hash: Hash) -> Option<Object> {
+        let slot = self.index.get(&hash)?;
+        if let Some(object) = slot.cached.get() {
+            return Some(object.clone());
+        }
+
+        match self.read(slot) {
+            Ok(object) => Some(object),
+            Err(error) => {
+                log::error!("Couldn't read object {}: {}", hash, error);
+                None
+            },
+        }
+    }
+
+    fn insert(&mut self, hash: Hash, object: Object) -> Result<()> {
+        if self.index.contains_key(&hash) {
+            return Ok(());
+        }
+
+        let offset = self.write(hash, &object).map_err(|error| {
+            log::error!("Couldn't write object {}: {}", hash, error);
+            error
+        })?;
+
+        self.index.insert(hash, Slot {
+            offset,
+            len: object.content().len(),
+            obj_type: object.obj_type(),
+            delta_hint: object.delta_hint().unwrap_or(Hash::zero()),
+            cached: OnceLock::new(),
+        });
+
+        Ok(())
+    }
+
+    fn has(&self, hash: Hash) -> bool {
+        self.index.contains_key(&hash)
+    }
+
+    fn prefetch(&self, hashes: &[Hash]) {
+        // reading in file order limits seeks
+        let mut slots = hashes.iter().filter_map(|hash| Some((*hash, self.index.get(hash)?))).collect::<Vec<_>>();
+        slots.retain(|(_, slot)| slot.cached.get().is_none());
+        slots.sort_by_key(|(_, slot)| slot.offset);
+
+        for (hash, slot) in slots {
+            match self.read(slot) {
+                Ok(object) => drop(slot.cached.set(object)),
+                Err(error) => log::error!("Couldn't read object {}: {}", hash, error),
+            }
+        }
+    }
+
+    fn remove(&mut self, hash: Hash) -> Option<Object> {
+        let mut slot = self.index.remove(&hash)?;
+        let object = match slot.cached.take() {
+            Some(object) => Some(object),
+            None => self.read(&slot).ok(),
+        };
+
+        if let Err(error) = self.write_marker(hash, REMOVAL_CODE, Hash::zero()) {
+            log::error!("Couldn't persist the removal of {}: {}", hash, error);
+        }
+
+        object
+    }
+
+    fn hashes(&self) -> Box<dyn Iterator<Item = Hash> + '_> {
+        Box::new(self.index.keys().copied())
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn duplicate(&self) -> Box<dyn ObjectBackend> {
+        let slot = |slot: &Slot| Slot {
+            cached: OnceLock::new(),
+            ..*slot
+        };
+
+        Box::new(Self {
+            file: self.file.clone(),
+            index: self.index.iter().map(|(hash, s)| (*hash, slot(s))).collect(),
+        })
+    }
+
+    fn release_memory(&mut self) {
+        for slot in self.index.values_mut() {
+            slot.cached.take();
+        }
+    }
+
+    /// A record with the new hint is appended; records may be shared
+    /// with clones of the store, so they are never rewritten
+    fn set_delta_hint(&mut self, hash: Hash, base: Option<Hash>) -> Result<()> {
+        if !self.index.contains_key(&hash) {
+            return Err(Error::MissingObject);
+        }
+
+        let delta_hint = base.unwrap_or(Hash::zero());
+        self.write_marker(hash, HINT_CODE, delta_hint)?;
+
+        let slot = self.index.get_mut(&hash).unwrap();
+        slot.delta_hint = delta_hint;
+        if let Some(object) = slot.cached.take() {
+            drop(slot.cached.set(object.with_delta_hint(base)));
+        }
+
+        Ok(())
+    }
+
+    /// The record is reserved at the end of the file with a zero
+    /// hash, which is replaced by [`Self::end_blob`]
+    fn begin_blob(&mut self, size: usize) -> Result<PendingBlob> {
+        let mut header = [0; RECORD_HEADER_LEN];
+        header[20] = type_code(ObjectType::Blob);
+        header[41..].copy_from_slice(&(size as u64).to_le_bytes());
+
+        let mut file = self.file.lock().unwrap();
+        let offset = file.seek(SeekFrom::End(0))?;
+        file.write_all(&header)?;
+
+        let position = offset + RECORD_HEADER_LEN as u64;
+        file.set_len(position + size as u64)?;
+
+        Ok(PendingBlob::Stored { position, size, written: 0 })
+    }
+
+    fn write_blob(&mut self, blob: &mut PendingBlob, chunk: &[u8]) -> Result<()> {
+        let PendingBlob::Stored { position, size, written } = blob else {
+            return Err(Error::InvalidObject);
+        };
+
+        if *written + chunk.len() > *size {
+            log::error!("Blob is bigger than its declared size ({} bytes)", size);
+            return Err(Error::InvalidObject);
+        }
+
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(*position + *written as u64))?;
+        file.write_all(chunk)?;
+        *written += chunk.len();
+
+        Ok(())
+    }
+
+    fn end_blob(&mut self, hash: Hash, blob: PendingBlob) -> Result<()> {
+        let PendingBlob::Stored { position, size, .. } = blob else {
+            return Err(Error::InvalidObject);
+        };
+
+        // an identical blob was stored before: this record stays abandoned
+        if self.index.contains_key(&hash) {
+            return Ok(());
+        }
+
+        let mut file = self.file.lock().unwrap();
+        file.seek(SeekFrom::Start(position - RECORD_HEADER_LEN as u64))?;
+        file.write_all(&hash.to_bytes())?;
+
+        self.index.insert(hash, Slot {
+            offset: position,
+            len: size,
+            obj_type: ObjectType::Blob,
+            delta_hint: Hash::zero(),
+            cached: OnceLock::new(),
+        });
+
+        Ok(())
+    }
+
+    /// The reserved record is truncated if nothing was stored after
+    /// it; otherwise, its zero hash makes [`Self::open`] skip it
+    fn abort_blob(&mut self, blob: PendingBlob) {
+        let PendingBlob::Stored { position, size, .. } = blob else { return };
+
+        let mut file = self.file.lock().unwrap();
+        let end = file.seek(SeekFrom::End(0));
+        if let Ok(true) = end.as_ref().map(|end| *end == position + size as u64) {
+            let result = file.set_len(position - RECORD_HEADER_LEN as u64);
+            if let Err(error) = result {
+                log::warn!("Couldn't release an aborted blob: {:?}", error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+    use crate::objectstore::object_hash;
+
+    fn blob(content: &str) -> (Hash, Object) {
+        let hash = object_hash(ObjectType::Blob, content.as_bytes()).unwrap();
+        (hash, Object::new(ObjectType::Blob, content, None))
+    }
+
+    #[test]
+    fn disk_backend_reopen() {
+        let path = std::env::temp_dir().join(format!("rustgit-disk-backend-{}.bin", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let (a, a_object) = blob("a\n");
+        let (b, b_object) = blob("b\n");
+        let (c, c_object) = blob("c\n");
+
+        let mut backend = DiskBackend::open(&path).unwrap();
+        backend.insert(a, a_object).unwrap();
+        backend.insert(b, b_object).unwrap();
+        backend.insert(c, c_object).unwrap();
+        backend.set_delta_hint(b, Some(a)).unwrap();
+        assert!(backend.remove(c).is_some());
+
+        // clones share records, but not their hints
+        let mut clone = backend.duplicate();
+        clone.set_delta_hint(a, Some(b)).unwrap();
+        assert_eq!(backend.load(a).unwrap().delta_hint(), None);
+
+        let len = fs::metadata(&path).unwrap().len();
+        let pending = backend.begin_blob(16).unwrap();
+        backend.abort_blob(pending);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+
+        let (d, _) = blob("streamed\n");
+        let mut pending = backend.begin_blob(9).unwrap();
+        backend.write_blob(&mut pending, b"stream").unwrap();
+        backend.write_blob(&mut pending, b"ed\n").unwrap();
+        backend.end_blob(d, pending).unwrap();
+
+        drop((backend, clone));
+        let backend = DiskBackend::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let mut hashes = backend.hashes().collect::<Vec<_>>();
+        hashes.sort();
+        let mut expected = vec![a, b, d];
+        expected.sort();
+        assert_eq!(hashes, expected);
+
+        assert_eq!(backend.load(b).unwrap().content(), b"b\n");
+        assert_eq!(backend.load(b).unwrap().delta_hint(), Some(a));
+        assert_eq!(backend.load(a).unwrap().delta_hint(), Some(b));
+        assert_eq!(backend.load(d).unwrap().content(), b"streamed\n");
+    }
+}
@@ -0,0 +1,194 @@
+//! Command-line front-end, enabled by the `cli` feature
+//!
+//! Every invocation clones the remote repository in memory
+//! before running its command; nothing is written to disk.
+
+use std::{env, fs, process::exit, io::{stdout, Write}};
+use rustgit::{Repository, Remote, Reference, Hash, EntryType, FileType, Mode, Commit};
+use rustgit::internals::ObjectType;
+
+const USAGE: &str = "\
+usage: rustgit [options] <command> [args]
+
+options:
+    --host <host:port>      SSH host (default: github.com:22)
+    --user <name>           SSH username (default: git)
+    --repo <path>           path of the repository on the server
+    --branch <name>         branch to clone (default: remote HEAD)
+    --commit <hash>         commit to clone
+    --depth <n>             shallow clone depth
+    --author <name,email>   author & committer of pushed commits
+    --force                 force-push
+
+The SSH key pair is read from the RUSTGIT_KEYPAIR_HEX environment variable.
+
+commands:
+    clone                           clone and list the root directory
+    fetch                           clone and print the head commit hash
+    log [count]                     print the commit history
+    ls-tree [path]                  list a directory of the head commit
+    cat-file <path|hash>            print a file or an object
+    push <message> <dst=src>...     commit local files to --branch and push";
+
+struct Options {
+    host: String,
+    user: String,
+    repo: Option<String>,
+    branch: Option<String>,
+    commit: Option<Hash>,
+    depth: Option<usize>,
+    author: Option<(String, String)>,
+    force: bool,
+    args: Vec<String>,
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("rustgit: {}", message);
+    exit(1)
+}
+
+fn parse_options() -> Options {
+    let mut options = Options {
+        host: "github.com:22".into(),
+        user: "git".into(),
+        repo: None,
+        branch: None,
+        commit: None,
+        depth: None,
+        author: None,
+        force: false,
+        args: Vec::new(),
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| fail(&format!("missing value for {}", arg)));
+
+        match arg.as_str() {
+            "--host" => options.host = value(),
+            "--user" => options.user = value(),
+            "--repo" => options.repo = Some(value()),
+            "--branch" => options.branch = Some(value()),
+            "--commit" => {
+                let hash = Hash::from_hex(&value()).unwrap_or_else(|| fail("invalid commit hash"));
+                options.commit = Some(hash);
+            },
+            "--depth" => {
+                let depth = value().parse().unwrap_or_else(|_| fail("invalid depth"));
+                options.depth = Some(depth);
+            },
+            "--author" => {
+                let author = value();
+                let (name, email) = author.split_once(',').unwrap_or_else(|| fail("invalid author"));
+                options.author = Some((name.into(), email.into()));
+            },
+            "--force" => options.force = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                exit(0);
+            },
+            _ => options.args.push(arg),
+        }
+    }
+
+    options
+}
+
+fn entry_type(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Directory => "tree",
+        Mode::Gitlink => "commit",
+        _ => "blob",
+    }
+}
+
+fn main() {
+    let options = parse_options();
+
+    let mut args = options.args.iter().map(String::as_str);
+    let command = args.next().unwrap_or_else(|| fail(USAGE));
+
+    let keypair = env::var("RUSTGIT_KEYPAIR_HEX").unwrap_or_else(|_| fail("RUSTGIT_KEYPAIR_HEX is not set"));
+    let path = options.repo.as_deref().unwrap_or_else(|| fail("--repo is required"));
+    let remote = Remote::new(options.host.as_str().into(), options.user.as_str().into(), path.into(), keypair.as_str().into());
+
+    let reference = match (&options.branch, options.commit) {
+        (Some(_), Some(_)) => fail("--branch and --commit are mutually exclusive"),
+        (Some(branch), None) => Reference::Branch(branch),
+        (None, Some(hash)) => Reference::Commit(hash),
+        (None, None) => Reference::Head,
+    };
+
+    let mut repo = Repository::new();
+    let check = |result: Result<(), rustgit::Error>| result.unwrap_or_else(|e| fail(&format!("{:?}", e)));
+    check(repo.clone(&remote, reference, options.depth));
+
+    match command {
+        "clone" => check(repo.for_each_entry("", EntryType::All, |name, mode, hash| {
+            println!("{:06o} {} {}\t{}", mode as u32, entry_type(mode), hash, name);
+        })),
+        "fetch" => println!("{}", repo.head()),
+        "log" => {
+            let count = args.next().map(|n| n.parse().unwrap_or_else(|_| fail("invalid count")));
+            let mut current = Some(repo.head());
+            let mut printed = 0;
+
+            while let Some(hash) = current.filter(|_| Some(printed) != count) {
+                // shallow clones don't contain the oldest commits
+                let Some(commit) = repo.get_object(hash) else { break };
+                let commit = Commit::parse(commit.content()).unwrap_or_else(|e| fail(&format!("{:?}", e)));
+
+                let summary = commit.message.lines().next().unwrap_or("");
+                println!("{} {} <{}> {}", hash, commit.author.name, commit.author.email, summary);
+
+                current = commit.parents.first().copied();
+                printed += 1;
+            }
+        },
+        "ls-tree" => check(repo.for_each_entry(args.next().unwrap_or(""), EntryType::All, |name, mode, hash| {
+            println!("{:06o} {} {}\t{}", mode as u32, entry_type(mode), hash, name);
+        })),
+        "cat-file" => {
+            let target = args.next().unwrap_or_else(|| fail("cat-file requires a path or a hash"));
+            let content = match Hash::from_hex(target) {
+                Some(hash) => match repo.get_object(hash) {
+                    Some(object) if object.obj_type() != ObjectType::Tree => object.content(),
+                    Some(_) => fail("cannot print a tree, use ls-tree"),
+                    None => fail("no such object"),
+                },
+                None => repo.read_file(target).unwrap_or_else(|e| fail(&format!("{:?}", e))),
+            };
+
+            stdout().write_all(content).unwrap_or_else(|e| fail(&e.to_string()));
+        },
+        "push" => {
+            let branch = options.branch.as_deref().unwrap_or_else(|| fail("push requires --branch"));
+            let (name, email) = options.author.as_ref().unwrap_or_else(|| fail("push requires --author"));
+            let message = args.next().unwrap_or_else(|| fail("push requires a commit message"));
+
+            for mapping in args {
+                let (dst, src) = mapping.split_once('=').unwrap_or_else(|| fail("files must be given as dst=src"));
+                let data = fs::read(src).unwrap_or_else(|e| fail(&format!("{}: {}", src, e)));
+                check(repo.stage(dst, Some((data, FileType::RegularFile))));
+            }
+
+            let signature = (name.as_str(), email.as_str());
+            let head = repo.commit(message, signature, signature, None).unwrap_or_else(|e| fail(&format!("{:?}", e)));
+            let report = repo.push(&remote, &[(branch, head)], options.force).unwrap_or_else(|e| fail(&format!("{:?}", e)));
+            if let Some(error) = &report.unpack_error {
+                fail(&format!("remote unpack failed: {}", error));
+            }
+
+            if let Some(error) = &report.remote_error {
+                fail(&format!("remote error: {}", error));
+            }
+
+            for (branch, reason) in report.rejected() {
+                fail(&format!("{} was rejected: {}", branch, reason));
+            }
+
+            println!("{}", head);
+        },
+        _ => fail(USAGE),
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::BTreeSet;
+use lmfu::{LiteMap, HashSet};
+
+use super::internals::{Result, Error, Hash, Repository, ObjectType, TreeIter, Commit};
+
+/// One commit out of this many gets a bitmap, along first parents
+const SELECTION_INTERVAL: usize = 100;
+
+/// Set of object indices
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Bitmap(Vec<u64>);
+
+impl Bitmap {
+    fn set(&mut self, bit: usize) {
+        let word = bit / 64;
+        if word >= self.0.len() {
+            self.0.resize(word + 1, 0);
+        }
+
+        self.0[word] |= 1 << (bit % 64);
+    }
+
+    fn get(&self, bit: usize) -> bool {
+        match self.0.get(bit / 64) {
+            Some(word) => word & (1 << (bit % 64)) != 0,
+            None => false,
+        }
+    }
+
+    fn or(&mut self, other: &Self) {
+        if other.0.len() > self.0.len() {
+            self.0.resize(other.0.len(), 0);
+        }
+
+        for (word, other) in self.0.iter_mut().zip(&other.0) {
+            *word |= other;
+        }
+    }
+
+    fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        let bits = |(i, word): (usize, &u64)| {
+            let word = *word;
+            (0..64).filter(move |b| word & (1 << b) != 0).map(move |b| i * 64 + b)
+        };
+
+        self.0.iter().enumerate().flat_map(bits)
+    }
+}
+
+/// Reachability bitmaps of selected commits
+#[derive(Clone)]
+pub(crate) struct Bitmaps {
+    /// Object to bit index
+    positions: LiteMap<Hash, usize>,
+    /// Bit index to object
+    objects: Vec<Hash>,
+    /// Selected commit to reachable objects
+    selected: LiteMap<Hash, Bitmap>,
+}
+
+/// Objects reachable from a commit
+pub(crate) struct Reachable {
+    bits: Bitmap,
+    /// Objects which aren't indexed in the bitmaps
+    extra: LiteMap<Hash, ()>,
+}
+
+impl Bitmaps {
+    fn new() -> Self {
+        Self {
+            positions: LiteMap::new(),
+            objects: Vec::new(),
+            selected: LiteMap::new(),
+        }
+    }
+}
+
+impl Reachable {
+    fn new() -> Self {
+        Self {
+            bits: Bitmap::default(),
+            extra: LiteMap::new(),
+        }
+    }
+
+    fn contains(&self, bitmaps: &Bitmaps, object: Hash) -> bool {
+        match bitmaps.positions.get(&object) {
+            Some(bit) => self.bits.get(*bit),
+            None => self.extra.contains_key(&object),
+        }
+    }
+
+    fn insert(&mut self, bitmaps: &Bitmaps, object: Hash) {
+        match bitmaps.positions.get(&object) {
+            Some(bit) => self.bits.set(*bit),
+            None => {
+                self.extra.insert(object, ());
+            },
+        }
+    }
+
+    fn for_each<F: FnMut(Hash)>(&self, bitmaps: &Bitmaps, mut callback: F) {
+        self.bits.ones().for_each(|bit| callback(bitmaps.objects[bit]));
+        self.extra.iter().for_each(|(hash, _)| callback(*hash));
+    }
+}
+
+impl Repository {
+    /// Builds reachability bitmaps, which are then kept up to date
+    ///
+    /// Bitmaps make thin pushes and reachability queries on
+    /// large repositories much faster, at the cost of some memory.
+    /// They are rebuilt after each clone.
+    pub fn enable_bitmaps(&mut self) -> Result<()> {
+        self.bitmaps = Some(self.build_bitmaps()?);
+        Ok(())
+    }
+
+    /// Drops reachability bitmaps
+    pub fn disable_bitmaps(&mut self) {
+        self.bitmaps = None;
+    }
+
+    pub(crate) fn refresh_bitmaps(&mut self) -> Result<()> {
+        match self.bitmaps.is_some() {
+            true => self.enable_bitmaps(),
+            false => Ok(()),
+        }
+    }
+
+    fn build_bitmaps(&self) -> Result<Bitmaps> {
+        let mut bitmaps = Bitmaps::new();
+
+        for hash in self.objects.hashes() {
+            bitmaps.positions.insert(hash, bitmaps.objects.len());
+            bitmaps.objects.push(hash);
+        }
+
+        let mut first_parents = Vec::new();
+        let mut current = Some(self.head);
+        while let Some(hash) = current {
+            let Some(commit) = self.objects.load(hash).filter(|o| o.obj_type() == ObjectType::Commit) else { break };
+            first_parents.push(hash);
+            current = Commit::parse(commit.content())?.parents.first().copied();
+        }
+
+        // oldest first, so that each bitmap reuses the previous one
+        for (i, commit) in first_parents.iter().enumerate().rev() {
+            if i == 0 || i % SELECTION_INTERVAL == SELECTION_INTERVAL - 1 {
+                let reachable = self.reach(&bitmaps, *commit, false)?;
+                bitmaps.selected.insert(*commit, reachable.bits);
+            }
+        }
+
+        log::info!("Built {} reachability bitmaps", bitmaps.selected.len());
+        Ok(bitmaps)
+    }
+
+    /// Walks the object graph, stopping at selected commits
+    ///
+    /// Commits are walked before trees, so that the walk reaches
+    /// selected commits before descending into trees which their
+    /// bitmaps cover. Trees aren't walked if `commits_only` is set.
+    fn reach(&self, bitmaps: &Bitmaps, from: Hash, commits_only: bool) -> Result<Reachable> {
+        let mut reachable = Reachable::new();
+        let mut commits = vec![from];
+        let mut others = Vec::new();
+
+        while let Some(hash) = commits.pop().or_else(|| others.pop()) {
+            if reachable.contains(bitmaps, hash) {
+                continue;
+            }
+
+            if let Some(bitmap) = bitmaps.selected.get(&hash) {
+                reachable.bits.or(bitmap);
+                continue;
+            }
+
+            // this is ok for shallow clones & gitlinks
+            let Some(object) = self.objects.load(hash) else { continue };
+            reachable.insert(bitmaps, hash);
+
+            match object.obj_type() {
+                ObjectType::Commit => {
+                    let commit = Commit::parse(object.content())?;
+                    commits.extend(commit.parents);
+                    if !commits_only {
+                        others.push(commit.tree);
+                    }
+                },
+                ObjectType::Tree if commits_only => (),
+                ObjectType::Tree => {
+                    let mut iter = TreeIter::new(object.content());
+                    while let Some((_, hash, _)) = iter.next()? {
+                        others.push(hash);
+                    }
+                },
+                ObjectType::Tag => {
+                    let content = object.content();
+                    let target = content.strip_prefix(b"object ").and_then(|c| c.get(..40));
+                    let target = target.and_then(|hex| core::str::from_utf8(hex).ok());
+                    others.push(target.and_then(Hash::from_hex).ok_or(Error::InvalidObject)?);
+                },
+                ObjectType::Blob => (),
+            }
+        }
+
+        Ok(reachable)
+    }
+
+    fn reach_any(&self, from: Hash, commits_only: bool) -> Result<(Reachable, Option<&Bitmaps>)> {
+        Ok(match &self.bitmaps {
+            Some(bitmaps) => (self.reach(bitmaps, from, commits_only)?, Some(bitmaps)),
+            None => (self.reach(&Bitmaps::new(), from, commits_only)?, None),
+        })
+    }
+
+    /// Checks if `object` is reachable from commit `from`
+    ///
+    /// This is faster with bitmaps, see [`Self::enable_bitmaps`].
+    pub fn is_reachable(&self, from: Hash, object: Hash) -> Result<bool> {
+        let (reachable, bitmaps) = self.reach_any(from, false)?;
+        Ok(reachable.contains(bitmaps.unwrap_or(&Bitmaps::new()), object))
+    }
+
+    /// Checks if commit `commit` is reachable from commit `from`,
+    /// without walking trees
+    pub(crate) fn is_commit_reachable(&self, from: Hash, commit: Hash) -> Result<bool> {
+        let (reachable, bitmaps) = self.reach_any(from, true)?;
+        Ok(reachable.contains(bitmaps.unwrap_or(&Bitmaps::new()), commit))
+    }
+
+    /// Commits reachable from `from`, including itself
+    pub(crate) fn reachable_commits(&self, from: Hash) -> Result<BTreeSet<Hash>> {
+        let mut commits = BTreeSet::new();
+        self.for_each_reachable_with(from, true, |hash| {
+            if self.objects.load(hash).is_some_and(|o| o.obj_type() == ObjectType::Commit) {
+                commits.insert(hash);
+            }
+        })?;
+
+        Ok(commits)
+    }
+
+    /// Adds all objects reachable from `from` to `to_skip`
+    pub(crate) fn skip_reachable(&self, from: Hash, to_skip: &mut HashSet<Hash>) -> Result<()> {
+        self.for_each_reachable(from, |hash| {
+            to_skip.insert(hash, ());
+        })
+    }
+
+    /// Calls `callback` on all objects reachable from `from`
+    pub(crate) fn for_each_reachable<F: FnMut(Hash)>(&self, from: Hash, callback: F) -> Result<()> {
+        self.for_each_reachable_with(from, false, callback)
+    }
+
+    fn for_each_reachable_with<F: FnMut(Hash)>(&self, from: Hash, commits_only: bool, callback: F) -> Result<()> {
+        let (reachable, bitmaps) = self.reach_any(from, commits_only)?;
+        reachable.for_each(bitmaps.unwrap_or(&Bitmaps::new()), callback);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileType;
+
+    fn reachable_set(repo: &Repository, from: Hash) -> BTreeSet<Hash> {
+        let mut set = BTreeSet::new();
+        repo.for_each_reachable(from, |hash| drop(set.insert(hash))).unwrap();
+        set
+    }
+
+    #[test]
+    fn same_answers_with_bitmaps() {
+        let mut repo = Repository::new();
+        let signature = ("Test", "test@example.com");
+        let mut commits = Vec::new();
+
+        for i in 0..(2 * SELECTION_INTERVAL + 20) {
+            let content = format!("{}\n", i).into_bytes();
+            repo.stage(&format!("dir/{}.txt", i % 7), Some((content, FileType::RegularFile))).unwrap();
+            commits.push(repo.commit("commit", signature, signature, Some(i as u64)).unwrap());
+        }
+
+        // a commit which isn't reachable from the head
+        let main = repo.head;
+        repo.head = commits[SELECTION_INTERVAL + 10];
+        repo.root = repo.get_commit_root(repo.head).unwrap();
+        repo.stage("side.txt", Some((b"side\n".to_vec(), FileType::RegularFile))).unwrap();
+        let side = repo.commit("side", signature, signature, Some(1000)).unwrap();
+        let side_blob = repo.find_entry("side.txt", crate::internals::EntryType::File).unwrap().0;
+        repo.head = main;
+        repo.root = repo.get_commit_root(main).unwrap();
+
+        let froms = [side, commits[0], commits[SELECTION_INTERVAL - 1], commits[SELECTION_INTERVAL + 5], main];
+        let without = froms.map(|from| reachable_set(&repo, from));
+
+        repo.enable_bitmaps().unwrap();
+        assert!(repo.bitmaps.as_ref().unwrap().selected.len() > 1);
+
+        for (from, expected) in froms.iter().zip(&without) {
+            assert_eq!(&reachable_set(&repo, *from), expected);
+        }
+
+        for (from, expected) in froms.iter().zip(&without) {
+            for object in [side, side_blob, commits[0], commits[SELECTION_INTERVAL], main] {
+                assert_eq!(repo.is_reachable(*from, object).unwrap(), expected.contains(&object));
+            }
+        }
+
+        assert!(!repo.is_reachable(main, side).unwrap());
+        assert!(!repo.is_reachable(main, side_blob).unwrap());
+        assert!(repo.is_reachable(side, commits[0]).unwrap());
+    }
+}
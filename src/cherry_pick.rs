@@ -0,0 +1,182 @@
+use super::internals::{Result, Error, Hash, Mode, EntryType, Repository, Commit, Signature, ObjectType};
+use super::repository::NewEntry;
+use super::diff::TreeChange;
+
+/// Paths to stage, see [`Repository::stage_all`]
+pub(crate) type StagedEntries = Vec<(String, Option<NewEntry>)>;
+
+/// Outcome of [`Repository::cherry_pick`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[must_use]
+pub enum CherryPick {
+    /// The new commit, which is now the current one
+    Committed(Hash),
+    /// The changes were already in the current commit
+    Empty,
+    /// Paths which were changed on both sides; nothing was staged
+    Conflicts(Vec<String>),
+}
+
+impl Repository {
+    /// Entry at `path` in the staged tree
+    fn staged_entry(&self, path: &str) -> Result<Option<(Hash, Mode)>> {
+        match self.find_entry(path, EntryType::All) {
+            Ok(entry) => Ok(Some(entry)),
+            Err(Error::PathError) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Three-way merge of a file, `None` meaning absent
+    ///
+    /// Returns `Err(())` on conflict.
+    fn pick_entry(
+        &self,
+        path: &str,
+        base: Option<(Hash, Mode)>,
+        ours: Option<(Hash, Mode)>,
+        theirs: Option<(Hash, Mode)>,
+    ) -> Result<core::result::Result<Option<NewEntry>, ()>> {
+        let (Some(base), Some(ours), Some(theirs)) = (base, ours, theirs) else {
+            // added or removed on both sides
+            return Ok(Err(()));
+        };
+
+        let (base_mode, ours_mode, theirs_mode) = (base.1 as u32, ours.1 as u32, theirs.1 as u32);
+        let mode = match (base_mode == ours_mode, base_mode == theirs_mode) {
+            (true, _) => theirs.1,
+            (_, true) => ours.1,
+            _ if ours_mode == theirs_mode => ours.1,
+            _ => return Ok(Err(())),
+        };
+
+        let Some(file_type) = mode.file_type() else {
+            return Ok(Err(()));
+        };
+
+        let merged = if ours.0 == theirs.0 || base.0 == theirs.0 {
+            NewEntry::Existing(ours.0, mode)
+        } else if base.0 == ours.0 {
+            NewEntry::Existing(theirs.0, mode)
+        } else if let Mode::Gitlink = mode {
+            return Ok(Err(()));
+        } else {
+            let blob = |hash| self.any_store_get(hash, ObjectType::Blob).ok_or(Error::MissingObject);
+            match self.merge_file(path, blob(base.0)?, blob(ours.0)?, blob(theirs.0)?) {
+                Some(data) => NewEntry::Data(data, file_type),
+                None => return Ok(Err(())),
+            }
+        };
+
+        Ok(Ok(Some(merged)))
+    }
+
+    /// Changes of `base_tree..theirs_tree`, applied to the staged
+    /// tree, and conflicting paths
+    pub(crate) fn three_way(&self, base_tree: Hash, theirs_tree: Hash) -> Result<(StagedEntries, Vec<String>)> {
+        let mut staged = Vec::new();
+        let mut conflicts = Vec::new();
+
+        for change in self.diff_trees_recursive(base_tree, theirs_tree)? {
+            let (base, theirs) = match &change {
+                TreeChange::Added { hash, mode, .. } => (None, Some((*hash, *mode))),
+                TreeChange::Removed { hash, mode, .. } => (Some((*hash, *mode)), None),
+                TreeChange::Modified { old, new, .. } => (Some(*old), Some(*new)),
+            };
+
+            let path = change.name();
+            let ours = self.staged_entry(path)?;
+            let same = |a: Option<(Hash, Mode)>, b: Option<(Hash, Mode)>| match (a, b) {
+                (Some(a), Some(b)) => a.0 == b.0 && a.1 as u32 == b.1 as u32,
+                (a, b) => a.is_none() && b.is_none(),
+            };
+
+            if same(ours, theirs) {
+                continue;
+            }
+
+            let entry = match same(ours, base) {
+                true => theirs.map(|(hash, mode)| NewEntry::Existing(hash, mode)),
+                false => match self.pick_entry(path, base, ours, theirs)? {
+                    Ok(entry) => entry,
+                    Err(()) => {
+                        conflicts.push(path.to_string());
+                        continue;
+                    },
+                },
+            };
+
+            staged.push((path.to_string(), entry));
+        }
+
+        Ok((staged, conflicts))
+    }
+
+    /// Stages entries returned by [`Self::three_way`]; on error,
+    /// the staged root is restored
+    pub(crate) fn stage_all(&mut self, entries: StagedEntries) -> Result<()> {
+        let root = self.root;
+        for (path, entry) in entries {
+            if let Err(error) = self.stage_entry(&path, entry) {
+                self.root = root;
+                self.journal_head();
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Replays the changes of a commit onto the current one
+    ///
+    /// The changes of `commit` relative to its first parent are
+    /// applied to the current files. Files which were also changed
+    /// since that parent are merged with their merge driver (see
+    /// [`Self::merge_file`]); if that fails, the conflicting paths
+    /// are returned and nothing is staged.
+    ///
+    /// The new commit keeps the message and the author of the
+    /// original one; its committer is the same person, at the
+    /// current time, unless the [`CommitPolicy`](crate::CommitPolicy)
+    /// sets another one.
+    ///
+    /// Returns `DirtyWorkspace` if there are staged changes and
+    /// `MissingObject` if the commit or its parent isn't in the
+    /// repository.
+    pub fn cherry_pick(&mut self, commit: Hash) -> Result<CherryPick> {
+        if self.get_commit_root(self.head)? != self.root {
+            return Err(Error::DirtyWorkspace);
+        }
+
+        let Some(raw) = self.objects.get_as(commit, ObjectType::Commit) else {
+            log::error!("Commit {} isn't in the repository", commit);
+            return Err(Error::MissingObject);
+        };
+
+        let raw = raw.to_vec();
+        let picked = Commit::parse(&raw)?;
+        let base_tree = match picked.parents.first() {
+            Some(parent) => self.get_commit_root(*parent)?.unwrap_or(Hash::zero()),
+            None => Hash::zero(),
+        };
+
+        let (staged, conflicts) = self.three_way(base_tree, picked.tree)?;
+
+        if !conflicts.is_empty() {
+            return Ok(CherryPick::Conflicts(conflicts));
+        }
+
+        if staged.is_empty() {
+            return Ok(CherryPick::Empty);
+        }
+
+        self.stage_all(staged)?;
+
+        let author = picked.author;
+        let committer = Signature::now(author.name, author.email).with_tz_offset(self.tz_offset);
+        let message = picked.message.strip_suffix('\n').unwrap_or(picked.message);
+
+        let builder = self.commit_builder().message(message).author(author).committer(committer);
+        Ok(CherryPick::Committed(builder.commit()?))
+    }
+}
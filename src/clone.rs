@@ -1,155 +1,452 @@
+use std::io::Read;
 use coolssh::{Connection, RunResult};
 
 use super::internals::{
     Result, Error, Remote, PacketLine, GitProtocol,
-    Hash, Repository, TcpStream, PackfileReader,
+    Hash, Repository, PackfileReader,
 };
+use super::fetch::{FetchRequest, read_fetch_features};
+use super::refs::{RefName, case_collisions};
 
 /// Specifies what to clone from a remote repository
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub enum Reference<'a> {
     Head,
+    /// Servers only send commits which aren't branch or tag tips if
+    /// they allow it (`uploadpack.allowReachableSHA1InWant` or
+    /// `uploadpack.allowAnySHA1InWant`); otherwise, cloning returns
+    /// `CommitNotFetchable`. GitHub & GitLab allow reachable commits.
     Commit(Hash),
     Branch(&'a str),
 }
 
 use Reference::{Head, Branch};
 
+/// Name of a reference on the remote, `None` for commits
+fn remote_ref_name(reference: &Reference) -> Option<String> {
+    match reference {
+        Head => Some("HEAD".into()),
+        Branch(branch) => Some(format!("refs/heads/{}", branch)),
+        Reference::Commit(_) => None,
+    }
+}
+
+/// References copied by [`CloneOptions::mirror`]
+fn is_mirrored(ref_name: &str) -> bool {
+    ref_name.starts_with("refs/heads/") || (ref_name.starts_with("refs/tags/") && !ref_name.ends_with("^{}"))
+}
+
+/// Settings of [`Repository::clone_with`]
+///
+/// ```ignore
+/// let options = CloneOptions::new().branch("main").depth(1).filter("blob:none");
+/// repo.clone_with(&remote, &options)?;
+/// ```
+#[derive(Debug)]
+pub struct CloneOptions<'a> {
+    reference: Reference<'a>,
+    /// Fetched in the same request, see [`Self::references`]
+    others: Vec<Reference<'a>>,
+    mirror: bool,
+    depth: Option<usize>,
+    deepen_since: Option<u64>,
+    filter: Option<&'a str>,
+}
+
+impl<'a> Default for CloneOptions<'a> {
+    fn default() -> Self {
+        Self {
+            reference: Head,
+            others: Vec::new(),
+            mirror: false,
+            depth: None,
+            deepen_since: None,
+            filter: None,
+        }
+    }
+}
+
+impl<'a> CloneOptions<'a> {
+    /// Clones the remote `HEAD`, with its whole history
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// What to clone, defaults to [`Reference::Head`]
+    pub fn reference(mut self, reference: Reference<'a>) -> Self {
+        self.reference = reference;
+        self
+    }
+
+    /// Fetches several references in one request
+    ///
+    /// The first one becomes the current commit; the tips of the
+    /// other branches are saved as `refs/heads/<branch>` and other
+    /// commits are only fetched. [`Reference::Head`] can only come
+    /// first: cloning returns `InvalidRefName` otherwise.
+    ///
+    /// ```ignore
+    /// let options = CloneOptions::new().references(&[Branch("main"), Branch("release")]);
+    /// ```
+    pub fn references(mut self, references: &[Reference<'a>]) -> Self {
+        if let [first, others @ ..] = references {
+            self.reference = *first;
+            self.others = others.to_vec();
+        }
+
+        self
+    }
+
+    /// Also fetches all branches & tags of the remote, saving
+    /// them under the same names (`refs/heads/*`, `refs/tags/*`)
+    pub fn mirror(mut self, mirror: bool) -> Self {
+        self.mirror = mirror;
+        self
+    }
+
+    /// Shortcut for `reference(Reference::Branch(branch))`
+    pub fn branch(self, branch: &'a str) -> Self {
+        self.reference(Branch(branch))
+    }
+
+    /// Limits the history to `depth` commits
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Limits the history to commits newer than a UNIX timestamp
+    pub fn deepen_since(mut self, timestamp: u64) -> Self {
+        self.deepen_since = Some(timestamp);
+        self
+    }
+
+    /// Omits some objects, example: `blob:none`
+    pub fn filter(mut self, spec: &'a str) -> Self {
+        self.filter = Some(spec);
+        self
+    }
+}
+
+/// Starts `git-upload-pack` using protocol version 2
+///
+/// Returns `GitProtocolError` if the remote refuses to run it.
+pub(crate) fn upload_pack<'a>(conn: &'a mut Connection, remote: &Remote) -> Result<GitProtocol<'a>> {
+    let env = [("GIT_PROTOCOL", "version=2")];
+    let command = format!("git-upload-pack {}", remote.path);
+
+    let mut protocol = match conn.run(&command, &env)? {
+        RunResult::Accepted(run) => GitProtocol::new(run),
+        _ => {
+            log::error!("Remote refused to run git-upload-pack");
+            return Err(Error::GitProtocolError);
+        },
+    };
+
+    protocol.set_idle_timeout(remote.connect_options.idle_timeout);
+    Ok(protocol)
+}
+
+/// Runs `command=ls-refs` and collects all advertised references
+pub(crate) fn ls_refs(protocol: &mut GitProtocol) -> Result<Vec<(String, Hash)>> {
+    let gpe = Error::GitProtocolError;
+
+    protocol.write_lines(&[
+        PacketLine::String("command=ls-refs\n"),
+        PacketLine::DelimiterPacket,
+        PacketLine::FlushPacket,
+    ])?;
+
+    let mut refs = Vec::new();
+    while let Some(line) = protocol.read_line_str()? {
+        let (hash_hex, ref_name) = line.split_once(' ').ok_or(gpe)?;
+        refs.push((ref_name.into(), Hash::from_hex(hash_hex).ok_or(gpe)?));
+    }
+
+    Ok(refs)
+}
+
+/// Lists the references advertised by a remote repository
+///
+/// This includes `HEAD`, branches (`refs/heads/*`) and tags
+/// (`refs/tags/*`), without importing any object.
+pub fn ls_remote(remote: &Remote) -> Result<Vec<(String, Hash)>> {
+    let mut conn = remote.connect()?;
+    let mut protocol = upload_pack(&mut conn, remote)?;
+
+    // skip the capability advertisement
+    read_fetch_features(&mut protocol)?;
+
+    ls_refs(&mut protocol)
+}
+
 impl Repository {
     /// Imports objects from a remote repository based on a reference
     ///
-    /// Note: Can return `Err(GitProtocolError)` when an invalid Commit
-    /// reference is specified (one which doesn't exist on the remote end).
+    /// See [`Self::clone_with`] for more settings.
     pub fn clone(
         &mut self,
         remote: &Remote,
         reference: Reference,
         depth: Option<usize>,
     ) -> Result<()> {
+        let mut options = CloneOptions::new().reference(reference);
+        options.depth = depth;
+        self.clone_with(remote, &options)
+    }
+
+    /// Imports objects from a remote repository
+    ///
+    /// Note: Returns `Err(CommitNotFetchable)` when an invalid Commit
+    /// reference is specified (one which doesn't exist on the remote end
+    /// or which the remote refuses to send), and `UnsupportedByRemote`
+    /// if the remote doesn't support the depth or filter settings.
+    ///
+    /// Branches & `HEAD` are resolved by the remote in the fetch request
+    /// if it supports `want-ref`, saving a round trip. Replacement
+    /// references (`refs/replace/*`) are then only listed if
+    /// [`Self::set_replace_objects`] was enabled.
+    pub fn clone_with(&mut self, remote: &Remote, options: &CloneOptions) -> Result<()> {
+        let reference = &options.reference;
+        let references = || core::iter::once(reference).chain(options.others.iter());
+        for (i, reference) in references().enumerate() {
+            match reference {
+                Branch(branch) => {
+                    RefName::new(&format!("refs/heads/{}", branch))?;
+                },
+                Head if i > 0 => {
+                    log::error!("HEAD can only be the first reference of a clone");
+                    return Err(Error::InvalidRefName);
+                },
+                Head | Reference::Commit(_) => (),
+            }
+        }
+
         let head_root = self.get_commit_root(self.head).unwrap();
         if self.upstream_head != self.head || (head_root.is_some() && head_root != self.root) {
             return Err(Error::DirtyWorkspace);
         }
 
-        let stream = TcpStream::connect(&*remote.host).unwrap();
-        let mut conn = Connection::new(stream, (&*remote.username, &*remote.keypair).into())?;
+        // cloning again only downloads new objects
+        let haves = self.local_haves()?;
 
-        conn.mutate_stream(|stream| {
-            let duration = std::time::Duration::from_millis(1000);
-            stream.set_read_timeout(Some(duration)).unwrap()
-        });
+        let mut conn = remote.connect()?;
+        let mut protocol = upload_pack(&mut conn, remote)?;
+        protocol.set_message_handler(self.message_handler.clone());
 
-        let env = [("GIT_PROTOCOL", "version=2")];
+        let features = read_fetch_features(&mut protocol)?;
 
-        let command = format!("git-upload-pack {}", remote.path);
-        let gpe = Error::GitProtocolError;
-        let mut protocol = match conn.run(&command, &env)? {
-            RunResult::Accepted(run) => GitProtocol::new(run),
-            _ => panic!("run was refused"),
-        };
+        // replacements are only known from ls-refs
+        let ref_in_want = features.iter().any(|feature| feature == "ref-in-want");
+        let use_want_ref = ref_in_want && !self.replace_objects && !options.mirror;
+        let names = references().filter_map(remote_ref_name).collect::<Vec<_>>();
+
+        // `(name, target)` of the remote references
+        let mut resolved = Vec::new();
+        let mut request = FetchRequest::new();
 
-        let mut shallow_supported = false;
-        while let Some(line) = protocol.read_line_str()? {
-            log::debug!("Server capability: {}", line);
-            if let Some(fetch_options) = line.strip_prefix("fetch=") {
-                for option in fetch_options.split(' ') {
-                    if option == "shallow" {
-                        shallow_supported = true;
-                    }
+        if use_want_ref {
+            for name in &names {
+                request = request.want_ref(name);
+            }
+        } else if !names.is_empty() || options.mirror {
+            for (ref_name, hash) in ls_refs(&mut protocol)? {
+                if let Some(original) = ref_name.strip_prefix("refs/replace/") {
+                    let original = Hash::from_hex(original).ok_or(Error::GitProtocolError)?;
+                    self.replacements.insert(original, hash);
+                } else if names.contains(&ref_name) || (options.mirror && is_mirrored(&ref_name)) {
+                    resolved.push((ref_name, hash));
                 }
             }
+
+            if let Some(name) = names.iter().find(|name| !resolved.iter().any(|(n, _)| n == *name)) {
+                log::error!("Reference {} wasn't advertised by remote server", name);
+                return Err(Error::NoSuchReference);
+            }
         }
 
-        if let Reference::Commit(hash) = reference {
-            self.head = hash;
-        } else {
-            self.head = Hash::zero();
-
-            protocol.write_lines(&[
-                PacketLine::String("command=ls-refs\n"),
-                PacketLine::DelimiterPacket,
-                PacketLine::FlushPacket,
-            ])?;
-
-            while let Some(line) = protocol.read_line_str()? {
-                let (hash_hex, ref_name) = line.split_once(' ').ok_or(gpe)?;
-                if let Head = reference {
-                    if ref_name == "HEAD" {
-                        self.head = Hash::from_hex(hash_hex).ok_or(gpe)?;
-                        // don't break so that all lines are read
-                    }
-                } else if let Branch(branch) = reference {
-                    if let Some(ref_name) = ref_name.strip_prefix("refs/heads/") {
-                        if ref_name == branch {
-                            self.head = Hash::from_hex(hash_hex).ok_or(gpe)?;
-                            // don't break so that all lines are read
-                        }
-                    }
-                }
+        let commits = references().filter_map(|reference| match reference {
+            Reference::Commit(hash) => Some(*hash),
+            _ => None,
+        });
+
+        let mut wants = Vec::new();
+        for hash in commits.chain(resolved.iter().map(|(_, hash)| *hash)) {
+            if !wants.contains(&hash) {
+                wants.push(hash);
+                request = request.want(hash);
             }
+        }
 
-            if self.head == Hash::zero() {
-                log::error!("Reference {:?} wasn't advertised by remote server", reference);
-                return Err(Error::NoSuchReference);
+        for have in haves {
+            request = request.have(have);
+        }
+
+        for commit in self.shallow_commits() {
+            request = request.shallow(commit);
+        }
+
+        if self.replace_objects {
+            for replacement in self.replacements.iter().map(|(_, replacement)| replacement) {
+                request = request.want(*replacement);
             }
         }
 
-        let want_head = format!("want {}", self.head);
+        if let Some(depth) = options.depth {
+            request = request.depth(depth);
+        }
 
-        if let Some(num) = depth {
-            if !shallow_supported {
-                log::error!("Remote server doesn't support depth settings");
-                return Err(Error::UnsupportedByRemote);
+        if let Some(timestamp) = options.deepen_since {
+            request = request.deepen_since(timestamp);
+        }
+
+        if let Some(spec) = options.filter {
+            request = request.filter(spec);
+        }
+
+        let response = request.execute_with(protocol, &features, &mut self.objects, self.pack_reuse)?;
+        self.record_shallow(&response);
+        if use_want_ref {
+            resolved = response.wanted_refs;
+        }
+
+        let target = |reference: &Reference| {
+            let name = match reference {
+                Reference::Commit(hash) => return Ok(*hash),
+                Head => "HEAD".to_string(),
+                Branch(branch) => format!("refs/heads/{}", branch),
+            };
+
+            match resolved.iter().find(|(resolved, _)| *resolved == name) {
+                Some((_, hash)) => Ok(*hash),
+                None => {
+                    log::error!("Remote server didn't resolve {}", name);
+                    Err(Error::GitProtocolError)
+                },
+            }
+        };
+
+        self.head = target(reference)?;
+
+        let mut saved = Vec::new();
+        for other in &options.others {
+            match other {
+                Branch(branch) => saved.push((format!("refs/heads/{}", branch), target(other)?)),
+                // HEAD was rejected above; commits have no name to save
+                Head | Reference::Commit(_) => (),
             }
+        }
 
-            let deepen = format!("deepen {}", num);
-            protocol.write_lines(&[
-                PacketLine::String("command=fetch\n"),
-                PacketLine::DelimiterPacket,
-                PacketLine::String(&want_head),
-                PacketLine::String("no-progress"),
-                PacketLine::String(&deepen),
-                // todo: thin-pack?
-                PacketLine::String("done"),
-                PacketLine::FlushPacket,
-            ])?;
-        } else {
-            protocol.write_lines(&[
-                PacketLine::String("command=fetch\n"),
-                PacketLine::DelimiterPacket,
-                PacketLine::String(&want_head),
-                PacketLine::String("no-progress"),
-                // todo: thin-pack?
-                PacketLine::String("done"),
-                PacketLine::FlushPacket,
-            ])?;
-        }
-
-        while Some(b"packfile\n".as_slice()) != protocol.read_line()? {}
-
-        let mut reader = PackfileReader::new(protocol)?;
-
-        reader.read_all_objects(&mut self.objects)?;
-
-        // todo: read footer
+        if options.mirror {
+            saved.extend(resolved.iter().filter(|(name, _)| is_mirrored(name)).cloned());
+        }
+
+        for (name, hash) in saved {
+            self.set_ref(&name, hash)?;
+        }
 
         self.upstream_head = self.head;
         self.root = self.get_commit_root(self.head)?;
+        self.record_reflog("clone");
+        self.refresh_bitmaps()?;
 
         Ok(())
     }
 
+    /// Imports objects from a packfile and recreates references
+    ///
+    /// `refs` are `(name, target)` pairs, such as the ones returned by
+    /// [`ls_remote`]. If a `HEAD` entry is present, it becomes the
+    /// current commit; other entries are saved as references. Deltas
+    /// can use objects of the repository as bases (thin packs). Returns
+    /// `MissingObject` if a target isn't in the packfile nor in the
+    /// repository, and `AmbiguousReference` if two names only differ
+    /// by case (see [`case_collisions`]) instead of letting the last
+    /// one win, and `InvalidPackfile` if the packfile's checksum doesn't
+    /// match; nothing is changed in these cases.
+    pub fn import_packfile_with_refs(&mut self, packfile: Vec<u8>, refs: &[(&str, Hash)]) -> Result<()> {
+        let existing = self.refs.iter().map(|(name, _)| name.as_str());
+        let collisions = case_collisions(refs.iter().map(|(name, _)| *name).chain(existing));
+        if !collisions.is_empty() {
+            log::error!("References only differ by case: {:?}", collisions);
+            return Err(Error::AmbiguousReference);
+        }
+
+        // objects go straight to the store, so thin packs can use
+        // its objects as bases; new ones are removed on failure
+        let mut added = Vec::new();
+        let result = PackfileReader::from_file(packfile).and_then(|mut reader| {
+            reader.read_objects_into(&mut self.objects, self.pack_reuse, Some(&mut added))?;
+            reader.verify_checksum().map(drop)
+        });
+
+        let result = result.and_then(|()| match refs.iter().find(|(_, target)| self.get_object(*target).is_none()) {
+            Some((name, target)) => {
+                log::error!("{} points to {}, which wasn't imported", name, target);
+                Err(Error::MissingObject)
+            },
+            None => Ok(()),
+        });
+
+        if let Err(error) = result {
+            for hash in added {
+                self.objects.remove(hash);
+            }
+
+            return Err(error);
+        }
+
+        let mut head = None;
+        for (name, target) in refs {
+            match *name {
+                "HEAD" => head = Some(*target),
+                name => {
+                    self.set_ref(name, *target)?;
+                },
+            }
+        }
+
+        if let Some(head) = head {
+            self.head = head;
+            self.upstream_head = head;
+            self.root = self.get_commit_root(head)?;
+            self.record_reflog("import");
+        }
+
+        self.refresh_bitmaps()
+    }
+
+    /// Imports objects from a packfile, then moves to `head`
+    ///
+    /// Returns `InvalidPackfile` if the checksum which ends
+    /// the packfile doesn't match its content.
     pub fn import_packfile(&mut self, packfile: Vec<u8>, head: Option<Hash>) -> Result<()> {
-        let mut reader = PackfileReader::from_file(packfile)?;
+        self.import_from(PackfileReader::from_file(packfile)?, head)
+    }
 
-        reader.read_all_objects(&mut self.objects)?;
+    /// Same as [`Self::import_packfile`], but the packfile is read
+    /// incrementally, so it doesn't have to fit in memory
+    ///
+    /// ```ignore
+    /// repo.import_packfile_from(io::stdin().lock(), Some(head))?;
+    /// ```
+    pub fn import_packfile_from<R: Read>(&mut self, packfile: R, head: Option<Hash>) -> Result<()> {
+        self.import_from(PackfileReader::from_reader(packfile)?, head)
+    }
+
+    fn import_from(&mut self, mut reader: PackfileReader, head: Option<Hash>) -> Result<()> {
+        reader.read_all_objects_with(&mut self.objects, self.pack_reuse)?;
+        reader.verify_checksum()?;
 
         if let Some(head) = head {
             self.head = head;
             self.upstream_head = head;
             self.root = self.get_commit_root(head)?;
+            self.record_reflog("import");
         }
 
+        self.refresh_bitmaps()?;
+
         Ok(())
     }
 }
\ No newline at end of file
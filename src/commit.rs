@@ -0,0 +1,367 @@
+use core::{fmt, str::from_utf8};
+
+use super::internals::{Result, Error, Hash, Repository, ObjectType, Write};
+use super::repository::now;
+use super::identity::IdentityPolicy;
+use super::tag::SIGNATURE_HEADERS;
+use super::journal::commit_event;
+
+/// Name, email and date of an author or committer
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Signature<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    /// UNIX timestamp, in seconds
+    pub timestamp: u64,
+    /// Offset from UTC, in minutes
+    pub tz_offset: i16,
+}
+
+impl<'a> Signature<'a> {
+    /// Creates a signature in the UTC timezone
+    pub fn new(name: &'a str, email: &'a str, timestamp: u64) -> Self {
+        Self {
+            name,
+            email,
+            timestamp,
+            tz_offset: 0,
+        }
+    }
+
+    /// Creates a signature with the current time, in the UTC timezone
+    pub fn now(name: &'a str, email: &'a str) -> Self {
+        Self::new(name, email, now())
+    }
+
+    /// Sets the offset from UTC, in minutes (example: `-300` for `-0500`)
+    pub fn with_tz_offset(mut self, tz_offset: i16) -> Self {
+        self.tz_offset = tz_offset;
+        self
+    }
+
+    /// Returns `InvalidObject` if this cannot be serialized
+    /// or if `policy` rejects the identity
+    pub(crate) fn check(&self, policy: &IdentityPolicy) -> Result<()> {
+        policy.check(self.name, self.email)?;
+
+        // git timezones are limited to +/-9959
+        match self.tz_offset.unsigned_abs() < 100 * 60 {
+            true => Ok(()),
+            false => Err(Error::InvalidObject),
+        }
+    }
+
+    /// Parses `Name <email> 1700000000 +0100`
+    pub fn parse(value: &'a str) -> Result<Self> {
+        let inv_bytes = Error::InvalidObject;
+        let (name, value) = value.split_once(" <").ok_or(inv_bytes)?;
+        let (email, value) = value.split_once("> ").ok_or(inv_bytes)?;
+        let (timestamp, timezone) = value.split_once(' ').ok_or(inv_bytes)?;
+
+        Ok(Self {
+            name,
+            email,
+            timestamp: timestamp.parse().map_err(|_| inv_bytes)?,
+            tz_offset: parse_timezone(timezone).ok_or(inv_bytes)?,
+        })
+    }
+}
+
+/// Parses `+hhmm` / `-hhmm` into minutes
+///
+/// This can be used on `CommitField::AuthorTimezone`
+/// and `CommitField::CommitterTimezone` values.
+pub fn parse_timezone(timezone: &str) -> Option<i16> {
+    let (sign, digits) = if let Some(digits) = timezone.strip_prefix('+') {
+        (1, digits)
+    } else {
+        (-1, timezone.strip_prefix('-')?)
+    };
+
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let hours: i16 = digits[..2].parse().ok()?;
+    let minutes: i16 = digits[2..].parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+impl<'a> fmt::Display for Signature<'a> {
+    /// Formats the signature as in commit objects
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.tz_offset < 0 { '-' } else { '+' };
+        let offset = self.tz_offset.unsigned_abs();
+        let (hours, minutes) = (offset / 60, offset % 60);
+        write!(f, "{} <{}> {} {}{:02}{:02}", self.name, self.email, self.timestamp, sign, hours, minutes)
+    }
+}
+
+/// Parsed commit object
+#[derive(Clone, Debug)]
+pub struct Commit<'a> {
+    pub tree: Hash,
+    pub parents: Vec<Hash>,
+    pub author: Signature<'a>,
+    pub committer: Signature<'a>,
+    /// Other headers (`gpgsig`, `encoding`, `mergetag`...), in order
+    ///
+    /// Continuation lines of multi-line values keep their leading space.
+    pub extra_headers: Vec<(&'a str, &'a str)>,
+    pub message: &'a str,
+}
+
+impl<'a> Commit<'a> {
+    /// Parses a serialized commit object
+    ///
+    /// Returns `InvalidObject` if the commit is malformed
+    /// or lacks a tree, an author or a committer.
+    pub fn parse(commit: &'a [u8]) -> Result<Self> {
+        let inv_bytes = Error::InvalidObject;
+        let text = from_utf8(commit).ok().ok_or(inv_bytes)?;
+        let (metadata, message) = text.split_once("\n\n").ok_or(inv_bytes)?;
+
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers = Vec::new();
+
+        let mut rest = metadata;
+        while !rest.is_empty() {
+            let (line, mut next) = rest.split_once('\n').unwrap_or((rest, ""));
+            let (key, _) = line.split_once(' ').ok_or(inv_bytes)?;
+
+            // a value continues on lines starting with a space
+            let mut end = line.len();
+            while next.starts_with(' ') {
+                let (continuation, after) = next.split_once('\n').unwrap_or((next, ""));
+                end += 1 + continuation.len();
+                next = after;
+            }
+
+            let value = &rest[key.len() + 1..end];
+            match key {
+                "tree" if tree.is_none() => tree = Some(Hash::from_hex(value).ok_or(inv_bytes)?),
+                "parent" => parents.push(Hash::from_hex(value).ok_or(inv_bytes)?),
+                "author" if author.is_none() => author = Some(Signature::parse(value)?),
+                "committer" if committer.is_none() => committer = Some(Signature::parse(value)?),
+                _ => extra_headers.push((key, value)),
+            }
+
+            rest = next;
+        }
+
+        Ok(Self {
+            tree: tree.ok_or(inv_bytes)?,
+            parents,
+            author: author.ok_or(inv_bytes)?,
+            committer: committer.ok_or(inv_bytes)?,
+            extra_headers,
+            message,
+        })
+    }
+
+    /// Value of the first extra header named `key`
+    pub fn header(&self, key: &str) -> Option<&'a str> {
+        self.extra_headers.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+    }
+}
+
+/// Settings for a new commit, see [`Repository::commit_builder`]
+pub struct CommitBuilder<'a> {
+    repo: &'a mut Repository,
+    message: &'a str,
+    author: Option<Signature<'a>>,
+    committer: Option<Signature<'a>>,
+    parents: Option<&'a [Hash]>,
+    extra_headers: Vec<(&'a str, &'a str)>,
+}
+
+impl Repository {
+    /// Prepares a new commit which saves staged files into the
+    /// repository.
+    ///
+    /// ```ignore
+    /// let author = Signature::now("Jane Doe", "jane@example.com");
+    /// let hash = repo.commit_builder().message("Update config").author(author).commit()?;
+    /// ```
+    pub fn commit_builder(&mut self) -> CommitBuilder<'_> {
+        CommitBuilder {
+            repo: self,
+            message: "",
+            author: None,
+            committer: None,
+            parents: None,
+            extra_headers: Vec::new(),
+        }
+    }
+
+    /// Creates a merge commit which saves staged files into the
+    /// repository.
+    ///
+    /// The current commit isn't implicitly added to `parents`;
+    /// it is usually the first one. See [`CommitBuilder::parents`].
+    pub fn commit_merge(&mut self, message: &str, signature: Signature, parents: &[Hash]) -> Result<Hash> {
+        self.commit_builder().message(message).author(signature).parents(parents).commit()
+    }
+}
+
+impl<'a> CommitBuilder<'a> {
+    pub fn message(mut self, message: &'a str) -> Self {
+        self.message = message;
+        self
+    }
+
+    /// This is mandatory
+    pub fn author(mut self, author: Signature<'a>) -> Self {
+        self.author = Some(author);
+        self
+    }
+
+    /// Defaults to the author
+    pub fn committer(mut self, committer: Signature<'a>) -> Self {
+        self.committer = Some(committer);
+        self
+    }
+
+    /// Sets the parent commits, replacing the current commit
+    ///
+    /// Returns `MissingObject` if one of them isn't in the repository
+    /// and `InvalidObject` if a commit appears twice.
+    pub fn parents(mut self, parents: &'a [Hash]) -> Self {
+        self.parents = Some(parents);
+        self
+    }
+
+    /// Adds a header, such as `encoding`
+    ///
+    /// Values can span multiple lines.
+    pub fn header(mut self, key: &'a str, value: &'a str) -> Self {
+        self.extra_headers.push((key, value));
+        self
+    }
+
+    /// Creates the commit and makes it the current one
+    ///
+    /// Returns `InvalidObject` if the author is missing, if a name
+    /// or email is rejected by the [`IdentityPolicy`], if the message
+    /// is rejected by the [`CommitPolicy`](crate::CommitPolicy), if a
+    /// timezone is out of range or if a header name is invalid.
+    ///
+    /// Returns `OutdatedBase` if the current commit is rejected by
+    /// [`CommitPolicy::check_upstream`](crate::CommitPolicy::check_upstream).
+    pub fn commit(self) -> Result<Hash> {
+        let serialized = self.payload()?;
+        if self.repo.journaling() {
+            self.repo.journal_write(&commit_event(&serialized))?;
+        }
+
+        self.repo.commit_staged()?;
+
+        self.repo.head = self.repo.objects.insert(ObjectType::Commit, serialized, None)?;
+        self.repo.record_reflog("commit");
+
+        Ok(self.repo.head)
+    }
+
+    /// Creates a signed commit and makes it the current one
+    ///
+    /// See [`Repository::commit_signed`] for the requirements
+    /// on `signer`.
+    pub fn commit_signed<S: FnOnce(&[u8]) -> Result<String>>(self, signer: S) -> Result<Hash> {
+        let payload = self.payload()?;
+        let signature = signer(&payload)?;
+
+        if !SIGNATURE_HEADERS.iter().any(|h| signature.starts_with(h)) {
+            log::error!("Commit signature doesn't start with a known armor header");
+            return Err(Error::InvalidObject);
+        }
+
+        // identities cannot contain newlines: this is the end of the headers
+        let headers_end = payload.windows(2).position(|w| w == b"\n\n").unwrap() + 1;
+        let (headers, message) = payload.split_at(headers_end);
+
+        let mut serialized = headers.to_vec();
+        write_header(&mut serialized, "gpgsig", signature.trim_end());
+        serialized.extend_from_slice(message);
+
+        if self.repo.journaling() {
+            self.repo.journal_write(&commit_event(&serialized))?;
+        }
+
+        self.repo.commit_staged()?;
+
+        self.repo.head = self.repo.objects.insert(ObjectType::Commit, serialized, None)?;
+        self.repo.record_reflog("commit");
+
+        Ok(self.repo.head)
+    }
+
+    fn payload(&self) -> Result<Vec<u8>> {
+        let Some(author) = self.author else {
+            log::error!("Commits must have an author");
+            return Err(Error::InvalidObject);
+        };
+
+        let policy = &self.repo.commit_policy;
+        let committer = policy.committer_for(self.committer.unwrap_or(author));
+        author.check(&self.repo.identity_policy)?;
+        committer.check(&self.repo.identity_policy)?;
+        let message = policy.apply(self.message, &committer)?;
+        if self.parents.is_none() {
+            policy.check_base(self.repo)?;
+        }
+
+        let mut serialized = Vec::new();
+
+        let root = self.repo.root.unwrap_or(Hash::zero());
+        writeln!(&mut serialized, "tree {}", root).unwrap();
+
+        let head = [self.repo.head];
+        let parents = match self.parents {
+            Some(parents) => parents,
+            None if head[0].is_zero() => &[],
+            None => &head,
+        };
+
+        for (i, parent) in parents.iter().enumerate() {
+            if parents[..i].contains(parent) {
+                log::error!("Duplicate parent: {}", parent);
+                return Err(Error::InvalidObject);
+            }
+
+            if self.parents.is_some() && self.repo.objects.get_as(*parent, ObjectType::Commit).is_none() {
+                log::error!("Parent commit {} isn't in the repository", parent);
+                return Err(Error::MissingObject);
+            }
+
+            writeln!(&mut serialized, "parent {}", parent).unwrap();
+        }
+
+        writeln!(&mut serialized, "author {}", author).unwrap();
+        writeln!(&mut serialized, "committer {}", committer).unwrap();
+
+        for (key, value) in &self.extra_headers {
+            let reserved = ["tree", "parent", "author", "committer", "gpgsig"].contains(key);
+            if reserved || key.is_empty() || key.contains(char::is_whitespace) {
+                log::error!("Invalid commit header: {:?}", key);
+                return Err(Error::InvalidObject);
+            }
+
+            write_header(&mut serialized, key, value);
+        }
+
+        write!(&mut serialized, "\n{}\n", message).unwrap();
+
+        Ok(serialized)
+    }
+}
+
+/// Continuation lines are prefixed with a space
+fn write_header(dst: &mut Vec<u8>, key: &str, value: &str) {
+    dst.extend_from_slice(key.as_bytes());
+    for line in value.split('\n') {
+        writeln!(dst, " {}", line).unwrap();
+    }
+}
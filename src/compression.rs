@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use miniz_oxide::{deflate::compress_to_vec_zlib, inflate::decompress_to_vec_zlib_with_limit};
+
+use super::internals::{Result, Error, Repository};
+
+/// Compression algorithm, see [`Repository::set_zlib_codec`]
+/// and [`Repository::set_storage_codec`]
+///
+/// For instance, zlib-ng can be plugged in with `flate2`:
+///
+/// ```ignore
+/// struct ZlibNg;
+///
+/// impl Codec for ZlibNg {
+///     fn id(&self) -> u8 {
+///         ZLIB_CODEC_ID
+///     }
+///
+///     fn compress(&self, data: &[u8], level: u8) -> Vec<u8> {
+///         let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level as u32));
+///         encoder.write_all(data).unwrap();
+///         encoder.finish().unwrap()
+///     }
+///
+///     fn decompress(&self, data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+///         let mut inflated = Vec::new();
+///         let decoder = ZlibDecoder::new(data).take(max_size as u64 + 1);
+///         decoder.read_to_end(&mut inflated).map_err(|_| Error::InvalidObject)?;
+///         Ok(inflated)
+///     }
+/// }
+///
+/// repo.set_zlib_codec(Arc::new(ZlibNg));
+/// ```
+pub trait Codec: Send + Sync {
+    /// Format of the compressed data, recorded in the crate's
+    /// own formats; zlib implementations return [`ZLIB_CODEC_ID`]
+    fn id(&self) -> u8;
+
+    /// Compresses `data`; `level` goes from 0 (no compression)
+    /// to 9 (smallest output)
+    fn compress(&self, data: &[u8], level: u8) -> Vec<u8>;
+
+    /// Decompresses `data`
+    ///
+    /// Should return `InvalidObject` if the data is invalid or
+    /// if it inflates to more than `max_size` bytes.
+    fn decompress(&self, data: &[u8], max_size: usize) -> Result<Vec<u8>>;
+}
+
+/// See [`Codec::id`]
+pub const ZLIB_CODEC_ID: u8 = 0;
+
+/// See [`Codec::id`]
+pub const ZSTD_CODEC_ID: u8 = 1;
+
+/// Default zlib implementation, based on `miniz_oxide`
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Miniz;
+
+impl Codec for Miniz {
+    fn id(&self) -> u8 {
+        ZLIB_CODEC_ID
+    }
+
+    fn compress(&self, data: &[u8], level: u8) -> Vec<u8> {
+        compress_to_vec_zlib(data, level)
+    }
+
+    fn decompress(&self, data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        decompress_to_vec_zlib_with_limit(data, max_size).map_err(|error| {
+            log::error!("inflate() => {:?}", error.status);
+            Error::InvalidObject
+        })
+    }
+}
+
+/// zstd implementation, which cannot be used for git formats
+///
+/// Compresses faster and better than zlib; see
+/// [`Repository::set_storage_codec`].
+#[cfg(feature = "zstd")]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Zstd;
+
+#[cfg(feature = "zstd")]
+impl Codec for Zstd {
+    fn id(&self) -> u8 {
+        ZSTD_CODEC_ID
+    }
+
+    fn compress(&self, data: &[u8], level: u8) -> Vec<u8> {
+        zstd::bulk::compress(data, level as i32).unwrap()
+    }
+
+    fn decompress(&self, data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        zstd::bulk::decompress(data, max_size).map_err(|error| {
+            log::error!("zstd: {}", error);
+            Error::InvalidObject
+        })
+    }
+}
+
+/// Codec which decompresses data recorded with `id`, if it is available
+pub(crate) fn codec_for_id(id: u8) -> Option<Arc<dyn Codec>> {
+    match id {
+        ZLIB_CODEC_ID => Some(Arc::new(Miniz)),
+        #[cfg(feature = "zstd")]
+        ZSTD_CODEC_ID => Some(Arc::new(Zstd)),
+        _ => None,
+    }
+}
+
+impl Repository {
+    /// Replaces [`Miniz`] for outgoing packfiles
+    ///
+    /// `codec` must produce zlib streams. Incoming packfiles
+    /// are streamed, so they are always inflated with `miniz_oxide`.
+    pub fn set_zlib_codec(&mut self, codec: Arc<dyn Codec>) {
+        self.zlib_codec = codec;
+    }
+
+    /// Replaces [`Miniz`] for the crate's own formats, like
+    /// snapshots (see [`Repository::save_snapshot`])
+    ///
+    /// Snapshots record the id of their codec; zlib and zstd
+    /// data can always be read back, other codecs need to be set
+    /// again before loading.
+    pub fn set_storage_codec(&mut self, codec: Arc<dyn Codec>) {
+        self.storage_codec = codec;
+    }
+}
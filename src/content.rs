@@ -0,0 +1,57 @@
+use super::internals::{Result, Repository};
+use super::drivers::looks_binary;
+
+/// Magic numbers of common binary formats
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"\x7fELF", "application/x-elf"),
+    (b"\0asm", "application/wasm"),
+];
+
+/// Guesses the MIME type of some content
+///
+/// - known magic numbers (PNG, JPEG, GIF, PDF, ZIP, gzip, ELF, wasm)
+/// - `application/octet-stream` if there's a NUL byte in the
+///   first 8000 bytes, like git
+/// - `text/plain; charset=utf-8` for valid UTF-8
+/// - `text/plain` for other text
+pub fn content_type(data: &[u8]) -> &'static str {
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(magic, _)| data.starts_with(magic)) {
+        return mime;
+    }
+
+    if looks_binary(data) {
+        "application/octet-stream"
+    } else if core::str::from_utf8(data).is_ok() {
+        "text/plain; charset=utf-8"
+    } else {
+        "text/plain"
+    }
+}
+
+impl Repository {
+    /// Checks if a file is binary, like git: if it has a NUL byte
+    /// in its first 8000 bytes
+    ///
+    /// Returns `PathError` if the path leads to nowhere.
+    ///
+    /// This can write-lock an internal RwLock for cache.
+    pub fn is_binary(&self, path: &str) -> Result<bool> {
+        Ok(looks_binary(self.read_file(path)?))
+    }
+
+    /// Guesses the MIME type of a file, see [`content_type`]
+    ///
+    /// Returns `PathError` if the path leads to nowhere.
+    ///
+    /// This can write-lock an internal RwLock for cache.
+    pub fn detect_content_type(&self, path: &str) -> Result<&'static str> {
+        Ok(content_type(self.read_file(path)?))
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use super::internals::{Result, Hash, Repository};
+
+/// Objects & bytes reachable from a branch, see [`DedupReport`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BranchUsage {
+    pub name: String,
+    pub objects: usize,
+    pub bytes: usize,
+    /// Objects which no other branch of the report can reach
+    pub unique_objects: usize,
+    pub unique_bytes: usize,
+}
+
+/// Storage shared by several branches, see [`Repository::dedup_report`]
+///
+/// Sizes are those of uncompressed object contents.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DedupReport {
+    pub branches: Vec<BranchUsage>,
+    /// Objects reachable from at least two branches
+    pub shared_objects: usize,
+    pub shared_bytes: usize,
+    /// Objects reachable from any branch, each counted once
+    pub stored_objects: usize,
+    pub stored_bytes: usize,
+}
+
+impl DedupReport {
+    /// Bytes which would be stored if each branch
+    /// was kept in a separate repository
+    pub fn separate_bytes(&self) -> usize {
+        self.branches.iter().map(|branch| branch.bytes).sum()
+    }
+
+    /// Bytes saved by keeping all branches in one repository
+    pub fn saved_bytes(&self) -> usize {
+        self.separate_bytes() - self.stored_bytes
+    }
+}
+
+impl Repository {
+    /// Reports shared & unique objects of several branches
+    ///
+    /// `branches` are revisions, resolved with [`Self::resolve`]
+    /// (example: `["main", "refs/remotes/origin/dev"]`). This helps
+    /// deciding whether to keep branches in a single repository
+    /// or to split them.
+    ///
+    /// This can be slow on large repositories: all reachable
+    /// objects are enumerated.
+    pub fn dedup_report(&self, branches: &[&str]) -> Result<DedupReport> {
+        // number of branches reaching each object, and its size
+        let mut reach = HashMap::<Hash, (usize, usize)>::new();
+        let mut per_branch = Vec::with_capacity(branches.len());
+
+        for name in branches {
+            let tip = self.resolve(name)?;
+            let mut objects = Vec::new();
+
+            self.for_each_reachable(tip, |hash| {
+                if let Some(object) = self.objects.get(hash) {
+                    objects.push((hash, object.content().len()));
+                }
+            })?;
+
+            objects.sort_unstable();
+            objects.dedup();
+
+            for (hash, size) in &objects {
+                reach.entry(*hash).or_insert((0, *size)).0 += 1;
+            }
+
+            per_branch.push((name.to_string(), objects));
+        }
+
+        let mut report = DedupReport::default();
+        for (count, size) in reach.values() {
+            report.stored_objects += 1;
+            report.stored_bytes += size;
+
+            if *count > 1 {
+                report.shared_objects += 1;
+                report.shared_bytes += size;
+            }
+        }
+
+        for (name, objects) in per_branch {
+            let mut usage = BranchUsage {
+                name,
+                ..BranchUsage::default()
+            };
+
+            for (hash, size) in objects {
+                usage.objects += 1;
+                usage.bytes += size;
+
+                if reach[&hash].0 == 1 {
+                    usage.unique_objects += 1;
+                    usage.unique_bytes += size;
+                }
+            }
+
+            report.branches.push(usage);
+        }
+
+        Ok(report)
+    }
+}
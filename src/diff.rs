@@ -0,0 +1,597 @@
+use core::{cmp::Ordering, fmt::Write};
+use std::borrow::Cow;
+
+use super::internals::{Result, Error, Hash, Mode, Repository, ObjectType};
+
+/// Default number of unchanged lines around changes
+pub const DEFAULT_CONTEXT: usize = 3;
+
+/// Like git, files with a NUL byte in their first 8000 bytes are binary
+pub const DEFAULT_BINARY_THRESHOLD: usize = 8000;
+
+/// Settings for [`Repository::diff_file_with`]
+#[derive(Clone, Debug)]
+pub struct DiffOptions {
+    /// Number of unchanged lines shown around changes
+    pub context: usize,
+    /// Number of bytes checked for a NUL byte by the
+    /// [`DiffDriver::Text`](crate::DiffDriver::Text) driver
+    pub binary_threshold: usize,
+    /// Ignores whitespace when comparing lines (`-w`)
+    pub ignore_all_space: bool,
+    /// Ignores changes in the amount of whitespace and
+    /// trailing whitespace (`-b`)
+    pub ignore_space_change: bool,
+    /// Ignores changes whose lines are all blank (`--ignore-blank-lines`)
+    ///
+    /// Blank lines next to other changes are still shown.
+    pub ignore_blank_lines: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            context: DEFAULT_CONTEXT,
+            binary_threshold: DEFAULT_BINARY_THRESHOLD,
+            ignore_all_space: false,
+            ignore_space_change: false,
+            ignore_blank_lines: false,
+        }
+    }
+}
+
+impl DiffOptions {
+    /// Key used to compare lines
+    fn line_key<'a>(&self, line: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.ignore_all_space {
+            Cow::Owned(line.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect())
+        } else if self.ignore_space_change {
+            // runs of whitespace become one space, trailing ones are dropped
+            let mut key = Vec::with_capacity(line.len());
+            let mut in_space = false;
+            for byte in line.iter().copied() {
+                match byte.is_ascii_whitespace() {
+                    true => in_space = true,
+                    false => {
+                        if in_space {
+                            key.push(b' ');
+                        }
+
+                        in_space = false;
+                        key.push(byte);
+                    },
+                }
+            }
+
+            Cow::Owned(key)
+        } else {
+            Cow::Borrowed(line)
+        }
+    }
+}
+
+/// Step of a line diff, with line indices
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum DiffOp {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Change of a directory entry, see [`Repository::diff_trees`]
+#[derive(Clone, Debug)]
+pub enum TreeChange {
+    Added {
+        name: String,
+        hash: Hash,
+        mode: Mode,
+    },
+    Removed {
+        name: String,
+        hash: Hash,
+        mode: Mode,
+    },
+    /// The content or the mode of an entry changed
+    Modified {
+        name: String,
+        old: (Hash, Mode),
+        new: (Hash, Mode),
+    },
+}
+
+impl TreeChange {
+    /// Name of the entry in its directory
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Added { name, .. } => name,
+            Self::Removed { name, .. } => name,
+            Self::Modified { name, .. } => name,
+        }
+    }
+}
+
+impl Repository {
+    /// Sorted entries of a tree; `Hash::zero()` is the empty tree
+    fn tree_entries(&self, tree: Hash) -> Result<Vec<(String, Hash, Mode)>> {
+        if tree.is_zero() {
+            return Ok(Vec::new());
+        }
+
+        self.fetch_dir(tree)?;
+        let dirs = self.directories.read().unwrap();
+        let directory = dirs.get(&tree).unwrap(/* fetch_dir ensures it's there */);
+
+        Ok(directory.iter().map(|(node, (hash, mode))| (node.to_string(), *hash, *mode)).collect())
+    }
+
+    /// Compares the entries of two trees, without recursing into
+    /// subdirectories
+    ///
+    /// A subdirectory is reported as modified if anything under it
+    /// changed. `Hash::zero()` can be used as an empty tree. Returns
+    /// `MissingObject` if a tree isn't in the repository.
+    ///
+    /// This can write-lock an internal RwLock for cache.
+    pub fn diff_trees(&self, a: Hash, b: Hash) -> Result<impl Iterator<Item = TreeChange>> {
+        let mut old = self.tree_entries(a)?.into_iter().peekable();
+        let mut new = self.tree_entries(b)?.into_iter().peekable();
+        let mut changes = Vec::new();
+
+        loop {
+            let order = match (old.peek(), new.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((old_name, ..)), Some((new_name, ..))) => old_name.cmp(new_name),
+            };
+
+            match order {
+                Ordering::Less => {
+                    let (name, hash, mode) = old.next().unwrap();
+                    changes.push(TreeChange::Removed { name, hash, mode });
+                },
+                Ordering::Greater => {
+                    let (name, hash, mode) = new.next().unwrap();
+                    changes.push(TreeChange::Added { name, hash, mode });
+                },
+                Ordering::Equal => {
+                    let (name, old_hash, old_mode) = old.next().unwrap();
+                    let (_, new_hash, new_mode) = new.next().unwrap();
+
+                    if old_hash != new_hash || old_mode as u32 != new_mode as u32 {
+                        let (old, new) = ((old_hash, old_mode), (new_hash, new_mode));
+                        changes.push(TreeChange::Modified { name, old, new });
+                    }
+                },
+            }
+        }
+
+        Ok(changes.into_iter())
+    }
+
+    /// Compares two trees, recursively
+    ///
+    /// Names are paths relative to the trees; only files (and
+    /// gitlinks) are reported. A directory replaced by a file, or
+    /// the other way around, is reported as removed & added files.
+    ///
+    /// This can write-lock an internal RwLock for cache.
+    pub fn diff_trees_recursive(&self, a: Hash, b: Hash) -> Result<Vec<TreeChange>> {
+        let mut changes = Vec::new();
+        self.diff_subtrees("", a, b, &mut changes)?;
+        Ok(changes)
+    }
+
+    fn diff_subtrees(&self, prefix: &str, a: Hash, b: Hash, changes: &mut Vec<TreeChange>) -> Result<()> {
+        let zero = Hash::zero();
+        let is_dir = |mode| matches!(mode, Mode::Directory);
+
+        for change in self.diff_trees(a, b)? {
+            let path = match prefix {
+                "" => change.name().to_string(),
+                prefix => format!("{}/{}", prefix, change.name()),
+            };
+
+            match change {
+                TreeChange::Added { hash, mode, .. } if is_dir(mode) => self.diff_subtrees(&path, zero, hash, changes)?,
+                TreeChange::Removed { hash, mode, .. } if is_dir(mode) => self.diff_subtrees(&path, hash, zero, changes)?,
+                TreeChange::Modified { old, new, .. } if is_dir(old.1) || is_dir(new.1) => {
+                    let old_tree = if is_dir(old.1) { old.0 } else { zero };
+                    let new_tree = if is_dir(new.1) { new.0 } else { zero };
+
+                    if !is_dir(old.1) {
+                        changes.push(TreeChange::Removed { name: path.clone(), hash: old.0, mode: old.1 });
+                    }
+
+                    self.diff_subtrees(&path, old_tree, new_tree, changes)?;
+
+                    if !is_dir(new.1) {
+                        changes.push(TreeChange::Added { name: path, hash: new.0, mode: new.1 });
+                    }
+                },
+                TreeChange::Added { hash, mode, .. } => changes.push(TreeChange::Added { name: path, hash, mode }),
+                TreeChange::Removed { hash, mode, .. } => changes.push(TreeChange::Removed { name: path, hash, mode }),
+                TreeChange::Modified { old, new, .. } => changes.push(TreeChange::Modified { name: path, old, new }),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Hash & content of a file in a commit; `Hash::zero()` has no files
+    pub(crate) fn blob_at(&self, commit: Hash, path: &str) -> Result<Option<(Hash, &[u8])>> {
+        if commit.is_zero() {
+            return Ok(None);
+        }
+
+        let Some(tree) = self.get_commit_root(commit)? else {
+            log::error!("Commit {} isn't in the repository", commit);
+            return Err(Error::MissingObject);
+        };
+
+        Ok(match self.hash_at(Some(tree), path)? {
+            Some(hash) => self.any_store_get(hash, ObjectType::Blob).map(|data| (hash, data)),
+            None => None,
+        })
+    }
+
+    /// Unified diff of a file between two commits
+    ///
+    /// The file can be missing from one commit (`Hash::zero()` is
+    /// a commit without files); diff drivers are used, see
+    /// [`Self::diff_text`]. The result is empty if the file didn't
+    /// change.
+    ///
+    /// Binary files are reported like git does, after an `index`
+    /// line with the abbreviated hashes of both versions; their
+    /// sizes follow, in bytes:
+    ///
+    /// ```text
+    /// index 3f2a1c9..0b44e7d
+    /// Binary files a/logo.png and b/logo.png differ (1342 -> 1408 bytes)
+    /// ```
+    ///
+    /// Returns `PathError` if the file is in neither commit and
+    /// `MissingObject` if a commit isn't in the repository.
+    pub fn diff_file(&self, commit_a: Hash, commit_b: Hash, path: &str) -> Result<String> {
+        self.diff_file_with(commit_a, commit_b, path, &DiffOptions::default())
+    }
+
+    /// Like [`Self::diff_file`], with custom settings
+    ///
+    /// The result is also empty if all changes are ignored
+    /// (see [`DiffOptions::ignore_all_space`] and others).
+    pub fn diff_file_with(&self, commit_a: Hash, commit_b: Hash, path: &str, options: &DiffOptions) -> Result<String> {
+        self.file_diff(commit_a, commit_b, path, options, true)
+    }
+
+    /// `index_line` is false if the caller already wrote one
+    pub(crate) fn file_diff(
+        &self,
+        commit_a: Hash,
+        commit_b: Hash,
+        path: &str,
+        options: &DiffOptions,
+        index_line: bool,
+    ) -> Result<String> {
+        let (old_hash, old) = self.blob_at(commit_a, path)?.unzip();
+        let (new_hash, new) = self.blob_at(commit_b, path)?.unzip();
+
+        if old.is_none() && new.is_none() {
+            return Err(Error::PathError);
+        }
+
+        let path = path.trim_matches('/');
+        let old_name = old.map(|_| format!("a/{}", path)).unwrap_or("/dev/null".into());
+        let new_name = new.map(|_| format!("b/{}", path)).unwrap_or("/dev/null".into());
+
+        if old == new {
+            return Ok(String::new());
+        }
+
+        let short = |hash: Option<Hash>| match hash {
+            Some(hash) => self.abbreviate(hash),
+            None => "0000000".to_string(),
+        };
+
+        let (old_hash, new_hash) = (short(old_hash), short(new_hash));
+        let sizes = (old.map_or(0, <[u8]>::len), new.map_or(0, <[u8]>::len));
+        let modified = old.is_some() && new.is_some();
+        let threshold = options.binary_threshold;
+        let old = self.diff_text_with(path, old.unwrap_or(&[]), threshold);
+        let new = self.diff_text_with(path, new.unwrap_or(&[]), threshold);
+
+        let mut diff = String::new();
+        match (old, new) {
+            (Some(old), Some(new)) => {
+                let hunks = unified_diff_with(&old, &new, options);
+                // a file which was added or removed is always shown
+                if hunks.is_empty() && modified {
+                    return Ok(diff);
+                }
+
+                write!(diff, "--- {}\n+++ {}\n", old_name, new_name).unwrap();
+                diff += &hunks;
+            },
+            _ => {
+                if index_line {
+                    writeln!(diff, "index {}..{}", old_hash, new_hash).unwrap();
+                }
+
+                writeln!(diff, "Binary files {} and {} differ ({} -> {} bytes)", old_name, new_name, sizes.0, sizes.1).unwrap();
+            },
+        }
+
+        Ok(diff)
+    }
+}
+
+/// Splits after each newline; the last line might lack one
+pub(crate) fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|b| *b == b'\n').collect()
+}
+
+/// Shortest edit script between two sequences (Myers' algorithm)
+pub(crate) fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<DiffOp> {
+    // common prefix & suffix are trivial
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a_mid, b_mid) = (&a[prefix..], &b[prefix..]);
+    let suffix = a_mid.iter().rev().zip(b_mid.iter().rev()).take_while(|(x, y)| x == y).count();
+    let (a_mid, b_mid) = (&a_mid[..a_mid.len() - suffix], &b_mid[..b_mid.len() - suffix]);
+
+    let mut ops = (0..prefix).map(|i| DiffOp::Equal(i, i)).collect::<Vec<_>>();
+
+    let shift = |op| match op {
+        DiffOp::Equal(i, j) => DiffOp::Equal(i + prefix, j + prefix),
+        DiffOp::Delete(i) => DiffOp::Delete(i + prefix),
+        DiffOp::Insert(j) => DiffOp::Insert(j + prefix),
+    };
+
+    ops.extend(myers_core(a_mid, b_mid).into_iter().map(shift));
+
+    let (a_end, b_end) = (a.len() - suffix, b.len() - suffix);
+    ops.extend((0..suffix).map(|i| DiffOp::Equal(a_end + i, b_end + i)));
+    ops
+}
+
+fn myers_core<T: PartialEq>(a: &[T], b: &[T]) -> Vec<DiffOp> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    let index = |k: isize| (k + offset) as usize;
+
+    // furthest x reached on each diagonal k = x - y
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    // v[-d-1..=d+1] before each round
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v[index(-d - 1)..=index(d + 1)].to_vec());
+
+        for k in (-d..=d).step_by(2) {
+            let down = k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]);
+            let mut x = match down {
+                true => v[index(k + 1)],
+                false => v[index(k - 1)] + 1,
+            };
+
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let saved = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+
+        let down = k == -d || (k != d && saved(k - 1) < saved(k + 1));
+        let prev_k = if down { k + 1 } else { k - 1 };
+        let prev_x = saved(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            ops.push(DiffOp::Equal(x as usize, y as usize));
+        }
+
+        if d > 0 {
+            ops.push(match x == prev_x {
+                true => DiffOp::Insert(prev_y as usize),
+                false => DiffOp::Delete(prev_x as usize),
+            });
+        }
+
+        (x, y) = (prev_x, prev_y);
+    }
+
+    ops.reverse();
+    ops
+}
+
+/// Groups operations into hunks: ranges of `ops` with context
+///
+/// Operations for which `ignored` returns true don't start hunks.
+pub(crate) fn hunks<F: Fn(&DiffOp) -> bool>(ops: &[DiffOp], context: usize, ignored: F) -> Vec<(usize, usize)> {
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+
+    for (i, op) in ops.iter().enumerate() {
+        if let DiffOp::Equal(..) = op {
+            continue;
+        }
+
+        if ignored(op) {
+            continue;
+        }
+
+        let start = i.saturating_sub(context);
+        let end = (i + 1 + context).min(ops.len());
+
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    hunks
+}
+
+/// `-a,b` / `+c,d` ranges of a hunk; `,1` is omitted like in git
+fn hunk_range(first: usize, count: usize) -> String {
+    // empty ranges point to the line before
+    let start = if count == 0 { first } else { first + 1 };
+    match count {
+        1 => format!("{}", start),
+        _ => format!("{},{}", start, count),
+    }
+}
+
+fn push_line(diff: &mut String, prefix: char, line: &[u8]) {
+    diff.push(prefix);
+    diff.push_str(&String::from_utf8_lossy(line));
+
+    if !line.ends_with(b"\n") {
+        diff.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+/// Unified diff hunks (`@@ -a,b +c,d @@`) between two texts
+///
+/// `context` is the number of unchanged lines shown around
+/// changes, usually [`DEFAULT_CONTEXT`]. File headers (`---`
+/// & `+++`) aren't included.
+pub fn unified_diff(old: &[u8], new: &[u8], context: usize) -> String {
+    unified_diff_with(old, new, &DiffOptions {
+        context,
+        ..DiffOptions::default()
+    })
+}
+
+/// Like [`unified_diff`], with whitespace settings
+///
+/// Lines which only differ by ignored whitespace are shown as
+/// unchanged, with their old version. `binary_threshold` isn't used.
+pub fn unified_diff_with(old: &[u8], new: &[u8], options: &DiffOptions) -> String {
+    let (old, new) = (split_lines(old), split_lines(new));
+    let old_keys = old.iter().map(|line| options.line_key(line)).collect::<Vec<_>>();
+    let new_keys = new.iter().map(|line| options.line_key(line)).collect::<Vec<_>>();
+    let ops = myers(&old_keys, &new_keys);
+    let mut diff = String::new();
+
+    let is_blank = |line: &[u8]| line.iter().all(u8::is_ascii_whitespace);
+    let ignored = |op: &DiffOp| options.ignore_blank_lines && match *op {
+        DiffOp::Delete(i) => is_blank(old[i]),
+        DiffOp::Insert(j) => is_blank(new[j]),
+        DiffOp::Equal(..) => false,
+    };
+
+    for (start, end) in hunks(&ops, options.context, ignored) {
+        let hunk = &ops[start..end];
+
+        // position of the first line of the hunk in each file
+        let (mut old_first, mut new_first) = (0, 0);
+        for op in &ops[..start] {
+            match op {
+                DiffOp::Equal(..) => (old_first, new_first) = (old_first + 1, new_first + 1),
+                DiffOp::Delete(_) => old_first += 1,
+                DiffOp::Insert(_) => new_first += 1,
+            }
+        }
+
+        let old_count = hunk.iter().filter(|op| !matches!(op, DiffOp::Insert(_))).count();
+        let new_count = hunk.iter().filter(|op| !matches!(op, DiffOp::Delete(_))).count();
+        let (old_range, new_range) = (hunk_range(old_first, old_count), hunk_range(new_first, new_count));
+        writeln!(diff, "@@ -{} +{} @@", old_range, new_range).unwrap();
+
+        for op in hunk {
+            match *op {
+                DiffOp::Equal(i, _) => push_line(&mut diff, ' ', old[i]),
+                DiffOp::Delete(i) => push_line(&mut diff, '-', old[i]),
+                DiffOp::Insert(j) => push_line(&mut diff, '+', new[j]),
+            }
+        }
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that `ops` turn `a` into `b`; returns the edit count
+    fn check_script(a: &[u8], b: &[u8], ops: &[DiffOp]) -> usize {
+        let (mut i, mut j) = (0, 0);
+
+        for op in ops {
+            match *op {
+                DiffOp::Equal(x, y) => {
+                    assert_eq!((x, y), (i, j));
+                    assert_eq!(a[x], b[y]);
+                    (i, j) = (i + 1, j + 1);
+                },
+                DiffOp::Delete(x) => {
+                    assert_eq!(x, i);
+                    i += 1;
+                },
+                DiffOp::Insert(y) => {
+                    assert_eq!(y, j);
+                    j += 1;
+                },
+            }
+        }
+
+        assert_eq!((i, j), (a.len(), b.len()));
+        ops.iter().filter(|op| !matches!(op, DiffOp::Equal(..))).count()
+    }
+
+    #[test]
+    fn myers_shortest_script() {
+        // example of Myers' paper
+        let (a, b) = (b"ABCABBA", b"CBABAC");
+        assert_eq!(check_script(a, b, &myers(a, b)), 5);
+
+        let (a, b) = (b"prefix-middle-suffix", b"prefix-center-suffix");
+        assert_eq!(check_script(a, b, &myers(a, b)), 10);
+    }
+
+    #[test]
+    fn myers_empty_side() {
+        assert_eq!(myers::<u8>(&[], &[]), []);
+        assert_eq!(myers(b"", b"ab"), [DiffOp::Insert(0), DiffOp::Insert(1)]);
+        assert_eq!(myers(b"ab", b""), [DiffOp::Delete(0), DiffOp::Delete(1)]);
+
+        assert_eq!(unified_diff(b"", b"a\nb\n", DEFAULT_CONTEXT), "@@ -0,0 +1,2 @@\n+a\n+b\n");
+        assert_eq!(unified_diff(b"a\n", b"", DEFAULT_CONTEXT), "@@ -1 +0,0 @@\n-a\n");
+    }
+
+    #[test]
+    fn myers_identical_input() {
+        let text = b"a\nb\nc\n";
+        assert_eq!(myers(text, text), (0..text.len()).map(|i| DiffOp::Equal(i, i)).collect::<Vec<_>>());
+        assert_eq!(unified_diff(text, text, DEFAULT_CONTEXT), "");
+    }
+
+    #[test]
+    fn no_trailing_newline() {
+        let no_newline = "\\ No newline at end of file\n";
+
+        let diff = unified_diff(b"a\nb", b"a\nc", DEFAULT_CONTEXT);
+        assert_eq!(diff, format!("@@ -1,2 +1,2 @@\n a\n-b\n{}+c\n{}", no_newline, no_newline));
+
+        let diff = unified_diff(b"a\nb", b"a\nb\n", DEFAULT_CONTEXT);
+        assert_eq!(diff, format!("@@ -1,2 +1,2 @@\n a\n-b\n{}+b\n", no_newline));
+    }
+}
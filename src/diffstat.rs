@@ -0,0 +1,122 @@
+use std::borrow::Cow;
+
+use super::internals::{Result, Error, Hash, Mode, Repository, ObjectType};
+use super::diff::{TreeChange, DiffOp, myers, split_lines};
+
+/// Changed lines of a file, see [`Repository::diffstat`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileStat {
+    pub path: String,
+    pub added: usize,
+    pub removed: usize,
+    /// Binary files (see [`Repository::diff_text`]) have
+    /// no line counts
+    pub binary: bool,
+    /// Sizes in bytes of the old & new versions, like
+    /// `Bin 1234 -> 2345 bytes` in `git diff --stat`
+    pub sizes: (usize, usize),
+}
+
+/// Impact of a range of commits, like `git diff --stat`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiffStat {
+    /// Changed files, in tree order
+    pub files: Vec<FileStat>,
+    pub added: usize,
+    pub removed: usize,
+}
+
+impl DiffStat {
+    /// Short summary, such as `3 files changed, 120 insertions(+), 45 deletions(-)`
+    pub fn summary(&self) -> String {
+        let plural = |n, word: &str| format!("{} {}{}", n, word, if n == 1 { "" } else { "s" });
+
+        let mut summary = format!("{} changed", plural(self.files.len(), "file"));
+        if self.added > 0 {
+            summary += &format!(", {}(+)", plural(self.added, "insertion"));
+        }
+
+        if self.removed > 0 {
+            summary += &format!(", {}(-)", plural(self.removed, "deletion"));
+        }
+
+        summary
+    }
+}
+
+impl Repository {
+    /// Content of a changed entry; gitlinks are shown like git does
+    fn stat_content(&self, hash: Hash, mode: Mode) -> Result<Cow<'_, [u8]>> {
+        match mode {
+            Mode::Gitlink => Ok(Cow::Owned(format!("Subproject commit {}\n", hash).into_bytes())),
+            _ => match self.any_store_get(hash, ObjectType::Blob) {
+                Some(data) => Ok(Cow::Borrowed(data)),
+                None => {
+                    log::error!("Blob {} isn't in the repository", hash);
+                    Err(Error::MissingObject)
+                },
+            },
+        }
+    }
+
+    /// Root tree of a commit; `Hash::zero()` is the empty tree
+    fn tree_or_empty(&self, commit: Hash) -> Result<Hash> {
+        if commit.is_zero() {
+            return Ok(Hash::zero());
+        }
+
+        match self.get_commit_root(commit)? {
+            Some(tree) => Ok(tree),
+            None => {
+                log::error!("Commit {} isn't in the repository", commit);
+                Err(Error::MissingObject)
+            },
+        }
+    }
+
+    /// Counts added & removed lines of each file between two commits
+    ///
+    /// `Hash::zero()` is a commit without files. Diff drivers are
+    /// used (see [`Self::diff_text`]); renames aren't detected.
+    ///
+    /// Returns `MissingObject` if a commit or a blob isn't in the
+    /// repository.
+    ///
+    /// This can write-lock an internal RwLock for cache.
+    pub fn diffstat(&self, from: Hash, to: Hash) -> Result<DiffStat> {
+        let mut stat = DiffStat::default();
+        for change in self.diff_trees_recursive(self.tree_or_empty(from)?, self.tree_or_empty(to)?)? {
+            let (old, new) = match &change {
+                TreeChange::Added { hash, mode, .. } => (None, Some((*hash, *mode))),
+                TreeChange::Removed { hash, mode, .. } => (Some((*hash, *mode)), None),
+                TreeChange::Modified { old, new, .. } => (Some(*old), Some(*new)),
+            };
+
+            let path = change.name();
+            let content = |entry: Option<(Hash, Mode)>| match entry {
+                Some((hash, mode)) => self.stat_content(hash, mode),
+                None => Ok(Cow::Borrowed(&[][..])),
+            };
+
+            let (old, new) = (content(old)?, content(new)?);
+            let sizes = (old.len(), new.len());
+            let (old, new) = (self.diff_text(path, &old), self.diff_text(path, &new));
+
+            let file = match (old, new) {
+                (Some(old), Some(new)) => {
+                    let ops = myers(&split_lines(&old), &split_lines(&new));
+                    let added = ops.iter().filter(|op| matches!(op, DiffOp::Insert(_))).count();
+                    let removed = ops.iter().filter(|op| matches!(op, DiffOp::Delete(_))).count();
+                    FileStat { path: path.into(), added, removed, binary: false, sizes }
+                },
+                _ => FileStat { path: path.into(), added: 0, removed: 0, binary: true, sizes },
+            };
+
+            stat.added += file.added;
+            stat.removed += file.removed;
+            stat.files.push(file);
+        }
+
+        Ok(stat)
+    }
+}
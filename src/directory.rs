@@ -47,6 +47,31 @@ impl From<FileType> for Mode {
 }
 
 impl Mode {
+    /// Parses an octal mode, as in tree objects
+    pub(crate) fn from_octal(mode: &str) -> Option<Self> {
+        match mode {
+            "040000" | "40000" => Some(Mode::Directory),
+            "100644" => Some(Mode::RegularFile),
+            "100664" => Some(Mode::GroupWriteableFile),
+            "100755" => Some(Mode::ExecutableFile),
+            "120000" => Some(Mode::SymbolicLink),
+            "160000" => Some(Mode::Gitlink),
+            _ => None,
+        }
+    }
+
+    /// `None` for directories
+    pub fn file_type(self) -> Option<FileType> {
+        match self {
+            Mode::Directory => None,
+            Mode::RegularFile => Some(FileType::RegularFile),
+            Mode::GroupWriteableFile => Some(FileType::GroupWriteableFile),
+            Mode::ExecutableFile => Some(FileType::ExecutableFile),
+            Mode::SymbolicLink => Some(FileType::SymbolicLink),
+            Mode::Gitlink => Some(FileType::Gitlink),
+        }
+    }
+
     pub fn matches(self, entry_type: EntryType) -> bool {
         match self {
             Mode::Directory => match entry_type {
@@ -61,11 +86,29 @@ impl Mode {
     }
 }
 
-pub struct Path<'a>(&'a str);
+/// Characters which separate the components of a [`Path`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PathSeparators {
+    /// Only `/`, like git
+    #[default]
+    Slash,
+    /// `/` and `\`, for paths coming from Windows
+    SlashOrBackslash,
+}
+
+/// Repository path, such as `src/main.rs`
+///
+/// Empty components and `.` components are skipped:
+/// `./src//main.rs` is `src/main.rs`.
+pub struct Path<'a>(&'a str, PathSeparators);
 
 impl<'a> Path<'a> {
     pub fn new(string: &'a str) -> Path<'a> {
-        Self(string)
+        Self(string, PathSeparators::Slash)
+    }
+
+    pub fn with_separators(string: &'a str, separators: PathSeparators) -> Path<'a> {
+        Self(string, separators)
     }
 
     pub fn dirs(&self) -> Result<impl Iterator<Item = &str>> {
@@ -84,6 +127,8 @@ impl<'a> Path<'a> {
     }
 
     pub fn all(&self) -> impl Iterator<Item = &str> + Clone {
-        self.0.split('/').filter(|part| !part.is_empty())
+        let backslash = self.1 == PathSeparators::SlashOrBackslash;
+        let is_separator = move |c: char| c == '/' || (backslash && c == '\\');
+        self.0.split(is_separator).filter(|part| !matches!(*part, "" | "."))
     }
 }
@@ -0,0 +1,295 @@
+use std::{fs, io, collections::BTreeSet};
+use std::fs::{File, OpenOptions};
+use std::path::{Path as FsPath, PathBuf};
+
+use super::internals::{Result, Error, Hash, FileType, Mode, Repository, ObjectType, Write, glob_match};
+
+/// Journal of [`Repository::checkout_resumable`]
+const CHECKOUT_JOURNAL: &str = ".rustgit-checkout";
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Self::IoError(error.kind())
+    }
+}
+
+/// Symbolic links are stored as their target path
+#[cfg(unix)]
+fn link_target(path: &FsPath) -> Result<Vec<u8>> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(fs::read_link(path)?.into_os_string().into_vec())
+}
+
+#[cfg(not(unix))]
+fn link_target(path: &FsPath) -> Result<Vec<u8>> {
+    let target = fs::read_link(path)?;
+    let target = target.to_str().ok_or(Error::PathError)?;
+    Ok(target.replace('\\', "/").into_bytes())
+}
+
+#[cfg(unix)]
+fn write_link(target: &[u8], path: &FsPath) -> Result<()> {
+    use std::{os::unix::ffi::OsStrExt, ffi::OsStr};
+    Ok(std::os::unix::fs::symlink(OsStr::from_bytes(target), path)?)
+}
+
+/// Like git with `core.symlinks=false`, links become plain files
+#[cfg(not(unix))]
+fn write_link(target: &[u8], path: &FsPath) -> Result<()> {
+    Ok(fs::write(path, target)?)
+}
+
+#[cfg(unix)]
+fn set_executable(path: &FsPath) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(fs::set_permissions(path, fs::Permissions::from_mode(0o755))?)
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &FsPath) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(metadata: &fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o111 != 0
+}
+
+#[cfg(not(unix))]
+fn is_executable(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+impl Repository {
+    /// Stages all files of a directory from the local filesystem
+    ///
+    /// - Files are staged under `repo_prefix` (`""` for the root).
+    /// - Executable files & symbolic links get the matching [`FileType`];
+    ///   links aren't followed.
+    /// - Paths (relative to `fs_path`) matching one of the `ignore`
+    ///   patterns are skipped, see [`glob_match`]; `.git` directories
+    ///   are always skipped.
+    ///
+    /// Returns the number of staged files, `IoError` if the filesystem
+    /// cannot be read or `PathError` if a file name isn't valid UTF-8.
+    pub fn stage_path<P: AsRef<FsPath>>(&mut self, fs_path: P, repo_prefix: &str, ignore: &[&str]) -> Result<usize> {
+        self.stage_dir(fs_path.as_ref(), repo_prefix.trim_matches('/'), "", ignore)
+    }
+
+    fn stage_dir(&mut self, dir: &FsPath, prefix: &str, relative: &str, ignore: &[&str]) -> Result<usize> {
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let mut staged = 0;
+        for entry in entries {
+            let name = entry.file_name();
+            let name = name.to_str().ok_or(Error::PathError)?;
+            let relative = match relative {
+                "" => name.to_string(),
+                relative => format!("{}/{}", relative, name),
+            };
+
+            if name == ".git" || ignore.iter().any(|pattern| glob_match(pattern, &relative)) {
+                continue;
+            }
+
+            let repo_path = match prefix {
+                "" => relative.clone(),
+                prefix => format!("{}/{}", prefix, relative),
+            };
+
+            let path = entry.path();
+            let metadata = fs::symlink_metadata(&path)?;
+            let file_type = metadata.file_type();
+
+            let data = if file_type.is_symlink() {
+                (link_target(&path)?, FileType::SymbolicLink)
+            } else if file_type.is_dir() {
+                staged += self.stage_dir(&path, prefix, &relative, ignore)?;
+                continue;
+            } else if file_type.is_file() {
+                let file_type = match is_executable(&metadata) {
+                    true => FileType::ExecutableFile,
+                    false => FileType::RegularFile,
+                };
+
+                (fs::read(&path)?, file_type)
+            } else {
+                log::warn!("Skipping special file {}", path.display());
+                continue;
+            };
+
+            self.stage(&repo_path, Some(data))?;
+            staged += 1;
+        }
+
+        Ok(staged)
+    }
+
+    /// Writes all files of the current tree (including staged
+    /// changes) under a directory of the local filesystem
+    ///
+    /// - `target_dir` and missing directories are created.
+    /// - Existing files are overwritten; other files are left as is.
+    /// - Executable files get mode `0755`, symbolic links are created
+    ///   as such (on unix) and gitlinks become empty directories.
+    ///
+    /// Returns the number of written files, `IoError` if the filesystem
+    /// cannot be written or `PathError` if a tree contains an unsafe
+    /// name such as `..`.
+    pub fn checkout_to_disk<P: AsRef<FsPath>>(&self, target_dir: P) -> Result<usize> {
+        let target_dir = target_dir.as_ref();
+        fs::create_dir_all(target_dir)?;
+
+        if self.root.is_none() {
+            return Ok(0);
+        }
+
+        let mut written = 0;
+        for entry in self.walk("")? {
+            let (repo_path, hash, mode) = entry?;
+            let path = disk_path(target_dir, &repo_path)?;
+            self.checkout_entry(&path, hash, mode, false)?;
+            written += !matches!(mode, Mode::Gitlink) as usize;
+        }
+
+        Ok(written)
+    }
+
+    /// Like [`Self::checkout_to_disk`], but can continue after an
+    /// interruption (power loss, full disk...)
+    ///
+    /// Each file is synced to disk, read back and checked against
+    /// its blob hash, then recorded in a journal file
+    /// (`.rustgit-checkout` in `target_dir`). When called again,
+    /// recorded files which are still intact are skipped. The
+    /// journal is deleted once the checkout is complete.
+    ///
+    /// Returns the number of written & skipped files. If a file
+    /// doesn't match its hash after being written, this returns
+    /// `IoError(InvalidData)`.
+    pub fn checkout_resumable<P: AsRef<FsPath>>(&self, target_dir: P) -> Result<(usize, usize)> {
+        let target_dir = target_dir.as_ref();
+        fs::create_dir_all(target_dir)?;
+
+        let journal_path = target_dir.join(CHECKOUT_JOURNAL);
+        let done = match fs::read_to_string(&journal_path) {
+            Ok(journal) => parse_journal(&journal),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut journal = OpenOptions::new().create(true).append(true).open(&journal_path)?;
+        let (mut written, mut skipped) = (0, 0);
+
+        let entries = match self.root {
+            Some(_) => Some(self.walk("")?),
+            None => None,
+        };
+
+        for entry in entries.into_iter().flatten() {
+            let (repo_path, hash, mode) = entry?;
+            let path = disk_path(target_dir, &repo_path)?;
+            let line = format!("{} {}\n", hash, repo_path);
+
+            if let Mode::Gitlink = mode {
+                fs::create_dir_all(&path)?;
+                continue;
+            }
+
+            let recorded = done.contains(&(hash, repo_path));
+            if recorded && self.is_intact(&path, hash, mode) {
+                skipped += 1;
+                continue;
+            }
+
+            self.checkout_entry(&path, hash, mode, true)?;
+
+            if !self.is_intact(&path, hash, mode) {
+                log::error!("{} doesn't match {} after being written", path.display(), hash);
+                return Err(Error::IoError(io::ErrorKind::InvalidData));
+            }
+
+            journal.write_all(line.as_bytes())?;
+            journal.sync_data()?;
+            written += 1;
+        }
+
+        drop(journal);
+        fs::remove_file(&journal_path)?;
+
+        Ok((written, skipped))
+    }
+
+    /// Writes a file, a symbolic link or an empty directory (gitlinks)
+    fn checkout_entry(&self, path: &FsPath, hash: Hash, mode: Mode, sync: bool) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if let Mode::Gitlink = mode {
+            fs::create_dir_all(path)?;
+            return Ok(());
+        }
+
+        let data = self.any_store_get(hash, ObjectType::Blob).ok_or(Error::MissingObject)?;
+
+        // links cannot be overwritten in place
+        if fs::symlink_metadata(path).is_ok_and(|m| !m.is_dir()) {
+            fs::remove_file(path)?;
+        }
+
+        if let Mode::SymbolicLink = mode {
+            return write_link(data, path);
+        }
+
+        let mut file = File::create(path)?;
+        file.write_all(data)?;
+
+        if sync {
+            file.sync_all()?;
+        }
+
+        if let Mode::ExecutableFile = mode {
+            set_executable(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks a file on disk against its blob hash
+    fn is_intact(&self, path: &FsPath, hash: Hash, mode: Mode) -> bool {
+        let data = match mode {
+            Mode::SymbolicLink if cfg!(unix) => link_target(path),
+            _ => fs::read(path).map_err(Error::from),
+        };
+
+        match data {
+            Ok(data) => self.objects.hash(ObjectType::Blob, &data).ok() == Some(self.replaced(hash)),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Lines of the journal are `<hash> <path>`
+fn parse_journal(journal: &str) -> BTreeSet<(Hash, String)> {
+    let parse_line = |line: &str| {
+        let (hash, path) = line.split_once(' ')?;
+        Some((Hash::from_hex(hash)?, path.to_string()))
+    };
+
+    // the last line can be truncated by an interruption
+    journal.lines().filter_map(parse_line).collect()
+}
+
+/// Returns `PathError` if a path could lead outside of `target_dir`
+fn disk_path(target_dir: &FsPath, repo_path: &str) -> Result<PathBuf> {
+    let unsafe_name = |name: &str| [".", "..", ".git"].contains(&name) || name.contains('\\');
+    if repo_path.split('/').any(unsafe_name) {
+        log::error!("Refusing to write {:?} outside of the target directory", repo_path);
+        return Err(Error::PathError);
+    }
+
+    Ok(target_dir.join(repo_path))
+}
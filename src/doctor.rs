@@ -0,0 +1,241 @@
+use std::net::TcpStream;
+use coolssh::{Connection, RunResult};
+
+use super::internals::{
+    Result, Remote, Repository, Hash, GitProtocol, PacketLine, PackfileReader,
+};
+
+/// Outcome of a single step of [`Repository::doctor`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Check {
+    Passed,
+    Failed(String),
+    /// A previous step failed
+    Skipped,
+}
+
+impl Check {
+    pub fn passed(&self) -> bool {
+        *self == Check::Passed
+    }
+
+    pub fn failed(&self) -> bool {
+        matches!(self, Check::Failed(_))
+    }
+
+    fn record<T>(&mut self, result: Result<T>) -> Option<T> {
+        match result {
+            Ok(value) => {
+                *self = Check::Passed;
+                Some(value)
+            },
+            Err(error) => {
+                *self = Check::Failed(format!("{:?}", error));
+                None
+            },
+        }
+    }
+}
+
+/// Most likely reason for a failed [`Diagnosis`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FailureCause {
+    /// The host couldn't be reached
+    Network,
+    /// The SSH session couldn't be established (username or key pair)
+    Credentials,
+    /// The server refused to serve this repository
+    ServerPolicy,
+    /// The server's replies couldn't be understood
+    Protocol,
+}
+
+/// Report produced by [`Repository::doctor`]
+#[derive(Clone, Debug)]
+pub struct Diagnosis {
+    /// TCP connection to the host
+    pub connect: Check,
+    /// SSH handshake & authentication
+    pub auth: Check,
+    /// `git-upload-pack` with protocol version 2
+    pub upload_pack: Check,
+    /// `command=ls-refs`
+    pub ls_refs: Check,
+    /// `command=fetch` of the remote HEAD, up to the packfile header
+    pub fetch: Check,
+    /// `git-receive-pack` advertisement (nothing is pushed)
+    pub receive_pack: Check,
+    pub upload_pack_caps: Vec<String>,
+    pub receive_pack_caps: Vec<String>,
+    pub refs: Vec<(String, Hash)>,
+}
+
+impl Diagnosis {
+    /// Returns `None` if all checks passed
+    pub fn cause(&self) -> Option<FailureCause> {
+        if self.connect.failed() {
+            Some(FailureCause::Network)
+        } else if self.auth.failed() {
+            Some(FailureCause::Credentials)
+        } else if self.upload_pack.failed() || self.receive_pack.failed() {
+            Some(FailureCause::ServerPolicy)
+        } else if self.ls_refs.failed() || self.fetch.failed() {
+            Some(FailureCause::Protocol)
+        } else {
+            None
+        }
+    }
+
+    fn open(&mut self, remote: &Remote) -> Option<Connection> {
+        let stream = match TcpStream::connect(&*remote.host) {
+            Ok(stream) => stream,
+            Err(error) => {
+                self.connect = Check::Failed(error.to_string());
+                return None;
+            },
+        };
+
+        self.connect = Check::Passed;
+        self.auth.record(remote.connect_with(stream))
+    }
+
+    fn check_upload_pack(&mut self, remote: &Remote) {
+        let Some(mut conn) = self.open(remote) else { return };
+
+        let env = [("GIT_PROTOCOL", "version=2")];
+        let command = format!("git-upload-pack {}", remote.path);
+        let mut protocol = match conn.run(&command, &env) {
+            Ok(RunResult::Accepted(run)) => GitProtocol::new(run),
+            Ok(_) => {
+                self.upload_pack = Check::Failed("git-upload-pack was refused".into());
+                return;
+            },
+            Err(error) => {
+                self.upload_pack = Check::Failed(format!("{:?}", error));
+                return;
+            },
+        };
+
+        protocol.set_idle_timeout(remote.connect_options.idle_timeout);
+
+        let caps = (|| {
+            let mut caps = Vec::new();
+            while let Some(line) = protocol.read_line_str()? {
+                caps.push(String::from(line));
+            }
+
+            Ok(caps)
+        })();
+
+        let Some(caps) = self.upload_pack.record(caps) else { return };
+        self.upload_pack_caps = caps;
+
+        let refs = crate::clone::ls_refs(&mut protocol);
+        let Some(refs) = self.ls_refs.record(refs) else { return };
+        self.refs = refs;
+
+        let head = self.refs.iter().find(|(name, _)| name == "HEAD");
+        let Some((_, head)) = head else {
+            // empty repository: nothing to fetch
+            return;
+        };
+
+        let shallow = self.upload_pack_caps.iter().any(|cap| match cap.strip_prefix("fetch=") {
+            Some(options) => options.split(' ').any(|option| option == "shallow"),
+            None => false,
+        });
+
+        let want_head = format!("want {}", head);
+        let mut request = vec![
+            PacketLine::String("command=fetch\n"),
+            PacketLine::DelimiterPacket,
+            PacketLine::String(&want_head),
+            PacketLine::String("no-progress"),
+        ];
+
+        if shallow {
+            request.push(PacketLine::String("deepen 1"));
+        }
+
+        request.push(PacketLine::String("done"));
+        request.push(PacketLine::FlushPacket);
+
+        let fetch = (|| {
+            protocol.write_lines(&request)?;
+            while Some(b"packfile\n".as_slice()) != protocol.read_line()? {}
+            PackfileReader::new(protocol).map(|_| ())
+        })();
+
+        self.fetch.record(fetch);
+    }
+
+    fn check_receive_pack(&mut self, remote: &Remote) {
+        let Some(mut conn) = self.open(remote) else { return };
+
+        let command = format!("git-receive-pack {}", remote.path);
+        let mut protocol = match conn.run(&command, &[]) {
+            Ok(RunResult::Accepted(run)) => GitProtocol::new(run),
+            Ok(_) => {
+                self.receive_pack = Check::Failed("git-receive-pack was refused".into());
+                return;
+            },
+            Err(error) => {
+                self.receive_pack = Check::Failed(format!("{:?}", error));
+                return;
+            },
+        };
+
+        protocol.set_idle_timeout(remote.connect_options.idle_timeout);
+
+        let caps = (|| {
+            let mut caps = Vec::new();
+            while let Some(line) = protocol.read_line_str()? {
+                if let Some((_, server_caps)) = line.split_once('\0') {
+                    caps.extend(server_caps.split(' ').map(String::from));
+                }
+            }
+
+            // an empty command list ends the session without updating anything
+            protocol.write_lines(&[ PacketLine::FlushPacket ])?;
+
+            Ok(caps)
+        })();
+
+        if let Some(caps) = self.receive_pack.record(caps) {
+            self.receive_pack_caps = caps;
+        }
+    }
+}
+
+impl Repository {
+    /// Runs a battery of non-destructive checks against a remote
+    ///
+    /// The checks are: TCP connection, SSH authentication,
+    /// `git-upload-pack` capabilities, `ls-refs`, a fetch of the
+    /// remote HEAD (aborted after the packfile header) and the
+    /// `git-receive-pack` advertisement. Nothing is imported or pushed.
+    ///
+    /// See [`Diagnosis::cause`] to tell credentials, protocol and
+    /// server policy failures apart.
+    pub fn doctor(remote: &Remote) -> Diagnosis {
+        let mut diagnosis = Diagnosis {
+            connect: Check::Skipped,
+            auth: Check::Skipped,
+            upload_pack: Check::Skipped,
+            ls_refs: Check::Skipped,
+            fetch: Check::Skipped,
+            receive_pack: Check::Skipped,
+            upload_pack_caps: Vec::new(),
+            receive_pack_caps: Vec::new(),
+            refs: Vec::new(),
+        };
+
+        diagnosis.check_upload_pack(remote);
+
+        if diagnosis.auth.passed() {
+            diagnosis.check_receive_pack(remote);
+        }
+
+        diagnosis
+    }
+}
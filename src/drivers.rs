@@ -0,0 +1,165 @@
+use std::borrow::Cow;
+
+use super::internals::Repository;
+use super::diff::DEFAULT_BINARY_THRESHOLD;
+
+/// Callback of [`DiffDriver::TextConv`]
+pub type TextConv = Box<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+/// Callback of [`MergeDriver::Custom`]: `(base, ours, theirs)`
+///
+/// Returns `None` if the versions cannot be merged.
+pub type MergeFn = Box<dyn Fn(&[u8], &[u8], &[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+/// How files are prepared before being diffed
+pub enum DiffDriver {
+    /// Detects binary files (with a NUL byte), diffs other files as is
+    Text,
+    /// Never shows a textual diff
+    Binary,
+    /// Converts files to text before diffing them
+    TextConv(TextConv),
+}
+
+/// How concurrent versions of a file are merged
+pub enum MergeDriver {
+    /// Merges if only one side changed the file
+    Text,
+    /// Never merges concurrent changes
+    Binary,
+    /// Merges using a callback
+    Custom(MergeFn),
+}
+
+/// Path-pattern-based driver registry of a repository
+#[derive(Default)]
+pub(crate) struct Drivers {
+    diff: Vec<(String, DiffDriver)>,
+    merge: Vec<(String, MergeDriver)>,
+}
+
+const TEXT_DIFF: DiffDriver = DiffDriver::Text;
+const TEXT_MERGE: MergeDriver = MergeDriver::Text;
+
+/// Like git, only the first 8000 bytes are checked
+pub(crate) fn looks_binary(data: &[u8]) -> bool {
+    looks_binary_within(data, DEFAULT_BINARY_THRESHOLD)
+}
+
+/// Checks for a NUL byte in the first `threshold` bytes
+pub(crate) fn looks_binary_within(data: &[u8], threshold: usize) -> bool {
+    data.iter().take(threshold).any(|b| *b == 0)
+}
+
+fn glob_match_bytes(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((b'*', rest)) if rest.first() == Some(&b'*') => {
+            // `**` also matches slashes
+            let rest = &rest[1..];
+            match rest.strip_prefix(b"/") {
+                // `**/` matches whole directories
+                Some(rest) => (0..=path.len())
+                    .filter(|i| *i == 0 || path[i - 1] == b'/')
+                    .any(|i| glob_match_bytes(rest, &path[i..])),
+                None => (0..=path.len()).any(|i| glob_match_bytes(rest, &path[i..])),
+            }
+        },
+        Some((b'*', rest)) => {
+            let max = path.iter().position(|b| *b == b'/').unwrap_or(path.len());
+            (0..=max).any(|i| glob_match_bytes(rest, &path[i..]))
+        },
+        Some((b'?', rest)) => match path.split_first() {
+            Some((c, path)) if *c != b'/' => glob_match_bytes(rest, path),
+            _ => false,
+        },
+        Some((c, rest)) => match path.split_first() {
+            Some((d, path)) if c == d => glob_match_bytes(rest, path),
+            _ => false,
+        },
+    }
+}
+
+/// Matches a path against a gitattributes-like pattern
+///
+/// - `*` and `?` don't match slashes, `**` does
+/// - patterns without a slash are matched against the file name
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+
+    match pattern.contains('/') {
+        true => glob_match_bytes(pattern.trim_start_matches('/').as_bytes(), path.as_bytes()),
+        false => {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            glob_match_bytes(pattern.as_bytes(), name.as_bytes())
+        },
+    }
+}
+
+/// Last matching pattern wins, like in `.gitattributes`
+fn lookup<'a, T>(drivers: &'a [(String, T)], path: &str) -> Option<&'a T> {
+    let mut iter = drivers.iter().rev();
+    iter.find(|(pattern, _)| glob_match(pattern, path)).map(|(_, driver)| driver)
+}
+
+impl Repository {
+    /// Registers a diff driver for paths matching `pattern`
+    ///
+    /// See [`glob_match`] for the pattern syntax. When several
+    /// patterns match a path, the last registered one is used.
+    pub fn set_diff_driver(&mut self, pattern: &str, driver: DiffDriver) {
+        self.drivers.diff.push((pattern.into(), driver));
+    }
+
+    /// Registers a merge driver for paths matching `pattern`
+    ///
+    /// See [`glob_match`] for the pattern syntax. When several
+    /// patterns match a path, the last registered one is used.
+    pub fn set_merge_driver(&mut self, pattern: &str, driver: MergeDriver) {
+        self.drivers.merge.push((pattern.into(), driver));
+    }
+
+    /// Diff driver to use for a path (defaults to [`DiffDriver::Text`])
+    pub fn diff_driver(&self, path: &str) -> &DiffDriver {
+        lookup(&self.drivers.diff, path).unwrap_or(&TEXT_DIFF)
+    }
+
+    /// Merge driver to use for a path (defaults to [`MergeDriver::Text`])
+    pub fn merge_driver(&self, path: &str) -> &MergeDriver {
+        lookup(&self.drivers.merge, path).unwrap_or(&TEXT_MERGE)
+    }
+
+    /// Prepares a file for diffing, using its diff driver
+    ///
+    /// Returns `None` if the file must be diffed as binary.
+    pub fn diff_text<'a>(&self, path: &str, data: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        self.diff_text_with(path, data, DEFAULT_BINARY_THRESHOLD)
+    }
+
+    /// Like [`Self::diff_text`], with a custom binary detection threshold
+    pub(crate) fn diff_text_with<'a>(&self, path: &str, data: &'a [u8], binary_threshold: usize) -> Option<Cow<'a, [u8]>> {
+        match self.diff_driver(path) {
+            DiffDriver::Text if looks_binary_within(data, binary_threshold) => None,
+            DiffDriver::Text => Some(Cow::Borrowed(data)),
+            DiffDriver::Binary => None,
+            DiffDriver::TextConv(convert) => Some(Cow::Owned(convert(data))),
+        }
+    }
+
+    /// Merges concurrent versions of a file, using its merge driver
+    ///
+    /// Returns `None` if the versions conflict.
+    pub fn merge_file(&self, path: &str, base: &[u8], ours: &[u8], theirs: &[u8]) -> Option<Vec<u8>> {
+        // trivial cases don't need a driver
+        if ours == theirs || base == theirs {
+            return Some(ours.to_vec());
+        } else if base == ours {
+            return Some(theirs.to_vec());
+        }
+
+        match self.merge_driver(path) {
+            MergeDriver::Text | MergeDriver::Binary => None,
+            MergeDriver::Custom(merge) => merge(base, ours, theirs),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use lmfu::json::{JsonFile, Path};
+
+use super::internals::{Result, Error, Hash, Repository, Commit, Signature, ObjectType};
+use super::json_merge::write_value;
+use super::diff::TreeChange;
+
+fn set_string(file: &mut JsonFile, path: &Path, key: &str, value: &str) {
+    let path = file.prop(path.clone(), key);
+    file.set_string(&path, value.into());
+}
+
+fn set_number(file: &mut JsonFile, path: &Path, key: &str, value: f64) {
+    let path = file.prop(path.clone(), key);
+    file.set_number(&path, value);
+}
+
+/// Hashes are strings, `None` is null
+fn set_hash(file: &mut JsonFile, path: &Path, key: &str, hash: Option<Hash>) {
+    match hash {
+        Some(hash) => set_string(file, path, key, &hash.to_string()),
+        None => {
+            file.prop(path.clone(), key);
+        },
+    }
+}
+
+fn write_signature(file: &mut JsonFile, path: &Path, signature: &Signature) {
+    file.set_object(path);
+    set_string(file, path, "name", signature.name);
+    set_string(file, path, "email", signature.email);
+    set_number(file, path, "timestamp", signature.timestamp as f64);
+    set_number(file, path, "tz_offset", signature.tz_offset as f64);
+}
+
+fn write_change(file: &mut JsonFile, path: &Path, change: &TreeChange) {
+    let (status, old, new) = match change {
+        TreeChange::Added { hash, .. } => ("added", None, Some(*hash)),
+        TreeChange::Removed { hash, .. } => ("removed", Some(*hash), None),
+        TreeChange::Modified { old, new, .. } => ("modified", Some(old.0), Some(new.0)),
+    };
+
+    file.set_object(path);
+    set_string(file, path, "path", change.name());
+    set_string(file, path, "status", status);
+    set_hash(file, path, "old", old);
+    set_hash(file, path, "new", new);
+}
+
+impl Repository {
+    fn write_commit(&self, file: &mut JsonFile, path: &Path, hash: Hash) -> Result<()> {
+        let Some(commit) = self.objects.get_as(hash, ObjectType::Commit) else {
+            log::error!("Commit {} isn't in the repository", hash);
+            return Err(Error::MissingObject);
+        };
+
+        let commit = Commit::parse(commit)?;
+
+        // merges are compared to their first parent, like `git log --first-parent`
+        let parent_tree = match commit.parents.first() {
+            Some(parent) => match self.objects.get_as(*parent, ObjectType::Commit) {
+                Some(parent) => Some(Commit::parse(parent)?.tree),
+                // shallow clone
+                None => None,
+            },
+            None => Some(Hash::zero()),
+        };
+
+        file.set_object(path);
+        set_hash(file, path, "hash", Some(hash));
+        set_hash(file, path, "tree", Some(commit.tree));
+
+        let parents = file.prop(path.clone(), "parents");
+        file.set_array(&parents);
+        for parent in &commit.parents {
+            let item = file.push(parents.clone());
+            file.set_string(&item, parent.to_string().as_str().into());
+        }
+
+        let author = file.prop(path.clone(), "author");
+        write_signature(file, &author, &commit.author);
+        let committer = file.prop(path.clone(), "committer");
+        write_signature(file, &committer, &commit.committer);
+        set_string(file, path, "message", commit.message);
+
+        let signature = file.prop(path.clone(), "signature");
+        if let Some(raw) = commit.header("gpgsig") {
+            file.set_string(&signature, raw.replace("\n ", "\n").as_str().into());
+        }
+
+        let changes = file.prop(path.clone(), "changes");
+        if let Some(tree) = parent_tree {
+            file.set_array(&changes);
+            for change in &self.diff_trees_recursive(tree, commit.tree)? {
+                let item = file.push(changes.clone());
+                write_change(file, &item, change);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serializes a commit to JSON
+    ///
+    /// ```json
+    /// {"hash":"...","tree":"...","parents":["..."],
+    ///  "author":{"name":"...","email":"...","timestamp":1700000000,"tz_offset":60},
+    ///  "committer":{...},"message":"...","signature":null,
+    ///  "changes":[{"path":"src/main.rs","status":"modified","old":"...","new":"..."}]}
+    /// ```
+    ///
+    /// `signature` is the armored signature, if any. `changes` lists
+    /// files changed since the first parent; it is `null` if that
+    /// parent is missing (shallow clone).
+    ///
+    /// Returns `MissingObject` if the commit isn't in the repository.
+    pub fn commit_json(&self, hash: Hash) -> Result<String> {
+        let mut file = JsonFile::new(None).unwrap();
+        self.write_commit(&mut file, &Path::new(), hash)?;
+
+        let mut json = String::new();
+        write_value(&mut json, &file, &Path::new(), None, 0);
+        Ok(json)
+    }
+
+    /// Serializes commits reachable from `tip` but not from `base`
+    /// to a JSON array, newest first
+    ///
+    /// This is `base..tip` in git terms; with no `base`, the whole
+    /// history of `tip` is serialized. See [`Self::commit_json`]
+    /// for the format of each commit.
+    pub fn log_json(&self, tip: Hash, base: Option<Hash>) -> Result<String> {
+        let excluded = match base {
+            Some(base) => self.ancestors(base)?,
+            None => Default::default(),
+        };
+
+        let mut file = JsonFile::new(None).unwrap();
+        file.set_array(&Path::new());
+        for item in self.log(tip) {
+            let (hash, _) = item?;
+            if !excluded.contains(&hash) {
+                let path = file.push(Path::new());
+                self.write_commit(&mut file, &path, hash)?;
+            }
+        }
+
+        let mut json = String::new();
+        write_value(&mut json, &file, &Path::new(), None, 0);
+        Ok(json)
+    }
+}
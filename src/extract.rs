@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+
+use super::internals::{
+    Result, Error, Hash, Write, Repository, Commit, ObjectStore, ObjectType, EntryType, TreeIter,
+};
+
+/// History of a repository created by [`Repository::extract`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ExtractMode {
+    /// A single commit with the subtree of the current commit
+    #[default]
+    Squash,
+    /// Commits which changed the subtree are rewritten,
+    /// like `git subtree split`
+    History,
+}
+
+/// Copies a tree and everything under it; missing objects
+/// (in partial clones) are skipped
+fn copy_tree(src: &ObjectStore, tree: Hash, dst: &mut ObjectStore) -> Result<()> {
+    if dst.has(tree) {
+        return Ok(());
+    }
+
+    let Some(object) = src.get(tree) else { return Ok(()) };
+    if object.obj_type() == ObjectType::Tree {
+        let mut iter = TreeIter::new(object.content());
+        while let Some((_, hash, _)) = iter.next()? {
+            copy_tree(src, hash, dst)?;
+        }
+    }
+
+    dst.insert_entry(object.clone())?;
+    Ok(())
+}
+
+impl Repository {
+    /// Tree at `prefix` in a commit, `None` if there's none
+    fn subtree_at(&self, commit: &Commit, prefix: &str) -> Result<Option<Hash>> {
+        if prefix.is_empty() {
+            return Ok(Some(commit.tree));
+        }
+
+        match self.find_entry_in(Some(commit.tree), prefix, EntryType::Directory) {
+            Ok((hash, _)) => Ok(Some(hash)),
+            Err(Error::PathError) => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Ancestors of the current commit, parents first
+    fn topological_history(&self) -> Result<Vec<Hash>> {
+        let mut order = Vec::new();
+        let mut visited = BTreeMap::new();
+        let mut stack = vec![(self.head, false)];
+
+        while let Some((hash, expanded)) = stack.pop() {
+            if expanded {
+                order.push(hash);
+                continue;
+            }
+
+            if hash.is_zero() || visited.insert(hash, ()).is_some() {
+                continue;
+            }
+
+            // missing commits (shallow clones) are skipped
+            let Some(raw) = self.objects.get_as(hash, ObjectType::Commit) else { continue };
+            stack.push((hash, true));
+            for parent in Commit::parse(raw)?.parents.iter().rev() {
+                stack.push((*parent, false));
+            }
+        }
+
+        Ok(order)
+    }
+
+    /// Creates a repository with a directory of the current commit
+    ///
+    /// Files under `prefix` are at the root of the new repository.
+    /// With [`ExtractMode::Squash`], its only commit has the author,
+    /// committer & message of the current commit. With
+    /// [`ExtractMode::History`], commits which changed the directory
+    /// are rewritten with their original metadata; signatures and
+    /// extra headers are dropped. Staged changes aren't extracted.
+    ///
+    /// ```ignore
+    /// let component = monorepo.extract("libs/parser", ExtractMode::History)?;
+    /// println!("{} commits", component.log(component.head()).count());
+    /// ```
+    ///
+    /// Returns `PathError` if the directory isn't in the current commit.
+    pub fn extract(&self, prefix: &str, mode: ExtractMode) -> Result<Repository> {
+        let prefix = prefix.trim_matches('/');
+        let Some(raw) = self.objects.get_as(self.head, ObjectType::Commit) else {
+            log::error!("Nothing was committed yet");
+            return Err(Error::MissingObject);
+        };
+
+        if self.subtree_at(&Commit::parse(raw)?, prefix)?.is_none() {
+            log::error!("{} isn't a directory of the current commit", prefix);
+            return Err(Error::PathError);
+        }
+
+        let commits = match mode {
+            ExtractMode::Squash => vec![self.head],
+            ExtractMode::History => self.topological_history()?,
+        };
+
+        let mut repo = Repository::new();
+        // rewritten commits & their tree
+        let mut rewritten = BTreeMap::<Hash, (Hash, Hash)>::new();
+
+        for hash in commits {
+            let commit = Commit::parse(self.objects.get_as(hash, ObjectType::Commit).unwrap())?;
+            let tree = self.subtree_at(&commit, prefix)?;
+
+            let mut parents = Vec::new();
+            if mode == ExtractMode::History {
+                for parent in &commit.parents {
+                    let Some(parent) = rewritten.get(parent) else { continue };
+                    if !parents.contains(parent) {
+                        parents.push(*parent);
+                    }
+                }
+            }
+
+            // commits which didn't change the directory are skipped
+            let unchanged = match (tree, &parents[..]) {
+                (None, []) => true,
+                (Some(tree), [(_, parent_tree)]) => tree == *parent_tree,
+                _ => false,
+            };
+
+            if unchanged {
+                if let [parent] = parents[..] {
+                    rewritten.insert(hash, parent);
+                }
+
+                continue;
+            }
+
+            let tree = match tree {
+                Some(tree) => {
+                    copy_tree(&self.objects, tree, &mut repo.objects)?;
+                    tree
+                },
+                // the directory was removed
+                None => repo.objects.insert(ObjectType::Tree, Vec::new(), None)?,
+            };
+
+            let mut serialized = Vec::new();
+            writeln!(&mut serialized, "tree {}", tree).unwrap();
+            for (parent, _) in &parents {
+                writeln!(&mut serialized, "parent {}", parent).unwrap();
+            }
+
+            writeln!(&mut serialized, "author {}", commit.author).unwrap();
+            writeln!(&mut serialized, "committer {}", commit.committer).unwrap();
+            write!(&mut serialized, "\n{}", commit.message).unwrap();
+
+            let new_hash = repo.objects.insert(ObjectType::Commit, serialized, None)?;
+            rewritten.insert(hash, (new_hash, tree));
+        }
+
+        if let Some((head, tree)) = rewritten.get(&self.head) {
+            repo.head = *head;
+            repo.root = Some(*tree);
+            repo.record_reflog("extract");
+        }
+
+        Ok(repo)
+    }
+}
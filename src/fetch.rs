@@ -0,0 +1,320 @@
+use super::internals::{
+    Result, Error, Hash, Remote, Repository, ObjectStore, ObjectType,
+    GitProtocol, PacketLine, PackfileReader,
+};
+use super::protocol::FetchResponse;
+use super::clone::upload_pack;
+
+/// Low-level `command=fetch` request (protocol version 2)
+///
+/// ```ignore
+/// let request = FetchRequest::new().want(commit).have(known).depth(10);
+/// repo.fetch_objects(&remote, &request)?;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FetchRequest {
+    wants: Vec<Hash>,
+    want_refs: Vec<String>,
+    haves: Vec<Hash>,
+    shallow: Vec<Hash>,
+    depth: Option<usize>,
+    deepen_since: Option<u64>,
+    deepen_not: Vec<String>,
+    filter: Option<String>,
+    thin_pack: bool,
+}
+
+/// Local commits offered as `have` lines, like git which
+/// gives up negotiating after 256 commits
+const MAX_HAVES: usize = 256;
+
+/// `have` lines sent in each negotiation round
+const HAVES_PER_ROUND: usize = 32;
+
+/// Reads the capability advertisement of `git-upload-pack`
+///
+/// Returns the features of the `fetch` command (`shallow`, `filter`...).
+pub fn read_fetch_features(protocol: &mut GitProtocol) -> Result<Vec<String>> {
+    let mut features = Vec::new();
+
+    while let Some(line) = protocol.read_line_str()? {
+        log::debug!("Server capability: {}", line);
+        if let Some(fetch_options) = line.strip_prefix("fetch=") {
+            features.extend(fetch_options.split(' ').map(String::from));
+        }
+    }
+
+    Ok(features)
+}
+
+impl FetchRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests a commit and the objects it refers to
+    pub fn want(mut self, commit: Hash) -> Self {
+        self.wants.push(commit);
+        self
+    }
+
+    /// Requests the commit a remote reference points to, such as
+    /// `refs/heads/main`, which saves an `ls-refs` round trip
+    ///
+    /// The remote resolves the reference; the commit is returned by
+    /// [`Self::execute`]. This requires the `ref-in-want` feature.
+    pub fn want_ref(mut self, reference: &str) -> Self {
+        self.want_refs.push(reference.into());
+        self
+    }
+
+    /// Tells the remote that this commit's objects are already known
+    ///
+    /// Haves are sent in rounds, newest commits first, until the
+    /// remote finds enough common commits to build the packfile.
+    pub fn have(mut self, commit: Hash) -> Self {
+        self.haves.push(commit);
+        self
+    }
+
+    /// Tells the remote that this commit's parents are missing,
+    /// see [`Repository::shallow_commits`]
+    pub fn shallow(mut self, commit: Hash) -> Self {
+        self.shallow.push(commit);
+        self
+    }
+
+    /// Limits the history to `depth` commits
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Limits the history to commits newer than a UNIX timestamp
+    pub fn deepen_since(mut self, timestamp: u64) -> Self {
+        self.deepen_since = Some(timestamp);
+        self
+    }
+
+    /// Excludes commits reachable from a remote reference
+    pub fn deepen_not(mut self, reference: &str) -> Self {
+        self.deepen_not.push(reference.into());
+        self
+    }
+
+    /// Omits some objects, example: `blob:none`
+    pub fn filter(mut self, spec: &str) -> Self {
+        self.filter = Some(spec.into());
+        self
+    }
+
+    /// Lets the remote send deltas against objects of the haves
+    ///
+    /// The store given to [`Self::execute`] must contain them,
+    /// which isn't the case after a filtered fetch.
+    pub fn thin_pack(mut self) -> Self {
+        self.thin_pack = true;
+        self
+    }
+
+    /// Returns `UnsupportedByRemote` if a setting requires
+    /// a feature missing from `features`
+    fn check(&self, features: &[String]) -> Result<()> {
+        let has = |feature| features.iter().any(|f| f == feature);
+        let deepens = self.depth.is_some() || self.deepen_since.is_some() || !self.deepen_not.is_empty();
+
+        if deepens && !has("shallow") {
+            log::error!("Remote server doesn't support depth settings");
+            return Err(Error::UnsupportedByRemote);
+        }
+
+        if self.filter.is_some() && !has("filter") {
+            log::error!("Remote server doesn't support object filters");
+            return Err(Error::UnsupportedByRemote);
+        }
+
+        if !self.want_refs.is_empty() && !has("ref-in-want") {
+            log::error!("Remote server doesn't support want-ref");
+            return Err(Error::UnsupportedByRemote);
+        }
+
+        Ok(())
+    }
+
+    /// Lines of a negotiation round; requests are stateless,
+    /// so everything but the haves is repeated in each round
+    ///
+    /// Progress is only requested if a message handler can show it.
+    fn lines(&self, haves: &[Hash], done: bool, progress: bool) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        lines.extend(self.wants.iter().map(|hash| format!("want {}", hash)));
+        lines.extend(self.want_refs.iter().map(|reference| format!("want-ref {}", reference)));
+        lines.extend(haves.iter().map(|hash| format!("have {}", hash)));
+        lines.extend(self.shallow.iter().map(|hash| format!("shallow {}", hash)));
+        if !progress {
+            lines.push("no-progress".into());
+        }
+
+        if let Some(depth) = self.depth {
+            lines.push(format!("deepen {}", depth));
+        }
+
+        if let Some(timestamp) = self.deepen_since {
+            lines.push(format!("deepen-since {}", timestamp));
+        }
+
+        lines.extend(self.deepen_not.iter().map(|reference| format!("deepen-not {}", reference)));
+
+        if let Some(spec) = &self.filter {
+            lines.push(format!("filter {}", spec));
+        }
+
+        if self.thin_pack {
+            lines.push("thin-pack".into());
+        }
+
+        if done {
+            lines.push("done".into());
+        }
+
+        lines
+    }
+
+    /// Sends the request and reads the packfile into `objects`
+    ///
+    /// `features` are those returned by [`read_fetch_features`].
+    /// Returns the sections of the response: commits found in common
+    /// during the negotiation, shallow boundaries and the references
+    /// resolved for [`Self::want_ref`]. Returns `CommitNotFetchable`
+    /// if the remote refuses to send one of the wanted commits,
+    /// `NoSuchReference` if a wanted reference doesn't exist and
+    /// `InvalidPackfile` if the packfile checksum is wrong.
+    pub fn execute(&self, protocol: GitProtocol, features: &[String], objects: &mut ObjectStore) -> Result<FetchResponse> {
+        self.execute_with(protocol, features, objects, false)
+    }
+
+    /// Also keeps compressed objects, see [`Object::compressed`]
+    ///
+    /// [`Object::compressed`]: crate::internals::Object::compressed
+    pub(crate) fn execute_with(
+        &self,
+        mut protocol: GitProtocol,
+        features: &[String],
+        objects: &mut ObjectStore,
+        keep_compressed: bool,
+    ) -> Result<FetchResponse> {
+        self.check(features)?;
+
+        if self.wants.is_empty() && self.want_refs.is_empty() {
+            log::error!("Fetch requests must have at least one want");
+            return Err(Error::NoSuchReference);
+        }
+
+        // commits acknowledged by the remote are sent again in
+        // each round, followed by the next batch of haves
+        let mut common = Vec::new();
+        let mut remaining = &self.haves[..];
+        let progress = protocol.has_message_handler();
+
+        let mut response = loop {
+            let (batch, rest) = remaining.split_at(remaining.len().min(HAVES_PER_ROUND));
+            remaining = rest;
+
+            let done = remaining.is_empty();
+            let haves = [&common[..], batch].concat();
+            let lines = self.lines(&haves, done, progress);
+            let mut request = vec![
+                PacketLine::String("command=fetch\n"),
+                PacketLine::DelimiterPacket,
+            ];
+
+            request.extend(lines.iter().map(|line| PacketLine::String(line)));
+            request.push(PacketLine::FlushPacket);
+            protocol.write_lines(&request)?;
+
+            // without `done`, the remote only sends a packfile once
+            // it's ready, after the acknowledgments
+            let response = protocol.read_fetch_response()?;
+            for hash in &response.acks {
+                if !common.contains(hash) {
+                    common.push(*hash);
+                }
+            }
+
+            if response.packfile {
+                log::info!("Negotiation: {} common commits, {} haves left", common.len(), remaining.len());
+                break response;
+            }
+
+            if done {
+                log::error!("Remote server didn't send a packfile");
+                return Err(Error::GitProtocolError);
+            }
+        };
+
+        response.acks = common;
+
+        let mut reader = PackfileReader::new(protocol)?;
+        reader.read_all_objects_with(objects, keep_compressed)?;
+        reader.verify_checksum()?;
+
+        Ok(response)
+    }
+}
+
+impl Repository {
+    /// Runs a fetch request, importing objects without changing
+    /// the current commit
+    ///
+    /// If the request has no haves, local commits are offered
+    /// (see [`FetchRequest::have`]), so that only missing objects
+    /// are downloaded. Shallow boundaries sent by the remote are
+    /// recorded. Returns the sections of the response, see
+    /// [`FetchRequest::execute`]. Like pushes, this takes `&mut self`
+    /// and cannot overlap with other operations (see [`Repository`]).
+    pub fn fetch_objects(&mut self, remote: &Remote, request: &FetchRequest) -> Result<FetchResponse> {
+        let mut request = request.clone();
+        if request.haves.is_empty() {
+            request.haves = self.local_haves()?;
+        }
+
+        if request.shallow.is_empty() {
+            request.shallow = self.shallow_commits().collect();
+        }
+
+        let mut conn = remote.connect()?;
+        let mut protocol = upload_pack(&mut conn, remote)?;
+        protocol.set_message_handler(self.message_handler.clone());
+        let features = read_fetch_features(&mut protocol)?;
+
+        let response = request.execute_with(protocol, &features, &mut self.objects, self.pack_reuse)?;
+        self.record_shallow(&response);
+        self.refresh_bitmaps()?;
+        Ok(response)
+    }
+
+    /// Updates shallow boundaries from a fetch response
+    pub(crate) fn record_shallow(&mut self, response: &FetchResponse) {
+        for hash in &response.shallow {
+            self.shallow.insert(*hash);
+        }
+
+        for hash in &response.unshallow {
+            self.shallow.remove(hash);
+        }
+    }
+
+    /// Recent local commits, newest first, to be sent as haves
+    pub(crate) fn local_haves(&self) -> Result<Vec<Hash>> {
+        let mut walk = self.log(Hash::zero());
+        let tips = [self.head, self.upstream_head].into_iter().chain(self.refs.iter().map(|(_, hash)| *hash));
+
+        // references can also point to tags
+        for tip in tips.filter(|tip| self.objects.get_as(*tip, ObjectType::Commit).is_some()) {
+            walk.push(tip);
+        }
+
+        walk.take(MAX_HAVES).map(|item| item.map(|(hash, _)| hash)).collect()
+    }
+}
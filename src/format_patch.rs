@@ -0,0 +1,225 @@
+use core::fmt::Write;
+
+use super::internals::{Result, Error, Hash, Mode, Repository, Commit, Signature, ObjectType};
+use super::diff::{TreeChange, DiffOptions};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// `Tue, 14 Nov 2023 22:13:20 +0100`
+fn rfc2822_date(signature: &Signature) -> String {
+    let local = signature.timestamp as i64 + signature.tz_offset as i64 * 60;
+    let (days, seconds) = (local.div_euclid(86400), local.rem_euclid(86400));
+
+    // civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    let sign = if signature.tz_offset < 0 { '-' } else { '+' };
+    let offset = signature.tz_offset.unsigned_abs();
+
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} {}{:02}{:02}",
+        WEEKDAYS[days.rem_euclid(7) as usize], day, MONTHS[month as usize - 1], year,
+        seconds / 3600, seconds / 60 % 60, seconds % 60,
+        sign, offset / 60, offset % 60,
+    )
+}
+
+/// RFC 2047 `Q` encoding, for non-ASCII header values
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        return value.into();
+    }
+
+    let mut encoded = String::from("=?UTF-8?q?");
+    for byte in value.bytes() {
+        match byte {
+            b' ' => encoded.push('_'),
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'!' | b'*' | b'+' | b'-' | b'/' => encoded.push(byte as char),
+            _ => write!(encoded, "={:02X}", byte).unwrap(),
+        }
+    }
+
+    encoded + "?="
+}
+
+impl Repository {
+    /// Commits of `base..tip`, oldest first
+    fn patch_range(&self, range: &str) -> Result<Vec<Hash>> {
+        // like git, a single revision means `rev..HEAD`
+        let (base, tip) = range.split_once("..").unwrap_or((range, ""));
+        let base = self.resolve(base)?;
+        let tip = match tip {
+            "" => self.resolve("HEAD")?,
+            tip => self.resolve(tip)?,
+        };
+
+        let excluded = self.ancestors(base)?;
+        let mut commits = Vec::new();
+        for item in self.log(tip) {
+            let (hash, _) = item?;
+            if !excluded.contains(&hash) {
+                commits.push(hash);
+            }
+        }
+
+        commits.reverse();
+        Ok(commits)
+    }
+
+    /// `diff --git` section of a changed file
+    fn patch_diff(&self, parent: Hash, commit: Hash, change: &TreeChange) -> Result<String> {
+        let path = change.name();
+        let mut diff = format!("diff --git a/{} b/{}\n", path, path);
+        let short = |hash: Hash| match hash.is_zero() {
+            true => "0000000".to_string(),
+            false => self.abbreviate(hash),
+        };
+
+        let (old, new) = match change {
+            TreeChange::Added { hash, mode, .. } => {
+                writeln!(diff, "new file mode {:o}", *mode as u32).unwrap();
+                writeln!(diff, "index {}..{}", short(Hash::zero()), short(*hash)).unwrap();
+                ((Hash::zero(), *mode), (*hash, *mode))
+            },
+            TreeChange::Removed { hash, mode, .. } => {
+                writeln!(diff, "deleted file mode {:o}", *mode as u32).unwrap();
+                writeln!(diff, "index {}..{}", short(*hash), short(Hash::zero())).unwrap();
+                ((*hash, *mode), (Hash::zero(), *mode))
+            },
+            TreeChange::Modified { old, new, .. } => {
+                let (old_mode, new_mode) = (old.1 as u32, new.1 as u32);
+                if old_mode != new_mode {
+                    write!(diff, "old mode {:o}\nnew mode {:o}\n", old_mode, new_mode).unwrap();
+                }
+
+                if old.0 != new.0 {
+                    write!(diff, "index {}..{}", short(old.0), short(new.0)).unwrap();
+                    match old_mode == new_mode {
+                        true => writeln!(diff, " {:o}", new_mode).unwrap(),
+                        false => diff.push('\n'),
+                    }
+                }
+
+                (*old, *new)
+            },
+        };
+
+        if old.0 == new.0 {
+            return Ok(diff);
+        }
+
+        let is_gitlink = |mode| matches!(mode, Mode::Gitlink);
+        if is_gitlink(old.1) || is_gitlink(new.1) {
+            let old_name = if old.0.is_zero() { "/dev/null".into() } else { format!("a/{}", path) };
+            let new_name = if new.0.is_zero() { "/dev/null".into() } else { format!("b/{}", path) };
+            write!(diff, "--- {}\n+++ {}\n", old_name, new_name).unwrap();
+
+            let count = |hash: Hash| if hash.is_zero() { ",0" } else { "" };
+            writeln!(diff, "@@ -1{} +1{} @@", count(old.0), count(new.0)).unwrap();
+
+            if !old.0.is_zero() {
+                writeln!(diff, "-Subproject commit {}", old.0).unwrap();
+            }
+
+            if !new.0.is_zero() {
+                writeln!(diff, "+Subproject commit {}", new.0).unwrap();
+            }
+
+            return Ok(diff);
+        }
+
+        // the index line was written above
+        diff += &self.file_diff(parent, commit, path, &DiffOptions::default(), false)?;
+        Ok(diff)
+    }
+
+    /// Serializes a commit as a patch email
+    fn patch_email(&self, hash: Hash, number: usize, total: usize) -> Result<String> {
+        let Some(raw) = self.objects.get_as(hash, ObjectType::Commit) else {
+            log::error!("Commit {} isn't in the repository", hash);
+            return Err(Error::MissingObject);
+        };
+
+        let commit = Commit::parse(raw)?;
+        let parent = commit.parents.first().copied().unwrap_or(Hash::zero());
+        let parent_tree = match parent.is_zero() {
+            true => Hash::zero(),
+            false => match self.get_commit_root(parent) {
+                Ok(tree) => tree.unwrap_or(Hash::zero()),
+                Err(error) => {
+                    log::error!("Parent of {} isn't in the repository (shallow clone?)", hash);
+                    return Err(error);
+                },
+            },
+        };
+
+        let message = commit.message.trim();
+        let (subject, body) = message.split_once("\n\n").unwrap_or((message, ""));
+        let subject = subject.split('\n').map(str::trim).collect::<Vec<_>>().join(" ");
+        let prefix = match total {
+            1 => "[PATCH]".to_string(),
+            _ => format!("[PATCH {}/{}]", number, total),
+        };
+
+        let mut diff = String::new();
+        for change in self.diff_trees_recursive(parent_tree, commit.tree)? {
+            diff += &self.patch_diff(parent, hash, &change)?;
+        }
+
+        let author = &commit.author;
+        let mut email = String::new();
+        writeln!(email, "From {} Mon Sep 17 00:00:00 2001", hash).unwrap();
+        writeln!(email, "From: {} <{}>", encode_header(author.name), author.email).unwrap();
+        writeln!(email, "Date: {}", rfc2822_date(author)).unwrap();
+        writeln!(email, "Subject: {} {}", prefix, encode_header(&subject)).unwrap();
+
+        if !(body.is_ascii() && diff.is_ascii()) {
+            email += "MIME-Version: 1.0\n";
+            email += "Content-Type: text/plain; charset=UTF-8\n";
+            email += "Content-Transfer-Encoding: 8bit\n";
+        }
+
+        email.push('\n');
+        if !body.is_empty() {
+            writeln!(email, "{}", body.trim_start()).unwrap();
+        }
+
+        write!(email, "---\n{}-- \nrustgit {}\n\n", diff, env!("CARGO_PKG_VERSION")).unwrap();
+        Ok(email)
+    }
+
+    /// Formats the commits of a range as patch emails, like `git format-patch`
+    ///
+    /// `range` is `base..tip` (`base..` is `base..HEAD`); a single
+    /// revision also means `rev..HEAD`. Revisions are resolved with
+    /// [`Self::resolve`]. One email is returned per commit, oldest
+    /// first, in the mbox format (they can be concatenated into a
+    /// mailbox or applied with `git am`).
+    ///
+    /// Like git, merge commits are skipped. Returns `MissingObject`
+    /// if the parent of a commit is missing (shallow clone).
+    pub fn format_patch(&self, range: &str) -> Result<Vec<String>> {
+        let mut commits = self.patch_range(range)?;
+        commits.retain(|hash| match self.objects.get_as(*hash, ObjectType::Commit) {
+            Some(raw) => Commit::parse(raw).map(|c| c.parents.len() < 2).unwrap_or(true),
+            None => true,
+        });
+
+        let total = commits.len();
+        let mut emails = Vec::with_capacity(total);
+        for (i, hash) in commits.into_iter().enumerate() {
+            emails.push(self.patch_email(hash, i + 1, total)?);
+        }
+
+        Ok(emails)
+    }
+}
@@ -0,0 +1,192 @@
+use std::collections::BTreeSet;
+
+use super::internals::{Result, Error, Hash, Repository, ObjectStore, ObjectType, TreeIter};
+
+/// Outcome of [`Repository::gc`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcReport {
+    /// Number of removed objects
+    pub removed: usize,
+    /// Size of removed objects, uncompressed
+    pub removed_bytes: usize,
+    /// Unreachable objects which were kept because they're
+    /// pinned or reachable from a pinned object
+    pub retained_by_pins: Vec<Hash>,
+}
+
+impl Repository {
+    /// Protects an object from [`Self::gc`]
+    ///
+    /// Objects reachable from it are also kept. Returns
+    /// `MissingObject` if it isn't in the repository.
+    pub fn pin(&mut self, hash: Hash) -> Result<()> {
+        if !self.objects.has(hash) {
+            log::error!("Cannot pin {}: it isn't in the repository", hash);
+            return Err(Error::MissingObject);
+        }
+
+        self.pins.insert(hash);
+        Ok(())
+    }
+
+    /// Removes a pin set by [`Self::pin`]
+    ///
+    /// Returns false if the object wasn't pinned.
+    pub fn unpin(&mut self, hash: Hash) -> bool {
+        self.pins.remove(&hash)
+    }
+
+    /// Pinned objects, see [`Self::pin`]
+    pub fn pinned(&self) -> impl Iterator<Item = Hash> + '_ {
+        self.pins.iter().copied()
+    }
+
+    /// Walks a staged tree, whose objects are either in `store`
+    /// or in the committed objects
+    fn keep_staged_tree(&self, store: &ObjectStore, root: Hash, keep: &mut BTreeSet<Hash>) -> Result<()> {
+        let mut stack = vec![root];
+        while let Some(hash) = stack.pop() {
+            match store.get_as(hash, ObjectType::Tree) {
+                Some(content) => {
+                    let mut iter = TreeIter::new(content);
+                    while let Some((_, hash, _)) = iter.next()? {
+                        stack.push(hash);
+                    }
+                },
+                None => self.for_each_reachable(hash, |hash| {
+                    keep.insert(hash);
+                })?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Objects which [`Self::gc`] keeps, regardless of pins
+    fn live_objects(&self) -> Result<BTreeSet<Hash>> {
+        let mut keep = BTreeSet::new();
+        let reflog = self.reflog.iter().flat_map(|entry| [entry.head, entry.upstream_head]);
+        let replacements = self.replacements.iter().flat_map(|(original, new)| [*original, *new]);
+        let tips = self.refs.iter().map(|(_, hash)| *hash)
+            .chain([self.head, self.upstream_head])
+            .chain(reflog)
+            .chain(replacements);
+
+        for tip in tips.filter(|tip| !tip.is_zero()) {
+            self.for_each_reachable(tip, |hash| {
+                keep.insert(hash);
+            })?;
+        }
+
+        if let Some(root) = self.root {
+            self.keep_staged_tree(&self.staged, root, &mut keep)?;
+        }
+
+        for stash in &self.stashes {
+            if let Some(root) = stash.root() {
+                self.keep_staged_tree(stash.objects(), root, &mut keep)?;
+            }
+        }
+
+        Ok(keep)
+    }
+
+    /// Removes objects which cannot be reached anymore
+    ///
+    /// Objects are kept if they're reachable from references,
+    /// the current & upstream commits, reflog entries, replacements,
+    /// staged or stashed changes, or pinned objects (see [`Self::pin`]).
+    /// The report lists objects which were only kept because of pins.
+    pub fn gc(&mut self) -> Result<GcReport> {
+        let keep = self.live_objects()?;
+
+        let mut pinned = BTreeSet::new();
+        for pin in &self.pins {
+            self.for_each_reachable(*pin, |hash| {
+                if !keep.contains(&hash) {
+                    pinned.insert(hash);
+                }
+            })?;
+        }
+
+        let garbage: Vec<_> = self.objects.hashes()
+            .filter(|hash| !(keep.contains(hash) || pinned.contains(hash)))
+            .collect();
+
+        let mut report = GcReport {
+            retained_by_pins: pinned.into_iter().collect(),
+            ..GcReport::default()
+        };
+
+        let mut directories = self.directories.write().unwrap();
+        for hash in garbage {
+            if let Some(object) = self.objects.remove(hash) {
+                report.removed += 1;
+                report.removed_bytes += object.content().len();
+                directories.remove(&hash);
+            }
+        }
+
+        drop(directories);
+        self.refresh_bitmaps()?;
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Repository, FileType};
+    use crate::internals::{Hash, ObjectType, EntryType};
+    use crate::repository::NewEntry;
+
+    /// Commits a file on top of `base`, then goes back to `base`;
+    /// returns the commit & the blob
+    fn side_commit(repo: &mut Repository, base: Hash, name: &str) -> (Hash, Hash) {
+        let signature = ("Test", "test@example.com");
+        let content = format!("{}\n", name).into_bytes();
+        repo.stage(name, Some((content, FileType::RegularFile))).unwrap();
+        let blob = repo.find_entry(name, EntryType::File).unwrap().0;
+        let commit = repo.commit(name, signature, signature, Some(0)).unwrap();
+
+        repo.head = base;
+        repo.root = repo.get_commit_root(base).unwrap();
+        (commit, blob)
+    }
+
+    #[test]
+    fn gc_keeps_live_objects() {
+        let mut repo = Repository::new();
+        let signature = ("Test", "test@example.com");
+        repo.stage("base.txt", Some((b"base\n".to_vec(), FileType::RegularFile))).unwrap();
+        let base = repo.commit("base", signature, signature, Some(0)).unwrap();
+
+        let (by_ref, by_ref_blob) = side_commit(&mut repo, base, "ref.txt");
+        let (by_reflog, by_reflog_blob) = side_commit(&mut repo, base, "reflog.txt");
+        let (garbage, garbage_blob) = side_commit(&mut repo, base, "garbage.txt");
+        let (stashed_commit, stashed_blob) = side_commit(&mut repo, base, "stashed.txt");
+        let orphan = repo.objects.insert(ObjectType::Blob, b"orphan\n".to_vec(), None).unwrap();
+
+        repo.set_ref("refs/heads/kept", by_ref).unwrap();
+        repo.reflog.retain(|entry| [base, by_reflog].contains(&entry.head));
+
+        let mode = FileType::RegularFile.into();
+        repo.stage_entry("stashed.txt", Some(NewEntry::Existing(stashed_blob, mode))).unwrap();
+        assert!(repo.stash_save().unwrap());
+
+        let report = repo.gc().unwrap();
+
+        for kept in [base, by_ref, by_ref_blob, by_reflog, by_reflog_blob, stashed_blob] {
+            assert!(repo.objects.has(kept), "{} was removed", kept);
+        }
+
+        for removed in [garbage, garbage_blob, stashed_commit, orphan] {
+            assert!(!repo.objects.has(removed), "{} was kept", removed);
+        }
+
+        // the tree of `stashed_commit` is the stashed root
+        assert_eq!(report.removed, 5);
+        assert_eq!(repo.stash_pop().unwrap(), Vec::<String>::new());
+        assert_eq!(repo.read_file("stashed.txt").unwrap(), b"stashed.txt\n");
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::BTreeSet;
+
+use super::internals::{Result, Hash, Repository, Commit, ObjectType};
+
+impl Repository {
+    /// All commits reachable from `tip`, including itself
+    ///
+    /// Missing commits (in shallow clones) are silently skipped.
+    pub(crate) fn ancestors(&self, tip: Hash) -> Result<BTreeSet<Hash>> {
+        let mut ancestors = BTreeSet::new();
+        let mut stack = vec![tip];
+
+        while let Some(hash) = stack.pop() {
+            if hash.is_zero() || ancestors.contains(&hash) {
+                continue;
+            }
+
+            let Some(commit) = self.objects.get_as(hash, ObjectType::Commit) else { continue };
+            ancestors.insert(hash);
+            stack.extend(Commit::parse(commit)?.parents);
+        }
+
+        Ok(ancestors)
+    }
+
+    /// Checks if `ancestor` is reachable from `descendant`
+    ///
+    /// A commit is its own ancestor and `Hash::zero()` (no commit)
+    /// is an ancestor of all commits. Pushing `descendant` over
+    /// `ancestor` is a fast-forward if this returns `true`.
+    ///
+    /// Missing commits (in shallow clones) are silently skipped.
+    /// This is faster with bitmaps, see [`Self::enable_bitmaps`].
+    pub fn is_ancestor(&self, ancestor: Hash, descendant: Hash) -> Result<bool> {
+        if ancestor.is_zero() || ancestor == descendant {
+            return Ok(true);
+        }
+
+        self.is_commit_reachable(descendant, ancestor)
+    }
+
+    /// Counts commits of the current branch which aren't upstream
+    /// and upstream commits which aren't on the current branch
+    ///
+    /// Example: `(3, 2)` means "3 ahead, 2 behind".
+    pub fn ahead_behind(&self) -> Result<(usize, usize)> {
+        self.ahead_behind_of(self.head, self.upstream_head)
+    }
+
+    /// Counts commits reachable from `a` but not from `b`,
+    /// and commits reachable from `b` but not from `a`
+    ///
+    /// This is faster with bitmaps, see [`Self::enable_bitmaps`].
+    pub fn ahead_behind_of(&self, a: Hash, b: Hash) -> Result<(usize, usize)> {
+        let ours = self.reachable_commits(a)?;
+        let theirs = self.reachable_commits(b)?;
+
+        let ahead = ours.difference(&theirs).count();
+        let behind = theirs.difference(&ours).count();
+        Ok((ahead, behind))
+    }
+}
@@ -0,0 +1,73 @@
+use super::internals::{Result, Error, Repository};
+use super::repository::check_identity;
+
+/// Callback of [`IdentityPolicy::Custom`]: `(name, email)`
+///
+/// Returns `false` to reject the identity.
+pub type IdentityCheck = Box<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+/// How author, committer & tagger identities are validated
+///
+/// Whatever the policy, newlines, `<` in names and `>` in emails
+/// are rejected: they would make objects unreadable.
+#[derive(Default)]
+pub enum IdentityPolicy {
+    /// Rejects `<`, `>` and newlines
+    #[default]
+    Default,
+    /// Like `Default`, also requires a non-empty name and
+    /// a `local@domain.tld` email without whitespace
+    Strict,
+    /// Accepts anything readable, including empty names and emails;
+    /// useful to reproduce historical objects
+    Relaxed,
+    /// Uses a callback
+    Custom(IdentityCheck),
+}
+
+fn is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else { return false };
+    let labels_ok = domain.split('.').all(|label| !label.is_empty());
+
+    !local.is_empty()
+        && domain.contains('.')
+        && labels_ok
+        && !domain.contains('@')
+        && !email.contains(char::is_whitespace)
+}
+
+impl IdentityPolicy {
+    /// Returns `InvalidObject` if the policy rejects this identity
+    pub fn check(&self, name: &str, email: &str) -> Result<()> {
+        let readable = !name.contains(['\n', '<']) && !email.contains(['\n', '>']);
+
+        let valid = readable && match self {
+            Self::Default => check_identity(&[name, email]).is_ok(),
+            Self::Strict => {
+                let trimmed = !name.trim().is_empty();
+                check_identity(&[name, email]).is_ok() && trimmed && is_valid_email(email)
+            },
+            Self::Relaxed => true,
+            Self::Custom(check) => check(name, email),
+        };
+
+        match valid {
+            true => Ok(()),
+            false => {
+                log::error!("Identity rejected by policy: {:?} <{:?}>", name, email);
+                Err(Error::InvalidObject)
+            },
+        }
+    }
+}
+
+impl Repository {
+    /// Sets how commit & tag identities are validated
+    pub fn set_identity_policy(&mut self, policy: IdentityPolicy) {
+        self.identity_policy = policy;
+    }
+
+    pub fn identity_policy(&self) -> &IdentityPolicy {
+        &self.identity_policy
+    }
+}
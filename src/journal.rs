@@ -0,0 +1,365 @@
+use core::str::from_utf8;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::Path as FsPath;
+
+use super::internals::{Result, Error, Hash, Mode, Repository, ObjectType, PathSeparators};
+use super::repository::NewEntry;
+
+/// Append-only log of changes, see [`Repository::open_journal`]
+///
+/// Events are a header line, followed by a payload for `stage`,
+/// `blob-chunk`, `commit` and `object` events:
+/// - `stage <mode> <len> <path>\n<data>`
+/// - `blob-start\n`, `blob-chunk <len>\n<data>` and `blob-end <hash>\n`,
+///   for files written with [`Repository::stage_writer`]
+/// - `link <mode> <hash> <path>\n`
+/// - `remove <path>\n`
+/// - `commit <len>\n<commit object>`
+/// - `object <type> <len>\n<content>`, for other objects (tags)
+/// - `head <head> <upstream_head> <root>\n`
+/// - `discard\n`
+/// - `ref <hash> <name>\n`
+/// - `unref <name>\n`
+/// - `separators <slash|slash-or-backslash>\n`
+pub(crate) struct Journal {
+    file: File,
+}
+
+fn check_line(value: &str) -> Result<&str> {
+    match value.contains('\n') {
+        true => {
+            log::error!("Cannot journal {:?}: it contains a newline", value);
+            Err(Error::PathError)
+        },
+        false => Ok(value),
+    }
+}
+
+/// Event of [`Repository::stage_entry`]
+pub(crate) fn entry_event(path: &str, entry: &Option<NewEntry>) -> Result<Vec<u8>> {
+    let path = check_line(path)?;
+    let mut event = Vec::new();
+
+    match entry {
+        Some(NewEntry::Data(data, file_type)) => {
+            writeln!(event, "stage {:o} {} {}", Mode::from(*file_type) as u32, data.len(), path).unwrap();
+            event.extend_from_slice(data);
+        },
+        Some(NewEntry::Existing(hash, mode)) => writeln!(event, "link {:o} {} {}", *mode as u32, hash, path).unwrap(),
+        None => writeln!(event, "remove {}", path).unwrap(),
+    }
+
+    Ok(event)
+}
+
+/// Event of a new commit object
+pub(crate) fn commit_event(serialized: &[u8]) -> Vec<u8> {
+    let mut event = format!("commit {}\n", serialized.len()).into_bytes();
+    event.extend_from_slice(serialized);
+    event
+}
+
+/// Event of a new object which isn't a commit
+pub(crate) fn object_event(obj_type: ObjectType, content: &[u8]) -> Vec<u8> {
+    let mut event = format!("object {} {}\n", obj_type, content.len()).into_bytes();
+    event.extend_from_slice(content);
+    event
+}
+
+fn separators_name(separators: PathSeparators) -> &'static str {
+    match separators {
+        PathSeparators::Slash => "slash",
+        PathSeparators::SlashOrBackslash => "slash-or-backslash",
+    }
+}
+
+/// Outcome of parsing one event
+enum Parsed<'a> {
+    /// `(header, payload, length of the event)`
+    Event(&'a str, &'a [u8], usize),
+    /// The last event was only partially written
+    Torn,
+}
+
+fn parse_event(bytes: &[u8]) -> Result<Parsed<'_>> {
+    let Some(line_end) = bytes.iter().position(|b| *b == b'\n') else {
+        return Ok(Parsed::Torn);
+    };
+
+    let header = from_utf8(&bytes[..line_end]).map_err(|_| Error::InvalidObject)?;
+    let rest = &bytes[line_end + 1..];
+
+    let payload_len = match header.split(' ').collect::<Vec<_>>()[..] {
+        ["stage", _, len, ..] | ["blob-chunk", len] | ["commit", len] | ["object", _, len] => len.parse().map_err(|_| Error::InvalidObject)?,
+        _ => 0,
+    };
+
+    match rest.get(..payload_len) {
+        Some(payload) => Ok(Parsed::Event(header, payload, line_end + 1 + payload_len)),
+        None => Ok(Parsed::Torn),
+    }
+}
+
+impl Repository {
+    pub(crate) fn journaling(&self) -> bool {
+        self.journal.is_some()
+    }
+
+    /// Appends an event to the journal, if any
+    pub(crate) fn journal_write(&mut self, event: &[u8]) -> Result<()> {
+        match &mut self.journal {
+            Some(journal) => Ok(journal.file.write_all(event)?),
+            None => Ok(()),
+        }
+    }
+
+    /// Same as [`Self::journal_write`], for infallible operations
+    pub(crate) fn journal_or_log(&mut self, event: &[u8]) {
+        if let Err(error) = self.journal_write(event) {
+            log::error!("Couldn't write to the journal: {:?}", error);
+        }
+    }
+
+    /// Journals a new object, then inserts it
+    ///
+    /// Commits have their own event, see [`commit_event`].
+    pub(crate) fn insert_object(&mut self, obj_type: ObjectType, content: Vec<u8>) -> Result<Hash> {
+        if self.journaling() {
+            self.journal_write(&object_event(obj_type, &content))?;
+        }
+
+        self.objects.insert(obj_type, content, None)
+    }
+
+    /// Journals a change of path separators
+    pub(crate) fn journal_separators(&mut self, separators: PathSeparators) {
+        if self.journaling() {
+            let event = format!("separators {}\n", separators_name(separators));
+            self.journal_or_log(event.as_bytes());
+        }
+    }
+
+    /// Journals the current & upstream commits and the staged root
+    pub(crate) fn journal_head(&mut self) {
+        if self.journaling() {
+            let event = format!("head {} {} {}\n", self.head, self.upstream_head, self.root.unwrap_or(Hash::zero()));
+            self.journal_or_log(event.as_bytes());
+        }
+    }
+
+    /// `blob` holds the chunks of the last `blob-start` event
+    fn replay_event(&mut self, header: &str, payload: &[u8], blob: &mut Vec<u8>) -> Result<()> {
+        let invalid = || {
+            log::error!("Invalid journal event: {:?}", header);
+            Error::InvalidObject
+        };
+
+        let (kind, args) = header.split_once(' ').unwrap_or((header, ""));
+        let hash = |hex| Hash::from_hex(hex).ok_or_else(invalid);
+
+        // changes are journaled before they're applied, so the
+        // ones which failed fail again & are skipped
+        let replay_stage = |repo: &mut Self, path: &str, entry| match repo.stage_entry(path, entry) {
+            Err(error) => {
+                log::warn!("Skipping a journaled change of {} which failed: {:?}", path, error);
+                Ok(())
+            },
+            Ok(()) => Ok(()),
+        };
+
+        match kind {
+            "stage" => {
+                // the length was checked by `parse_event`
+                let mut parts = args.splitn(3, ' ');
+                let mode = parts.next().ok_or_else(invalid)?;
+                let path = parts.nth(1).ok_or_else(invalid)?;
+                let file_type = Mode::from_octal(mode).and_then(Mode::file_type).ok_or_else(invalid)?;
+                replay_stage(self, path, Some(NewEntry::Data(payload.to_vec(), file_type)))
+            },
+            "link" => {
+                let mut parts = args.splitn(3, ' ');
+                let mode = Mode::from_octal(parts.next().ok_or_else(invalid)?).ok_or_else(invalid)?;
+                let target = hash(parts.next().ok_or_else(invalid)?)?;
+                let path = parts.next().ok_or_else(invalid)?;
+                replay_stage(self, path, Some(NewEntry::Existing(target, mode)))
+            },
+            "remove" => replay_stage(self, args, None),
+            "blob-start" => {
+                blob.clear();
+                Ok(())
+            },
+            "blob-chunk" => {
+                blob.extend_from_slice(payload);
+                Ok(())
+            },
+            "blob-end" => {
+                let expected = hash(args)?;
+                let stored = self.staged.insert(ObjectType::Blob, core::mem::take(blob), None)?;
+                match stored == expected {
+                    true => Ok(()),
+                    false => Err(invalid()),
+                }
+            },
+            "object" => {
+                let (obj_type, _) = args.split_once(' ').ok_or_else(invalid)?;
+                let obj_type = match obj_type {
+                    "tree" => ObjectType::Tree,
+                    "blob" => ObjectType::Blob,
+                    "tag" => ObjectType::Tag,
+                    _ => return Err(invalid()),
+                };
+
+                self.objects.insert(obj_type, payload.to_vec(), None).map(drop)
+            },
+            "separators" => {
+                let separators = [PathSeparators::Slash, PathSeparators::SlashOrBackslash];
+                let separators = separators.into_iter().find(|s| separators_name(*s) == args);
+                self.path_separators = separators.ok_or_else(invalid)?;
+                Ok(())
+            },
+            "commit" => {
+                self.commit_staged()?;
+                self.head = self.objects.insert(ObjectType::Commit, payload.to_vec(), None)?;
+                Ok(())
+            },
+            "head" => {
+                let hashes = args.split(' ').map(hash).collect::<Result<Vec<_>>>()?;
+                let [head, upstream_head, root] = hashes[..] else { return Err(invalid()) };
+                self.head = head;
+                self.upstream_head = upstream_head;
+                self.root = Some(root).filter(|root| !root.is_zero());
+                Ok(())
+            },
+            "discard" => {
+                self.discard_changes();
+                Ok(())
+            },
+            "ref" => {
+                let (target, name) = args.split_once(' ').ok_or_else(invalid)?;
+                self.refs.insert(name.into(), hash(target)?);
+                Ok(())
+            },
+            "unref" => {
+                self.refs.remove(args);
+                Ok(())
+            },
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Persists changes to an append-only journal file
+    ///
+    /// If the file exists, its events are replayed first, then
+    /// staged writes, new commits & tags, reference updates, path
+    /// separators and movements of the current & upstream commits
+    /// are appended to it as they happen. This is cheaper than full snapshots when commits
+    /// are frequent:
+    ///
+    /// ```ignore
+    /// let mut repo = Repository::new();
+    /// repo.open_journal("repo.journal")?;
+    /// repo.stage("log.txt", Some((line, FileType::RegularFile)))?;
+    /// repo.commit("Append a line", author, author, None)?;
+    /// ```
+    ///
+    /// Objects received from remotes (clone, fetch) aren't journaled:
+    /// the journal must be replayed on a repository in the same state
+    /// as when it was opened for the first time (an empty repository
+    /// or the same clone). An incomplete last event (interrupted write)
+    /// is dropped. Returns the number of replayed events, `IoError` if
+    /// the file cannot be opened and `InvalidObject` if an event is
+    /// malformed.
+    pub fn open_journal<P: AsRef<FsPath>>(&mut self, path: P) -> Result<usize> {
+        let path = path.as_ref();
+        self.journal = None;
+
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut offset = 0;
+        let mut events = 0;
+        let mut blob = Vec::new();
+        while offset < bytes.len() {
+            match parse_event(&bytes[offset..])? {
+                Parsed::Event(header, payload, len) => {
+                    self.replay_event(header, payload, &mut blob)?;
+                    offset += len;
+                    events += 1;
+                },
+                Parsed::Torn => {
+                    log::warn!("Dropping an incomplete journal event at offset {}", offset);
+                    break;
+                },
+            }
+        }
+
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(path)?;
+        file.set_len(offset as u64)?;
+        drop(file);
+
+        let file = OpenOptions::new().append(true).open(path)?;
+        self.journal = Some(Journal { file });
+        Ok(events)
+    }
+
+    /// Stops appending changes to the journal
+    pub fn close_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// Flushes the journal to the storage device
+    pub fn sync_journal(&mut self) -> Result<()> {
+        match &mut self.journal {
+            Some(journal) => Ok(journal.file.sync_data()?),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::{Repository, FileType};
+
+    #[test]
+    fn replay_after_crash() {
+        let path = std::env::temp_dir().join(format!("rustgit-journal-{}.journal", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let signature = ("Test", "test@example.com");
+
+        let mut repo = Repository::new();
+        repo.open_journal(&path).unwrap();
+        repo.stage("a.txt", Some((b"a\n".to_vec(), FileType::RegularFile))).unwrap();
+        repo.commit("add a", signature, signature, Some(0)).unwrap();
+        let complete_len = fs::metadata(&path).unwrap().len();
+        repo.stage("b.txt", Some((b"b\n".to_vec(), FileType::RegularFile))).unwrap();
+        repo.close_journal();
+
+        // the last event was interrupted
+        let torn_len = fs::metadata(&path).unwrap().len() - 2;
+        fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(torn_len).unwrap();
+
+        let mut replayed = Repository::new();
+        replayed.open_journal(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete_len);
+        assert_eq!(replayed.head(), repo.head());
+        assert_eq!(replayed.read_file("a.txt").unwrap(), b"a\n");
+        assert!(replayed.read_file("b.txt").is_err());
+
+        // new events follow the last complete one
+        replayed.stage("c.txt", Some((b"c\n".to_vec(), FileType::RegularFile))).unwrap();
+        replayed.close_journal();
+
+        let mut reopened = Repository::new();
+        reopened.open_journal(&path).unwrap();
+        reopened.close_journal();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(reopened.head(), repo.head());
+        assert_eq!(reopened.read_file("c.txt").unwrap(), b"c\n");
+        assert!(reopened.read_file("b.txt").is_err());
+    }
+}
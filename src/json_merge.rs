@@ -0,0 +1,329 @@
+use core::fmt::Write;
+use lmfu::json::{JsonFile, Path, Value};
+
+use super::drivers::MergeDriver;
+
+/// Deeper documents are rejected, see [`scan`]
+const MAX_DEPTH: usize = 128;
+
+fn dump_string(dst: &mut String, string: &str) {
+    dst.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => dst.push_str("\\\""),
+            '\\' => dst.push_str("\\\\"),
+            '\n' => dst.push_str("\\n"),
+            '\t' => dst.push_str("\\t"),
+            '\r' => dst.push_str("\\r"),
+            c if (c as u32) < 0x20 => write!(dst, "\\u{:04x}", c as u32).unwrap(),
+            c => dst.push(c),
+        }
+    }
+
+    dst.push('"');
+}
+
+/// Side of a merge, if it has a value at the current path
+type Side<'a> = Option<&'a JsonFile>;
+
+fn member<'a>(side: Side<'a>, path: &Path, key: &str) -> Side<'a> {
+    let keys = side?.get(path).as_object()?;
+    keys.iter().any(|k| &**k == key).then_some(side?)
+}
+
+fn equal(a: &JsonFile, b: &JsonFile, path: &Path) -> bool {
+    match (a.get(path), b.get(path)) {
+        (Value::Array(len), Value::Array(other_len)) => {
+            len == other_len && (0..*len).all(|i| equal(a, b, &path.clone().i_num(i)))
+        },
+        (Value::Object(keys), Value::Object(other_keys)) => {
+            keys.len() == other_keys.len() && keys.iter().all(|key| {
+                other_keys.iter().any(|k| **k == **key) && equal(a, b, &path.clone().i_str(key))
+            })
+        },
+        (value, other) => value == other,
+    }
+}
+
+fn same(a: Side, b: Side, path: &Path) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => equal(a, b, path),
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+/// Copies a value; documents share paths, as arrays aren't merged
+fn copy(src: &JsonFile, dst: &mut JsonFile, path: &Path) {
+    match src.get(path) {
+        Value::Array(len) => {
+            dst.set_array(path);
+            for _ in 0..*len {
+                let item = dst.push(path.clone());
+                copy(src, dst, &item);
+            }
+        },
+        Value::Object(keys) => {
+            dst.set_object(path);
+            for key in keys.iter() {
+                let member = dst.prop(path.clone(), key);
+                copy(src, dst, &member);
+            }
+        },
+        Value::String(string) => dst.set_string(path, string.clone()),
+        Value::Number(number) => dst.set_number(path, *number),
+        Value::Boolean(boolean) => dst.set_boolean(path, *boolean),
+        Value::Null => dst.remove(path),
+    }
+}
+
+/// Writes the merged value to `output`; returns false if it is
+/// absent, `Err` if there is a conflict
+fn merge3(base: Side, ours: Side, theirs: Side, path: &Path, output: &mut JsonFile) -> Result<bool, ()> {
+    let mut pick = |side: Side| match side {
+        Some(side) => {
+            copy(side, output, path);
+            true
+        },
+        None => false,
+    };
+
+    if same(ours, theirs, path) || same(base, theirs, path) {
+        return Ok(pick(ours));
+    } else if same(base, ours, path) {
+        return Ok(pick(theirs));
+    }
+
+    let (Some(our_file), Some(their_file)) = (ours, theirs) else { return Err(()) };
+    let (Some(our_keys), Some(their_keys)) = (our_file.get(path).as_object(), their_file.get(path).as_object()) else {
+        return Err(());
+    };
+
+    // a key added on both sides is merged against an empty base
+    let base = base.filter(|base| base.get(path).as_object().is_some());
+
+    output.set_object(path);
+    let their_new = their_keys.iter().filter(|key| member(ours, path, key).is_none());
+
+    // deleted keys are found in `base` only
+    for key in our_keys.iter().chain(their_new) {
+        let child = path.clone().i_str(key);
+        let sides = (member(base, path, key), member(ours, path, key), member(theirs, path, key));
+        if merge3(sides.0, sides.1, sides.2, &child, output)? {
+            output.prop(path.clone(), key);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Checks the nesting depth of a document, as parsing it recurses;
+/// returns its boolean literals in order, and its number of
+/// object members
+fn scan(src: &str) -> Option<(Vec<bool>, usize)> {
+    let mut booleans = Vec::new();
+    let (mut members, mut depth) = (0, 0);
+    let (mut in_string, mut escaped) = (false, false);
+
+    for (i, byte) in src.bytes().enumerate() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => (),
+            }
+
+            continue;
+        }
+
+        match byte {
+            b'"' => in_string = true,
+            b':' => members += 1,
+            b'[' | b'{' if depth == MAX_DEPTH => return None,
+            b'[' | b'{' => depth += 1,
+            b']' | b'}' => depth -= 1,
+            b't' if src[i..].starts_with("true") => booleans.push(true),
+            b'f' if src[i..].starts_with("false") => booleans.push(false),
+            _ => (),
+        }
+    }
+
+    Some((booleans, members))
+}
+
+/// Sets booleans in document order; returns the number of object members
+fn restore_booleans(file: &mut JsonFile, path: &Path, booleans: &mut impl Iterator<Item = bool>) -> Option<usize> {
+    let mut members = 0;
+    match file.get(path).clone() {
+        Value::Boolean(_) => file.set_boolean(path, booleans.next()?),
+        Value::Array(len) => for i in 0..len {
+            members += restore_booleans(file, &path.clone().i_num(i), booleans)?;
+        },
+        Value::Object(keys) => for key in keys.iter() {
+            members += 1 + restore_booleans(file, &path.clone().i_str(key), booleans)?;
+        },
+        _ => (),
+    }
+
+    Some(members)
+}
+
+fn parse(src: &[u8]) -> Option<JsonFile> {
+    let src = core::str::from_utf8(src).ok()?;
+    let (booleans, members) = scan(src)?;
+    let mut file = JsonFile::new(Some(src)).ok()?;
+
+    // lmfu parses `false` as `true`; duplicate keys would
+    // misalign the literals, so they are rejected
+    let mut booleans = booleans.into_iter();
+    let restored = restore_booleans(&mut file, &Path::new(), &mut booleans)?;
+    (restored == members && booleans.next().is_none()).then_some(file)
+}
+
+/// Writes the value at `path`; `indent` is `None` for compact output
+pub(crate) fn write_value(dst: &mut String, file: &JsonFile, path: &Path, indent: Option<&str>, depth: usize) {
+    let newline = |dst: &mut String, depth| if let Some(indent) = indent {
+        dst.push('\n');
+        for _ in 0..depth {
+            dst.push_str(indent);
+        }
+    };
+
+    let separator = match indent {
+        Some(_) => ": ",
+        None => ":",
+    };
+
+    match file.get(path) {
+        Value::Null => dst.push_str("null"),
+        Value::Boolean(b) => write!(dst, "{}", b).unwrap(),
+        Value::Number(n) if n.is_finite() => write!(dst, "{}", n).unwrap(),
+        Value::Number(_) => dst.push_str("null"),
+        Value::String(s) => dump_string(dst, s),
+        Value::Array(0) => dst.push_str("[]"),
+        Value::Object(keys) if keys.is_empty() => dst.push_str("{}"),
+        Value::Array(len) => {
+            dst.push('[');
+            for i in 0..*len {
+                if i > 0 {
+                    dst.push(',');
+                }
+
+                newline(dst, depth + 1);
+                write_value(dst, file, &path.clone().i_num(i), indent, depth + 1);
+            }
+
+            newline(dst, depth);
+            dst.push(']');
+        },
+        Value::Object(keys) => {
+            dst.push('{');
+            for (i, key) in keys.iter().enumerate() {
+                if i > 0 {
+                    dst.push(',');
+                }
+
+                newline(dst, depth + 1);
+                dump_string(dst, key);
+                dst.push_str(separator);
+                write_value(dst, file, &path.clone().i_str(key), indent, depth + 1);
+            }
+
+            newline(dst, depth);
+            dst.push('}');
+        },
+    }
+}
+
+/// Three-way merge of JSON documents
+///
+/// Object keys are merged recursively; this only fails if a value
+/// was changed differently on both sides, or if a document isn't
+/// valid JSON. The output uses the indentation of `ours`.
+pub fn json_merge(base: &[u8], ours: &[u8], theirs: &[u8]) -> Option<Vec<u8>> {
+    let (base, our_file, theirs) = (parse(base)?, parse(ours)?, parse(theirs)?);
+    let mut merged = JsonFile::new(None).ok()?;
+    let root = Path::new();
+    if !merge3(Some(&base), Some(&our_file), Some(&theirs), &root, &mut merged).ok()? {
+        return None;
+    }
+
+    // reuse the whitespace of the first indented line
+    let ours = core::str::from_utf8(ours).ok()?;
+    let indent = ours.split('\n').nth(1).map(|line| {
+        let len = line.len() - line.trim_start_matches([' ', '\t']).len();
+        &line[..len]
+    });
+
+    let mut output = String::new();
+    write_value(&mut output, &merged, &root, indent.filter(|i| !i.is_empty()), 0);
+
+    if ours.ends_with('\n') {
+        output.push('\n');
+    }
+
+    Some(output.into_bytes())
+}
+
+impl MergeDriver {
+    /// Structural merge driver for JSON files, see [`json_merge`]
+    ///
+    /// ```ignore
+    /// repo.set_merge_driver("*.json", MergeDriver::json());
+    /// ```
+    pub fn json() -> Self {
+        Self::Custom(Box::new(json_merge))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::json_merge;
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> Option<String> {
+        let merged = json_merge(base.as_bytes(), ours.as_bytes(), theirs.as_bytes())?;
+        Some(String::from_utf8(merged).unwrap())
+    }
+
+    #[test]
+    fn conflicting_keys() {
+        assert_eq!(merge(r#"{"a":1}"#, r#"{"a":2}"#, r#"{"a":3}"#), None);
+        assert_eq!(merge(r#"{}"#, r#"{"a":2}"#, r#"{"a":3}"#), None);
+        assert_eq!(merge(r#"{"a":1}"#, r#"{}"#, r#"{"a":3}"#), None);
+
+        // same change on both sides
+        assert_eq!(merge(r#"{"a":1}"#, r#"{"a":2,"b":false}"#, r#"{"a":2,"b":false}"#).unwrap(), r#"{"a":2,"b":false}"#);
+        assert_eq!(merge(r#"{"a":1,"b":1}"#, r#"{"a":2,"b":1}"#, r#"{"a":1}"#).unwrap(), r#"{"a":2}"#);
+    }
+
+    #[test]
+    fn nested_objects() {
+        let base = r#"{"o":{"x":1,"y":1,"gone":null},"n":"s"}"#;
+        let ours = r#"{"o":{"x":2,"y":1,"gone":null},"n":"s"}"#;
+        let theirs = r#"{"o":{"x":1,"y":false,"z":{"deep":true}},"n":"s"}"#;
+        let merged = merge(base, ours, theirs).unwrap();
+        assert_eq!(merged, r#"{"o":{"x":2,"y":false,"z":{"deep":true}},"n":"s"}"#);
+
+        let theirs = r#"{"o":{"x":3,"y":1,"gone":null},"n":"s"}"#;
+        assert_eq!(merge(base, ours, theirs), None);
+    }
+
+    #[test]
+    fn arrays() {
+        let base = r#"{"l":[1,2],"m":[{"k":true}]}"#;
+        let ours = r#"{"l":[1,2,3],"m":[{"k":true}]}"#;
+        let theirs = r#"{"l":[1,2],"m":[{"k":false},{"k":true}]}"#;
+        assert_eq!(merge(base, ours, theirs).unwrap(), r#"{"l":[1,2,3],"m":[{"k":false},{"k":true}]}"#);
+
+        // arrays are replaced as a whole, not merged
+        let theirs = r#"{"l":[0,1,2],"m":[{"k":true}]}"#;
+        assert_eq!(merge(base, ours, theirs), None);
+    }
+
+    #[test]
+    fn keeps_our_indentation() {
+        let ours = "{\n  \"a\": 2,\n  \"l\": [\n    1\n  ]\n}\n";
+        let merged = merge(r#"{"a":1,"l":[1]}"#, ours, r#"{"a":1,"l":[1],"b":true}"#).unwrap();
+        assert_eq!(merged, "{\n  \"a\": 2,\n  \"l\": [\n    1\n  ],\n  \"b\": true\n}\n");
+    }
+}
@@ -1,7 +1,8 @@
 #![doc = include_str!("../README.md")]
 
-use std::{net::TcpStream, io::Write};
+use std::{net::{TcpStream, ToSocketAddrs}, io::{self, Write}, time::Duration};
 use lmfu::{json::{JsonFile, Path as JsonPath}, ArcStr};
+use coolssh::Connection;
 pub use coolssh::{create_ed25519_keypair, dump_ed25519_pk_openssh, Error as SshError};
 
 mod objectstore;
@@ -11,32 +12,202 @@ mod protocol;
 mod packfile;
 mod clone;
 mod push;
+mod doctor;
+mod synthetic;
+mod replace;
+mod tag;
+mod commit;
+mod bitmap;
+mod fetch;
+mod refs;
+mod walk;
+mod diff;
+mod watch;
+mod disk;
+mod revwalk;
+mod identity;
+mod stage_writer;
+mod state;
+mod graph;
+mod policy;
+mod export;
+mod resolve;
+mod content;
+mod apply;
+mod workspace;
+mod format_patch;
+mod cherry_pick;
+mod tracking;
+mod dedup;
+mod rebase;
+mod reflog;
+mod journal;
+mod stash;
+mod gc;
+mod drivers;
+mod json_merge;
+mod backend;
+mod loose;
+mod ref_cache;
+mod diffstat;
+mod extract;
+mod snapshot;
+mod pack_index;
+mod compression;
+mod sideband;
+
+#[cfg(feature = "python")]
+mod python;
+
+#[cfg(feature = "ssh-signing")]
+mod sshsig;
+
+#[cfg(feature = "ssh-signing")]
+pub use sshsig::{ssh_sign, ssh_verify};
+
+#[cfg(feature = "zstd")]
+pub use compression::Zstd;
 
 pub use {
-    repository::Repository, directory::{Mode, EntryType, FileType},
-    clone::Reference, objectstore::Hash,
+    repository::Repository, directory::{Mode, EntryType, FileType, PathSeparators},
+    clone::{Reference, CloneOptions, ls_remote}, objectstore::Hash,
+    ref_cache::{RefCache, ls_remote_if_changed},
+    doctor::{Diagnosis, Check, FailureCause}, push::{PushOptions, PushReport, RefStatus},
+    drivers::{DiffDriver, MergeDriver, TextConv, MergeFn},
+    commit::{Commit, Signature, CommitBuilder}, fetch::FetchRequest, protocol::FetchResponse, walk::Walk, diff::{TreeChange, DiffOptions, unified_diff, unified_diff_with, DEFAULT_CONTEXT, DEFAULT_BINARY_THRESHOLD}, watch::Watch,
+    revwalk::RevWalk, identity::{IdentityPolicy, IdentityCheck},
+    stage_writer::StageWriter, state::StateDiff, refs::RefName,
+    policy::{CommitPolicy, MessageCheck, MergeInfo, MergeTemplate, UpstreamCheck}, workspace::Workspace,
+    cherry_pick::CherryPick, tracking::{RefUpdate, UpstreamStatus},
+    dedup::{DedupReport, BranchUsage}, rebase::Rebase,
+    reflog::ReflogEntry, gc::GcReport, diffstat::{DiffStat, FileStat}, extract::ExtractMode,
+    packfile::PackOptions, sideband::{RemoteMessage, MessageHandler, ProgressHandler}, compression::{Codec, Miniz, ZLIB_CODEC_ID, ZSTD_CODEC_ID},
 };
 
+/// Types needed for everyday tasks
+///
+/// ```ignore
+/// use rustgit::prelude::*;
+/// ```
+pub mod prelude {
+    pub use super::{
+        Workspace, Repository, Remote, ConnectOptions, Reference, CloneOptions, Error, Hash,
+        FileType, Mode, EntryType, Commit, Signature,
+        PushOptions, PushReport, RefStatus, ls_remote,
+    };
+}
+
 /// object store, directories, packfiles, git protocol
 pub mod internals {
     pub(crate) use super::{
-        TcpStream, Write, Remote, Result, Error, Repository,
-        EntryType, FileType, Mode, Hash,
+        Write, Remote, Result, Error, Repository,
+        EntryType, FileType, Mode, PathSeparators, Hash, Commit, Signature,
     };
     pub use {
         super::objectstore::{
-            ObjectStore, Object, ObjectType, TreeIter, CommitParentsIter,
-            CommitField, get_commit_field, get_commit_field_hash,
+            ObjectStore, Object, Content, ObjectType, Sharding, StoreStats, TypeStats, TreeIter, CommitParentsIter,
+            CommitField, get_commit_field, get_commit_field_hash, split_commit_signature,
         },
+        super::backend::{ObjectBackend, DiskBackend, PendingBlob},
+        super::loose::{loose_path, encode_loose, decode_loose},
+        super::ref_cache::advertisement_etag,
         super::directory::{Directory, Path},
-        super::protocol::{PacketLine, GitProtocol},
+        super::protocol::{PacketLine, GitProtocol, ReceivePackSession, encode_lines, decode_line},
+        super::synthetic::SyntheticParams,
+        super::tag::split_tag_signature,
+        super::drivers::glob_match,
+        super::json_merge::json_merge,
+        super::content::content_type,
+        super::refs::case_collisions,
+        super::fetch::read_fetch_features,
+        super::commit::parse_timezone,
         super::packfile::{
             PackfileReader, PackfileObject, PackfileSender,
-            dump_packfile_header, dump_packfile_object,
+            PackfileEntry, ObjectEncoding, DeltaBase,
+            PackSummary, verify_packfile,
+            dump_packfile_header, dump_packfile_object, dump_packfile_object_with,
         },
+        super::pack_index::PackIndex,
     };
 }
 
+/// Settings of the connections to a [`Remote`]
+///
+/// ```ignore
+/// let remote = remote.with_connect_options(ConnectOptions {
+///     connect_timeout: Some(Duration::from_secs(10)),
+///     idle_timeout: Some(Duration::from_secs(60)),
+///     ..Default::default()
+/// });
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ConnectOptions {
+    /// Time allowed to establish the TCP connection; defaults
+    /// to none, leaving it to the OS
+    pub connect_timeout: Option<Duration>,
+    /// Interval at which the connection is polled while waiting
+    /// for data; defaults to one second. Must not be zero.
+    ///
+    /// This isn't a deadline, see [`Self::idle_timeout`].
+    pub read_timeout: Option<Duration>,
+    /// Time allowed to send data; defaults to none.
+    /// Must not be zero.
+    pub write_timeout: Option<Duration>,
+    /// Time after which an operation fails with `IoError(TimedOut)`
+    /// if nothing was sent or received; it also bounds each read of
+    /// the SSH handshake. Defaults to none. Must not be zero.
+    pub idle_timeout: Option<Duration>,
+    /// Idle time before TCP keepalive probes are sent; defaults
+    /// to none. Requires the `keepalive` feature.
+    pub keepalive: Option<Duration>,
+    /// Disables Nagle's algorithm (`TCP_NODELAY`); defaults to false
+    pub nodelay: bool,
+}
+
+impl Default for ConnectOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: None,
+            read_timeout: Some(Duration::from_millis(1000)),
+            write_timeout: None,
+            idle_timeout: None,
+            keepalive: None,
+            nodelay: false,
+        }
+    }
+}
+
+/// Tries each address of `host` until one accepts the connection
+fn connect_timeout(host: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut error = io::Error::new(io::ErrorKind::NotFound, "host has no address");
+    for address in host.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = e,
+        }
+    }
+
+    Err(error)
+}
+
+#[cfg(feature = "keepalive")]
+fn set_keepalive(stream: &TcpStream, idle: Option<Duration>) -> io::Result<()> {
+    let socket = socket2::SockRef::from(stream);
+    match idle {
+        Some(idle) => socket.set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(idle)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(feature = "keepalive"))]
+fn set_keepalive(_stream: &TcpStream, idle: Option<Duration>) -> io::Result<()> {
+    if idle.is_some() {
+        log::warn!("TCP keepalive requires the `keepalive` feature");
+    }
+
+    Ok(())
+}
+
 /// SSH & Remote Repository Settings
 #[derive(Debug)]
 pub struct Remote {
@@ -48,6 +219,8 @@ pub struct Remote {
     pub path: ArcStr,
     /// Must be registered at the remote
     pub keypair: ArcStr,
+    /// Timeouts & socket options
+    pub connect_options: ConnectOptions,
 }
 
 impl Remote {
@@ -62,9 +235,16 @@ impl Remote {
             username,
             path,
             keypair,
+            connect_options: ConnectOptions::default(),
         }
     }
 
+    /// Sets timeouts & socket options, see [`ConnectOptions`]
+    pub fn with_connect_options(mut self, options: ConnectOptions) -> Self {
+        self.connect_options = options;
+        self
+    }
+
     /// Reads remote access configuration from a [`JsonFile`]
     ///
     /// At `path`, the json file is expected to contain an
@@ -85,8 +265,42 @@ impl Remote {
             username,
             path,
             keypair,
+            connect_options: ConnectOptions::default(),
         })
     }
+
+    /// Returns `IoError` if the host cannot be reached
+    pub(crate) fn connect(&self) -> Result<Connection> {
+        let stream = match self.connect_options.connect_timeout {
+            Some(timeout) => connect_timeout(&self.host, timeout)?,
+            None => TcpStream::connect(&*self.host)?,
+        };
+
+        self.connect_with(stream)
+    }
+
+    /// Returns `IoError(InvalidInput)` if a timeout is zero
+    pub(crate) fn connect_with(&self, stream: TcpStream) -> Result<Connection> {
+        let options = self.connect_options;
+        let timeouts = [options.read_timeout, options.write_timeout, options.idle_timeout];
+        if timeouts.contains(&Some(Duration::ZERO)) {
+            log::error!("Connection timeouts must not be zero");
+            return Err(Error::IoError(io::ErrorKind::InvalidInput));
+        }
+
+        stream.set_nodelay(options.nodelay)?;
+        set_keepalive(&stream, options.keepalive)?;
+        stream.set_write_timeout(options.write_timeout)?;
+        stream.set_read_timeout(options.idle_timeout)?;
+
+        // the handshake must not be cut short by the poll interval
+        let handle = stream.try_clone()?;
+        let auth = (&*self.username, &*self.keypair).into();
+        let conn = Connection::new(stream, auth)?;
+        handle.set_read_timeout(options.read_timeout)?;
+
+        Ok(conn)
+    }
 }
 
 /// Errors that can occur during repository manipulation
@@ -102,6 +316,23 @@ pub enum Error {
     InvalidPackfile,
     MustForcePush,
     UnsupportedByRemote,
+    AmbiguousPrefix,
+    InvalidKeypair,
+    /// The remote doesn't allow fetching this commit by hash
+    CommitNotFetchable,
+    IoError(std::io::ErrorKind),
+    /// A reference name doesn't follow `git check-ref-format` rules
+    InvalidRefName,
+    /// Several references only differ by case
+    AmbiguousReference,
+    /// A patch cannot be parsed
+    InvalidPatch,
+    /// A hunk of a patch doesn't match the file it modifies
+    PatchMismatch,
+    /// The current commit is behind its remote-tracking branch
+    OutdatedBase,
+    /// The remote server aborted with an error message
+    RemoteError,
 }
 
 impl From<SshError> for Error {
@@ -0,0 +1,134 @@
+use std::{fs, io};
+use std::path::{Path as FsPath, PathBuf};
+
+use super::internals::{Result, Error, Hash, ObjectStore, ObjectType};
+use miniz_oxide::inflate::decompress_to_vec_zlib_with_limit;
+
+use super::compression::Codec;
+
+/// zlib level used by git (`core.looseCompression` defaults to 1)
+const LOOSE_COMPRESSION: u8 = 1;
+
+/// `<type> <size>\0` fits in these bytes
+const MAX_HEADER_LEN: usize = 32;
+
+/// Path of a loose object: `<objects_dir>/xx/yyyy...`
+pub fn loose_path<P: AsRef<FsPath>>(objects_dir: P, hash: Hash) -> PathBuf {
+    let hex = hash.to_string();
+    objects_dir.as_ref().join(&hex[..2]).join(&hex[2..])
+}
+
+/// Serializes an object as a loose object: a zlib stream
+/// of `<type> <size>\0<content>`, compressed with `codec`
+pub fn encode_loose(obj_type: ObjectType, content: &[u8], codec: &dyn Codec) -> Vec<u8> {
+    let mut raw = format!("{} {}\0", obj_type, content.len()).into_bytes();
+    raw.extend_from_slice(content);
+    codec.compress(&raw, LOOSE_COMPRESSION)
+}
+
+/// Inflates the start of a loose object; returns the declared size
+/// of the whole inflated object
+fn loose_size(data: &[u8]) -> Result<usize> {
+    // a partial inflate is reported as an error, along with the output
+    let start = match decompress_to_vec_zlib_with_limit(data, MAX_HEADER_LEN) {
+        Ok(raw) => raw,
+        Err(error) => error.output,
+    };
+
+    let invalid = || {
+        log::error!("Invalid loose object header");
+        Error::InvalidObject
+    };
+
+    let header_len = start.iter().position(|byte| *byte == 0).ok_or_else(invalid)?;
+    let header = core::str::from_utf8(&start[..header_len]).map_err(|_| invalid())?;
+    let (_, size) = header.split_once(' ').ok_or_else(invalid)?;
+    let size = size.parse::<usize>().map_err(|_| invalid())?;
+    (header_len + 1).checked_add(size).ok_or_else(invalid)
+}
+
+/// Parses a loose object, see [`encode_loose`]
+///
+/// Returns `InvalidObject` if the data cannot be inflated, if the
+/// type is unknown or if the size doesn't match the content. The
+/// declared size bounds the inflated size.
+pub fn decode_loose(data: &[u8], codec: &dyn Codec) -> Result<(ObjectType, Vec<u8>)> {
+    let mut raw = codec.decompress(data, loose_size(data)?).inspect_err(|_| {
+        log::error!("Couldn't inflate loose object");
+    })?;
+
+    let header_len = raw.iter().position(|byte| *byte == 0).ok_or(Error::InvalidObject)?;
+    let header = core::str::from_utf8(&raw[..header_len]).map_err(|_| Error::InvalidObject)?;
+    let (obj_type, size) = header.split_once(' ').ok_or(Error::InvalidObject)?;
+
+    let obj_type = match obj_type {
+        "commit" => ObjectType::Commit,
+        "tree" => ObjectType::Tree,
+        "blob" => ObjectType::Blob,
+        "tag" => ObjectType::Tag,
+        _ => {
+            log::error!("Unknown loose object type: {:?}", obj_type);
+            return Err(Error::InvalidObject);
+        },
+    };
+
+    let size = size.parse::<usize>().map_err(|_| Error::InvalidObject)?;
+    if raw.len() - header_len - 1 != size {
+        log::error!("Loose object size mismatch: {} != {}", raw.len() - header_len - 1, size);
+        return Err(Error::InvalidObject);
+    }
+
+    raw.drain(..header_len + 1);
+    Ok((obj_type, raw))
+}
+
+impl ObjectStore {
+    /// Writes an object as a loose object, readable by git
+    ///
+    /// `objects_dir` is usually `.git/objects`. Like git, existing
+    /// files are kept, and new ones are written to a temporary file
+    /// which is then renamed.
+    ///
+    /// Returns the path of the loose object, `MissingObject`
+    /// if it isn't in the store, or `IoError`.
+    pub fn write_loose<P: AsRef<FsPath>>(&self, objects_dir: P, hash: Hash, codec: &dyn Codec) -> Result<PathBuf> {
+        let object = self.get(hash).ok_or(Error::MissingObject)?;
+        let path = loose_path(objects_dir, hash);
+        if path.exists() {
+            return Ok(path);
+        }
+
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir)?;
+
+        let tmp_path = dir.join(format!("tmp_obj_{}", hash));
+        fs::write(&tmp_path, encode_loose(object.obj_type(), object.content(), codec))?;
+        if let Err(error) = fs::rename(&tmp_path, &path) {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(error.into());
+        }
+
+        Ok(path)
+    }
+
+    /// Reads a loose object into the store
+    ///
+    /// Returns `MissingObject` if there's no such loose object,
+    /// `InvalidObject` if it cannot be parsed or if its content
+    /// doesn't match `hash`, or `IoError`.
+    pub fn read_loose<P: AsRef<FsPath>>(&mut self, objects_dir: P, hash: Hash, codec: &dyn Codec) -> Result<Hash> {
+        let data = match fs::read(loose_path(objects_dir, hash)) {
+            Ok(data) => data,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Err(Error::MissingObject),
+            Err(error) => return Err(error.into()),
+        };
+
+        let (obj_type, content) = decode_loose(&data, codec)?;
+        if self.hash(obj_type, &content)? != hash {
+            log::error!("Loose object {} is corrupted", hash);
+            return Err(Error::InvalidObject);
+        }
+
+        self.insert(obj_type, content, None)
+    }
+}
@@ -1,8 +1,47 @@
-use core::{fmt, array::from_fn, str::from_utf8};
+use core::{fmt, array::from_fn, str::from_utf8, mem::size_of};
+use std::{sync::Arc, collections::HashMap};
 use lmfu::LiteMap;
-use sha1::{Sha1, Digest};
+use sha1::Digest;
 
-use super::internals::{Result, Error, Directory, Write, Mode};
+use super::internals::{Result, Error, Directory, Write, Mode, Commit, ObjectBackend, PendingBlob};
+use super::backend::BLOB_RESERVE_LIMIT;
+
+/// SHA-1 implementation used for objects and packfiles
+#[cfg(not(feature = "sha1dc"))]
+pub(crate) use sha1::Sha1 as Hasher;
+
+/// SHA-1 implementation used for objects and packfiles
+///
+/// This one detects known collision attacks, like git does.
+#[cfg(feature = "sha1dc")]
+pub(crate) use sha1_checked::Sha1 as Hasher;
+
+#[cfg(not(feature = "sha1dc"))]
+pub(crate) fn finalize(hasher: Hasher) -> Result<[u8; 20]> {
+    Ok(hasher.finalize().into())
+}
+
+/// Returns `InvalidObject` if the hashed bytes carry a known
+/// collision attack
+#[cfg(feature = "sha1dc")]
+pub(crate) fn finalize(hasher: Hasher) -> Result<[u8; 20]> {
+    let result = hasher.try_finalize();
+
+    if result.has_collision() {
+        log::error!("SHA-1 collision attack detected");
+        return Err(Error::InvalidObject);
+    }
+
+    Ok((*result.hash()).into())
+}
+
+/// Hash of an object, as computed by [`ObjectStore::hash`]
+pub(crate) fn object_hash(obj_type: ObjectType, content: &[u8]) -> Result<Hash> {
+    let mut hasher = Hasher::new();
+    write!(&mut hasher, "{} {}\0", obj_type, content.len()).unwrap();
+    hasher.update(content);
+    finalize(hasher).map(Hash::new)
+}
 
 /// The key to a git object
 ///
@@ -55,10 +94,6 @@ impl Hash {
         }
     }
 
-    fn first_byte(&self) -> usize {
-        self.0[0].to_ne_bytes()[0] as _
-    }
-
     pub fn to_bytes(&self) -> [u8; 20] {
         let mut array = [0; 20];
 
@@ -103,13 +138,36 @@ impl fmt::Display for ObjectType {
     }
 }
 
+/// Content of an object, shared by clones of the object
+///
+/// It's the buffer the object was created with: moving a `Vec`
+/// in doesn't copy it.
+pub type Content = Arc<Vec<u8>>;
+
+#[derive(Clone)]
 pub struct Object {
     obj_type: ObjectType,
-    content: Box<[u8]>,
+    content: Content,
     delta_hint: Hash,
+    compressed: Option<Arc<[u8]>>,
 }
 
 impl Object {
+    pub fn new<C: Into<Vec<u8>>>(obj_type: ObjectType, content: C, delta_hint: Option<Hash>) -> Self {
+        Self {
+            obj_type,
+            content: Arc::new(content.into()),
+            delta_hint: delta_hint.unwrap_or(Hash::zero()),
+            compressed: None,
+        }
+    }
+
+    /// `compressed` must be a zlib stream of the content
+    pub(crate) fn with_compressed<C: Into<Arc<[u8]>>>(mut self, compressed: C) -> Self {
+        self.compressed = Some(compressed.into());
+        self
+    }
+
     pub fn obj_type(&self) -> ObjectType {
         self.obj_type
     }
@@ -118,22 +176,190 @@ impl Object {
         &*self.content
     }
 
+    /// Shared handle on the content, which outlives borrows of the store
+    pub fn content_arc(&self) -> Content {
+        self.content.clone()
+    }
+
+    pub(crate) fn with_delta_hint(mut self, base: Option<Hash>) -> Self {
+        self.delta_hint = base.unwrap_or(Hash::zero());
+        self
+    }
+
+    /// Suggested delta base, see [`ObjectStore::set_delta_hint`]
     pub fn delta_hint(&self) -> Option<Hash> {
         match self.delta_hint.is_zero() {
             true => None,
             false => Some(self.delta_hint),
         }
     }
+
+    /// zlib stream of the content, as received in a packfile
+    ///
+    /// It's copied as is to new packfiles instead of compressing
+    /// the content again; see [`Repository::set_pack_reuse`].
+    ///
+    /// [`Repository::set_pack_reuse`]: crate::Repository::set_pack_reuse
+    pub fn compressed(&self) -> Option<&[u8]> {
+        self.compressed.as_deref()
+    }
+}
+
+/// Number & size of objects of a type, see [`StoreStats`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TypeStats {
+    pub count: usize,
+    /// Total size of the contents
+    pub bytes: usize,
+}
+
+/// Contents of an [`ObjectStore`], see [`ObjectStore::stats`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    pub commits: TypeStats,
+    pub trees: TypeStats,
+    pub blobs: TypeStats,
+    pub tags: TypeStats,
+    /// Estimated memory used by the store besides contents
+    pub overhead_bytes: usize,
 }
 
-pub struct ObjectStore([LiteMap<Hash, Object>; 256]);
+impl StoreStats {
+    /// Number of objects of all types
+    pub fn objects(&self) -> usize {
+        self.commits.count + self.trees.count + self.blobs.count + self.tags.count
+    }
+
+    /// Contents & overhead, in bytes
+    pub fn total_bytes(&self) -> usize {
+        let contents = self.commits.bytes + self.trees.bytes + self.blobs.bytes + self.tags.bytes;
+        contents + self.overhead_bytes
+    }
+}
+
+/// How an [`ObjectStore`] spreads objects over internal maps
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Sharding {
+    /// One sorted map, for tiny repositories
+    Single,
+    /// 256 sorted maps, by first byte of the hash (default)
+    Buckets256,
+    /// 4096 sorted maps, by first 12 bits of the hash
+    Buckets4096,
+    /// One hash map, for millions of objects
+    HashMap,
+    /// A custom [`ObjectBackend`], see [`ObjectStore::with_backend`]
+    ///
+    /// With [`ObjectStore::with_sharding`], this wraps a default store.
+    Backend,
+}
+
+enum Shards {
+    /// Sorted buckets, selected by the top `bits` of hashes
+    Sorted { buckets: Vec<LiteMap<Hash, Object>>, bits: u32 },
+    Hashed(HashMap<Hash, Object>),
+    Backend(Box<dyn ObjectBackend>),
+}
+
+impl Clone for Shards {
+    fn clone(&self) -> Self {
+        match self {
+            Shards::Sorted { buckets, bits } => Shards::Sorted { buckets: buckets.clone(), bits: *bits },
+            Shards::Hashed(map) => Shards::Hashed(map.clone()),
+            Shards::Backend(backend) => Shards::Backend(backend.duplicate()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ObjectStore(Shards);
+
+impl Hash {
+    /// Top `bits` bits of the hash (at most 32)
+    fn top_bits(&self, bits: u32) -> usize {
+        let bytes = self.to_bytes();
+        let top = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        top.checked_shr(32 - bits).unwrap_or(0) as usize
+    }
+}
 
 impl ObjectStore {
+    /// Creates a store with [`Sharding::Buckets256`]
     pub fn new() -> Self {
-        Self(from_fn(|_| LiteMap::new()))
+        Self::with_sharding(Sharding::Buckets256)
     }
 
-    pub fn serialize_directory(&mut self, dir: &Directory, delta_hint: Option<Hash>) -> Hash {
+    pub fn with_sharding(sharding: Sharding) -> Self {
+        let sorted = |bits| Shards::Sorted {
+            buckets: (0..1usize << bits).map(|_| LiteMap::new()).collect(),
+            bits,
+        };
+
+        Self(match sharding {
+            Sharding::Single => sorted(0),
+            Sharding::Buckets256 => sorted(8),
+            Sharding::Buckets4096 => sorted(12),
+            Sharding::HashMap => Shards::Hashed(HashMap::new()),
+            Sharding::Backend => Shards::Backend(Box::new(Self::new())),
+        })
+    }
+
+    /// Creates a store which keeps its objects in `backend`
+    ///
+    /// ```ignore
+    /// let backend = DiskBackend::open("objects.bin")?;
+    /// let repo = Repository::with_object_store(ObjectStore::with_backend(Box::new(backend)));
+    /// ```
+    pub fn with_backend(backend: Box<dyn ObjectBackend>) -> Self {
+        Self(Shards::Backend(backend))
+    }
+
+    /// Creates a store suited to about `objects` objects
+    pub fn with_capacity_hint(objects: usize) -> Self {
+        Self::with_sharding(match objects {
+            0..=999 => Sharding::Single,
+            1_000..=99_999 => Sharding::Buckets256,
+            100_000..=999_999 => Sharding::Buckets4096,
+            _ => Sharding::HashMap,
+        })
+    }
+
+    pub fn sharding(&self) -> Sharding {
+        match &self.0 {
+            Shards::Sorted { bits: 0, .. } => Sharding::Single,
+            Shards::Sorted { bits: 8, .. } => Sharding::Buckets256,
+            Shards::Sorted { .. } => Sharding::Buckets4096,
+            Shards::Hashed(_) => Sharding::HashMap,
+            Shards::Backend(_) => Sharding::Backend,
+        }
+    }
+
+    /// Number of objects in the store
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            Shards::Sorted { buckets, .. } => buckets.iter().map(|bucket| bucket.len()).sum(),
+            Shards::Hashed(map) => map.len(),
+            Shards::Backend(backend) => backend.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All hashes which may share their first `nibbles` hex digits
+    /// with `hash`
+    pub(crate) fn neighbours(&self, hash: Hash, nibbles: usize) -> Box<dyn Iterator<Item = Hash> + '_> {
+        match &self.0 {
+            Shards::Sorted { buckets, bits } if nibbles * 4 >= *bits as usize => {
+                Box::new(buckets[hash.top_bits(*bits)].iter_keys().copied())
+            },
+            Shards::Backend(backend) => backend.neighbours(hash, nibbles),
+            _ => Box::new(self.hashes()),
+        }
+    }
+
+    pub fn serialize_directory(&mut self, dir: &Directory, delta_hint: Option<Hash>) -> Result<Hash> {
         let mut serialized = Vec::new();
 
         for (node, (hash, mode)) in dir.iter() {
@@ -145,42 +371,122 @@ impl ObjectStore {
             }
         }
 
-        self.insert(ObjectType::Tree, serialized.into_boxed_slice(), delta_hint)
+        self.insert(ObjectType::Tree, serialized, delta_hint)
+    }
+
+    /// With the `sha1dc` feature, returns `InvalidObject` if
+    /// `content` carries a known SHA-1 collision attack
+    pub fn hash(&self, obj_type: ObjectType, content: &[u8]) -> Result<Hash> {
+        object_hash(obj_type, content)
+    }
+
+    /// Returns `IoError` if the backend couldn't store the object,
+    /// see [`ObjectBackend::insert`]
+    pub fn insert_entry(&mut self, entry: Object) -> Result<Hash> {
+        let hash = self.hash(entry.obj_type, &entry.content)?;
+        self.insert_hashed(hash, entry)?;
+        Ok(hash)
+    }
+
+    pub(crate) fn insert_hashed(&mut self, hash: Hash, entry: Object) -> Result<()> {
+        match &mut self.0 {
+            Shards::Sorted { buckets, bits } => {
+                buckets[hash.top_bits(*bits)].insert(hash, entry);
+            },
+            Shards::Hashed(map) => {
+                map.insert(hash, entry);
+            },
+            Shards::Backend(backend) => backend.insert(hash, entry)?,
+        }
+
+        Ok(())
+    }
+
+    /// See [`ObjectBackend::begin_blob`]
+    pub(crate) fn begin_blob(&mut self, size: usize) -> Result<PendingBlob> {
+        match &mut self.0 {
+            Shards::Backend(backend) => backend.begin_blob(size),
+            _ => Ok(PendingBlob::Buffer(Vec::with_capacity(size.min(BLOB_RESERVE_LIMIT)))),
+        }
+    }
+
+    /// See [`ObjectBackend::write_blob`]
+    pub(crate) fn write_blob(&mut self, blob: &mut PendingBlob, chunk: &[u8]) -> Result<()> {
+        match (&mut self.0, blob) {
+            (Shards::Backend(backend), blob) => backend.write_blob(blob, chunk),
+            (_, PendingBlob::Buffer(content)) => {
+                content.extend_from_slice(chunk);
+                Ok(())
+            },
+            (_, PendingBlob::Stored { .. }) => Err(Error::InvalidObject),
+        }
     }
 
-    pub fn hash(&self, obj_type: ObjectType, content: &[u8]) -> Hash {
-        let mut hasher = Sha1::new();
-        write!(&mut hasher, "{} {}\0", obj_type, content.len()).unwrap();
-        hasher.update(content);
-        Hash::new(hasher.finalize().into())
+    /// See [`ObjectBackend::end_blob`]
+    pub(crate) fn end_blob(&mut self, hash: Hash, blob: PendingBlob) -> Result<()> {
+        match (&mut self.0, blob) {
+            (Shards::Backend(backend), blob) => backend.end_blob(hash, blob),
+            (_, PendingBlob::Buffer(content)) => self.insert_hashed(hash, Object::new(ObjectType::Blob, content, None)),
+            (_, PendingBlob::Stored { .. }) => Err(Error::InvalidObject),
+        }
     }
 
-    pub fn insert_entry(&mut self, entry: Object) -> Hash {
-        let hash = self.hash(entry.obj_type, &entry.content);
-        self.0[hash.first_byte()].insert(hash, entry);
-        hash
+    /// See [`ObjectBackend::abort_blob`]
+    pub(crate) fn abort_blob(&mut self, blob: PendingBlob) {
+        if let Shards::Backend(backend) = &mut self.0 {
+            backend.abort_blob(blob);
+        }
     }
 
-    pub fn insert(
+    /// Drops the compressed form of objects, see [`Object::compressed`]
+    pub(crate) fn drop_compressed(&mut self) -> Result<()> {
+        let hashes = self.hashes().collect::<Vec<_>>();
+        for hash in hashes {
+            if self.get(hash).and_then(Object::compressed).is_none() {
+                continue;
+            }
+
+            let mut entry = self.remove(hash).unwrap();
+            entry.compressed = None;
+            self.insert_hashed(hash, entry)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns `IoError` if the backend couldn't store the object,
+    /// see [`ObjectBackend::insert`]
+    pub fn insert<C: Into<Vec<u8>>>(
         &mut self,
         obj_type: ObjectType,
-        content: Box<[u8]>,
+        content: C,
         delta_hint: Option<Hash>,
-    ) -> Hash {
-        let delta_hint = delta_hint.unwrap_or(Hash::zero());
-        self.insert_entry(Object {
-            obj_type,
-            content,
-            delta_hint,
-        })
+    ) -> Result<Hash> {
+        self.insert_entry(Object::new(obj_type, content, delta_hint))
     }
 
     pub fn get(&self, object: Hash) -> Option<&Object> {
-        self.0[object.first_byte()].get(&object)
+        match &self.0 {
+            Shards::Sorted { buckets, bits } => buckets[object.top_bits(*bits)].get(&object),
+            Shards::Hashed(map) => map.get(&object),
+            Shards::Backend(backend) => backend.get(object),
+        }
+    }
+
+    /// Same as [`Self::get`], but disk-backed stores don't keep
+    /// the object in memory, see [`ObjectBackend::load`]
+    pub fn load(&self, object: Hash) -> Option<Object> {
+        match &self.0 {
+            Shards::Backend(backend) => backend.load(object),
+            _ => self.get(object).cloned(),
+        }
     }
 
     pub fn has(&self, object: Hash) -> bool {
-        self.0[object.first_byte()].contains_key(&object)
+        match &self.0 {
+            Shards::Backend(backend) => backend.has(object),
+            _ => self.get(object).is_some(),
+        }
     }
 
     pub fn get_as(&self, object: Hash, obj_type: ObjectType) -> Option<&[u8]> {
@@ -197,10 +503,200 @@ impl ObjectStore {
     }
 
     pub fn remove(&mut self, object: Hash) -> Option<Object> {
-        self.0[object.first_byte()].remove(&object)
+        match &mut self.0 {
+            Shards::Sorted { buckets, bits } => buckets[object.top_bits(*bits)].remove(&object),
+            Shards::Hashed(map) => map.remove(&object),
+            Shards::Backend(backend) => backend.remove(object),
+        }
+    }
+
+    /// Sets the object which `object` should be delta-compressed
+    /// against when packing; `None` clears the hint
+    ///
+    /// Hints are a suggestion: `base` doesn't have to be in the store.
+    /// For instance, a firmware blob can be hinted to delta against
+    /// the previous version. When pushing, the base can also be one
+    /// the remote already has. Hints are also set when objects are
+    /// reconstructed from deltas of received packfiles.
+    ///
+    /// Returns `MissingObject` if `object` isn't in the store and
+    /// `InvalidObject` if `base` is `object` itself.
+    pub fn set_delta_hint(&mut self, object: Hash, base: Option<Hash>) -> Result<()> {
+        if base == Some(object) {
+            log::error!("Object {} cannot be its own delta base", object);
+            return Err(Error::InvalidObject);
+        }
+
+        let entry = match &mut self.0 {
+            Shards::Sorted { buckets, bits } => buckets[object.top_bits(*bits)].get_mut(&object),
+            Shards::Hashed(map) => map.get_mut(&object),
+            Shards::Backend(backend) => return backend.set_delta_hint(object, base),
+        };
+
+        entry.ok_or(Error::MissingObject)?.delta_hint = base.unwrap_or(Hash::zero());
+        Ok(())
+    }
+
+    /// Delta base hint of an object, see [`Self::set_delta_hint`]
+    pub fn delta_hint(&self, object: Hash) -> Option<Hash> {
+        self.get(object)?.delta_hint()
+    }
+
+    /// Hints that these objects will be read soon,
+    /// see [`ObjectBackend::prefetch`]
+    ///
+    /// This does nothing for in-memory stores.
+    pub fn prefetch(&self, hashes: &[Hash]) {
+        if let Shards::Backend(backend) = &self.0 {
+            backend.prefetch(hashes);
+        }
+    }
+
+    /// Drops objects which the backend cached in memory, if any
+    pub fn release_memory(&mut self) {
+        if let Shards::Backend(backend) = &mut self.0 {
+            backend.release_memory();
+        }
+    }
+
+    pub(crate) fn hashes(&self) -> Box<dyn Iterator<Item = Hash> + '_> {
+        match &self.0 {
+            Shards::Sorted { buckets, .. } => Box::new(buckets.iter().flat_map(|bucket| bucket.iter_keys().copied())),
+            Shards::Hashed(map) => Box::new(map.keys().copied()),
+            Shards::Backend(backend) => backend.hashes(),
+        }
+    }
+
+    /// Iterates on all objects, in no particular order
+    ///
+    /// With a disk-backed store, objects are read as they're iterated.
+    pub fn iter(&self) -> impl Iterator<Item = (Hash, &Object)> + '_ {
+        self.hashes().filter_map(|hash| self.get(hash).map(|object| (hash, object)))
+    }
+
+    /// Iterates on all objects of a type, see [`Self::iter`]
+    pub fn iter_type(&self, obj_type: ObjectType) -> impl Iterator<Item = (Hash, &Object)> + '_ {
+        self.iter().filter(move |(_, object)| object.obj_type == obj_type)
+    }
+
+    pub fn commits(&self) -> impl Iterator<Item = (Hash, &Object)> + '_ {
+        self.iter_type(ObjectType::Commit)
+    }
+
+    pub fn trees(&self) -> impl Iterator<Item = (Hash, &Object)> + '_ {
+        self.iter_type(ObjectType::Tree)
+    }
+
+    pub fn blobs(&self) -> impl Iterator<Item = (Hash, &Object)> + '_ {
+        self.iter_type(ObjectType::Blob)
+    }
+
+    pub fn tags(&self) -> impl Iterator<Item = (Hash, &Object)> + '_ {
+        self.iter_type(ObjectType::Tag)
+    }
+
+    /// Counts objects and their sizes, per type
+    ///
+    /// The overhead is an estimate: map entries and reference
+    /// counts of contents. With a disk-backed store, all objects
+    /// are read, but not kept in memory.
+    pub fn stats(&self) -> StoreStats {
+        // reference counts are stored next to contents
+        let per_object = size_of::<(Hash, Object)>() + 2 * size_of::<usize>();
+        let mut stats = StoreStats::default();
+
+        for object in self.hashes().filter_map(|hash| self.load(hash)) {
+            let type_stats = match object.obj_type {
+                ObjectType::Commit => &mut stats.commits,
+                ObjectType::Tree => &mut stats.trees,
+                ObjectType::Blob => &mut stats.blobs,
+                ObjectType::Tag => &mut stats.tags,
+            };
+
+            type_stats.count += 1;
+            type_stats.bytes += object.content.len();
+            stats.overhead_bytes += per_object;
+        }
+
+        stats
+    }
+
+    /// Finds all objects whose hash starts with a hexadecimal prefix
+    ///
+    /// The prefix must be 4 to 40 characters long;
+    /// otherwise, this returns `InvalidObject`.
+    pub fn find_prefix(&self, prefix: &str) -> Result<impl Iterator<Item = Hash> + '_> {
+        let valid_len = (MIN_PREFIX_LEN..=40).contains(&prefix.len());
+        if !valid_len || !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(Error::InvalidObject);
+        }
+
+        // the first digits select the bucket
+        let mut padded = prefix.to_ascii_lowercase();
+        let nibbles = padded.len();
+        padded.extend((nibbles..40).map(|_| '0'));
+        let hash = Hash::from_hex(&padded).unwrap();
+
+        let prefix = prefix.to_ascii_lowercase();
+        let iter = self.neighbours(hash, nibbles);
+        Ok(iter.filter(move |hash| hash.to_string().starts_with(&prefix)))
+    }
+
+    /// Finds the single object whose hash starts with a hexadecimal prefix
+    ///
+    /// Returns `AmbiguousPrefix` if multiple objects match
+    /// and `MissingObject` if none does.
+    pub fn resolve_prefix(&self, prefix: &str) -> Result<Hash> {
+        let mut matches = self.find_prefix(prefix)?;
+        let hash = matches.next().ok_or(Error::MissingObject)?;
+
+        match matches.next() {
+            Some(_) => Err(Error::AmbiguousPrefix),
+            None => Ok(hash),
+        }
+    }
+
+    /// Shortest unambiguous length of a hexadecimal prefix of `hash`
+    ///
+    /// Like git's `core.abbrev=auto`, this is never less than 7.
+    pub fn abbrev_len(&self, hash: Hash) -> usize {
+        let bytes = hash.to_bytes();
+        let mut len = DEFAULT_ABBREV_LEN;
+
+        for other in self.neighbours(hash, DEFAULT_ABBREV_LEN) {
+            if other != hash {
+                len = len.max(common_nibbles(&bytes, &other.to_bytes()) + 1);
+            }
+        }
+
+        len
     }
 }
 
+/// Shortest prefix accepted by [`ObjectStore::resolve_prefix`]
+pub const MIN_PREFIX_LEN: usize = 4;
+
+/// Shortest prefix produced by [`ObjectStore::abbrev_len`]
+pub const DEFAULT_ABBREV_LEN: usize = 7;
+
+fn common_nibbles(a: &[u8; 20], b: &[u8; 20]) -> usize {
+    let mut nibbles = 0;
+
+    for (a, b) in a.iter().zip(b) {
+        if a == b {
+            nibbles += 2;
+        } else {
+            if a >> 4 == b >> 4 {
+                nibbles += 1;
+            }
+
+            break;
+        }
+    }
+
+    nibbles
+}
+
 pub struct TreeIter<'a> {
     entries: &'a [u8],
 }
@@ -226,17 +722,9 @@ impl<'a> TreeIter<'a> {
             hash_bytes.copy_from_slice(other_bytes.get(1..21).ok_or(inv_bytes)?);
             let hash = Hash::new(hash_bytes);
 
-            let mode = match mode {
-                "040000" | "40000" => Mode::Directory,
-                "100644" => Mode::RegularFile,
-                "100664" => Mode::GroupWriteableFile,
-                "100755" => Mode::ExecutableFile,
-                "120000" => Mode::SymbolicLink,
-                "160000" => Mode::Gitlink,
-                _ => {
-                    log::error!("Invalid mode in directory: {}", mode);
-                    return Err(inv_bytes);
-                },
+            let Some(mode) = Mode::from_octal(mode) else {
+                log::error!("Invalid mode in directory: {}", mode);
+                return Err(inv_bytes);
             };
 
             self.entries = other_bytes.get(21..).ok_or(inv_bytes)?;
@@ -260,6 +748,11 @@ pub enum CommitField {
     CommitterEmail,
     CommitterTimestamp,
     CommitterTimezone,
+    /// Armored signature from the `gpgsig` header
+    ///
+    /// Continuation lines keep their leading space;
+    /// see [`split_commit_signature`] to get the bare signature.
+    Signature,
     Message,
 }
 
@@ -273,6 +766,22 @@ pub fn get_commit_field<'a>(commit: &'a [u8], field: CommitField) -> Result<Opti
             "" => None,
             msg => Some(msg),
         })
+    } else if let CommitField::Signature = field {
+        // the tree header always comes first
+        let Some(start) = metadata.find("\ngpgsig ") else {
+            return Ok(None);
+        };
+
+        let value = &metadata[start + 8..];
+        let mut end = value.len();
+        for (i, _) in value.match_indices('\n') {
+            if !value[i + 1..].starts_with(' ') {
+                end = i;
+                break;
+            }
+        }
+
+        Ok(Some(&value[..end]))
     } else {
         let field_name = match field {
             CommitField::Tree => "tree",
@@ -285,6 +794,7 @@ pub fn get_commit_field<'a>(commit: &'a [u8], field: CommitField) -> Result<Opti
             CommitField::CommitterEmail |
             CommitField::CommitterTimestamp |
             CommitField::CommitterTimezone => "committer",
+            CommitField::Signature |
             CommitField::Message => unreachable!(),
         };
 
@@ -297,6 +807,7 @@ pub fn get_commit_field<'a>(commit: &'a [u8], field: CommitField) -> Result<Opti
             }
 
             match field {
+                CommitField::Signature |
                 CommitField::Message => unreachable!(),
                 CommitField::Tree => return Ok(Some(value)),
                 CommitField::Parent(n) => match n == parent_index {
@@ -326,6 +837,29 @@ pub fn get_commit_field<'a>(commit: &'a [u8], field: CommitField) -> Result<Opti
     }
 }
 
+/// Splits a commit object into its signed payload and its armored signature
+///
+/// The payload is the commit without its `gpgsig` header.
+/// Returns `None` if the commit isn't signed.
+pub fn split_commit_signature(commit: &[u8]) -> Option<(Vec<u8>, String)> {
+    let raw = get_commit_field(commit, CommitField::Signature).ok()??;
+
+    let mut signature = String::new();
+    for line in raw.lines() {
+        signature += line.strip_prefix(' ').unwrap_or(line);
+        signature.push('\n');
+    }
+
+    // `raw` points inside `commit`
+    let start = raw.as_ptr() as usize - commit.as_ptr() as usize - "gpgsig ".len();
+    let end = start + "gpgsig ".len() + raw.len() + 1;
+
+    let mut payload = commit[..start].to_vec();
+    payload.extend_from_slice(&commit[end..]);
+
+    Some((payload, signature))
+}
+
 pub fn get_commit_field_hash(commit: &[u8], field: CommitField) -> Result<Option<Hash>> {
     match get_commit_field(commit, field)? {
         Some(hex) => Ok(Some(Hash::from_hex(hex).ok_or(Error::InvalidObject)?)),
@@ -334,25 +868,86 @@ pub fn get_commit_field_hash(commit: &[u8], field: CommitField) -> Result<Option
 }
 
 pub struct CommitParentsIter<'a> {
-    commit: &'a [u8],
+    commit: Result<Commit<'a>>,
     parent_index: usize,
 }
 
 impl<'a> CommitParentsIter<'a> {
     pub fn new(commit_object: &'a [u8]) -> CommitParentsIter<'a> {
         Self {
-            commit: commit_object,
+            commit: Commit::parse(commit_object),
             parent_index: 0,
         }
     }
 
     pub fn next(&mut self) -> Result<Option<Hash>> {
-        let field = CommitField::Parent(self.parent_index);
-        if let Some(parent) = get_commit_field_hash(self.commit, field)? {
-            self.parent_index += 1;
-            Ok(Some(parent))
-        } else {
-            Ok(None)
+        let commit = self.commit.as_ref().map_err(|e| *e)?;
+        let parent = commit.parents.get(self.parent_index).copied();
+        self.parent_index += parent.is_some() as usize;
+        Ok(parent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two blobs whose hashes share at least `nibbles` hex digits
+    fn colliding_blobs(nibbles: usize) -> (Vec<u8>, Vec<u8>) {
+        let mut seen = HashMap::new();
+
+        for i in 0.. {
+            let content = format!("blob {}\n", i).into_bytes();
+            let mut prefix = object_hash(ObjectType::Blob, &content).unwrap().to_string();
+            prefix.truncate(nibbles);
+
+            if let Some(other) = seen.insert(prefix, content.clone()) {
+                return (other, content);
+            }
+        }
+
+        unreachable!()
+    }
+
+    #[test]
+    fn ambiguous_prefix() {
+        let (a, b) = colliding_blobs(MIN_PREFIX_LEN);
+
+        for sharding in [Sharding::Single, Sharding::Buckets256, Sharding::Buckets4096, Sharding::HashMap, Sharding::Backend] {
+            let mut store = ObjectStore::with_sharding(sharding);
+            let a = store.insert(ObjectType::Blob, a.clone(), None).unwrap();
+            let b = store.insert(ObjectType::Blob, b.clone(), None).unwrap();
+            let prefix = &a.to_string()[..MIN_PREFIX_LEN];
+
+            assert_eq!(store.find_prefix(prefix).unwrap().count(), 2);
+            assert!(matches!(store.resolve_prefix(prefix), Err(Error::AmbiguousPrefix)));
+            assert!(matches!(store.resolve_prefix(&prefix[..3]), Err(Error::InvalidObject)));
+
+            for hash in [a, b] {
+                let len = store.abbrev_len(hash);
+                assert_eq!(store.resolve_prefix(&hash.to_string()[..len]).unwrap(), hash);
+            }
+        }
+    }
+
+    #[test]
+    fn abbreviation_grows_with_collisions() {
+        let (a, b) = colliding_blobs(DEFAULT_ABBREV_LEN);
+
+        for sharding in [Sharding::Single, Sharding::Buckets256, Sharding::Buckets4096, Sharding::HashMap, Sharding::Backend] {
+            let mut store = ObjectStore::with_sharding(sharding);
+            let a = store.insert(ObjectType::Blob, a.clone(), None).unwrap();
+            assert_eq!(store.abbrev_len(a), DEFAULT_ABBREV_LEN);
+
+            let b = store.insert(ObjectType::Blob, b.clone(), None).unwrap();
+            let len = common_nibbles(&a.to_bytes(), &b.to_bytes()) + 1;
+            assert!(len > DEFAULT_ABBREV_LEN);
+            assert_eq!(store.abbrev_len(a), len);
+            assert_eq!(store.abbrev_len(b), len);
+
+            let a_hex = a.to_string();
+            assert_eq!(store.resolve_prefix(&a_hex[..len]).unwrap(), a);
+            assert!(matches!(store.resolve_prefix(&a_hex[..len - 1]), Err(Error::AmbiguousPrefix)));
         }
     }
 }
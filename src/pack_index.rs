@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use sha1::Digest;
+
+use super::internals::{Result, Error, Hash, ObjectType, PackfileReader, DeltaBase};
+use super::objectstore::{Hasher, finalize, object_hash};
+use super::packfile::reconstruct;
+use super::compression::Codec;
+
+const IPF: Error = Error::InvalidPackfile;
+
+/// `\377tOc` followed by version 2
+const IDX_SIG_V2: [u8; 8] = [0xff, b't', b'O', b'c', 0, 0, 0, 2];
+const FANOUT_SZ: usize = 256 * 4;
+/// Offsets with this bit set point into the table of 64-bit offsets
+const LARGE_OFFSET: u32 = 0x8000_0000;
+
+/// CRC-32 (IEEE) lookup table, as used by zlib & pack indexes
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+};
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+
+    !crc
+}
+
+fn read_u32(bytes: &[u8], i: usize) -> u32 {
+    u32::from_be_bytes(bytes[i * 4..][..4].try_into().unwrap())
+}
+
+/// Index of a packfile, in the `.idx` version 2 format
+///
+/// Lists the hash, offset and CRC-32 of each object of a pack.
+/// Together with the pack, this is what git stores in
+/// `.git/objects/pack/`:
+///
+/// ```ignore
+/// let index = PackIndex::build(&pack, &Miniz)?;
+/// fs::write(format!("pack-{}.pack", index.pack_checksum()), &pack)?;
+/// fs::write(format!("pack-{}.idx", index.pack_checksum()), index.to_bytes()?)?;
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackIndex {
+    /// `(hash, offset, crc32)`, sorted by hash bytes
+    /// (`Hash` itself doesn't compare in byte order)
+    entries: Vec<(Hash, u64, u32)>,
+    pack_checksum: Hash,
+}
+
+/// Objects of a pack which were inflated by [`PackIndex::build`]
+#[derive(Default)]
+struct Resolved {
+    by_offset: HashMap<usize, (ObjectType, Box<[u8]>)>,
+    by_hash: HashMap<Hash, usize>,
+}
+
+impl Resolved {
+    fn insert(&mut self, offset: usize, obj_type: ObjectType, content: Box<[u8]>) -> Result<Hash> {
+        let hash = object_hash(obj_type, &content)?;
+        self.by_hash.insert(hash, offset);
+        self.by_offset.insert(offset, (obj_type, content));
+        Ok(hash)
+    }
+}
+
+impl PackIndex {
+    /// Indexes a complete packfile
+    ///
+    /// Every object is inflated and deltas are resolved to compute
+    /// the hashes, so the contents are all in memory for a while.
+    ///
+    /// Returns `InvalidPackfile` if the pack is malformed, if its
+    /// checksum is wrong or if a delta base isn't in the pack
+    /// (thin packs cannot be indexed). Entries are inflated with `codec`.
+    pub fn build(pack: &[u8], codec: &dyn Codec) -> Result<Self> {
+        let Some(checksum_offset) = pack.len().checked_sub(20) else {
+            log::error!("Packfile is too short");
+            return Err(IPF);
+        };
+
+        let mut hasher = Hasher::new();
+        hasher.update(&pack[..checksum_offset]);
+        let pack_checksum = Hash::new(pack[checksum_offset..].try_into().unwrap());
+        if Hash::new(finalize(hasher)?) != pack_checksum {
+            log::error!("Packfile checksum mismatch");
+            return Err(IPF);
+        }
+
+        let mut reader = PackfileReader::from_file(pack.to_vec())?;
+        let mut resolved = Resolved::default();
+        let mut entries = Vec::with_capacity(reader.num_objects());
+        let mut pending = Vec::new();
+
+        for entry in reader.entries() {
+            let entry = entry?;
+            let start = entry.offset + entry.header_size;
+            let raw = pack.get(entry.offset..start + entry.compressed_size).ok_or(IPF)?;
+
+            let content = match codec.decompress(&raw[entry.header_size..], entry.size) {
+                Ok(content) if content.len() == entry.size => content,
+                _ => {
+                    log::error!("Couldn't inflate packfile entry at offset {}", entry.offset);
+                    return Err(IPF);
+                },
+            };
+
+            let crc = crc32(raw);
+            match entry.encoding.object_type() {
+                Some(obj_type) => {
+                    let hash = resolved.insert(entry.offset, obj_type, content.into())?;
+                    entries.push((hash, entry.offset as u64, crc));
+                },
+                None => pending.push((entry, content, crc)),
+            }
+        }
+
+        // deltas can depend on deltas which come later in the pack
+        while !pending.is_empty() {
+            let count = pending.len();
+            let mut i = 0;
+
+            while i < pending.len() {
+                let base_offset = match pending[i].0.base {
+                    Some(DeltaBase::Offset(offset)) => Some(offset),
+                    Some(DeltaBase::Hash(hash)) => resolved.by_hash.get(&hash).copied(),
+                    None => return Err(IPF),
+                };
+
+                let Some((obj_type, base)) = base_offset.and_then(|offset| resolved.by_offset.get(&offset)) else {
+                    i += 1;
+                    continue;
+                };
+
+                let (entry, delta, crc) = pending.swap_remove(i);
+                let (obj_type, content) = (*obj_type, reconstruct(&delta, base)?);
+                let hash = resolved.insert(entry.offset, obj_type, content)?;
+                entries.push((hash, entry.offset as u64, crc));
+            }
+
+            if pending.len() == count {
+                log::error!("Packfile has {} deltas with a missing base", count);
+                return Err(IPF);
+            }
+        }
+
+        entries.sort_unstable_by_key(|(hash, _, _)| hash.to_bytes());
+        Ok(Self {
+            entries,
+            pack_checksum,
+        })
+    }
+
+    /// Parses a `.idx` file (version 2)
+    ///
+    /// Returns `InvalidPackfile` if the index is truncated,
+    /// malformed, or if its checksum is wrong.
+    pub fn parse(idx: &[u8]) -> Result<Self> {
+        let invalid = |reason: &str| {
+            log::error!("Invalid pack index: {}", reason);
+            IPF
+        };
+
+        if idx.len() < IDX_SIG_V2.len() + FANOUT_SZ + 40 {
+            return Err(invalid("too short"));
+        }
+
+        if !idx.starts_with(&IDX_SIG_V2) {
+            return Err(invalid("unsupported version"));
+        }
+
+        let checksum_offset = idx.len() - 20;
+        let mut hasher = Hasher::new();
+        hasher.update(&idx[..checksum_offset]);
+        if finalize(hasher)?[..] != idx[checksum_offset..] {
+            return Err(invalid("checksum mismatch"));
+        }
+
+        let fanout = &idx[IDX_SIG_V2.len()..][..FANOUT_SZ];
+        let num_objects = read_u32(fanout, 255) as usize;
+        for i in 1..256 {
+            if read_u32(fanout, i - 1) > read_u32(fanout, i) {
+                return Err(invalid("unsorted fan-out table"));
+            }
+        }
+
+        let hashes_start = IDX_SIG_V2.len() + FANOUT_SZ;
+        let crcs_start = hashes_start + num_objects * 20;
+        let offsets_start = crcs_start + num_objects * 4;
+        let large_start = offsets_start + num_objects * 4;
+
+        let trailer_start = checksum_offset - 20;
+        let large = idx.get(large_start..trailer_start).ok_or_else(|| invalid("too short"))?;
+        let (hashes, crcs) = (&idx[hashes_start..crcs_start], &idx[crcs_start..offsets_start]);
+        let offsets = &idx[offsets_start..large_start];
+
+        let mut entries = Vec::<(Hash, u64, u32)>::with_capacity(num_objects);
+        for i in 0..num_objects {
+            let hash = Hash::new(hashes[i * 20..][..20].try_into().unwrap());
+            let offset = match read_u32(offsets, i) {
+                offset if offset & LARGE_OFFSET == 0 => offset as u64,
+                offset => {
+                    let j = (offset & !LARGE_OFFSET) as usize;
+                    let bytes = large.get(j * 8..j * 8 + 8).ok_or_else(|| invalid("missing 64-bit offset"))?;
+                    u64::from_be_bytes(bytes.try_into().unwrap())
+                },
+            };
+
+            if entries.last().is_some_and(|(previous, _, _)| previous.to_bytes() >= hash.to_bytes()) {
+                return Err(invalid("unsorted hashes"));
+            }
+
+            entries.push((hash, offset, read_u32(crcs, i)));
+        }
+
+        let mut counts = [0; 256];
+        for (hash, _, _) in &entries {
+            counts[hash.to_bytes()[0] as usize] += 1;
+        }
+
+        let mut total = 0;
+        let fanout_matches = counts.iter().enumerate().all(|(byte, count)| {
+            total += count;
+            total == read_u32(fanout, byte) as usize
+        });
+
+        if !fanout_matches {
+            return Err(invalid("fan-out table doesn't match the hashes"));
+        }
+
+        Ok(Self {
+            entries,
+            pack_checksum: Hash::new(idx[trailer_start..checksum_offset].try_into().unwrap()),
+        })
+    }
+
+    /// Serializes the index in the `.idx` version 2 format
+    ///
+    /// With the `sha1dc` feature, returns `InvalidObject` if the
+    /// index checksum hits a known SHA-1 collision attack.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut idx = Vec::with_capacity(IDX_SIG_V2.len() + FANOUT_SZ + self.entries.len() * 28 + 40);
+        idx.extend_from_slice(&IDX_SIG_V2);
+
+        let mut count = 0;
+        for byte in 0..256 {
+            let with_byte = self.entries[count..].iter().take_while(|(hash, _, _)| hash.to_bytes()[0] as usize == byte);
+            count += with_byte.count();
+            idx.extend_from_slice(&(count as u32).to_be_bytes());
+        }
+
+        for (hash, _, _) in &self.entries {
+            idx.extend_from_slice(&hash.to_bytes());
+        }
+
+        for (_, _, crc) in &self.entries {
+            idx.extend_from_slice(&crc.to_be_bytes());
+        }
+
+        let mut large = Vec::new();
+        for (_, offset, _) in &self.entries {
+            let offset = match u32::try_from(*offset) {
+                Ok(offset) if offset & LARGE_OFFSET == 0 => offset,
+                _ => {
+                    large.push(*offset);
+                    LARGE_OFFSET | (large.len() - 1) as u32
+                },
+            };
+
+            idx.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        for offset in large {
+            idx.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        idx.extend_from_slice(&self.pack_checksum.to_bytes());
+
+        let mut hasher = Hasher::new();
+        hasher.update(&idx);
+        idx.extend_from_slice(&finalize(hasher)?);
+        Ok(idx)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Checksum of the indexed packfile (its last 20 bytes),
+    /// which git uses in pack file names
+    pub fn pack_checksum(&self) -> Hash {
+        self.pack_checksum
+    }
+
+    fn entry(&self, hash: Hash) -> Option<&(Hash, u64, u32)> {
+        let key = hash.to_bytes();
+        let i = self.entries.binary_search_by_key(&key, |(hash, _, _)| hash.to_bytes()).ok()?;
+        Some(&self.entries[i])
+    }
+
+    /// Offset of an object in the packfile
+    pub fn find(&self, hash: Hash) -> Option<u64> {
+        self.entry(hash).map(|(_, offset, _)| *offset)
+    }
+
+    /// CRC-32 of the compressed entry of an object,
+    /// including its header
+    pub fn crc32(&self, hash: Hash) -> Option<u32> {
+        self.entry(hash).map(|(_, _, crc)| *crc)
+    }
+
+    /// Iterates on `(hash, offset, crc32)` entries, sorted by hash bytes
+    pub fn iter(&self) -> impl Iterator<Item = (Hash, u64, u32)> + '_ {
+        self.entries.iter().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lmfu::HashSet;
+    use super::*;
+    use crate::{Repository, FileType};
+    use crate::compression::Miniz;
+
+    #[test]
+    fn built_index_round_trip() {
+        let mut repo = Repository::new();
+        let signature = ("Test", "test@example.com");
+        for i in 0..3 {
+            repo.stage(&format!("{}.txt", i), Some((vec![b'a' + i as u8; 100], FileType::RegularFile))).unwrap();
+            repo.commit("commit", signature, signature, Some(i)).unwrap();
+        }
+
+        let mut pack = Vec::new();
+        repo.pack(HashSet::new(), &[("main", repo.head())], &mut pack, |_, _| ()).unwrap();
+        let mut hasher = Hasher::new();
+        hasher.update(&pack);
+        pack.extend_from_slice(&finalize(hasher).unwrap());
+
+        let index = PackIndex::build(&pack, &Miniz).unwrap();
+        assert_eq!(index.len(), repo.objects.iter().count());
+        assert_eq!(index.pack_checksum().to_bytes()[..], pack[pack.len() - 20..]);
+        for (hash, offset, crc) in index.iter() {
+            assert!(repo.objects.has(hash));
+            assert!((offset as usize) < pack.len() - 20);
+            assert_eq!(index.crc32(hash), Some(crc));
+        }
+
+        assert_eq!(PackIndex::parse(&index.to_bytes().unwrap()).unwrap(), index);
+    }
+
+    #[test]
+    fn large_offsets_round_trip() {
+        // the last byte of each u32 differs from the first, so that
+        // byte order and `Hash` order disagree on little-endian hosts
+        let hash = |i: u8| {
+            let mut bytes = [i; 20];
+            bytes[3] = !i;
+            Hash::new(bytes)
+        };
+
+        let index = PackIndex {
+            entries: vec![
+                (hash(0x00), 12, 1),
+                (hash(0x01), 0x7fff_ffff, 2),
+                (hash(0x80), 0x8000_0000, 3),
+                (hash(0x81), 0xffff_ffff, 4),
+                (hash(0xff), 0x1_2345_6789, 5),
+            ],
+            pack_checksum: hash(0x42),
+        };
+
+        let idx = index.to_bytes().unwrap();
+        // 3 offsets go to the table of 64-bit offsets
+        assert_eq!(idx.len(), IDX_SIG_V2.len() + FANOUT_SZ + 5 * 28 + 3 * 8 + 40);
+        let parsed = PackIndex::parse(&idx).unwrap();
+        assert_eq!(parsed, index);
+        assert_eq!(parsed.find(hash(0xff)), Some(0x1_2345_6789));
+        assert_eq!(parsed.find(hash(0x01)), Some(0x7fff_ffff));
+        assert_eq!(parsed.crc32(hash(0x80)), Some(3));
+        assert_eq!(parsed.find(hash(0x02)), None);
+
+        let mut corrupted = idx.clone();
+        corrupted[IDX_SIG_V2.len() + FANOUT_SZ] ^= 1;
+        assert!(matches!(PackIndex::parse(&corrupted), Err(Error::InvalidPackfile)));
+        assert!(matches!(PackIndex::parse(&idx[..idx.len() - 21]), Err(Error::InvalidPackfile)));
+    }
+}
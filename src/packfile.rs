@@ -1,16 +1,20 @@
 use core::{str::from_utf8, mem::size_of};
+use std::{collections::{HashMap, BTreeSet, VecDeque}, io::{self, Read}, sync::Arc};
 use lmfu::HashSet;
-use sha1::{Sha1, Digest};
+use sha1::Digest;
 
 use super::internals::{
-    Result, Error, Write, ObjectStore, ObjectType, Hash,
-    CommitField, GitProtocol, CommitParentsIter, TreeIter,
-    get_commit_field_hash,
+    Result, Error, Write, ObjectStore, Object, Content, ObjectType, Hash,
+    Commit, GitProtocol, TreeIter,
 };
+use super::objectstore::{Hasher, finalize, object_hash};
+use super::backend::type_code;
+use super::compression::{Codec, Miniz};
+use super::sideband::RemoteMessage;
 
 use miniz_oxide::inflate::{core::{DecompressorOxide, decompress, inflate_flags}, TINFLStatus};
-use miniz_oxide::deflate::{core::{CompressorOxide, compress, deflate_flags, TDEFLStatus, TDEFLFlush}};
 
+/// Type of a packfile entry, as encoded in its header
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ObjectEncoding {
     Commit = 1,
@@ -21,6 +25,19 @@ pub enum ObjectEncoding {
     RefDelta = 7,
 }
 
+impl ObjectEncoding {
+    /// Type of the object, `None` for deltas
+    pub fn object_type(self) -> Option<ObjectType> {
+        match self {
+            Self::Commit => Some(ObjectType::Commit),
+            Self::Tree => Some(ObjectType::Tree),
+            Self::Blob => Some(ObjectType::Blob),
+            Self::Tag => Some(ObjectType::Tag),
+            Self::OfsDelta | Self::RefDelta => None,
+        }
+    }
+}
+
 impl TryFrom<u8> for ObjectEncoding {
     type Error = Error;
     fn try_from(value: u8) -> Result<Self> {
@@ -42,39 +59,108 @@ pub enum PackfileObject<T> {
     Tree(T), // 2
     Blob(T), // 3
     Tag(T), // 4
+    /// Delta & distance back to the entry of its base, in bytes
     OfsDelta(T, usize), // 6
     RefDelta(T, Hash), // 7
 }
 
+/// Base object of a delta entry
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeltaBase {
+    /// Offset of the base entry in the packfile (`OfsDelta`)
+    Offset(usize),
+    /// Hash of the base object (`RefDelta`)
+    Hash(Hash),
+}
+
+/// Metadata of a packfile entry, see [`PackfileReader::next_entry`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PackfileEntry {
+    /// Offset of the entry from the start of the packfile
+    pub offset: usize,
+    /// Size of the header, including the delta base reference
+    pub header_size: usize,
+    pub encoding: ObjectEncoding,
+    /// Inflated size; for deltas, this is the size of the delta
+    pub size: usize,
+    /// Size of the zlib stream which follows the header
+    pub compressed_size: usize,
+    pub base: Option<DeltaBase>,
+}
+
 const U32: usize = size_of::<u32>();
 const SIG_V2: [u8; U32 + U32] = [b'P', b'A', b'C', b'K', 0, 0, 0, 2];
 const BYTE_MSB: u8 = 0b1000_0000; // 0x80
 const IPF: Error = Error::InvalidPackfile;
 pub(crate) const HEADER_SZ: usize = U32 + U32 + U32;
+/// zlib level of outgoing objects
+const PACK_COMPRESSION: u8 = 1;
+/// Bytes pulled at once by [`PackfileReader::from_reader`]
+const READ_CHUNK: usize = 64 * 1024;
 
 pub struct PackfileReader<'a> {
     protocol: Option<GitProtocol<'a>>,
+    reader: Option<Box<dyn Read + 'a>>,
+    /// Reused by [`Self::inflate`]
+    decompressor: Box<DecompressorOxide>,
+    /// Hashes consumed bytes, see [`Self::verify_checksum`]
+    hasher: Hasher,
     pub out: Vec<u8>,
     buffer: Vec<u8>,
     num_objects: usize,
+    /// Offset of the start of `buffer` in the packfile
+    offset: usize,
+    entries_read: usize,
 }
 
 impl<'a> PackfileReader<'a> {
     pub fn new(protocol: GitProtocol<'a>) -> Result<PackfileReader<'a>> {
         Self::init(Self {
             protocol: Some(protocol),
+            reader: None,
             buffer: Vec::new(),
             out: Vec::new(),
             num_objects: 0,
+            offset: 0,
+            entries_read: 0,
+            decompressor: Box::default(),
+            hasher: Hasher::new(),
         })
     }
 
     pub fn from_file(file: Vec<u8>) -> Result<PackfileReader<'a>> {
         Self::init(Self {
             protocol: None,
+            reader: None,
             buffer: file,
             out: Vec::new(),
             num_objects: 0,
+            offset: 0,
+            entries_read: 0,
+            decompressor: Box::default(),
+            hasher: Hasher::new(),
+        })
+    }
+
+    /// Pulls the packfile from `reader` as it's parsed,
+    /// instead of holding all of it in memory
+    ///
+    /// ```ignore
+    /// let file = File::open("pack-1234.pack")?;
+    /// let mut reader = PackfileReader::from_reader(BufReader::new(file))?;
+    /// reader.read_all_objects(&mut objects)?;
+    /// ```
+    pub fn from_reader<R: Read + 'a>(reader: R) -> Result<PackfileReader<'a>> {
+        Self::init(Self {
+            protocol: None,
+            reader: Some(Box::new(reader)),
+            buffer: Vec::new(),
+            out: Vec::new(),
+            num_objects: 0,
+            offset: 0,
+            entries_read: 0,
+            decompressor: Box::default(),
+            hasher: Hasher::new(),
         })
     }
 
@@ -87,7 +173,7 @@ impl<'a> PackfileReader<'a> {
                     u32_bytes.copy_from_slice(&self.buffer[SIG_V2.len()..][..U32]);
                     self.num_objects = u32::from_be_bytes(u32_bytes) as usize;
 
-                    self.buffer.drain(0..HEADER_SZ);
+                    self.consume(HEADER_SZ);
 
                     break Ok(self);
                 } else {
@@ -103,28 +189,41 @@ impl<'a> PackfileReader<'a> {
     // must not be called without expecting a line
     // returns buffer len
     fn read_line(&mut self) -> Result<usize> {
+        if let Some(reader) = self.reader.as_deref_mut() {
+            return read_chunk(reader, &mut self.buffer);
+        }
+
         let proto_error = Error::GitProtocolError;
         let protocol = self.protocol.as_mut().ok_or(IPF)?;
-        match protocol.read_line()? {
+        let (line_type, message) = match protocol.read_line()? {
             Some(bytes) => {
                 let line_type = *bytes.get(0).ok_or(proto_error)?;
                 let data = &bytes[1..];
 
-                match line_type {
-                    1 => {
-                        self.buffer.extend_from_slice(data);
-                        self.out.extend_from_slice(data);
-                    },
-                    2 => log::info!("Server Message: {}", from_utf8(data).ok().ok_or(proto_error)?),
-                    _ => log::error!("Server Error: {}", from_utf8(data).ok().ok_or(proto_error)?),
+                if line_type == 1 {
+                    self.buffer.extend_from_slice(data);
+                    self.out.extend_from_slice(data);
+                    return Ok(self.buffer.len());
                 }
 
-                match line_type == 0 || line_type > 2 {
-                    true => Err(proto_error),
-                    false => Ok(self.buffer.len()),
-                }
+                (line_type, from_utf8(data).ok().ok_or(proto_error)?.to_string())
+            },
+            None => return Err(proto_error),
+        };
+
+        match line_type {
+            2 => {
+                protocol.report(RemoteMessage::Progress(&message));
+                Ok(self.buffer.len())
+            },
+            3 => {
+                protocol.report(RemoteMessage::Error(&message));
+                Err(Error::RemoteError)
+            },
+            _ => {
+                log::error!("Invalid side-band channel: {}", line_type);
+                Err(proto_error)
             },
-            None => Err(proto_error),
         }
     }
 
@@ -132,6 +231,13 @@ impl<'a> PackfileReader<'a> {
         self.num_objects
     }
 
+    /// Drops `len` bytes from the start of the buffer
+    fn consume(&mut self, len: usize) {
+        self.hasher.update(&self.buffer[..len]);
+        self.buffer.drain(0..len);
+        self.offset += len;
+    }
+
     fn read_size(&mut self) -> Result<(ObjectEncoding, usize)> {
         let mut i = 0;
         let mut size = 0;
@@ -150,7 +256,7 @@ impl<'a> PackfileReader<'a> {
                 if byte & BYTE_MSB == 0 {
                     let raw_type = (self.buffer[0] >> 4) & 0b111;
                     let enc_type = ObjectEncoding::try_from(raw_type)?;
-                    self.buffer.drain(0..i);
+                    self.consume(i);
                     break Ok((enc_type, size));
                 }
             } else {
@@ -159,12 +265,28 @@ impl<'a> PackfileReader<'a> {
         }
     }
 
+    /// Reads the checksum which ends the packfile, once all
+    /// entries were read
+    ///
+    /// Returns `InvalidPackfile` if it doesn't match the bytes read
+    /// so far.
+    pub(crate) fn verify_checksum(&mut self) -> Result<Hash> {
+        let computed = Hash::new(finalize(self.hasher.clone())?);
+        let checksum = self.read_hash()?;
+        if computed != checksum {
+            log::error!("Packfile checksum mismatch: {} != {}", computed, checksum);
+            return Err(IPF);
+        }
+
+        Ok(checksum)
+    }
+
     fn read_hash(&mut self) -> Result<Hash> {
         loop {
             if let Some(slice) = self.buffer.get(0..20) {
                 let mut array = [0; 20];
                 array.copy_from_slice(slice);
-                self.buffer.drain(0..20);
+                self.consume(20);
                 break Ok(Hash::new(array));
             } else {
                 self.read_line()?;
@@ -172,105 +294,448 @@ impl<'a> PackfileReader<'a> {
         }
     }
 
-    pub fn next_object(&mut self) -> Result<PackfileObject<Box<[u8]>>> {
+    /// Reads the negative offset of an `OfsDelta` base
+    fn read_base_offset(&mut self) -> Result<usize> {
+        let mut i = 0;
+        let mut distance = 0usize;
+
+        loop {
+            if let Some(byte) = self.buffer.get(i) {
+                if i > 0 {
+                    distance = distance.checked_add(1).ok_or(IPF)?;
+                }
+
+                distance = distance.checked_mul(128).ok_or(IPF)? | (byte & 0x7f) as usize;
+                i += 1;
+
+                if byte & BYTE_MSB == 0 {
+                    self.consume(i);
+                    break Ok(distance);
+                }
+            } else {
+                self.read_line()?;
+            }
+        }
+    }
+
+    /// Reads the type, size and delta base of the next entry
+    fn read_entry_header(&mut self) -> Result<(ObjectEncoding, usize, Option<DeltaBase>)> {
+        let offset = self.offset;
         let (encoding, size) = self.read_size()?;
 
-        let hash = match encoding {
-            ObjectEncoding::RefDelta => self.read_hash()?,
-            _ => Hash::zero(),
+        let base = match encoding {
+            ObjectEncoding::RefDelta => Some(DeltaBase::Hash(self.read_hash()?)),
+            ObjectEncoding::OfsDelta => {
+                let distance = self.read_base_offset()?;
+                Some(DeltaBase::Offset(offset.checked_sub(distance).ok_or(IPF)?))
+            },
+            _ => None,
         };
 
-        log::trace!("Inflating a {:?} to {} bytes", encoding, size);
+        Ok((encoding, size, base))
+    }
+
+    pub fn next_object(&mut self) -> Result<PackfileObject<Box<[u8]>>> {
+        self.next_object_with(None)
+    }
+
+    /// Reads the metadata of the next entry
+    ///
+    /// The entry is inflated to find the end of its zlib stream,
+    /// but its content is discarded and deltas aren't resolved.
+    pub fn next_entry(&mut self) -> Result<PackfileEntry> {
+        let offset = self.offset;
+        let (encoding, size, base) = self.read_entry_header()?;
+        let header_size = self.offset - offset;
+
+        let (_, compressed_size) = self.inflate(size)?;
+        self.consume(compressed_size);
+        self.entries_read += 1;
+
+        Ok(PackfileEntry {
+            offset,
+            header_size,
+            encoding,
+            size,
+            compressed_size,
+            base,
+        })
+    }
+
+    /// Iterates on the metadata of the remaining entries,
+    /// see [`Self::next_entry`]
+    ///
+    /// Iteration stops after the first error.
+    pub fn entries(&mut self) -> impl Iterator<Item = Result<PackfileEntry>> + use<'_, 'a> {
+        core::iter::from_fn(move || {
+            if self.entries_read >= self.num_objects {
+                return None;
+            }
+
+            let entry = self.next_entry();
+            if entry.is_err() {
+                self.entries_read = self.num_objects;
+            }
+
+            Some(entry)
+        })
+    }
+
+    /// Also appends the compressed object data to `compressed`
+    fn next_object_with(&mut self, compressed: Option<&mut Vec<u8>>) -> Result<PackfileObject<Box<[u8]>>> {
+        let offset = self.offset;
+        let (encoding, size, base) = self.read_entry_header()?;
+
+        let (inflated, to_skip) = self.inflate(size)?;
+        if let Some(compressed) = compressed {
+            compressed.extend_from_slice(&self.buffer[..to_skip]);
+        }
+
+        self.consume(to_skip);
+        self.entries_read += 1;
+
+        match (encoding, base) {
+            (ObjectEncoding::Commit, _) => Ok(PackfileObject::Commit(inflated)),
+            (ObjectEncoding::Tree, _) => Ok(PackfileObject::Tree(inflated)),
+            (ObjectEncoding::Blob, _) => Ok(PackfileObject::Blob(inflated)),
+            (ObjectEncoding::Tag, _) => Ok(PackfileObject::Tag(inflated)),
+            (ObjectEncoding::OfsDelta, Some(DeltaBase::Offset(base))) => Ok(PackfileObject::OfsDelta(inflated, offset - base)),
+            (ObjectEncoding::RefDelta, Some(DeltaBase::Hash(hash))) => Ok(PackfileObject::RefDelta(inflated, hash)),
+            _ => Err(IPF),
+        }
+    }
+
+    /// Inflates the zlib stream at the start of the buffer
+    ///
+    /// Returns the content and the size of the stream,
+    /// which isn't consumed.
+    fn inflate(&mut self, size: usize) -> Result<(Box<[u8]>, usize)> {
+        log::trace!("Inflating {} bytes", size);
 
         let mut inflated = vec![0; size].into_boxed_slice();
 
         let flags = inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF
                   | inflate_flags::TINFL_FLAG_PARSE_ZLIB_HEADER
-                  | inflate_flags::TINFL_FLAG_COMPUTE_ADLER32;
-
-        // todo: reuse the decompressor (advance inflated and drain input)
+                  | inflate_flags::TINFL_FLAG_COMPUTE_ADLER32
+                  | inflate_flags::TINFL_FLAG_HAS_MORE_INPUT;
 
-        let to_skip = loop {
-            let new_decomp = &mut DecompressorOxide::new();
+        // the decompressor keeps its state when more data is
+        // needed, so each byte is only inflated once
+        self.decompressor.init();
+        let mut read = 0;
+        let mut written = 0;
 
-            match decompress(new_decomp, &*self.buffer, &mut inflated, 0, flags) {
-                (TINFLStatus::Done, read, written) => match written == size {
-                    true => break read,
-                    false => (),
+        loop {
+            let input = &self.buffer[read..];
+            let (status, in_progress, out_progress) = decompress(&mut self.decompressor, input, &mut inflated, written, flags);
+            read += in_progress;
+            written += out_progress;
+
+            match status {
+                TINFLStatus::Done if written == size => break,
+                TINFLStatus::NeedsMoreInput => {
+                    self.read_line()?;
                 },
-                (TINFLStatus::FailedCannotMakeProgress, _, _) => (),
                 e => {
-                    log::error!("inflate() => {:?}", e);
+                    log::error!("inflate() => {:?} ({}/{} bytes)", e, written, size);
                     return Err(IPF);
                 },
             }
+        }
 
-            self.read_line()?;
-        };
+        Ok((inflated, read))
+    }
 
-        self.buffer.drain(0..to_skip);
+    pub fn read_all_objects(&mut self, objects: &mut ObjectStore) -> Result<()> {
+        self.read_all_objects_with(objects, false)
+    }
 
-        match encoding {
-            ObjectEncoding::Commit => Ok(PackfileObject::Commit(inflated)),
-            ObjectEncoding::Tree => Ok(PackfileObject::Tree(inflated)),
-            ObjectEncoding::Blob => Ok(PackfileObject::Blob(inflated)),
-            ObjectEncoding::Tag => Ok(PackfileObject::Tag(inflated)),
-            ObjectEncoding::OfsDelta => Err(IPF),
-            ObjectEncoding::RefDelta => Ok(PackfileObject::RefDelta(inflated, hash)),
-        }
+    /// Also keeps the compressed data of non-delta objects in
+    /// them, see [`Object::compressed`]
+    pub(crate) fn read_all_objects_with(&mut self, objects: &mut ObjectStore, keep_compressed: bool) -> Result<()> {
+        self.read_objects_into(objects, keep_compressed, None)
     }
 
-    pub fn read_all_objects(&mut self, objects: &mut ObjectStore) -> Result<()> {
+    /// Same as [`Self::read_all_objects_with`]; objects which weren't
+    /// in the store are listed in `added`, even if this fails
+    pub(crate) fn read_objects_into(
+        &mut self,
+        objects: &mut ObjectStore,
+        keep_compressed: bool,
+        mut added: Option<&mut Vec<Hash>>,
+    ) -> Result<()> {
         let mut pending_delta = Vec::new();
+        let mut compressed = Vec::new();
+        // hashes of the entries read so far, for `OfsDelta` bases
+        let mut by_offset = HashMap::new();
 
         for _ in 0..self.num_objects {
-            let object = self.next_object()?;
-
-            if let PackfileObject::RefDelta(delta, hash) = object {
-                if let Some(src) = objects.get(hash) {
-                    let src_type = src.obj_type();
-                    let dst = reconstruct(&delta, src.content())?;
-                    let result_hash = objects.insert(src_type, dst, Some(hash));
-                    log::trace!("Reconstructed {:>6} {}", src_type, result_hash);
-                } else {
-                    log::trace!("Missing delta source {}, will try again later", hash);
-                    pending_delta.push((delta, hash));
-                }
-            } else {
-                let (typ, hash) = match object {
-                    PackfileObject::Commit(obj) => ("commit", objects.insert(ObjectType::Commit, obj, None)),
-                    PackfileObject::Tree(obj) => ("tree", objects.insert(ObjectType::Tree, obj, None)),
-                    PackfileObject::Blob(obj) => ("blob", objects.insert(ObjectType::Blob, obj, None)),
-                    PackfileObject::Tag(obj) => ("tag", objects.insert(ObjectType::Tag, obj, None)),
-                    _ => unreachable!(),
-                };
+            compressed.clear();
+            let offset = self.offset;
+            let object = self.next_object_with(keep_compressed.then_some(&mut compressed))?;
+
+            let (obj_type, content) = match object {
+                PackfileObject::Commit(obj) => (ObjectType::Commit, obj),
+                PackfileObject::Tree(obj) => (ObjectType::Tree, obj),
+                PackfileObject::Blob(obj) => (ObjectType::Blob, obj),
+                PackfileObject::Tag(obj) => (ObjectType::Tag, obj),
+                // deltas are only reused with their base
+                PackfileObject::OfsDelta(delta, distance) => {
+                    let base = DeltaBase::Offset(offset - distance);
+                    if !resolve_delta(objects, &mut added, &mut by_offset, offset, base, &delta)? {
+                        pending_delta.push((offset, base, delta));
+                    }
+
+                    continue;
+                },
+                PackfileObject::RefDelta(delta, hash) => {
+                    if !resolve_delta(objects, &mut added, &mut by_offset, offset, DeltaBase::Hash(hash), &delta)? {
+                        log::trace!("Missing delta source {}, will try again later", hash);
+                        pending_delta.push((offset, DeltaBase::Hash(hash), delta));
+                    }
 
-                log::trace!("Inserted {:>11} {}", typ, hash);
+                    continue;
+                },
+            };
+
+            let mut entry = Object::new(obj_type, content, None);
+            if keep_compressed {
+                entry = entry.with_compressed(compressed.as_slice());
             }
+
+            let hash = insert_tracked(objects, &mut added, entry)?;
+            by_offset.insert(offset, hash);
+            log::trace!("Inserted {:>11} {}", obj_type, hash);
         }
 
+        // bases of thin packs can be deltas which come later in the pack
         while !pending_delta.is_empty() {
-            for i in 0..pending_delta.len() {
-                let (delta, hash) = &pending_delta[i];
-                if let Some(src) = objects.get(*hash) {
-                    let src_type = src.obj_type();
-                    let dst = reconstruct(&delta, src.content())?;
-                    let result_hash = objects.insert(src_type, dst, Some(*hash));
-                    pending_delta.remove(i);
-
-                    log::trace!("Reconstructed {:>6} {}", src_type, result_hash);
-                    break;
+            let count = pending_delta.len();
+            let mut i = 0;
+
+            while i < pending_delta.len() {
+                let (offset, base, delta) = &pending_delta[i];
+                match resolve_delta(objects, &mut added, &mut by_offset, *offset, *base, delta)? {
+                    true => { pending_delta.swap_remove(i); },
+                    false => i += 1,
                 }
             }
 
-            log::error!("Can't reconstruct delta: missing objects");
-            return Err(IPF);
+            if pending_delta.len() == count {
+                log::error!("Can't reconstruct {} deltas: missing objects", count);
+                return Err(IPF);
+            }
         }
 
         Ok(())
     }
 }
 
+/// Inserts an object, listing it in `added` if it's new
+fn insert_tracked(objects: &mut ObjectStore, added: &mut Option<&mut Vec<Hash>>, entry: Object) -> Result<Hash> {
+    let Some(added) = added else { return objects.insert_entry(entry) };
+
+    let hash = objects.hash(entry.obj_type(), entry.content())?;
+    if !objects.has(hash) {
+        added.push(hash);
+    }
+
+    objects.insert_hashed(hash, entry)?;
+    Ok(hash)
+}
+
+/// Applies a delta if its base is known; returns false otherwise
+fn resolve_delta(
+    objects: &mut ObjectStore,
+    added: &mut Option<&mut Vec<Hash>>,
+    by_offset: &mut HashMap<usize, Hash>,
+    offset: usize,
+    base: DeltaBase,
+    delta: &[u8],
+) -> Result<bool> {
+    let base = match base {
+        DeltaBase::Offset(base) => by_offset.get(&base).copied(),
+        DeltaBase::Hash(hash) => Some(hash),
+    };
+
+    let Some(src) = base.and_then(|hash| objects.get(hash)) else { return Ok(false) };
+    let src_type = src.obj_type();
+    let dst = reconstruct(delta, src.content())?;
+    let result_hash = insert_tracked(objects, added, Object::new(src_type, dst, base))?;
+    by_offset.insert(offset, result_hash);
+
+    log::trace!("Reconstructed {:>6} {}", src_type, result_hash);
+    Ok(true)
+}
+
+/// Appends data from `reader` to `buffer`; returns the buffer length
+fn read_chunk(reader: &mut dyn Read, buffer: &mut Vec<u8>) -> Result<usize> {
+    let len = buffer.len();
+    buffer.resize(len + READ_CHUNK, 0);
+
+    let read = loop {
+        match reader.read(&mut buffer[len..]) {
+            Ok(read) => break read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => (),
+            Err(error) => {
+                buffer.truncate(len);
+                return Err(error.into());
+            },
+        }
+    };
+
+    buffer.truncate(len + read);
+    match read {
+        0 => {
+            log::error!("Unexpected end of packfile");
+            Err(IPF)
+        },
+        _ => Ok(buffer.len()),
+    }
+}
+
+/// Result of [`verify_packfile`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackSummary {
+    pub commits: usize,
+    pub trees: usize,
+    pub blobs: usize,
+    pub tags: usize,
+    /// Entries stored as deltas, which are also counted by type
+    /// once resolved
+    pub deltas: usize,
+    /// Longest chain of deltas
+    pub max_delta_depth: usize,
+    /// Bases of deltas which aren't in the pack, as in thin packs;
+    /// these deltas (and the ones based on them) aren't resolved
+    pub missing_bases: Vec<Hash>,
+    /// Deltas which couldn't be resolved because of missing bases
+    pub unresolved: usize,
+    /// Trailing checksum of the pack
+    pub checksum: Hash,
+}
+
+impl PackSummary {
+    /// Number of entries in the pack
+    pub fn objects(&self) -> usize {
+        self.commits + self.trees + self.blobs + self.tags + self.unresolved
+    }
+}
+
+/// Checks a packfile without importing its objects, like `git verify-pack`
+///
+/// The header is checked, every entry is inflated, deltas are
+/// resolved and the trailing checksum is verified. Resolved objects
+/// are kept in memory until the end. Useful to debug server output:
+///
+/// ```ignore
+/// let summary = verify_packfile(File::open("pack-1234.pack")?)?;
+/// println!("{} objects, {} deltas", summary.objects(), summary.deltas);
+/// ```
+///
+/// Returns `InvalidPackfile` if the pack is malformed, if a delta
+/// cannot be applied, if an offset delta has no base or if the
+/// checksum is wrong, and `IoError` if reading fails.
+pub fn verify_packfile<R: Read>(reader: R) -> Result<PackSummary> {
+    let mut pack = PackfileReader::from_reader(reader)?;
+
+    let mut summary = PackSummary {
+        commits: 0,
+        trees: 0,
+        blobs: 0,
+        tags: 0,
+        deltas: 0,
+        max_delta_depth: 0,
+        missing_bases: Vec::new(),
+        unresolved: 0,
+        checksum: Hash::zero(),
+    };
+
+    // resolved objects: type, content & delta depth
+    let mut by_offset = HashMap::<usize, (ObjectType, Arc<[u8]>, usize)>::new();
+    let mut by_hash = HashMap::new();
+    let mut pending = Vec::new();
+
+    for _ in 0..pack.num_objects {
+        let offset = pack.offset;
+        let (encoding, size, base) = pack.read_entry_header()?;
+        let (inflated, compressed_size) = pack.inflate(size)?;
+        pack.consume(compressed_size);
+
+        match (encoding.object_type(), base) {
+            (Some(obj_type), _) => {
+                by_hash.insert(object_hash(obj_type, &inflated)?, offset);
+                by_offset.insert(offset, (obj_type, inflated.into(), 0));
+            },
+            (None, Some(base)) => {
+                summary.deltas += 1;
+                pending.push((offset, base, inflated));
+            },
+            (None, None) => return Err(IPF),
+        }
+    }
+
+    // deltas can depend on deltas which come later in the pack
+    loop {
+        let count = pending.len();
+        let mut i = 0;
+
+        while i < pending.len() {
+            let base_offset = match pending[i].1 {
+                DeltaBase::Offset(offset) => Some(offset),
+                DeltaBase::Hash(hash) => by_hash.get(&hash).copied(),
+            };
+
+            let Some((obj_type, base, depth)) = base_offset.and_then(|offset| by_offset.get(&offset)) else {
+                i += 1;
+                continue;
+            };
+
+            let (offset, _, delta) = pending.swap_remove(i);
+            let (obj_type, depth) = (*obj_type, depth + 1);
+            let content = reconstruct(&delta, base)?;
+
+            summary.max_delta_depth = summary.max_delta_depth.max(depth);
+            by_hash.insert(object_hash(obj_type, &content)?, offset);
+            by_offset.insert(offset, (obj_type, content.into(), depth));
+        }
+
+        if pending.is_empty() || pending.len() == count {
+            break;
+        }
+    }
+
+    for (offset, base, _) in &pending {
+        match base {
+            DeltaBase::Hash(hash) if !by_hash.contains_key(hash) => {
+                if !summary.missing_bases.contains(hash) {
+                    summary.missing_bases.push(*hash);
+                }
+            },
+            DeltaBase::Hash(_) => (),
+            DeltaBase::Offset(base) if !by_offset.contains_key(base) && !pending.iter().any(|(o, _, _)| o == base) => {
+                log::error!("Delta at offset {} has no base at offset {}", offset, base);
+                return Err(IPF);
+            },
+            DeltaBase::Offset(_) => (),
+        }
+    }
+
+    summary.unresolved = pending.len();
+    for (obj_type, _, _) in by_offset.values() {
+        match obj_type {
+            ObjectType::Commit => summary.commits += 1,
+            ObjectType::Tree => summary.trees += 1,
+            ObjectType::Blob => summary.blobs += 1,
+            ObjectType::Tag => summary.tags += 1,
+        }
+    }
+
+    summary.checksum = pack.verify_checksum()?;
+
+    Ok(summary)
+}
+
 fn read_hdr_size(delta: &[u8], i: &mut usize) -> Result<usize> {
     let mut size = 0;
     let mut shift = 0;
@@ -302,7 +767,7 @@ fn checked_shift_add(src: u8, dst: &mut usize, shift: &mut usize, shift_inc: usi
     }
 }
 
-fn reconstruct(delta: &[u8], src: &[u8]) -> Result<Box<[u8]>> {
+pub(crate) fn reconstruct(delta: &[u8], src: &[u8]) -> Result<Box<[u8]>> {
     let mut i = 0;
     let _src_buf_size = read_hdr_size(&delta, &mut i)?;
     let dst_buf_size = read_hdr_size(&delta, &mut i)?;
@@ -368,6 +833,103 @@ fn reconstruct(delta: &[u8], src: &[u8]) -> Result<Box<[u8]>> {
     Ok(dst.into_boxed_slice())
 }
 
+/// Smallest base length for [`make_delta`]
+const DELTA_BLOCK: usize = 16;
+
+fn write_delta_size(mut size: usize, delta: &mut Vec<u8>) {
+    loop {
+        let byte = size as u8 & 0x7f;
+        size >>= 7;
+        match size {
+            0 => break delta.push(byte),
+            _ => delta.push(byte | BYTE_MSB),
+        }
+    }
+}
+
+fn write_delta_insert(data: &[u8], delta: &mut Vec<u8>) {
+    for chunk in data.chunks(0x7f) {
+        delta.push(chunk.len() as u8);
+        delta.extend_from_slice(chunk);
+    }
+}
+
+fn write_delta_copy(mut offset: usize, mut len: usize, delta: &mut Vec<u8>) {
+    while len > 0 {
+        let chunk = len.min(0xffff);
+        let mut instruction = BYTE_MSB;
+        let mut args = Vec::with_capacity(6);
+
+        for i in 0..4 {
+            let byte = (offset >> (i * 8)) as u8;
+            if byte != 0 {
+                instruction |= 1 << i;
+                args.push(byte);
+            }
+        }
+
+        for i in 0..2 {
+            let byte = (chunk >> (i * 8)) as u8;
+            if byte != 0 {
+                instruction |= 0x10 << i;
+                args.push(byte);
+            }
+        }
+
+        delta.push(instruction);
+        delta.extend_from_slice(&args);
+        offset += chunk;
+        len -= chunk;
+    }
+}
+
+/// Encodes `target` as a delta against `base`, see [`reconstruct`]
+///
+/// Returns `None` if the delta wouldn't be smaller than `max_size`.
+fn make_delta(base: &[u8], target: &[u8], max_size: usize) -> Option<Vec<u8>> {
+    if base.len() < DELTA_BLOCK || base.len() > u32::MAX as usize {
+        return None;
+    }
+
+    let mut index = HashMap::new();
+    for offset in (0..=base.len() - DELTA_BLOCK).step_by(DELTA_BLOCK) {
+        index.entry(&base[offset..][..DELTA_BLOCK]).or_insert(offset);
+    }
+
+    let mut delta = Vec::new();
+    write_delta_size(base.len(), &mut delta);
+    write_delta_size(target.len(), &mut delta);
+
+    let mut inserted = 0;
+    let mut i = 0;
+    while i + DELTA_BLOCK <= target.len() {
+        let Some(offset) = index.get(&target[i..][..DELTA_BLOCK]).copied() else {
+            i += 1;
+            continue;
+        };
+
+        let extra = base[offset + DELTA_BLOCK..].iter()
+            .zip(&target[i + DELTA_BLOCK..])
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        write_delta_insert(&target[inserted..i], &mut delta);
+        write_delta_copy(offset, DELTA_BLOCK + extra, &mut delta);
+        i += DELTA_BLOCK + extra;
+        inserted = i;
+
+        if delta.len() >= max_size {
+            return None;
+        }
+    }
+
+    write_delta_insert(&target[inserted..], &mut delta);
+    match delta.len() < max_size {
+        true => Some(delta),
+        false => None,
+    }
+}
+
 fn write_encoding_size<W: Write>(mut size: usize, encoding: u8, dst: &mut W) {
     assert!(encoding < 8);
 
@@ -391,58 +953,122 @@ pub fn dump_packfile_header<W: Write>(num_objects: usize, dst: &mut W) {
 }
 
 pub fn dump_packfile_object<W: Write>(object: PackfileObject<&[u8]>, dst: &mut W) {
-    use TDEFLStatus::*;
+    dump_packfile_object_with(object, PACK_COMPRESSION, dst)
+}
+
+/// Same as [`dump_packfile_object`], with a zlib level from 0 to 9
+///
+/// Entries are compressed with [`Miniz`].
+pub fn dump_packfile_object_with<W: Write>(object: PackfileObject<&[u8]>, level: u8, dst: &mut W) {
+    let inflated = dump_packfile_object_header(object, dst);
+    dst.write_all(&Miniz.compress(inflated, level)).unwrap();
+}
 
-    let (inflated, hash, code) = match object {
+/// Writes the header of an entry; returns the data to compress
+fn dump_packfile_object_header<'o, W: Write>(object: PackfileObject<&'o [u8]>, dst: &mut W) -> &'o [u8] {
+    let (inflated, base, code) = match object {
         PackfileObject::Commit(bytes) => (bytes, None, 1),
         PackfileObject::Tree(bytes) => (bytes, None, 2),
         PackfileObject::Blob(bytes) => (bytes, None, 3),
         PackfileObject::Tag(bytes) => (bytes, None, 4),
-        PackfileObject::OfsDelta(_, _) => unreachable!(),
-        PackfileObject::RefDelta(bytes, hash) => (bytes, Some(hash), 7),
+        PackfileObject::OfsDelta(bytes, distance) => (bytes, Some(DeltaBase::Offset(distance)), 6),
+        PackfileObject::RefDelta(bytes, hash) => (bytes, Some(DeltaBase::Hash(hash)), 7),
     };
 
     let size = inflated.len();
 
     write_encoding_size(size, code, dst);
 
-    if let Some(hash) = hash {
-        dst.write(&hash.to_bytes()).unwrap();
+    match base {
+        Some(DeltaBase::Hash(hash)) => dst.write_all(&hash.to_bytes()).unwrap(),
+        Some(DeltaBase::Offset(distance)) => write_base_offset(distance, dst),
+        None => (),
     }
 
-    let flags = deflate_flags::TDEFL_COMPUTE_ADLER32
-              | deflate_flags::TDEFL_FILTER_MATCHES
-              | deflate_flags::TDEFL_WRITE_ZLIB_HEADER;
+    inflated
+}
 
-    let mut comp = CompressorOxide::new(flags);
-    let mut to_deflate = &inflated[..];
-    let mut buf = [0; 8096];
+/// Writes the distance to the base of an `OfsDelta`,
+/// see [`PackfileReader::read_base_offset`]
+fn write_base_offset<W: Write>(mut distance: usize, dst: &mut W) {
+    let mut bytes = [0; 10];
+    let mut i = bytes.len() - 1;
+    bytes[i] = distance as u8 & 0x7f;
+
+    while distance >= 0x80 {
+        distance = (distance >> 7) - 1;
+        i -= 1;
+        bytes[i] = BYTE_MSB | (distance as u8 & 0x7f);
+    }
 
-    loop {
-        let flush = match to_deflate.is_empty() {
-            true => TDEFLFlush::Finish,
-            false => TDEFLFlush::None,
-        };
+    dst.write_all(&bytes[i..]).unwrap();
+}
 
-        match compress(&mut comp, to_deflate, &mut buf, flush) {
-            (Okay | PutBufFailed, in_progress, out_progress) => {
-                dst.write(&buf[..out_progress]).unwrap();
-                to_deflate = &to_deflate[in_progress..];
-            },
-            (Done, _, out_progress) => {
-                dst.write(&buf[..out_progress]).unwrap();
-                break;
-            },
-            e => panic!("deflate() => {:?}", e),
-        };
-    }
+/// Entry of a packfile whose compression is deferred,
+/// see [`Packer::finish`]
+#[cfg(feature = "parallel")]
+enum PendingEntry {
+    /// Copied as is
+    Raw(Vec<u8>),
+    /// Header, followed by the data to compress
+    Deflate(Vec<u8>, Content),
+}
+
+/// Compresses entries on `threads` threads, writing them in order
+#[cfg(feature = "parallel")]
+fn dump_parallel<W: Write>(entries: &[PendingEntry], codec: &dyn Codec, level: u8, threads: usize, dst: &mut W) {
+    use std::{thread, sync::{mpsc, atomic::{AtomicUsize, Ordering}}};
+
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let (sender, next) = (sender.clone(), &next);
+            scope.spawn(move || {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let compressed = match entries.get(i) {
+                        Some(PendingEntry::Deflate(_, data)) => codec.compress(data, level),
+                        Some(PendingEntry::Raw(_)) => Vec::new(),
+                        None => break,
+                    };
+
+                    if sender.send((i, compressed)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        drop(sender);
+
+        // entries compressed out of order wait for their predecessors
+        let mut ready = HashMap::new();
+        let mut written = 0;
+        for (i, compressed) in receiver {
+            ready.insert(i, compressed);
+            while let Some(compressed) = ready.remove(&written) {
+                match &entries[written] {
+                    PendingEntry::Raw(bytes) => dst.write_all(bytes).unwrap(),
+                    PendingEntry::Deflate(header, _) => {
+                        dst.write_all(header).unwrap();
+                        dst.write_all(&compressed).unwrap();
+                    },
+                }
+
+                written += 1;
+            }
+        }
+    });
 }
 
 pub struct PackfileSender<'a> {
     protocol: GitProtocol<'a>,
     buffer: Vec<u8>,
     result: Result<()>,
-    hasher: Sha1,
+    hasher: Hasher,
+    offset: usize,
 }
 
 impl<'a> PackfileSender<'a> {
@@ -451,12 +1077,28 @@ impl<'a> PackfileSender<'a> {
             protocol,
             buffer: Vec::new(),
             result: Ok(()),
-            hasher: Sha1::new(),
+            hasher: Hasher::new(),
+            offset: 0,
         }
     }
 
+    /// Number of bytes written so far, header included
+    ///
+    /// This is the offset of the next entry; remembering it allows
+    /// later entries to be written as `OfsDelta`s:
+    ///
+    /// ```ignore
+    /// let base_offset = sender.offset();
+    /// dump_packfile_object(PackfileObject::Blob(base), &mut sender);
+    /// let distance = sender.offset() - base_offset;
+    /// dump_packfile_object(PackfileObject::OfsDelta(delta, distance), &mut sender);
+    /// ```
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
     pub fn finish(mut self) -> Result<GitProtocol<'a>> {
-        let checksum: [u8; 20] = self.hasher.clone().finalize().into();
+        let checksum = finalize(self.hasher.clone())?;
         self.buffer.extend_from_slice(&checksum);
         self.flush().unwrap();
         self.result?;
@@ -469,6 +1111,7 @@ const MAX: usize = 64000;
 impl<'a> Write for PackfileSender<'a> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         self.hasher.update(buf);
+        self.offset += buf.len();
         self.buffer.extend_from_slice(buf);
         if self.buffer.len() > MAX {
             self.flush()?;
@@ -490,8 +1133,239 @@ impl<'a> Write for PackfileSender<'a> {
     }
 }
 
+/// Settings of the packfiles built by [`Repository::pack`],
+/// see [`Repository::set_pack_options`]
+///
+/// [`Repository::pack`]: crate::Repository::pack
+/// [`Repository::set_pack_options`]: crate::Repository::set_pack_options
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PackOptions {
+    /// zlib level, from 0 (fastest, largest packs) to 9 (slowest,
+    /// smallest packs); defaults to 1
+    pub compression_level: u8,
+    /// Number of previously packed objects tried as delta bases
+    /// for each object, like `pack.window` in git; defaults to 0,
+    /// which disables deltas
+    pub window: usize,
+    /// Threads compressing objects; 0 uses one per CPU
+    ///
+    /// Objects are compressed on the calling thread unless the
+    /// `parallel` feature is enabled. With more than one thread,
+    /// the whole pack is prepared before it's written.
+    pub threads: usize,
+    /// Compresses objects twice, once to compute the size of the
+    /// pack, instead of keeping the compressed pack in memory
+    ///
+    /// This is slower, but memory use doesn't grow with the size
+    /// of the pack; defaults to false.
+    pub two_pass: bool,
+    /// Deltas reference their base by offset (`OfsDelta`) rather
+    /// than by hash, which makes them smaller; defaults to true
+    ///
+    /// Pushes fall back to hashes if the remote server doesn't
+    /// support the `ofs-delta` capability. Deltas are always
+    /// referenced by hash when compressing on multiple threads.
+    pub ofs_delta: bool,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            compression_level: PACK_COMPRESSION,
+            window: 0,
+            threads: 1,
+            two_pass: false,
+            ofs_delta: true,
+        }
+    }
+}
+
+/// Longest chain of deltas in built packfiles, like `pack.depth` in git
+const MAX_DELTA_DEPTH: usize = 50;
+
+/// State of [`ObjectStore::pack_with`]
+pub(crate) struct Packer {
+    options: PackOptions,
+    /// Delta chain depth of the objects packed so far
+    depths: HashMap<Hash, usize>,
+    /// Offset of the entries of these objects, from the first one
+    offsets: HashMap<Hash, usize>,
+    /// Bytes written so far; unknown if `pending` is set
+    written: usize,
+    /// Last packed objects, tried as delta bases
+    recent: VecDeque<Hash>,
+    /// Set if objects are compressed on multiple threads
+    #[cfg(feature = "parallel")]
+    pending: Option<Vec<PendingEntry>>,
+    /// Commits whose parents aren't packed
+    shallow: BTreeSet<Hash>,
+    codec: Arc<dyn Codec>,
+}
+
+impl Packer {
+    pub(crate) fn new(options: PackOptions) -> Self {
+        Self {
+            #[cfg(feature = "parallel")]
+            pending: (options.threads != 1).then(Vec::new),
+            options,
+            depths: HashMap::new(),
+            offsets: HashMap::new(),
+            written: 0,
+            recent: VecDeque::new(),
+            shallow: BTreeSet::new(),
+            codec: Arc::new(Miniz),
+        }
+    }
+
+    /// Compresses entries with `codec`,
+    /// see [`Repository::set_zlib_codec`]
+    ///
+    /// [`Repository::set_zlib_codec`]: crate::Repository::set_zlib_codec
+    pub(crate) fn with_codec(mut self, codec: Arc<dyn Codec>) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    /// Stops at shallow boundaries, see [`Repository::is_shallow`]
+    ///
+    /// [`Repository::is_shallow`]: crate::Repository::is_shallow
+    pub(crate) fn stop_at(mut self, shallow: &BTreeSet<Hash>) -> Self {
+        self.shallow = shallow.clone();
+        self
+    }
+
+    /// True if entries are deferred until [`Self::finish`]
+    #[cfg(feature = "parallel")]
+    fn is_parallel(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn is_parallel(&self) -> bool {
+        false
+    }
+
+    /// Writes an entry, or defers it if compression is parallel
+    fn dump<W: Write>(&mut self, object: PackfileObject<&[u8]>, data: &Content, dst: &mut W) {
+        let mut header = Vec::new();
+
+        #[cfg(feature = "parallel")]
+        if let Some(pending) = &mut self.pending {
+            dump_packfile_object_header(object, &mut header);
+            pending.push(PendingEntry::Deflate(header, data.clone()));
+            return;
+        }
+
+        #[cfg(not(feature = "parallel"))]
+        let _ = data;
+
+        let data = dump_packfile_object_header(object, &mut header);
+        let compressed = self.codec.compress(data, self.options.compression_level);
+        dst.write_all(&header).unwrap();
+        dst.write_all(&compressed).unwrap();
+        self.written += header.len() + compressed.len();
+    }
+
+    /// Distance back to the entry of a packed object,
+    /// if it can be written as an `OfsDelta` base
+    fn distance_to(&self, base: Hash) -> Option<usize> {
+        match self.options.ofs_delta && !self.is_parallel() {
+            true => self.offsets.get(&base).map(|offset| self.written - offset),
+            false => None,
+        }
+    }
+
+    /// Copies the compressed form of an object, if it was kept
+    /// (see [`Object::compressed`]); returns false otherwise
+    fn reuse<W: Write>(&mut self, object: &Object, dst: &mut W) -> bool {
+        let Some(compressed) = object.compressed() else { return false };
+
+        let mut bytes = Vec::with_capacity(compressed.len() + 10);
+        write_encoding_size(object.content().len(), type_code(object.obj_type()), &mut bytes);
+        bytes.extend_from_slice(compressed);
+
+        #[cfg(feature = "parallel")]
+        if let Some(pending) = &mut self.pending {
+            pending.push(PendingEntry::Raw(bytes));
+            return true;
+        }
+
+        dst.write_all(&bytes).unwrap();
+        self.written += bytes.len();
+        true
+    }
+
+    /// Writes the entries which were deferred by [`Self::dump`]
+    ///
+    /// Must be called once all objects are packed.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn finish<W: Write>(&mut self, dst: &mut W) {
+        let Some(pending) = self.pending.take() else { return };
+        let threads = match self.options.threads {
+            0 => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            threads => threads,
+        };
+
+        dump_parallel(&pending, &*self.codec, self.options.compression_level, threads, dst);
+    }
+
+    /// Entries are never deferred without the `parallel` feature
+    #[cfg(not(feature = "parallel"))]
+    pub(crate) fn finish<W: Write>(&mut self, _dst: &mut W) {}
+}
+
 impl ObjectStore {
     pub fn pack<W: Write>(&self, object: Hash, to_skip: &mut HashSet<Hash>, dst: &mut W) -> Result<usize> {
+        let mut packer = Packer::new(PackOptions::default());
+        let count = self.pack_with(object, to_skip, &mut packer, dst)?;
+        packer.finish(dst);
+        Ok(count)
+    }
+
+    /// Finds the smallest delta against a packed object, if any;
+    /// returns the delta, its base & the depth of the chain
+    ///
+    /// The hinted base can also be one the receiver already has
+    /// (it's in `to_skip`), which makes a thin pack.
+    fn find_delta(
+        &self,
+        object: Hash,
+        content: &[u8],
+        hint: Option<Hash>,
+        to_skip: &HashSet<Hash>,
+        packer: &Packer,
+    ) -> Option<(Vec<u8>, Hash, usize)> {
+        if packer.options.window == 0 {
+            return None;
+        }
+
+        let obj_type = self.load(object)?.obj_type();
+        let mut best: Option<(Vec<u8>, Hash, usize)> = None;
+        let candidates = hint.into_iter().chain(packer.recent.iter().rev().copied());
+
+        for base in candidates.filter(|base| *base != object) {
+            let external = (Some(base) == hint && to_skip.contains_key(&base)).then_some(0);
+            let depth = packer.depths.get(&base).copied().or(external);
+            let Some(depth) = depth.filter(|depth| *depth < MAX_DELTA_DEPTH) else { continue };
+            let Some(base_object) = self.load(base).filter(|base| base.obj_type() == obj_type) else { continue };
+
+            let max_size = best.as_ref().map(|(delta, _, _)| delta.len()).unwrap_or(content.len() / 2);
+            if let Some(delta) = make_delta(base_object.content(), content, max_size) {
+                best = Some((delta, base, depth + 1));
+            }
+        }
+
+        best
+    }
+
+    /// Packs an object after the objects it references
+    pub(crate) fn pack_with<W: Write>(
+        &self,
+        object: Hash,
+        to_skip: &mut HashSet<Hash>,
+        packer: &mut Packer,
+        dst: &mut W,
+    ) -> Result<usize> {
         if to_skip.contains_key(&object) {
             return Ok(0);
         }
@@ -503,47 +1377,173 @@ impl ObjectStore {
 
         let mut count = 1;
 
-        let entry = self.get(object).ok_or(Error::MissingObject)?;
+        // disk-backed stores don't keep packed objects in memory
+        let entry = self.load(object).ok_or(Error::MissingObject)?;
         match entry.obj_type() {
             ObjectType::Commit => {
-                let mut iter = CommitParentsIter::new(&entry.content());
-                while let Some(hash) = iter.next()? {
-                    count += self.pack(hash, to_skip, dst)?;
+                let commit = Commit::parse(entry.content())?;
+                if !packer.shallow.contains(&object) {
+                    for parent in commit.parents {
+                        count += self.pack_with(parent, to_skip, packer, dst)?;
+                    }
                 }
 
-                let tree = get_commit_field_hash(&entry.content(), CommitField::Tree)?;
-                count += self.pack(tree.ok_or(Error::InvalidObject)?, to_skip, dst)?;
+                count += self.pack_with(commit.tree, to_skip, packer, dst)?;
             },
             ObjectType::Tree => {
                 let mut iter = TreeIter::new(&entry.content());
                 while let Some((_, hash, _)) = iter.next()? {
-                    count += self.pack(hash, to_skip, dst)?;
+                    count += self.pack_with(hash, to_skip, packer, dst)?;
                 }
             },
             ObjectType::Blob => (),
             ObjectType::Tag => (),
         }
 
-        let raw_dump = true;
-        if let Some(other_object) = entry.delta_hint() {
-            if other_object != object {
-                // todo
-            } else {
-                log::warn!("object's delta_hint was itself");
-            }
+        let hint = entry.delta_hint();
+        if hint == Some(object) {
+            log::warn!("object's delta_hint was itself");
         }
 
-        if raw_dump {
-            dump_packfile_object(match entry.obj_type() {
-                ObjectType::Commit => PackfileObject::Commit(&entry.content()),
-                ObjectType::Tree => PackfileObject::Tree(&entry.content()),
-                ObjectType::Blob => PackfileObject::Blob(&entry.content()),
-                ObjectType::Tag => PackfileObject::Tag(&entry.content()),
-            }, dst);
+        let mut depth = 0;
+        let offset = packer.written;
+        if !packer.reuse(&entry, dst) {
+            match self.find_delta(object, entry.content(), hint, to_skip, packer) {
+                Some((delta, base, delta_depth)) => {
+                    depth = delta_depth;
+                    let data = Arc::new(delta);
+                    let object = match packer.distance_to(base) {
+                        Some(distance) => PackfileObject::OfsDelta(&data[..], distance),
+                        None => PackfileObject::RefDelta(&data[..], base),
+                    };
+
+                    packer.dump(object, &data, dst);
+                },
+                None => {
+                    let data = entry.content_arc();
+                    packer.dump(match entry.obj_type() {
+                        ObjectType::Commit => PackfileObject::Commit(&data[..]),
+                        ObjectType::Tree => PackfileObject::Tree(&data[..]),
+                        ObjectType::Blob => PackfileObject::Blob(&data[..]),
+                        ObjectType::Tag => PackfileObject::Tag(&data[..]),
+                    }, &data, dst);
+                },
+            }
         }
 
         to_skip.insert(object, ());
 
+        if packer.options.window > 0 {
+            packer.depths.insert(object, depth);
+            packer.offsets.insert(object, offset);
+            packer.recent.push_back(object);
+            if packer.recent.len() > packer.options.window {
+                packer.recent.pop_front();
+            }
+        }
+
         Ok(count)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Repository, FileType};
+
+    #[test]
+    fn ofs_delta_round_trip() {
+        let mut repo = Repository::new();
+        let signature = ("Test", "test@example.com");
+        let mut text = (0..200).map(|i| format!("line {}\n", i)).collect::<String>();
+
+        for i in 0..5 {
+            text.push_str(&format!("appended {}\n", i));
+            repo.stage("file.txt", Some((text.clone().into_bytes(), FileType::RegularFile))).unwrap();
+            repo.commit("commit", signature, signature, Some(i)).unwrap();
+        }
+
+        repo.set_pack_options(PackOptions { window: 10, ..Default::default() });
+        let mut packfile = Vec::new();
+        repo.pack(HashSet::new(), &[("main", repo.head())], &mut packfile, |_, _| ()).unwrap();
+        append_checksum(&mut packfile);
+
+        let mut reader = PackfileReader::from_file(packfile.clone()).unwrap();
+        let encodings = reader.entries().map(|entry| entry.unwrap().encoding).collect::<Vec<_>>();
+        assert!(encodings.contains(&ObjectEncoding::OfsDelta));
+
+        let mut imported = Repository::new();
+        imported.import_packfile(packfile, Some(repo.head())).unwrap();
+        assert_eq!(imported.read_file("file.txt").unwrap(), text.as_bytes());
+        for (hash, _) in repo.objects.iter() {
+            assert!(imported.objects.has(hash));
+        }
+    }
+
+    /// `Repository::pack` leaves the trailer to the sender
+    fn append_checksum(packfile: &mut Vec<u8>) {
+        let mut hasher = Hasher::new();
+        hasher.update(&packfile);
+        packfile.extend_from_slice(&finalize(hasher).unwrap());
+    }
+
+    /// Packfile with a blob of `base` plus a line, as a delta against it
+    fn thin_packfile(base: &str) -> (Vec<u8>, Hash) {
+        let target = format!("{}appended\n", base);
+        let delta = make_delta(base.as_bytes(), target.as_bytes(), usize::MAX).unwrap();
+        let base_hash = object_hash(ObjectType::Blob, base.as_bytes()).unwrap();
+
+        let mut packfile = Vec::new();
+        dump_packfile_header(1, &mut packfile);
+        dump_packfile_object(PackfileObject::RefDelta(&delta, base_hash), &mut packfile);
+
+        append_checksum(&mut packfile);
+        (packfile, object_hash(ObjectType::Blob, target.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn thin_pack_with_refs() {
+        let base = (0..100).map(|i| format!("line {}\n", i)).collect::<String>();
+        let (packfile, target) = thin_packfile(&base);
+
+        let mut repo = Repository::new();
+        let base_hash = repo.objects.insert(ObjectType::Blob, base.clone(), None).unwrap();
+        repo.import_packfile_with_refs(packfile, &[("refs/tags/blob", target)]).unwrap();
+
+        assert_eq!(repo.get_ref("refs/tags/blob"), Some(target));
+        assert_eq!(repo.objects.get(target).unwrap().delta_hint(), Some(base_hash));
+    }
+
+    #[test]
+    fn failed_import_with_refs_is_rolled_back() {
+        let base = (0..100).map(|i| format!("line {}\n", i)).collect::<String>();
+        let (packfile, target) = thin_packfile(&base);
+
+        let mut repo = Repository::new();
+        repo.objects.insert(ObjectType::Blob, base, None).unwrap();
+        let missing = object_hash(ObjectType::Blob, b"missing").unwrap();
+        let refs = [("refs/tags/blob", target), ("refs/tags/missing", missing)];
+
+        assert!(matches!(repo.import_packfile_with_refs(packfile, &refs), Err(Error::MissingObject)));
+        assert!(!repo.objects.has(target));
+        assert_eq!(repo.objects.len(), 1);
+        assert_eq!(repo.get_ref("refs/tags/blob"), None);
+    }
+
+    #[test]
+    fn imports_verify_checksums() {
+        let base = (0..100).map(|i| format!("line {}\n", i)).collect::<String>();
+        let (mut packfile, target) = thin_packfile(&base);
+        *packfile.last_mut().unwrap() ^= 1;
+
+        let mut repo = Repository::new();
+        repo.objects.insert(ObjectType::Blob, base, None).unwrap();
+
+        let result = repo.import_packfile_with_refs(packfile.clone(), &[("refs/tags/blob", target)]);
+        assert!(matches!(result, Err(Error::InvalidPackfile)));
+        assert!(!repo.objects.has(target));
+
+        let result = repo.import_packfile_from(packfile.as_slice(), None);
+        assert!(matches!(result, Err(Error::InvalidPackfile)));
+    }
+}
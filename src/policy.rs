@@ -0,0 +1,219 @@
+use super::internals::{Result, Error, Hash, Repository, Signature};
+use super::tracking::UpstreamStatus;
+
+/// Callback of [`CommitPolicy::require_message`]
+///
+/// Returns `false` to reject a commit message.
+pub type MessageCheck = Box<dyn Fn(&str) -> bool + Send + Sync>;
+
+/// Callback of [`CommitPolicy::merge_template`]
+pub type MergeTemplate = Box<dyn Fn(&MergeInfo) -> String + Send + Sync>;
+
+/// Callback of [`CommitPolicy::check_upstream`]
+///
+/// Returns `false` to reject a commit based on this commit.
+pub type UpstreamCheck = Box<dyn Fn(UpstreamStatus) -> bool + Send + Sync>;
+
+/// What a merge commit merges, see [`Repository::merge_message`]
+#[derive(Copy, Clone, Debug)]
+pub struct MergeInfo<'a> {
+    /// Merged reference (`refs/heads/feature`, `refs/tags/v1.0`...)
+    /// or hex-encoded commit hash
+    pub source: &'a str,
+    /// Branch receiving the merge
+    pub into: &'a str,
+    /// Paths which had conflicts
+    pub conflicts: &'a [String],
+}
+
+impl<'a> MergeInfo<'a> {
+    /// Canonical git message, such as `Merge branch 'feature' into dev`
+    ///
+    /// Like git, ` into <branch>` is omitted for `main` & `master`,
+    /// and conflicting paths are listed in a `Conflicts:` paragraph.
+    pub fn default_message(&self) -> String {
+        let source = self.source;
+        let mut message = if let Some(branch) = source.strip_prefix("refs/heads/") {
+            format!("Merge branch '{}'", branch)
+        } else if let Some(branch) = source.strip_prefix("refs/remotes/") {
+            format!("Merge remote-tracking branch '{}'", branch)
+        } else if let Some(tag) = source.strip_prefix("refs/tags/") {
+            format!("Merge tag '{}'", tag)
+        } else if Hash::from_hex(source).is_some() {
+            format!("Merge commit '{}'", source)
+        } else {
+            format!("Merge branch '{}'", source)
+        };
+
+        let into = self.into.strip_prefix("refs/heads/").unwrap_or(self.into);
+        if !matches!(into, "main" | "master") {
+            message += &format!(" into {}", into);
+        }
+
+        if !self.conflicts.is_empty() {
+            message += "\n\nConflicts:";
+            for path in self.conflicts {
+                message += &format!("\n\t{}", path);
+            }
+        }
+
+        message
+    }
+}
+
+/// Rules applied to all new commits, see [`Repository::set_commit_policy`]
+///
+/// ```ignore
+/// let issue_ref = regex::Regex::new(r"#\d+")?;
+/// let policy = CommitPolicy::new()
+///     .sign_off(true)
+///     .committer("CI Bot", "ci@example.com")
+///     .require_message(Box::new(move |message| issue_ref.is_match(message)));
+///
+/// repo.set_commit_policy(policy);
+/// ```
+#[derive(Default)]
+pub struct CommitPolicy {
+    sign_off: bool,
+    committer: Option<(String, String)>,
+    message_check: Option<MessageCheck>,
+    merge_template: Option<MergeTemplate>,
+    upstream_check: Option<(String, UpstreamCheck)>,
+}
+
+impl CommitPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a `Signed-off-by` trailer with the committer identity
+    pub fn sign_off(mut self, enabled: bool) -> Self {
+        self.sign_off = enabled;
+        self
+    }
+
+    /// Replaces the name & email of all committers
+    ///
+    /// The committer timestamp is kept.
+    pub fn committer(mut self, name: &str, email: &str) -> Self {
+        self.committer = Some((name.into(), email.into()));
+        self
+    }
+
+    /// Rejects commits whose message doesn't pass `check`
+    pub fn require_message(mut self, check: MessageCheck) -> Self {
+        self.message_check = Some(check);
+        self
+    }
+
+    /// Generates the messages of merge commits
+    ///
+    /// By default, [`MergeInfo::default_message`] is used.
+    pub fn merge_template(mut self, template: MergeTemplate) -> Self {
+        self.merge_template = Some(template);
+        self
+    }
+
+    /// Checks the current commit against `tracking_ref`
+    /// before committing on top of it
+    ///
+    /// `check` receives the result of [`Repository::upstream_status`];
+    /// if it returns `false`, the commit is rejected with `OutdatedBase`.
+    /// Interactive applications can prompt the user to pull first:
+    ///
+    /// ```ignore
+    /// let policy = CommitPolicy::new()
+    ///     .check_upstream("refs/remotes/origin/main", Box::new(|status| !status.is_outdated()));
+    /// ```
+    ///
+    /// Commits with explicit parents (see [`CommitBuilder::parents`](crate::CommitBuilder::parents))
+    /// aren't checked: merges usually integrate upstream commits.
+    pub fn check_upstream(mut self, tracking_ref: &str, check: UpstreamCheck) -> Self {
+        self.upstream_check = Some((tracking_ref.into(), check));
+        self
+    }
+
+    /// Returns `OutdatedBase` if the upstream check rejects the current commit
+    pub(crate) fn check_base(&self, repo: &Repository) -> Result<()> {
+        let Some((tracking_ref, check)) = &self.upstream_check else { return Ok(()) };
+        let status = repo.upstream_status(tracking_ref)?;
+
+        match check(status) {
+            true => Ok(()),
+            false => {
+                log::error!("Commit rejected by policy: {} is {:?}", tracking_ref, status);
+                Err(Error::OutdatedBase)
+            },
+        }
+    }
+
+    /// Returns the committer to use instead of `committer`
+    pub(crate) fn committer_for<'a>(&'a self, committer: Signature<'a>) -> Signature<'a> {
+        match &self.committer {
+            Some((name, email)) => Signature {
+                name,
+                email,
+                ..committer
+            },
+            None => committer,
+        }
+    }
+
+    /// Returns `InvalidObject` if the message is rejected,
+    /// or the message with its trailers
+    pub(crate) fn apply(&self, message: &str, committer: &Signature) -> Result<String> {
+        if let Some(check) = &self.message_check {
+            if !check(message) {
+                log::error!("Commit message rejected by policy: {:?}", message);
+                return Err(Error::InvalidObject);
+            }
+        }
+
+        let mut message = message.to_string();
+        if self.sign_off {
+            let trailer = format!("Signed-off-by: {} <{}>", committer.name, committer.email);
+
+            if !message.lines().any(|line| line == trailer) {
+                let trimmed_len = message.trim_end().len();
+                message.truncate(trimmed_len);
+
+                // trailers are grouped in the last paragraph
+                let last_line = message.lines().last().unwrap_or("");
+                let separator = match last_line.starts_with("Signed-off-by: ") {
+                    true => "\n",
+                    false if message.is_empty() => "",
+                    false => "\n\n",
+                };
+
+                message += separator;
+                message += &trailer;
+            }
+        }
+
+        Ok(message)
+    }
+}
+
+impl Repository {
+    /// Sets rules which apply to all new commits
+    pub fn set_commit_policy(&mut self, policy: CommitPolicy) {
+        self.commit_policy = policy;
+    }
+
+    pub fn commit_policy(&self) -> &CommitPolicy {
+        &self.commit_policy
+    }
+
+    /// Message of a commit merging `source` into `into`
+    ///
+    /// This uses the template of the commit policy, if any
+    /// (see [`CommitPolicy::merge_template`]). `conflicts`
+    /// lists paths which had to be resolved.
+    pub fn merge_message(&self, source: &str, into: &str, conflicts: &[String]) -> String {
+        let info = MergeInfo { source, into, conflicts };
+        match &self.commit_policy.merge_template {
+            Some(template) => template(&info),
+            None => info.default_message(),
+        }
+    }
+}
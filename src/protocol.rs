@@ -1,6 +1,8 @@
 use core::{str::from_utf8};
+use std::{io, time::{Duration, Instant}};
 use coolssh::{Run, RunEvent};
-use super::internals::{Result, Error, Write};
+use super::internals::{Result, Error, Hash, Write};
+use super::sideband::{RemoteMessage, MessageHandler, report_message};
 
 pub enum PacketLine<'a> {
     String(&'a str),
@@ -10,11 +12,211 @@ pub enum PacketLine<'a> {
     ResponseEndPacket,
 }
 
+/// Appends pkt-line encoded `lines` to `dst`
+pub fn encode_lines(lines: &[PacketLine], dst: &mut Vec<u8>) {
+    for line in lines {
+        match line {
+            PacketLine::String(string) => {
+                write!(dst, "{:04x}{}", string.len() + 4, string)
+            },
+            PacketLine::Bytes(bytes) => {
+                write!(dst, "{:04x}", bytes.len() + 4).unwrap();
+                dst.write_all(bytes)
+            },
+            PacketLine::FlushPacket => write!(dst, "0000"),
+            PacketLine::DelimiterPacket => write!(dst, "0001"),
+            PacketLine::ResponseEndPacket => write!(dst, "0002"),
+        }.unwrap();
+    }
+}
+
+/// Parses the pkt-line at the start of `buffer`
+///
+/// Returns `None` if `buffer` doesn't contain a full line yet,
+/// otherwise the line and the number of bytes it spans.
+/// Data lines are returned as `PacketLine::Bytes`.
+pub fn decode_line(buffer: &[u8]) -> Result<Option<(PacketLine<'_>, usize)>> {
+    fn parse_len(bytes: &[u8]) -> Option<usize> {
+        let hex_len = from_utf8(bytes).ok()?;
+        usize::from_str_radix(hex_len, 16).ok()
+    }
+
+    let Some(slice) = buffer.get(..4) else { return Ok(None) };
+    let len = parse_len(slice).ok_or(Error::GitProtocolError)?;
+
+    Ok(match len {
+        0 => Some((PacketLine::FlushPacket, 4)),
+        1 => Some((PacketLine::DelimiterPacket, 4)),
+        2 | 3 => Some((PacketLine::ResponseEndPacket, 4)),
+        _ => buffer.get(4..len).map(|data| (PacketLine::Bytes(data), len)),
+    })
+}
+
+/// Sections of a `command=fetch` response (protocol version 2),
+/// see [`GitProtocol::read_fetch_response`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FetchResponse {
+    /// Commits acknowledged as common (`acknowledgments` section)
+    pub acks: Vec<Hash>,
+    /// Set if the remote is ready to send the packfile
+    pub ready: bool,
+    /// New shallow boundaries (`shallow-info` section): these
+    /// commits were sent without their parents
+    pub shallow: Vec<Hash>,
+    /// Commits which aren't shallow boundaries anymore
+    pub unshallow: Vec<Hash>,
+    /// `(reference, commit)` pairs resolved for `want-ref` lines
+    pub wanted_refs: Vec<(String, Hash)>,
+    /// Set if the `packfile` section follows
+    pub packfile: bool,
+}
+
+/// References & capabilities advertised by `git-receive-pack`
+/// (protocol version 0), see [`Self::read`]
+///
+/// Lines can also be fed from a recorded transcript:
+///
+/// ```ignore
+/// let mut session = ReceivePackSession::default();
+/// session.parse_line("8a3f... refs/heads/main\0report-status delete-refs ofs-delta")?;
+/// assert!(session.has("ofs-delta"));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReceivePackSession {
+    /// `(name, target)` pairs, peeled tags (`^{}`) included
+    pub refs: Vec<(String, Hash)>,
+    /// Objects of alternate repositories (`.have` lines)
+    pub alternates: Vec<Hash>,
+    /// Commits whose parents the remote doesn't have (`shallow` lines)
+    pub shallow: Vec<Hash>,
+    /// Capabilities, with their value if any (`agent=git/2.43.0`)
+    pub capabilities: Vec<(String, Option<String>)>,
+}
+
+impl ReceivePackSession {
+    /// Reads the advertisement, up to the flush packet
+    pub fn read(protocol: &mut GitProtocol) -> Result<Self> {
+        let mut session = Self::default();
+        while let Some(line) = protocol.read_line_str()? {
+            if let Some(error) = line.strip_prefix("ERR ") {
+                let error = error.to_string();
+                protocol.report(RemoteMessage::Error(&error));
+                return Err(remote_error(&error));
+            }
+
+            session.parse_line(line)?;
+        }
+
+        Ok(session)
+    }
+
+    /// Parses a line of the advertisement
+    ///
+    /// Capabilities follow a NUL byte on the first line. Empty
+    /// repositories advertise a `capabilities^{}` placeholder,
+    /// which isn't a reference. Shallow remotes list their
+    /// boundaries after the references.
+    pub fn parse_line(&mut self, line: &str) -> Result<()> {
+        let line = match line.split_once('\0') {
+            Some((line, capabilities)) => {
+                for capability in capabilities.split(' ').filter(|c| !c.is_empty()) {
+                    log::debug!("PUSH-CAP: {}", capability);
+                    self.capabilities.push(match capability.split_once('=') {
+                        Some((name, value)) => (name.into(), Some(value.into())),
+                        None => (capability.into(), None),
+                    });
+                }
+
+                line
+            },
+            None => line,
+        };
+
+        if let Some(error) = line.strip_prefix("ERR ") {
+            log::error!("Server Error: {}", error);
+            return Err(remote_error(error));
+        }
+
+        let gpe = Error::GitProtocolError;
+        if let Some(hash_hex) = line.strip_prefix("shallow ") {
+            self.shallow.push(Hash::from_hex(hash_hex).ok_or(gpe)?);
+            return Ok(());
+        }
+
+        let (hash_hex, name) = line.split_once(' ').ok_or(gpe)?;
+        let hash = Hash::from_hex(hash_hex).ok_or(gpe)?;
+
+        match name {
+            "capabilities^{}" => (),
+            ".have" => self.alternates.push(hash),
+            name => self.refs.push((name.into(), hash)),
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if the remote supports a capability
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|(name, _)| name == capability)
+    }
+
+    /// Value of a capability, such as `agent`
+    pub fn value(&self, capability: &str) -> Option<&str> {
+        self.capabilities.iter().find(|(name, _)| name == capability)?.1.as_deref()
+    }
+
+    /// Target of an advertised reference
+    pub fn get(&self, name: &str) -> Option<Hash> {
+        self.refs.iter().find(|(n, _)| n == name).map(|(_, hash)| *hash)
+    }
+}
+
+/// Error for an `ERR` line sent by the remote
+pub(crate) fn remote_error(error: &str) -> Error {
+    // servers without `allow-tip-sha1-in-want` or
+    // `allow-reachable-sha1-in-want` only accept tips
+    if error.contains("not our ref") {
+        Error::CommitNotFetchable
+    } else if error.contains("unknown ref") {
+        Error::NoSuchReference
+    } else {
+        Error::RemoteError
+    }
+}
+
 pub struct GitProtocol<'a> {
     run: Run<'a>,
     receive_buffer: Vec<u8>,
     send_buffer: Vec<u8>,
     to_skip: usize,
+    handler: Option<MessageHandler>,
+    idle: IdleTimer,
+}
+
+/// Deadline of [`GitProtocol::set_idle_timeout`]
+struct IdleTimer {
+    timeout: Option<Duration>,
+    /// Last time something was sent or received
+    last_event: Instant,
+}
+
+impl IdleTimer {
+    /// Polls the remote; returns `IoError(TimedOut)` if the
+    /// connection has been idle for longer than the timeout
+    fn poll<'r>(&mut self, run: &'r mut Run<'_>) -> Result<RunEvent<'r>> {
+        let event = run.poll()?;
+        match (&event, self.timeout) {
+            (RunEvent::None, Some(timeout)) if self.last_event.elapsed() > timeout => {
+                log::error!("Connection was idle for {:?}", timeout);
+                Err(Error::IoError(io::ErrorKind::TimedOut))
+            },
+            (RunEvent::None, _) => Ok(event),
+            _ => {
+                self.last_event = Instant::now();
+                Ok(event)
+            },
+        }
+    }
 }
 
 impl<'a> GitProtocol<'a> {
@@ -24,34 +226,65 @@ impl<'a> GitProtocol<'a> {
             receive_buffer: Vec::new(),
             send_buffer: Vec::new(),
             to_skip: 0,
+            handler: None,
+            idle: IdleTimer {
+                timeout: None,
+                last_event: Instant::now(),
+            },
         }
     }
 
+    /// Gives up if nothing is sent or received for `timeout`,
+    /// see [`ConnectOptions::idle_timeout`]
+    ///
+    /// [`ConnectOptions::idle_timeout`]: crate::ConnectOptions::idle_timeout
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.idle.timeout = timeout;
+    }
+
+    /// Receives the messages of the remote instead of the log
+    pub fn set_message_handler(&mut self, handler: Option<MessageHandler>) {
+        self.handler = handler;
+    }
+
+    /// Returns true if messages of the remote go to a handler
+    pub fn has_message_handler(&self) -> bool {
+        self.handler.is_some()
+    }
+
+    /// Sends a message of the remote to the handler, or logs it
+    pub fn report(&self, message: RemoteMessage) {
+        report_message(self.handler.as_ref(), message);
+    }
+
+    /// Flush & delimiter packets are returned as `None`,
+    /// see [`Self::read_packet`] to tell them apart
     pub fn read_line(&mut self) -> Result<Option<&[u8]>> {
-        fn parse_len(bytes: &[u8]) -> Option<usize> {
-            let hex_len = from_utf8(bytes).ok()?;
-            usize::from_str_radix(hex_len, 16).ok()
-        }
+        Ok(match self.read_packet()? {
+            PacketLine::Bytes(bytes) => Some(bytes),
+            _ => None,
+        })
+    }
 
+    /// Reads the next pkt-line; data lines are `PacketLine::Bytes`
+    pub fn read_packet(&mut self) -> Result<PacketLine<'_>> {
         self.receive_buffer.drain(0..self.to_skip);
         self.to_skip = 0;
 
         loop {
-            if let Some(slice) = self.receive_buffer.get(..4) {
-                let len = parse_len(slice).ok_or(Error::GitProtocolError)?;
-                if len < 4 {
-                    self.to_skip = 4;
-                    break Ok(None);
-                } else if self.receive_buffer.len() >= len {
-                    self.to_skip = len;
-                    break match self.receive_buffer.get(4..len) {
-                        Some(data) => Ok(Some(data)),
-                        None => Err(Error::GitProtocolError),
-                    };
-                }
+            if let Some((line, len)) = decode_line(&self.receive_buffer)? {
+                let line = match line {
+                    PacketLine::Bytes(_) => PacketLine::Bytes(&self.receive_buffer[4..len]),
+                    PacketLine::FlushPacket => PacketLine::FlushPacket,
+                    PacketLine::DelimiterPacket => PacketLine::DelimiterPacket,
+                    _ => PacketLine::ResponseEndPacket,
+                };
+
+                self.to_skip = len;
+                break Ok(line);
             }
 
-            match self.run.poll()? {
+            match self.idle.poll(&mut self.run)? {
                 RunEvent::None => (),
                 RunEvent::Data(data) => self.receive_buffer.extend_from_slice(data),
                 RunEvent::ExtDataStderr(data) => log::warn!("Remote stderr: {}", from_utf8(data).unwrap()),
@@ -70,23 +303,74 @@ impl<'a> GitProtocol<'a> {
         })
     }
 
-    pub fn write_lines(&mut self, lines: &[PacketLine]) -> Result<()> {
-        for line in lines {
-            match line {
-                PacketLine::String(string) => {
-                    write!(&mut self.send_buffer, "{:04x}{}", string.len() + 4, string)
+    /// Reads the sections of a `command=fetch` response, up to the
+    /// `packfile` section header or the end of the response
+    ///
+    /// `packfile-uris` sections are ignored. Returns an error
+    /// if the remote sent an `ERR` line (`CommitNotFetchable` if
+    /// a want was refused, `NoSuchReference` for an unknown
+    /// `want-ref`, `RemoteError` otherwise) or a malformed section.
+    pub fn read_fetch_response(&mut self) -> Result<FetchResponse> {
+        #[derive(Copy, Clone, PartialEq)]
+        enum Section {
+            None,
+            Acknowledgments,
+            ShallowInfo,
+            WantedRefs,
+            Ignored,
+        }
+
+        let gpe = Error::GitProtocolError;
+        let hash = |hex: &str| Hash::from_hex(hex).ok_or(gpe);
+        let mut response = FetchResponse::default();
+        let mut section = Section::None;
+
+        loop {
+            let line = match self.read_packet()? {
+                PacketLine::Bytes(bytes) => from_utf8(bytes).ok().ok_or(gpe)?.trim(),
+                PacketLine::DelimiterPacket => {
+                    section = Section::None;
+                    continue;
+                },
+                _ => return Ok(response),
+            };
+
+            if let Some(error) = line.strip_prefix("ERR ") {
+                let error = error.to_string();
+                self.report(RemoteMessage::Error(&error));
+                return Err(remote_error(&error));
+            }
+
+            let (keyword, arg) = line.split_once(' ').unwrap_or((line, ""));
+            match (section, keyword) {
+                (Section::None, "packfile") => {
+                    response.packfile = true;
+                    return Ok(response);
                 },
-                PacketLine::Bytes(bytes) => {
-                    write!(&mut self.send_buffer, "{:04x}", bytes.len() + 4).unwrap();
-                    self.send_buffer.write(bytes).map(|_| ())
+                (Section::None, "acknowledgments") => section = Section::Acknowledgments,
+                (Section::None, "shallow-info") => section = Section::ShallowInfo,
+                (Section::None, "wanted-refs") => section = Section::WantedRefs,
+                (Section::None, "packfile-uris") => section = Section::Ignored,
+                (Section::Acknowledgments, "ACK") => response.acks.push(hash(arg)?),
+                (Section::Acknowledgments, "NAK") => (),
+                (Section::Acknowledgments, "ready") => response.ready = true,
+                (Section::ShallowInfo, "shallow") => response.shallow.push(hash(arg)?),
+                (Section::ShallowInfo, "unshallow") => response.unshallow.push(hash(arg)?),
+                (Section::WantedRefs, hash_hex) => response.wanted_refs.push((arg.into(), hash(hash_hex)?)),
+                (Section::Ignored, _) => log::debug!("Ignored: {}", line),
+                _ => {
+                    log::error!("Unexpected line in fetch response: {:?}", line);
+                    return Err(gpe);
                 },
-                PacketLine::FlushPacket => write!(&mut self.send_buffer, "0000"),
-                PacketLine::DelimiterPacket => write!(&mut self.send_buffer, "0001"),
-                PacketLine::ResponseEndPacket => write!(&mut self.send_buffer, "0002"),
-            }.unwrap();
+            }
         }
+    }
+
+    pub fn write_lines(&mut self, lines: &[PacketLine]) -> Result<()> {
+        encode_lines(lines, &mut self.send_buffer);
 
         self.run.write(&self.send_buffer, Error::GitProtocolError)?;
+        self.idle.last_event = Instant::now();
 
         self.send_buffer.clear();
 
@@ -94,12 +378,14 @@ impl<'a> GitProtocol<'a> {
     }
 
     pub fn write_raw(&mut self, data: &[u8]) -> Result<()> {
-        self.run.write(data, Error::GitProtocolError)
+        self.run.write(data, Error::GitProtocolError)?;
+        self.idle.last_event = Instant::now();
+        Ok(())
     }
 
     pub fn wait_for_exit(&mut self, ignore_data: bool) -> Result<()> {
         loop {
-            match self.run.poll()? {
+            match self.idle.poll(&mut self.run)? {
                 RunEvent::None => (),
                 RunEvent::Data(_) if ignore_data => (),
                 RunEvent::Stopped(Some(0)) => break Ok(()),
@@ -112,3 +398,78 @@ impl<'a> GitProtocol<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN: &str = "8a3f1c0e5b7d9f2a4c6e8b0d1f3a5c7e9b2d4f6a";
+    const TAG: &str = "1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e";
+    const PEELED: &str = "f0e1d2c3b4a5968778695a4b3c2d1e0f1a2b3c4d";
+    const ZERO: &str = "0000000000000000000000000000000000000000";
+
+    fn parse(lines: &[String]) -> Result<ReceivePackSession> {
+        let mut session = ReceivePackSession::default();
+        for line in lines {
+            session.parse_line(line)?;
+        }
+
+        Ok(session)
+    }
+
+    fn hash(hex: &str) -> Hash {
+        Hash::from_hex(hex).unwrap()
+    }
+
+    #[test]
+    fn advertisement() {
+        let caps = "report-status report-status-v2 delete-refs side-band-64k quiet atomic ofs-delta object-format=sha1 agent=git/2.43.0";
+        let session = parse(&[
+            format!("{} refs/heads/main\0{}", MAIN, caps),
+            format!("{} refs/tags/v1.0", TAG),
+            format!("{} refs/tags/v1.0^{{}}", PEELED),
+            format!("{} .have", PEELED),
+        ]).unwrap();
+
+        assert_eq!(session.refs.len(), 3);
+        assert_eq!(session.get("refs/heads/main"), Some(hash(MAIN)));
+        assert_eq!(session.get("refs/tags/v1.0^{}"), Some(hash(PEELED)));
+        assert_eq!(session.alternates, [hash(PEELED)]);
+        assert!(session.has("quiet") && session.has("delete-refs") && session.has("ofs-delta"));
+        assert!(!session.has("push-options"));
+        assert_eq!(session.value("agent"), Some("git/2.43.0"));
+        assert_eq!(session.value("object-format"), Some("sha1"));
+        assert_eq!(session.value("atomic"), None);
+    }
+
+    #[test]
+    fn empty_repository() {
+        let session = parse(&[
+            format!("{} capabilities^{{}}\0report-status delete-refs side-band-64k quiet agent=github/spokes-receive-pack-4c1d", ZERO),
+        ]).unwrap();
+
+        assert!(session.refs.is_empty());
+        assert!(session.has("report-status"));
+        assert_eq!(session.value("agent"), Some("github/spokes-receive-pack-4c1d"));
+    }
+
+    #[test]
+    fn shallow_remote() {
+        let session = parse(&[
+            format!("{} refs/heads/main\0report-status", MAIN),
+            format!("shallow {}", TAG),
+        ]).unwrap();
+
+        assert_eq!(session.refs, [("refs/heads/main".into(), hash(MAIN))]);
+        assert_eq!(session.shallow, [hash(TAG)]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = parse(&["ERR access denied or repository not exported".into()]);
+        assert!(matches!(error, Err(Error::RemoteError)));
+
+        let error = parse(&[format!("{}refs/heads/main", MAIN)]);
+        assert!(matches!(error, Err(Error::GitProtocolError)));
+    }
+}
@@ -1,85 +1,214 @@
-use coolssh::{Connection, RunResult};
+use coolssh::RunResult;
 use lmfu::{HashSet, LiteMap};
 
 use super::internals::{
-    Result, Error, TcpStream, Write, Hash, Remote, Repository,
-    GitProtocol, PacketLine, PackfileSender, dump_packfile_header,
+    Result, Error, Write, Hash, Remote, Repository,
+    GitProtocol, PacketLine, PackfileSender, ReceivePackSession, dump_packfile_header,
 };
+use super::refs::RefName;
+use super::packfile::{PackOptions, Packer};
+use super::sideband::{read_side_band, decode_lines};
+
+/// Settings for [`Repository::push_with`]
+#[derive(Clone, Debug, Default)]
+pub struct PushOptions {
+    /// Update remote branches even if they aren't
+    /// ancestors of the new commits
+    pub force: bool,
+    /// Either all branches are updated, or none of them
+    ///
+    /// Returns `UnsupportedByRemote` if the remote server
+    /// doesn't support the `atomic` capability.
+    pub atomic: bool,
+    /// Sent as `push-option` lines, which are available to
+    /// server hooks (example: `merge_request.create` on GitLab)
+    ///
+    /// Returns `UnsupportedByRemote` if this isn't empty and the
+    /// remote server doesn't support the `push-options` capability.
+    pub push_options: Vec<String>,
+}
+
+/// Outcome of the update of a remote branch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RefStatus {
+    Ok,
+    /// The remote refused the update, giving a reason
+    Rejected(String),
+}
+
+/// Per-branch result of [`Repository::push_with`]
+#[derive(Clone, Debug)]
+#[must_use]
+pub struct PushReport {
+    /// Set if the remote couldn't unpack the packfile
+    pub unpack_error: Option<String>,
+    /// Set if the remote aborted with an error message (side-band
+    /// channel 3); `refs` is then empty
+    pub remote_error: Option<String>,
+    /// Status of each updated branch
+    pub refs: Vec<(String, RefStatus)>,
+}
+
+impl PushReport {
+    /// Returns true if all branches were updated
+    pub fn is_ok(&self) -> bool {
+        self.unpack_error.is_none() && self.remote_error.is_none() && self.rejected().next().is_none()
+    }
+
+    /// Iterates on `(branch, reason)` pairs for rejected updates
+    pub fn rejected(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.refs.iter().filter_map(|(ref_name, status)| match status {
+            RefStatus::Ok => None,
+            RefStatus::Rejected(reason) => Some((ref_name.as_str(), reason.as_str())),
+        })
+    }
+}
 
 impl Repository {
     /// Push committed changes upstream
+    ///
+    /// See [`Self::push_with`] for more settings.
     pub fn push(
         &mut self,
         remote: &Remote,
         updated_heads: &[(&str, Hash)],
         force_push: bool,
-    ) -> Result<()> {
-        let iter = updated_heads.iter().map(|(name, hash)| (*name, (*hash, Hash::zero())));
-        let mut head_map = LiteMap::<&str, (Hash, Hash), Vec<_>>::from_iter(iter);
+    ) -> Result<PushReport> {
+        let options = PushOptions {
+            force: force_push,
+            ..PushOptions::default()
+        };
+
+        self.push_with(remote, updated_heads, &options)
+    }
 
-        let stream = TcpStream::connect(&*remote.host).unwrap();
-        let auth = (&*remote.username, &*remote.keypair).into();
-        let mut conn = Connection::new(stream, auth)?;
+    /// Push committed changes upstream
+    ///
+    /// References rejected by the remote (`non-fast-forward`,
+    /// `hook declined`...) are listed in the returned [`PushReport`].
+    ///
+    /// Passing `Hash::zero()` as the new value of a head deletes
+    /// that branch on the remote; this requires the `delete-refs`
+    /// capability and returns `NoSuchReference` if the branch
+    /// isn't advertised by the remote.
+    ///
+    /// Progress & hook output of the remote are sent to the message
+    /// handler (see [`Self::set_message_handler`]) if the remote
+    /// supports `side-band-64k`; if it aborts with an error message,
+    /// the message is also returned in [`PushReport::remote_error`].
+    ///
+    /// Returns `InvalidRefName` before connecting if a head name
+    /// isn't valid, see [`RefName`]. This takes `&mut self`, so it
+    /// cannot overlap with a fetch on a shared repository (see
+    /// [`Repository`]).
+    pub fn push_with(
+        &mut self,
+        remote: &Remote,
+        updated_heads: &[(&str, Hash)],
+        options: &PushOptions,
+    ) -> Result<PushReport> {
+        for (name, _) in updated_heads {
+            RefName::new(&format!("refs/heads/{}", name))?;
+        }
 
-        conn.mutate_stream(|stream| {
-            let duration = std::time::Duration::from_millis(1000);
-            stream.set_read_timeout(Some(duration)).unwrap()
-        });
+        let force_push = options.force;
+        let iter = updated_heads.iter().map(|(name, hash)| (*name, (*hash, Hash::zero())));
+        let mut head_map = LiteMap::<&str, (Hash, Hash), Vec<_>>::from_iter(iter);
 
+        let mut conn = remote.connect()?;
         let command = format!("git-receive-pack {}", remote.path);
         let mut protocol = match conn.run(&command, &[])? {
             RunResult::Accepted(run) => GitProtocol::new(run),
-            _ => panic!("run was refused"),
+            _ => {
+                log::error!("Remote refused to run git-receive-pack");
+                return Err(Error::GitProtocolError);
+            },
         };
 
-        let mut _bytes = ByteCounter(0);
+        protocol.set_idle_timeout(remote.connect_options.idle_timeout);
+        protocol.set_message_handler(self.message_handler.clone());
+
+        let session = ReceivePackSession::read(&mut protocol)?;
         let mut to_skip = HashSet::new();
-        let mut thin_pack = false;
-        let mut report_status = false;
         let mut client_caps = String::from("\0report-status");
 
-        while let Some(line) = protocol.read_line_str()? {
-            let line = match line.split_once('\0') {
-                Some((line, server_caps)) => {
-                    for cap in server_caps.split(' ') {
-                        if cap == "thin-pack" {
-                            client_caps += " thin-pack";
-                            thin_pack = true;
-                        }
-                        if cap == "report-status" {
-                            report_status = true;
-                        }
-                        log::debug!("PUSH-CAP: {}", cap);
-                    }
+        let thin_pack = session.has("thin-pack");
+        if thin_pack {
+            client_caps += " thin-pack";
+        }
 
-                    line
-                },
-                None => line,
-            };
+        // progress & hook output arrive on separate channels
+        let side_band = session.has("side-band-64k");
+        if side_band {
+            client_caps += " side-band-64k";
+        }
+
+        // progress would only be logged
+        if session.has("quiet") && self.message_handler.is_none() {
+            client_caps += " quiet";
+        }
+
+        for (ref_name, commit_hash) in &session.refs {
+            let Some(ref_name) = ref_name.strip_prefix("refs/heads/") else { continue };
+            let commit_hash = *commit_hash;
+
+            if let Some((new_hash, _)) = head_map.get(ref_name) {
+                // deleting a branch doesn't require it to be an ancestor
+                let fast_forward = self.objects.has(commit_hash) && self.is_ancestor(commit_hash, *new_hash)?;
+                if force_push || new_hash.is_zero() || fast_forward {
+                    if let Some((_, old_hash)) = head_map.get_mut(ref_name) {
+                        *old_hash = commit_hash;
+                    }
 
-            if let Some((hash_hex, ref_name)) = line.split_once(" refs/heads/") {
-                let commit_hash = Hash::from_hex(hash_hex).ok_or(Error::GitProtocolError)?;
-                if head_map.contains_key(ref_name) {
-                    if force_push || self.objects.has(commit_hash) {
-                        if let Some((_, old_hash)) = head_map.get_mut(ref_name) {
-                            *old_hash = commit_hash;
-                        }
-
-                        if thin_pack {
-                            self.objects.pack(commit_hash, &mut to_skip, &mut _bytes)?;
-                        }
-                    } else {
-                        return Err(Error::MustForcePush);
+                    if thin_pack {
+                        self.skip_reachable(commit_hash, &mut to_skip)?;
                     }
+                } else {
+                    return Err(Error::MustForcePush);
                 }
             }
         }
 
-        if !report_status {
+        if !session.has("report-status") {
             log::error!("Remote server doesn't support report-status");
             return Err(Error::UnsupportedByRemote);
         }
 
+        if options.atomic {
+            if !session.has("atomic") {
+                log::error!("Remote server doesn't support atomic pushes");
+                return Err(Error::UnsupportedByRemote);
+            }
+
+            client_caps += " atomic";
+        }
+
+        if !options.push_options.is_empty() {
+            if !session.has("push-options") {
+                log::error!("Remote server doesn't support push options");
+                return Err(Error::UnsupportedByRemote);
+            }
+
+            client_caps += " push-options";
+        }
+
+        let mut has_deletions = false;
+        for (ref_name, (new_hash, old_hash)) in head_map.iter() {
+            if new_hash.is_zero() {
+                if old_hash.is_zero() {
+                    log::error!("Cannot delete {}: it wasn't advertised by remote server", ref_name);
+                    return Err(Error::NoSuchReference);
+                }
+
+                has_deletions = true;
+            }
+        }
+
+        if has_deletions && !session.has("delete-refs") {
+            log::error!("Remote server doesn't support delete-refs");
+            return Err(Error::UnsupportedByRemote);
+        }
+
         for (ref_name, (new_hash, old_hash)) in head_map.iter() {
             let line = format!("{} {} refs/heads/{}{}\n", old_hash, new_hash, ref_name, client_caps);
             client_caps.clear();
@@ -89,29 +218,88 @@ impl Repository {
 
         protocol.write_lines(&[ PacketLine::FlushPacket ])?;
 
-        let mut sender = PackfileSender::new(protocol);
-        self.pack(to_skip, updated_heads, &mut sender, |_, _| ())?;
-        let mut protocol = sender.finish()?;
+        if !options.push_options.is_empty() {
+            for option in &options.push_options {
+                protocol.write_lines(&[ PacketLine::String(option) ])?;
+            }
+
+            protocol.write_lines(&[ PacketLine::FlushPacket ])?;
+        }
+
+        let updated_heads = updated_heads
+            .iter()
+            .filter(|(_, hash)| !hash.is_zero())
+            .copied()
+            .collect::<Vec<_>>();
+
+        // no packfile must be sent when all commands are deletions
+        let mut protocol = match updated_heads.is_empty() {
+            true => protocol,
+            false => {
+                let mut pack_options = self.pack_options;
+                pack_options.ofs_delta &= session.has("ofs-delta");
+
+                let mut sender = PackfileSender::new(protocol);
+                self.pack_with_options(pack_options, to_skip, &updated_heads, &mut sender, |_, _| ())?;
+                sender.finish()?
+            },
+        };
 
         let fail = |got: &dyn core::fmt::Debug, expected| {
             log::error!("Unexpected line from remote: {:?} (was expecting {:?})", got, expected);
         };
 
-        {
-            let line = protocol.read_line_str()?;
-            if line != Some("unpack ok") {
-                fail(&line, "unpack ok");
+        let mut report = PushReport {
+            unpack_error: None,
+            remote_error: None,
+            refs: Vec::new(),
+        };
+
+        let report_lines = match side_band {
+            true => {
+                let (data, error) = read_side_band(&mut protocol)?;
+                if error.is_some() {
+                    report.remote_error = error;
+                    return Ok(report);
+                }
+
+                decode_lines(&data)?
+            },
+            false => {
+                let mut lines = Vec::new();
+                while let Some(line) = protocol.read_line_str()? {
+                    lines.push(line.to_string());
+                }
+
+                lines
+            },
+        };
+
+        let mut lines = report_lines.iter().map(String::as_str);
+        match lines.next().and_then(|line| line.strip_prefix("unpack ")) {
+            Some("ok") => (),
+            Some(error) => report.unpack_error = Some(error.into()),
+            None => {
+                fail(&None::<&str>, "unpack {status}");
                 return Err(Error::GitProtocolError);
-            }
+            },
         }
 
-        while let Some(line) = protocol.read_line_str()? {
-            if let Some(ref_name) = line.strip_prefix("ok refs/heads/") {
-                head_map.remove(ref_name);
+        for line in lines {
+            let (ref_name, status) = if let Some(ref_name) = line.strip_prefix("ok refs/heads/") {
+                (ref_name, RefStatus::Ok)
+            } else if let Some(rejection) = line.strip_prefix("ng refs/heads/") {
+                let (ref_name, reason) = rejection.split_once(' ').unwrap_or((rejection, ""));
+                (ref_name, RefStatus::Rejected(reason.into()))
             } else {
-                log::error!("Unexpected line from remote: {:?}", line);
-                fail(&line, "ok refs/heads/{ref_name}");
+                fail(&line, "ok|ng refs/heads/{ref_name}");
                 return Err(Error::GitProtocolError);
+            };
+
+            if head_map.remove(ref_name).is_some() {
+                report.refs.push((ref_name.into(), status));
+            } else {
+                log::warn!("Remote reported an unexpected reference: {}", ref_name);
             }
         }
 
@@ -120,38 +308,119 @@ impl Repository {
             return Err(Error::GitProtocolError);
         }
 
-        // hmmm this may not always be correct
-        self.upstream_head = self.head;
+        if report.is_ok() {
+            // hmmm this may not always be correct
+            self.upstream_head = self.head;
+            self.record_reflog("push");
+        }
+
+        Ok(report)
+    }
+
+    /// Keeps objects compressed as they are received
+    ///
+    /// When enabled, clones and fetches keep the compressed data
+    /// of the objects they import (except deltas) in the objects
+    /// (see [`Object::compressed`]), and packfiles copy it as is
+    /// instead of compressing these objects again, like git's pack
+    /// reuse. This speeds up pushes of large repositories at the
+    /// cost of memory.
+    ///
+    /// Disabling it drops the compressed data, which returns `IoError`
+    /// if the object backend couldn't store the objects again.
+    ///
+    /// [`Object::compressed`]: crate::internals::Object::compressed
+    pub fn set_pack_reuse(&mut self, enabled: bool) -> Result<()> {
+        if self.pack_reuse && !enabled {
+            self.objects.drop_compressed()?;
+        }
 
+        self.pack_reuse = enabled;
         Ok(())
     }
 
+    /// Sets the compression level & delta window of the
+    /// packfiles built by [`Self::pack`] and pushes
+    ///
+    /// ```ignore
+    /// // slow link: spend CPU time to send less data
+    /// repo.set_pack_options(PackOptions {
+    ///     compression_level: 9,
+    ///     window: 10,
+    ///     ..Default::default()
+    /// });
+    /// ```
+    pub fn set_pack_options(&mut self, options: PackOptions) {
+        self.pack_options = options;
+    }
+
+    /// Number of objects which can be copied as is to packfiles,
+    /// see [`Self::set_pack_reuse`]
+    pub fn reusable_objects(&self) -> usize {
+        self.objects.iter().filter(|(_, object)| object.compressed().is_some()).count()
+    }
+
     pub fn pack<W: Write, F: Fn(&mut W, usize)>(
         &self,
+        to_skip: HashSet<Hash>,
+        heads_to_include: &[(&str, Hash)],
+        dst: &mut W,
+        size_hint: F,
+    ) -> Result<()> {
+        self.pack_with_options(self.pack_options, to_skip, heads_to_include, dst, size_hint)
+    }
+
+    fn pack_with_options<W: Write, F: Fn(&mut W, usize)>(
+        &self,
+        options: PackOptions,
         mut to_skip: HashSet<Hash>,
         heads_to_include: &[(&str, Hash)],
         dst: &mut W,
         size_hint: F,
     ) -> Result<()> {
+        if !options.two_pass {
+            // objects are compressed once, into a buffer
+            let mut packer = Packer::new(options).stop_at(&self.shallow).with_codec(self.zlib_codec.clone());
+            let mut buffer = Vec::new();
+            let mut count = 0;
+
+            for (_, commit_hash) in heads_to_include {
+                count += self.objects.pack_with(*commit_hash, &mut to_skip, &mut packer, &mut buffer)?;
+            }
+
+            packer.finish(&mut buffer);
+            log::info!("Packfile: {} objects, {} bytes", count, buffer.len());
+
+            size_hint(dst, crate::packfile::HEADER_SZ + buffer.len());
+            dump_packfile_header(count, dst);
+            return Ok(dst.write_all(&buffer)?);
+        }
+
         let (num_objects, bytes) = {
             let mut to_skip = to_skip.clone();
+            let mut packer = Packer::new(options).stop_at(&self.shallow).with_codec(self.zlib_codec.clone());
             let mut count = 0;
             let mut bytes = ByteCounter(0);
 
             for (_, commit_hash) in heads_to_include {
-                count += self.objects.pack(*commit_hash, &mut to_skip, &mut bytes)?;
+                count += self.objects.pack_with(*commit_hash, &mut to_skip, &mut packer, &mut bytes)?;
             }
 
+            packer.finish(&mut bytes);
+
             log::info!("Packfile: {} objects, {} bytes", count, bytes.0);
             (count, bytes.0)
         };
 
         size_hint(dst, crate::packfile::HEADER_SZ + bytes);
         dump_packfile_header(num_objects, dst);
+        let mut packer = Packer::new(options).stop_at(&self.shallow).with_codec(self.zlib_codec.clone());
         for (_, commit_hash) in heads_to_include {
-            self.objects.pack(*commit_hash, &mut to_skip, dst)?;
+            self.objects.pack_with(*commit_hash, &mut to_skip, &mut packer, dst)?;
         }
 
+        packer.finish(dst);
+
         Ok(())
     }
 }
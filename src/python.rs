@@ -0,0 +1,179 @@
+//! Python bindings, enabled by the `python` feature
+//!
+//! Build the extension module with `maturin build --features python`.
+
+use pyo3::{prelude::*, create_exception, exceptions::PyException, types::PyBytes};
+
+use super::internals::{Error, Remote, Repository, Hash, FileType, EntryType};
+use super::{Reference, RefStatus};
+
+create_exception!(rustgit, RustgitError, PyException);
+
+impl From<Error> for PyErr {
+    fn from(error: Error) -> Self {
+        RustgitError::new_err(format!("{:?}", error))
+    }
+}
+
+fn parse_hash(hex: &str) -> PyResult<Hash> {
+    Hash::from_hex(hex).ok_or_else(|| RustgitError::new_err(format!("Invalid hash: {}", hex)))
+}
+
+/// SSH & Remote Repository Settings
+#[pyclass(name = "Remote")]
+pub struct PyRemote(Remote);
+
+#[pymethods]
+impl PyRemote {
+    #[new]
+    fn new(host: &str, username: &str, path: &str, keypair: &str) -> Self {
+        Self(Remote::new(host.into(), username.into(), path.into(), keypair.into()))
+    }
+}
+
+/// Local repository residing in memory
+#[pyclass(name = "Repository", unsendable)]
+pub struct PyRepository(Repository);
+
+#[pymethods]
+impl PyRepository {
+    #[new]
+    fn new() -> Self {
+        Self(Repository::new())
+    }
+
+    /// Imports objects from a remote repository
+    ///
+    /// `branch` and `commit` are mutually exclusive;
+    /// when both are `None`, the remote HEAD is cloned.
+    #[pyo3(signature = (remote, branch = None, commit = None, depth = None))]
+    fn clone(
+        &mut self,
+        remote: &PyRemote,
+        branch: Option<&str>,
+        commit: Option<&str>,
+        depth: Option<usize>,
+    ) -> PyResult<()> {
+        let reference = match (branch, commit) {
+            (Some(_), Some(_)) => {
+                let msg = "branch and commit cannot be specified together";
+                return Err(RustgitError::new_err(msg));
+            },
+            (Some(branch), None) => Reference::Branch(branch),
+            (None, Some(hex)) => Reference::Commit(parse_hash(hex)?),
+            (None, None) => Reference::Head,
+        };
+
+        Ok(self.0.clone(&remote.0, reference, depth)?)
+    }
+
+    fn read_file<'py>(&self, py: Python<'py>, path: &str) -> PyResult<Bound<'py, PyBytes>> {
+        Ok(PyBytes::new(py, self.0.read_file(path)?))
+    }
+
+    fn read_text(&self, path: &str) -> PyResult<String> {
+        Ok(self.0.read_text(path)?.into())
+    }
+
+    fn file_exists(&self, path: &str) -> PyResult<bool> {
+        Ok(self.0.file_exists(path)?)
+    }
+
+    /// Lists the names of entries in a directory
+    fn read_dir(&self, path: &str) -> PyResult<Vec<String>> {
+        let mut names = Vec::new();
+        self.0.for_each_entry(path, EntryType::All, |name, _, _| names.push(name.into()))?;
+        Ok(names)
+    }
+
+    /// Stages a file; `data = None` stages a deletion
+    #[pyo3(signature = (path, data, executable = false))]
+    fn stage(&mut self, path: &str, data: Option<Vec<u8>>, executable: bool) -> PyResult<()> {
+        let file_type = match executable {
+            true => FileType::ExecutableFile,
+            false => FileType::RegularFile,
+        };
+
+        Ok(self.0.stage(path, data.map(|data| (data, file_type)))?)
+    }
+
+    /// Returns the hex-encoded hash of the new commit
+    #[pyo3(signature = (message, author, committer, timestamp = None))]
+    fn commit(
+        &mut self,
+        message: &str,
+        author: (String, String),
+        committer: (String, String),
+        timestamp: Option<u64>,
+    ) -> PyResult<String> {
+        let author = (author.0.as_str(), author.1.as_str());
+        let committer = (committer.0.as_str(), committer.1.as_str());
+        Ok(self.0.commit(message, author, committer, timestamp)?.to_string())
+    }
+
+    /// `heads` is a list of `(branch, hex_hash)` tuples
+    ///
+    /// Returns a list of `(branch, rejection_reason)` tuples,
+    /// where `rejection_reason` is `None` for updated branches.
+    #[pyo3(signature = (remote, heads, force = false))]
+    fn push(
+        &mut self,
+        remote: &PyRemote,
+        heads: Vec<(String, String)>,
+        force: bool,
+    ) -> PyResult<Vec<(String, Option<String>)>> {
+        let heads = heads
+            .iter()
+            .map(|(name, hex)| Ok((name.as_str(), parse_hash(hex)?)))
+            .collect::<PyResult<Vec<_>>>()?;
+
+        let report = self.0.push(&remote.0, &heads, force)?;
+        if let Some(error) = report.unpack_error {
+            return Err(RustgitError::new_err(format!("remote unpack failed: {}", error)));
+        }
+
+        if let Some(error) = report.remote_error {
+            return Err(RustgitError::new_err(format!("remote error: {}", error)));
+        }
+
+        Ok(report.refs.into_iter().map(|(name, status)| match status {
+            RefStatus::Ok => (name, None),
+            RefStatus::Rejected(reason) => (name, Some(reason)),
+        }).collect())
+    }
+
+    /// Hex-encoded hash of the current commit, if any
+    fn head(&self) -> Option<String> {
+        Some(self.0.head()).filter(|hash| !hash.is_zero()).map(|hash| hash.to_string())
+    }
+
+    /// Hex-encoded hash of the upstream commit, if any
+    fn upstream_head(&self) -> Option<String> {
+        Some(self.0.upstream_head()).filter(|hash| !hash.is_zero()).map(|hash| hash.to_string())
+    }
+
+    fn is_dirty(&self) -> bool {
+        self.0.is_dirty()
+    }
+
+    fn discard_commits(&mut self) {
+        self.0.discard_commits()
+    }
+
+    fn discard_changes(&mut self) {
+        self.0.discard_changes()
+    }
+
+    fn discard(&mut self) {
+        self.0.discard()
+    }
+}
+
+#[pymodule]
+fn rustgit(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyRemote>()?;
+    module.add_class::<PyRepository>()?;
+    module.add("RustgitError", module.py().get_type::<RustgitError>())?;
+    Ok(())
+}
+
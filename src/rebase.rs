@@ -0,0 +1,81 @@
+use super::internals::{Result, Error, Hash, Repository, Commit, ObjectType};
+use super::cherry_pick::CherryPick;
+
+/// Outcome of [`Repository::rebase`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[must_use]
+pub enum Rebase {
+    /// The new current commit
+    Done(Hash),
+    /// Replaying `commit` failed on these paths; nothing was changed
+    Conflicts {
+        commit: Hash,
+        paths: Vec<String>,
+    },
+}
+
+impl Repository {
+    /// Replays local commits on top of another commit
+    ///
+    /// Commits between `upstream_head` and `head` (those which
+    /// weren't pushed) are cherry-picked onto `onto`, oldest first
+    /// (see [`Self::cherry_pick`]); commits whose changes are already
+    /// in `onto` are dropped. Then `head` is the last rewritten commit
+    /// and `upstream_head` is `onto`, so pushing is a fast-forward if
+    /// `onto` is the tip of the remote branch.
+    ///
+    /// If a commit cannot be replayed, the repository is left
+    /// unchanged and the conflicting paths are returned.
+    ///
+    /// Returns `DirtyWorkspace` if there are staged changes,
+    /// `InvalidObject` if a local commit is a merge and
+    /// `MissingObject` if `onto` isn't in the repository.
+    pub fn rebase(&mut self, onto: Hash) -> Result<Rebase> {
+        if self.get_commit_root(self.head)? != self.root {
+            return Err(Error::DirtyWorkspace);
+        }
+
+        let Some(onto_root) = self.get_commit_root(onto)? else {
+            log::error!("Commit {} isn't in the repository", onto);
+            return Err(Error::MissingObject);
+        };
+
+        let excluded = self.ancestors(self.upstream_head)?;
+        let mut commits = Vec::new();
+        let mut next = self.head;
+
+        while !next.is_zero() && !excluded.contains(&next) {
+            let raw = self.objects.get_as(next, ObjectType::Commit).ok_or(Error::MissingObject)?;
+            let commit = Commit::parse(raw)?;
+            if commit.parents.len() > 1 {
+                log::error!("Cannot rebase merge commit {}", next);
+                return Err(Error::InvalidObject);
+            }
+
+            commits.push(next);
+            next = commit.parents.first().copied().unwrap_or(Hash::zero());
+        }
+
+        let (head, root) = (self.head, self.root);
+        self.head = onto;
+        self.root = Some(onto_root);
+
+        for commit in commits.into_iter().rev() {
+            let result = match self.cherry_pick(commit) {
+                Ok(CherryPick::Committed(_) | CherryPick::Empty) => continue,
+                Ok(CherryPick::Conflicts(paths)) => Ok(Rebase::Conflicts { commit, paths }),
+                Err(error) => Err(error),
+            };
+
+            // rewritten commits stay in the store, unreferenced
+            self.head = head;
+            self.root = root;
+            self.record_reflog("rebase (abort)");
+            return result;
+        }
+
+        self.upstream_head = onto;
+        self.record_reflog("rebase");
+        Ok(Rebase::Done(self.head))
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use sha1::Digest;
+
+use super::internals::{Result, Hash, Remote};
+use super::objectstore::{Hasher, finalize};
+use super::clone::ls_remote;
+
+/// Last references advertised by remotes, see [`ls_remote_if_changed`]
+///
+/// Remotes are identified by their host, username and path.
+#[derive(Clone, Debug, Default)]
+pub struct RefCache {
+    entries: HashMap<String, (Hash, Vec<(String, Hash)>)>,
+}
+
+fn remote_key(remote: &Remote) -> String {
+    format!("{}@{}:{}", remote.username, remote.host, remote.path)
+}
+
+/// Fingerprint of an advertisement, which doesn't depend
+/// on the order of the references
+///
+/// With the `sha1dc` feature, returns `InvalidObject` if the
+/// references carry a known SHA-1 collision attack.
+pub fn advertisement_etag(refs: &[(String, Hash)]) -> Result<Hash> {
+    let mut sorted = refs.iter().collect::<Vec<_>>();
+    sorted.sort();
+
+    let mut hasher = Hasher::new();
+    for (name, target) in sorted {
+        hasher.update(target.to_bytes());
+        hasher.update(name.as_bytes());
+        hasher.update(b"\n");
+    }
+
+    finalize(hasher).map(Hash::new)
+}
+
+impl RefCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// References which `remote` advertised last, if any
+    pub fn get(&self, remote: &Remote) -> Option<&[(String, Hash)]> {
+        self.entries.get(&remote_key(remote)).map(|(_, refs)| refs.as_slice())
+    }
+
+    /// Fingerprint of the last advertisement of `remote`,
+    /// see [`advertisement_etag`]
+    pub fn etag(&self, remote: &Remote) -> Option<Hash> {
+        self.entries.get(&remote_key(remote)).map(|(etag, _)| *etag)
+    }
+
+    /// Forgets the advertisement of `remote`: the next call to
+    /// [`ls_remote_if_changed`] returns its references
+    pub fn invalidate(&mut self, remote: &Remote) {
+        self.entries.remove(&remote_key(remote));
+    }
+
+    /// Records an advertisement; returns false if it didn't change
+    ///
+    /// Fails like [`advertisement_etag`].
+    pub fn update(&mut self, remote: &Remote, refs: Vec<(String, Hash)>) -> Result<bool> {
+        let etag = advertisement_etag(&refs)?;
+        Ok(match self.entries.get(&remote_key(remote)) {
+            Some((previous, _)) if *previous == etag => false,
+            _ => {
+                self.entries.insert(remote_key(remote), (etag, refs));
+                true
+            },
+        })
+    }
+}
+
+/// Like [`ls_remote`], but returns `None` if the advertised
+/// references didn't change since the last call with this cache
+///
+/// This still connects to the remote and lists all of its
+/// references: git has no cheaper way to ask whether they changed.
+/// What's saved is the fetch which would follow, so pollers can
+/// skip it when this returns `None`:
+///
+/// ```ignore
+/// let mut cache = RefCache::new();
+/// loop {
+///     if ls_remote_if_changed(&remote, &mut cache)?.is_some() {
+///         repo.fetch_refs(&remote, "origin", false)?;
+///     }
+///     std::thread::sleep(Duration::from_secs(60));
+/// }
+/// ```
+pub fn ls_remote_if_changed(remote: &Remote, cache: &mut RefCache) -> Result<Option<Vec<(String, Hash)>>> {
+    let refs = ls_remote(remote)?;
+    Ok(match cache.update(remote, refs.clone())? {
+        true => Some(refs),
+        false => None,
+    })
+}
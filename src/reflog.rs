@@ -0,0 +1,71 @@
+use super::internals::{Result, Error, Hash, Repository};
+use super::repository::now;
+
+/// Position of the current & upstream commits after a change,
+/// see [`Repository::reflog`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReflogEntry {
+    pub head: Hash,
+    pub upstream_head: Hash,
+    /// UNIX timestamp, in seconds
+    pub timestamp: u64,
+    /// What moved them: `commit`, `clone`, `push`, `discard`...
+    pub action: &'static str,
+}
+
+impl Repository {
+    /// Adds a reflog entry if `head` or `upstream_head` moved
+    pub(crate) fn record_reflog(&mut self, action: &'static str) {
+        let (head, upstream_head) = (self.head, self.upstream_head);
+        let moved = match self.reflog.last() {
+            Some(last) => last.head != head || last.upstream_head != upstream_head,
+            None => !(head.is_zero() && upstream_head.is_zero()),
+        };
+
+        if moved {
+            self.reflog.push(ReflogEntry {
+                head,
+                upstream_head,
+                timestamp: now(),
+                action,
+            });
+
+            self.journal_head();
+        }
+    }
+
+    /// Iterates on the past positions of the current & upstream
+    /// commits, newest first
+    ///
+    /// An entry is recorded each time a commit, clone, push, fetch
+    /// or reset moves them; this is kept in memory only.
+    pub fn reflog(&self) -> impl Iterator<Item = &ReflogEntry> {
+        self.reflog.iter().rev()
+    }
+
+    /// Makes the commit of a reflog entry the current one
+    ///
+    /// `n` is the position in [`Self::reflog`] (`0` is the current
+    /// state), like `HEAD@{n}`. Like `git reset --hard`, staged
+    /// changes are discarded; `upstream_head` doesn't change.
+    ///
+    /// This can bring back commits dropped by [`Self::discard_commits`].
+    /// Returns `NoSuchReference` if there's no such entry, and
+    /// `InvalidObject` if its commit can't be parsed;
+    /// nothing is changed in these cases.
+    pub fn reset_to_reflog_entry(&mut self, n: usize) -> Result<()> {
+        let Some(index) = self.reflog.len().checked_sub(n + 1) else {
+            log::error!("The reflog only has {} entries", self.reflog.len());
+            return Err(Error::NoSuchReference);
+        };
+
+        let head = self.reflog[index].head;
+        // resolved first, so that an invalid commit leaves head as is
+        self.get_commit_root(head)?;
+
+        self.head = head;
+        self.discard_changes();
+        self.record_reflog("reset");
+        Ok(())
+    }
+}
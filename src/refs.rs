@@ -0,0 +1,206 @@
+use core::{fmt, ops::Deref};
+
+use super::internals::{Result, Error, Hash, Repository};
+
+/// Reference name which follows `git check-ref-format` rules
+///
+/// One-level names (such as `HEAD`) are allowed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RefName<'a>(&'a str);
+
+impl<'a> RefName<'a> {
+    /// Returns `InvalidRefName` if git would reject this name
+    ///
+    /// Rejected: empty components, `..`, `@{`, `//`, control
+    /// characters, spaces, `~^:?*[\`, components starting with
+    /// a dot or ending with `.lock`, names ending with a dot or
+    /// a slash, and `@` alone.
+    pub fn new(name: &'a str) -> Result<Self> {
+        let forbidden = |c: char| c.is_ascii_control() || " ~^:?*[\\".contains(c);
+
+        let valid = !name.is_empty()
+            && name != "@"
+            && !name.ends_with('.')
+            && !name.contains("..")
+            && !name.contains("@{")
+            && !name.contains(forbidden)
+            && name.split('/').all(|part| {
+                !part.is_empty() && !part.starts_with('.') && !part.ends_with(".lock")
+            });
+
+        match valid {
+            true => Ok(Self(name)),
+            false => {
+                log::error!("Invalid reference name: {:?}", name);
+                Err(Error::InvalidRefName)
+            },
+        }
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+}
+
+impl<'a> Deref for RefName<'a> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.0
+    }
+}
+
+impl<'a> fmt::Display for RefName<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+/// Groups names which only differ by ASCII case
+///
+/// Such references cannot coexist on case-insensitive filesystems
+/// (Windows, macOS), so servers hosted there may advertise one name
+/// or the other. Exact duplicates aren't collisions.
+pub fn case_collisions<'a, I: IntoIterator<Item = &'a str>>(names: I) -> Vec<Vec<&'a str>> {
+    let mut names = names.into_iter().collect::<Vec<_>>();
+    names.sort_by_cached_key(|name| (name.to_ascii_lowercase(), *name));
+    names.dedup();
+
+    let mut groups = Vec::new();
+    let mut start = 0;
+    for i in 1..=names.len() {
+        let same = i < names.len() && names[i].eq_ignore_ascii_case(names[start]);
+        if !same {
+            if i - start > 1 {
+                groups.push(names[start..i].to_vec());
+            }
+
+            start = i;
+        }
+    }
+
+    groups
+}
+
+impl Repository {
+    /// Groups references whose names only differ by ASCII case,
+    /// see [`case_collisions`]
+    pub fn ref_case_collisions(&self) -> Vec<Vec<&str>> {
+        case_collisions(self.refs.iter().map(|(name, _)| name.as_str()))
+    }
+
+    /// Finds a reference, ignoring ASCII case
+    ///
+    /// An exact match is preferred; otherwise this returns
+    /// `AmbiguousReference` if several references match.
+    pub fn find_ref_ignore_case(&self, name: &str) -> Result<Option<(&str, Hash)>> {
+        if let Some((name, hash)) = self.refs.iter().find(|(n, _)| n.as_str() == name) {
+            return Ok(Some((name.as_str(), *hash)));
+        }
+
+        let mut matches = self.refs().filter(|(n, _)| n.eq_ignore_ascii_case(name));
+        match (matches.next(), matches.next()) {
+            (Some(_), Some(_)) => {
+                log::error!("Several references match {:?}", name);
+                Err(Error::AmbiguousReference)
+            },
+            (found, _) => Ok(found),
+        }
+    }
+
+    /// Target of a reference, such as `refs/heads/main`
+    pub fn get_ref(&self, name: &str) -> Option<Hash> {
+        self.refs.get(name).copied()
+    }
+
+    /// Creates or updates a reference, returning its previous target
+    ///
+    /// Returns `InvalidRefName` if `name` isn't valid (see [`RefName`])
+    /// and `MissingObject` if `target` isn't in the repository.
+    pub fn set_ref(&mut self, name: &str, target: Hash) -> Result<Option<Hash>> {
+        RefName::new(name)?;
+
+        if self.get_object(target).is_none() {
+            log::error!("Cannot point {} to missing object {}", name, target);
+            return Err(Error::MissingObject);
+        }
+
+        if self.journaling() {
+            self.journal_write(format!("ref {} {}\n", target, name).as_bytes())?;
+        }
+
+        Ok(self.refs.insert(name.into(), target))
+    }
+
+    /// Updates a reference only if its target is `expected`
+    ///
+    /// `None` means the reference doesn't exist yet. Returns
+    /// `Ok(false)` without changing anything if the reference
+    /// was modified in the meantime; see [`Self::set_ref`] for
+    /// errors.
+    pub fn compare_and_set_ref(&mut self, name: &str, expected: Option<Hash>, target: Hash) -> Result<bool> {
+        if self.get_ref(name) != expected {
+            return Ok(false);
+        }
+
+        self.set_ref(name, target)?;
+        Ok(true)
+    }
+
+    /// Deletes a reference, returning its previous target
+    pub fn remove_ref(&mut self, name: &str) -> Option<Hash> {
+        let removed = self.refs.remove(name);
+        if removed.is_some() && self.journaling() {
+            self.journal_or_log(format!("unref {}\n", name).as_bytes());
+        }
+
+        removed
+    }
+
+    /// Iterates on `(name, target)` pairs, sorted by name
+    pub fn refs(&self) -> impl Iterator<Item = (&str, Hash)> {
+        self.refs.iter().map(|(name, hash)| (name.as_str(), *hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RefName;
+
+    #[test]
+    fn ref_name_rules() {
+        let cases = [
+            ("refs/heads/main", true),
+            ("HEAD", true),
+            ("refs/tags/v1.0", true),
+            ("refs/heads/feature/a-b_c", true),
+            ("refs/heads/a@b", true),
+            ("", false),
+            ("@", false),
+            ("refs/heads/a..b", false),
+            ("refs/heads/a@{1}", false),
+            ("refs/heads/main.lock", false),
+            ("refs/heads/main.lock/x", false),
+            ("refs/heads/a\x01b", false),
+            ("refs/heads/a\x7fb", false),
+            ("refs/heads/a\tb", false),
+            ("refs/heads/a b", false),
+            ("refs/heads/a~1", false),
+            ("refs/heads/a^", false),
+            ("refs/heads/a:b", false),
+            ("refs/heads/a?", false),
+            ("refs/heads/a*", false),
+            ("refs/heads/a[b", false),
+            ("refs/heads/a\\b", false),
+            ("refs//heads", false),
+            ("/refs/heads/main", false),
+            ("refs/heads/", false),
+            ("refs/heads/.hidden", false),
+            ("refs/heads/main.", false),
+        ];
+
+        for (name, valid) in cases {
+            assert_eq!(RefName::new(name).is_ok(), valid, "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use super::internals::{Result, Hash, Repository};
+
+// same limit as git
+const MAX_REPLACE_DEPTH: usize = 5;
+
+impl Repository {
+    /// Follows replacement references if they're enabled
+    ///
+    /// Replacement objects which weren't imported are ignored.
+    pub(crate) fn replaced(&self, mut hash: Hash) -> Hash {
+        if self.replace_objects {
+            for _ in 0..MAX_REPLACE_DEPTH {
+                match self.replacements.get(&hash) {
+                    Some(replacement) if self.objects.has(*replacement) => hash = *replacement,
+                    _ => break,
+                }
+            }
+        }
+
+        hash
+    }
+
+    /// Enables or disables the substitution of replaced objects
+    ///
+    /// When enabled, objects recorded as replaced (see
+    /// [`Self::add_replacement`]) are transparently substituted
+    /// by their replacement when reading files, directories and
+    /// commits. Objects sent by [`Self::push`] are never replaced.
+    ///
+    /// [`Self::clone`] records the `refs/replace/*` references of
+    /// the remote and, when this is enabled, also imports the
+    /// replacement objects.
+    ///
+    /// This is disabled by default.
+    pub fn set_replace_objects(&mut self, enabled: bool) -> Result<()> {
+        self.update_replacements(|repo| repo.replace_objects = enabled)
+    }
+
+    /// Records `replacement` as a substitute for `original`
+    ///
+    /// This is the equivalent of `refs/replace/<original>`.
+    pub fn add_replacement(&mut self, original: Hash, replacement: Hash) -> Result<()> {
+        self.update_replacements(|repo| {
+            repo.replacements.insert(original, replacement);
+        })
+    }
+
+    /// Forgets about the replacement of `original`
+    pub fn remove_replacement(&mut self, original: Hash) -> Result<Option<Hash>> {
+        self.update_replacements(|repo| repo.replacements.remove(&original))
+    }
+
+    /// Iterates on `(original, replacement)` pairs
+    pub fn replacements(&self) -> impl Iterator<Item = (Hash, Hash)> + '_ {
+        self.replacements.iter().map(|(original, replacement)| (*original, *replacement))
+    }
+
+    fn update_replacements<T, F: FnOnce(&mut Self) -> T>(&mut self, callback: F) -> Result<T> {
+        let clean = self.root == self.get_commit_root(self.head)?;
+
+        // cached directories may have been read through a replacement
+        self.invalidate_replaced_dirs();
+        let result = callback(self);
+        self.invalidate_replaced_dirs();
+
+        if clean {
+            self.root = self.get_commit_root(self.head)?;
+        }
+
+        Ok(result)
+    }
+
+    fn invalidate_replaced_dirs(&mut self) {
+        let dirs = self.directories.get_mut().unwrap();
+        for original in self.replacements.iter().map(|(original, _)| original) {
+            dirs.remove(original);
+        }
+    }
+}
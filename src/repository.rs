@@ -1,14 +1,77 @@
 use core::str::from_utf8;
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::sync::RwLock;
-use lmfu::LiteMap;
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+use lmfu::{LiteMap, ArcStr};
 
 use super::internals::{
-    Result, Error, Mode, Directory, Path, TreeIter, Hash, CommitField, FileType,
-    ObjectStore, EntryType, Write, ObjectType, get_commit_field_hash,
+    Result, Error, Mode, Directory, Path, TreeIter, Hash, FileType, Commit, Signature,
+    ObjectStore, EntryType, ObjectType, Object, Content, PathSeparators, split_commit_signature,
 };
+use super::drivers::Drivers;
+use super::bitmap::Bitmaps;
+use super::identity::IdentityPolicy;
+use super::policy::CommitPolicy;
+use super::packfile::PackOptions;
+use super::sideband::MessageHandler;
+use super::compression::{Codec, Miniz};
+use super::reflog::ReflogEntry;
+use super::journal::{Journal, entry_event};
+use super::stash::Stash;
+
+/// Current UNIX timestamp, in seconds
+pub(crate) fn now() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        _ => 0,
+    }
+}
+
+/// Names & emails cannot contain `<`, `>` or `\n`
+pub(crate) fn check_identity(strings: &[&str]) -> Result<()> {
+    for string in strings {
+        let has_newline = string.contains('\n');
+        let has_open = string.contains('<');
+        let has_close = string.contains('>');
+        if has_newline || has_open || has_close {
+            return Err(Error::InvalidObject);
+        }
+    }
+
+    Ok(())
+}
+
+// read methods take `&self`: repositories can be shared between threads
+const _: () = {
+    fn assert_send_sync<T: Send + Sync>() {}
+    let _ = assert_send_sync::<Repository>;
+};
+
+/// Leaf of [`Repository::stage_entry`]
+pub(crate) enum NewEntry {
+    /// Content of a new blob
+    Data(Vec<u8>, FileType),
+    /// Object which is already in a store
+    Existing(Hash, Mode),
+}
 
 /// Local repository residing in memory
+///
+/// Read methods take `&self` and internal caches are locked, so
+/// a repository can serve reads from multiple threads:
+///
+/// ```ignore
+/// let repo = Arc::new(RwLock::new(repo));
+/// let reader = repo.clone();
+/// std::thread::spawn(move || {
+///     let repo = reader.read().unwrap();
+///     let readme = repo.read_text("README.md");
+/// });
+/// ```
+///
+/// Clones, fetches and pushes take `&mut self`: behind such a lock,
+/// a push waits for the write lock held by a running fetch, so they
+/// never modify the stores at the same time.
 pub struct Repository {
     pub(crate) directories: RwLock<LiteMap<Hash, Directory>>,
     pub(crate) objects: ObjectStore,
@@ -16,6 +79,28 @@ pub struct Repository {
     pub(crate) upstream_head: Hash,
     pub(crate) head: Hash,
     pub(crate) root: Option<Hash>,
+    pub(crate) replacements: LiteMap<Hash, Hash>,
+    pub(crate) replace_objects: bool,
+    pub(crate) drivers: Drivers,
+    pub(crate) bitmaps: Option<Bitmaps>,
+    pub(crate) tz_offset: i16,
+    pub(crate) refs: LiteMap<ArcStr, Hash>,
+    pub(crate) identity_policy: IdentityPolicy,
+    pub(crate) commit_policy: CommitPolicy,
+    /// Set if pack reuse is enabled
+    pub(crate) pack_reuse: bool,
+    pub(crate) pack_options: PackOptions,
+    pub(crate) zlib_codec: Arc<dyn Codec>,
+    pub(crate) storage_codec: Arc<dyn Codec>,
+    pub(crate) reflog: Vec<ReflogEntry>,
+    pub(crate) journal: Option<Journal>,
+    pub(crate) stashes: Vec<Stash>,
+    /// Objects protected from [`Repository::gc`]
+    pub(crate) pins: BTreeSet<Hash>,
+    /// Commits fetched without their parents
+    pub(crate) shallow: BTreeSet<Hash>,
+    pub(crate) message_handler: Option<MessageHandler>,
+    pub(crate) path_separators: PathSeparators,
 }
 
 impl Repository {
@@ -28,10 +113,171 @@ impl Repository {
             upstream_head: Hash::zero(),
             head: Hash::zero(),
             root: None,
+            replacements: LiteMap::new(),
+            replace_objects: false,
+            drivers: Drivers::default(),
+            bitmaps: None,
+            tz_offset: 0,
+            refs: LiteMap::new(),
+            identity_policy: IdentityPolicy::default(),
+            commit_policy: CommitPolicy::default(),
+            pack_reuse: false,
+            zlib_codec: Arc::new(Miniz),
+            storage_codec: Arc::new(Miniz),
+            pack_options: PackOptions::default(),
+            reflog: Vec::new(),
+            journal: None,
+            stashes: Vec::new(),
+            pins: BTreeSet::new(),
+            shallow: BTreeSet::new(),
+            message_handler: None,
+            path_separators: PathSeparators::Slash,
+        }
+    }
+
+    /// Creates a repository whose object store is suited to
+    /// about `objects` objects, see [`ObjectStore::with_capacity_hint`]
+    pub fn with_capacity_hint(objects: usize) -> Self {
+        Self {
+            objects: ObjectStore::with_capacity_hint(objects),
+            ..Self::new()
+        }
+    }
+
+    /// Creates a repository which stores committed objects in `objects`
+    ///
+    /// See [`ObjectStore::with_backend`] to keep them out of memory.
+    pub fn with_object_store(objects: ObjectStore) -> Self {
+        Self {
+            objects,
+            ..Self::new()
         }
     }
 
+    /// Drops committed objects which the object store cached in memory
+    ///
+    /// This only has an effect with a disk-backed store,
+    /// see [`ObjectStore::release_memory`].
+    pub fn release_memory(&mut self) {
+        self.objects.release_memory();
+    }
+
+    /// Hash of the current commit
+    ///
+    /// This is `Hash::zero()` if nothing was cloned or committed yet.
+    pub fn head(&self) -> Hash {
+        self.head
+    }
+
+    /// Hash of the remote commit which [`Self::head`] is based on
+    ///
+    /// This is the commit which was cloned, fetched or pushed last,
+    /// or `Hash::zero()` if there's none.
+    pub fn upstream_head(&self) -> Hash {
+        self.upstream_head
+    }
+
+    /// Returns true if the history was fetched with a limited depth
+    ///
+    /// Parents of the shallow boundary (see [`Self::shallow_commits`])
+    /// aren't in the repository; logs and packfiles stop there.
+    pub fn is_shallow(&self) -> bool {
+        !self.shallow.is_empty()
+    }
+
+    /// Commits whose parents weren't fetched, like `.git/shallow`
+    pub fn shallow_commits(&self) -> impl Iterator<Item = Hash> + '_ {
+        self.shallow.iter().copied()
+    }
+
+    /// Hash of the staged root tree, if any files are staged
+    pub fn root_tree(&self) -> Option<Hash> {
+        self.root
+    }
+
+    /// Returns true if the staged files differ from the current commit
+    pub fn is_dirty(&self) -> bool {
+        match self.get_commit_root(self.head) {
+            Ok(root) => root != self.root,
+            Err(_) => true,
+        }
+    }
+
+    /// Checks if an object is in the repository, staged or committed
+    ///
+    /// Unlike [`Self::get_object`], replacements aren't substituted.
+    pub fn contains(&self, hash: Hash) -> bool {
+        self.staged.has(hash) || self.objects.has(hash)
+    }
+
+    /// Sets the timezone of commits & tags created with [`Self::commit`],
+    /// [`Self::tag`] and their variants
+    ///
+    /// `tz_offset` is the offset from UTC, in minutes; it defaults to 0.
+    pub fn set_timezone(&mut self, tz_offset: i16) {
+        self.tz_offset = tz_offset;
+    }
+
+    /// Sets how path arguments are split, see [`Path`]
+    ///
+    /// With [`PathSeparators::SlashOrBackslash`], `src\main.rs`
+    /// is `src/main.rs` instead of a file named `src\main.rs`.
+    pub fn set_path_separators(&mut self, separators: PathSeparators) {
+        self.journal_separators(separators);
+        self.path_separators = separators;
+    }
+
+    pub(crate) fn path<'a>(&self, path: &'a str) -> Path<'a> {
+        Path::with_separators(path, self.path_separators)
+    }
+
+    /// Offset from UTC of new commits & tags, in minutes
+    pub fn timezone(&self) -> i16 {
+        self.tz_offset
+    }
+
+    /// Looks up an object, staged or committed
+    ///
+    /// Replacement objects are substituted if enabled,
+    /// see [`Self::set_replace_objects`].
+    pub fn get_object(&self, hash: Hash) -> Option<&Object> {
+        let hash = self.replaced(hash);
+        match self.staged.get(hash) {
+            Some(object) => Some(object),
+            None => self.objects.get(hash),
+        }
+    }
+
+    /// Finds the single object whose hash starts with a hexadecimal prefix
+    ///
+    /// Both staged and committed objects are considered.
+    /// Returns `AmbiguousPrefix` if multiple objects match
+    /// and `MissingObject` if none does.
+    pub fn resolve_prefix(&self, prefix: &str) -> Result<Hash> {
+        let mut found = None;
+
+        for hash in self.objects.find_prefix(prefix)?.chain(self.staged.find_prefix(prefix)?) {
+            match found {
+                Some(other) if other != hash => return Err(Error::AmbiguousPrefix),
+                _ => found = Some(hash),
+            }
+        }
+
+        found.ok_or(Error::MissingObject)
+    }
+
+    /// Shortest unambiguous hexadecimal prefix of a hash
+    ///
+    /// Like git's `core.abbrev=auto`, this is at least 7 characters long.
+    pub fn abbreviate(&self, hash: Hash) -> String {
+        let len = self.objects.abbrev_len(hash).max(self.staged.abbrev_len(hash));
+        let mut hex = hash.to_string();
+        hex.truncate(len);
+        hex
+    }
+
     pub (crate) fn any_store_get(&self, hash: Hash, obj_type: ObjectType) -> Option<&[u8]> {
+        let hash = self.replaced(hash);
         match self.staged.get_as(hash, obj_type) {
             Some(entries) => Some(entries),
             None => self.objects.get_as(hash, obj_type),
@@ -88,11 +334,9 @@ impl Repository {
     }
 
     pub(crate) fn get_commit_root(&self, commit_hash: Hash) -> Result<Option<Hash>> {
+        let commit_hash = self.replaced(commit_hash);
         match self.objects.get_as(commit_hash, ObjectType::Commit) {
-            Some(commit) => match get_commit_field_hash(commit, CommitField::Tree)? {
-                Some(hash) => Ok(Some(hash)),
-                None => Err(Error::InvalidObject),
-            },
+            Some(commit) => Ok(Some(Commit::parse(commit)?.tree)),
             None => Ok(None),
         }
     }
@@ -119,9 +363,19 @@ impl Repository {
     /// Returns `PathError` if the path leads to nowhere.
     ///
     /// This can write-lock an internal RwLock for cache.
-    pub fn for_each_entry<F: FnMut(&str, Mode, Hash)>(&self, path: &str, entry_type: EntryType, mut callback: F) -> Result<()> {
-        let path = Path::new(path);
-        let mut current = self.root.ok_or(Error::PathError)?;
+    pub fn for_each_entry<F: FnMut(&str, Mode, Hash)>(&self, path: &str, entry_type: EntryType, callback: F) -> Result<()> {
+        self.for_each_entry_in(self.root, path, entry_type, callback)
+    }
+
+    fn for_each_entry_in<F: FnMut(&str, Mode, Hash)>(
+        &self,
+        root: Option<Hash>,
+        path: &str,
+        entry_type: EntryType,
+        mut callback: F,
+    ) -> Result<()> {
+        let path = self.path(path);
+        let mut current = root.ok_or(Error::PathError)?;
 
         for subdir in path.all() {
             current = self.find_in_dir(current, subdir, EntryType::Directory)?.0;
@@ -145,15 +399,118 @@ impl Repository {
     ///
     /// This can write-lock an internal RwLock for cache.
     pub fn read_file(&self, path: &str) -> Result<&[u8]> {
-        let path = Path::new(path);
-        let mut current = self.root.ok_or(Error::PathError)?;
+        let (hash, _mode) = self.find_file(path)?;
+        self.any_store_get(hash, ObjectType::Blob).ok_or(Error::MissingObject)
+    }
+
+    /// Returns the content of a file that was staged or commited before,
+    /// as a shared buffer which doesn't borrow the repository.
+    ///
+    /// Returns `PathError` if the path leads to nowhere.
+    ///
+    /// This can write-lock an internal RwLock for cache.
+    pub fn read_file_arc(&self, path: &str) -> Result<Content> {
+        let (hash, _mode) = self.find_file(path)?;
+        let hash = self.replaced(hash);
+        let object = self.staged.get(hash).or_else(|| self.objects.get(hash));
+
+        match object {
+            Some(object) if object.obj_type() == ObjectType::Blob => Ok(object.content_arc()),
+            _ => Err(Error::MissingObject),
+        }
+    }
+
+    /// Root tree of a commit, `MissingObject` if it isn't there
+    fn commit_root_at(&self, commit: Hash) -> Result<Option<Hash>> {
+        match self.get_commit_root(commit)? {
+            Some(root) => Ok(Some(root)),
+            None => {
+                log::error!("Commit {} isn't in the repository", commit);
+                Err(Error::MissingObject)
+            },
+        }
+    }
+
+    /// Returns the content of a file in a commit
+    ///
+    /// The current commit and staged files are left untouched.
+    /// Returns `PathError` if the path leads to nowhere and
+    /// `MissingObject` if the commit isn't in the repository.
+    ///
+    /// This can write-lock an internal RwLock for cache.
+    pub fn read_file_at(&self, commit: Hash, path: &str) -> Result<&[u8]> {
+        let root = self.commit_root_at(commit)?;
+        let (hash, _mode) = self.find_entry_in(root, path, EntryType::File)?;
+        self.any_store_get(hash, ObjectType::Blob).ok_or(Error::MissingObject)
+    }
+
+    /// Lists the `(name, mode, hash)` entries of a staged or
+    /// committed directory, sorted by name
+    ///
+    /// An empty path is the root directory. Returns `PathError`
+    /// if the path leads to nowhere.
+    ///
+    /// This can write-lock an internal RwLock for cache.
+    pub fn read_dir(&self, path: &str) -> Result<Vec<(String, Mode, Hash)>> {
+        let mut entries = Vec::new();
+        self.for_each_entry(path, EntryType::All, |name, mode, hash| {
+            entries.push((name.to_string(), mode, hash));
+        })?;
+
+        Ok(entries)
+    }
+
+    /// Lists the `(name, mode, hash)` entries of a directory in a
+    /// commit, sorted by name
+    ///
+    /// An empty path is the root directory. See [`Self::read_file_at`]
+    /// for errors.
+    ///
+    /// This can write-lock an internal RwLock for cache.
+    pub fn read_dir_at(&self, commit: Hash, path: &str) -> Result<Vec<(String, Mode, Hash)>> {
+        let root = self.commit_root_at(commit)?;
+        let mut entries = Vec::new();
+        self.for_each_entry_in(root, path, EntryType::All, |name, mode, hash| {
+            entries.push((name.to_string(), mode, hash));
+        })?;
+
+        Ok(entries)
+    }
+
+    /// Returns the hash, mode & size of a file that was staged or
+    /// commited before.
+    ///
+    /// Returns `PathError` if the path leads to nowhere. The size
+    /// of a gitlink (submodule) is zero.
+    ///
+    /// This can write-lock an internal RwLock for cache.
+    pub fn metadata(&self, path: &str) -> Result<(Hash, Mode, usize)> {
+        let (hash, mode) = self.find_file(path)?;
+        let size = match mode {
+            Mode::Gitlink => 0,
+            _ => self.any_store_get(hash, ObjectType::Blob).ok_or(Error::MissingObject)?.len(),
+        };
+
+        Ok((hash, mode, size))
+    }
+
+    pub(crate) fn find_file(&self, path: &str) -> Result<(Hash, Mode)> {
+        self.find_entry(path, EntryType::File)
+    }
+
+    pub(crate) fn find_entry(&self, path: &str, entry_type: EntryType) -> Result<(Hash, Mode)> {
+        self.find_entry_in(self.root, path, entry_type)
+    }
+
+    pub(crate) fn find_entry_in(&self, root: Option<Hash>, path: &str, entry_type: EntryType) -> Result<(Hash, Mode)> {
+        let path = self.path(path);
+        let mut current = root.ok_or(Error::PathError)?;
 
         for subdir in path.dirs()? {
             current = self.find_in_dir(current, subdir, EntryType::Directory)?.0;
         }
 
-        let (hash, _mode) = self.find_in_dir(current, path.file()?, EntryType::File)?;
-        self.any_store_get(hash, ObjectType::Blob).ok_or(Error::MissingObject)
+        self.find_in_dir(current, path.file()?, entry_type)
     }
 
     /// Returns the content of a file that was staged or commited before.
@@ -195,7 +552,7 @@ impl Repository {
         mut directory: Directory,
         steps: &mut I,
         file_name: &str,
-        data: Option<(Vec<u8>, FileType)>,
+        data: Option<NewEntry>,
     ) -> Result<Option<Directory>> {
         let mut result = None;
 
@@ -214,15 +571,19 @@ impl Repository {
             };
 
             if let Some(subdir) = self.update_dir(subdir, steps, file_name, data)? {
-                let hash = self.staged.serialize_directory(&subdir, delta_hint);
+                let hash = self.staged.serialize_directory(&subdir, delta_hint)?;
                 self.directories.get_mut().unwrap().insert(hash, subdir);
                 result = Some((hash, Mode::Directory));
             }
         } else {
-            if let Some((data, ft)) = data {
-                let hash = self.staged.insert(ObjectType::Blob, data.into(), delta_hint);
-                result = Some((hash, ft.into()));
-            }
+            result = match data {
+                Some(NewEntry::Data(data, ft)) => {
+                    let hash = self.staged.insert(ObjectType::Blob, data, delta_hint)?;
+                    Some((hash, ft.into()))
+                },
+                Some(NewEntry::Existing(hash, mode)) => Some((hash, mode)),
+                None => None,
+            };
         }
 
         Ok(if let Some((hash, mode)) = result {
@@ -251,7 +612,48 @@ impl Repository {
     ///
     /// Should only fail if the repository was already corrupted.
     pub fn stage(&mut self, path: &str, data: Option<(Vec<u8>, FileType)>) -> Result<()> {
-        let path = Path::new(path);
+        self.stage_entry(path, data.map(|(data, ft)| NewEntry::Data(data, ft)))
+    }
+
+    /// Moves a file or directory to a new path
+    ///
+    /// The entry keeps its hash and mode: nothing is read or
+    /// re-hashed. Missing directories are created as needed and
+    /// emptied ones are deleted.
+    ///
+    /// Returns `PathError` if `from` leads to nowhere, if `to`
+    /// already exists or if a directory would be moved into itself.
+    pub fn rename(&mut self, from: &str, to: &str) -> Result<()> {
+        let (hash, mode) = self.find_entry(from, EntryType::All)?;
+
+        let (from_path, to_path) = (self.path(from), self.path(to));
+        let from_parts = from_path.all().collect::<Vec<_>>();
+        let to_parts = to_path.all().collect::<Vec<_>>();
+
+        if to_parts.starts_with(&from_parts) {
+            log::error!("Cannot move {} into itself ({})", from, to);
+            return Err(Error::PathError);
+        }
+
+        match self.find_entry(to, EntryType::All) {
+            Err(Error::PathError) => (),
+            Ok(_) => {
+                log::error!("Cannot move {}: {} already exists", from, to);
+                return Err(Error::PathError);
+            },
+            Err(e) => return Err(e),
+        }
+
+        self.stage_entry(from, None)?;
+        self.stage_entry(to, Some(NewEntry::Existing(hash, mode)))
+    }
+
+    pub(crate) fn stage_entry(&mut self, path: &str, data: Option<NewEntry>) -> Result<()> {
+        if self.journaling() {
+            self.journal_write(&entry_event(path, &data)?)?;
+        }
+
+        let path = self.path(path);
 
         let root_dir = match self.root {
             Some(hash) => self.remove_dir(hash)?,
@@ -263,7 +665,7 @@ impl Repository {
 
         if let Some(root_dir) = self.update_dir(root_dir, &mut subdirs, file_name, data)? {
             let prev_hash = self.root.and_then(|h| self.find_committed_hash_root(h));
-            let hash = self.staged.serialize_directory(&root_dir, prev_hash);
+            let hash = self.staged.serialize_directory(&root_dir, prev_hash)?;
             if self.objects.has(hash) {
                 self.staged.remove(hash);
             }
@@ -277,24 +679,23 @@ impl Repository {
         Ok(())
     }
 
-    pub(crate) fn commit_object(&mut self, hash: Hash) {
-        if let Some(dir_entry) = self.staged.remove(hash) {
-            if dir_entry.obj_type() == ObjectType::Tree {
-
-                // mem::replace
-                // this unwrap is questionable
-                let dir = self.directories.get_mut().unwrap().insert(hash, Directory::new()).unwrap();
+    /// Objects stay staged until they're stored
+    pub(crate) fn commit_object(&mut self, hash: Hash) -> Result<()> {
+        let Some(dir_entry) = self.staged.load(hash) else { return Ok(()) };
 
-                for (hash, _mode) in dir.iter_values() {
-                    self.commit_object(*hash);
-                }
+        if dir_entry.obj_type() == ObjectType::Tree {
+            // this unwrap is questionable
+            let dir = self.directories.get_mut().unwrap().get(&hash).unwrap();
+            let children = dir.values().map(|(hash, _mode)| *hash).collect::<Vec<_>>();
 
-                // mem::replace
-                self.directories.get_mut().unwrap().insert(hash, dir).unwrap();
+            for hash in children {
+                self.commit_object(hash)?;
             }
-
-            self.objects.insert_entry(dir_entry);
         }
+
+        self.objects.insert_entry(dir_entry)?;
+        self.staged.remove(hash);
+        Ok(())
     }
 
     /// Creates a new commit which saves staged files into the
@@ -302,9 +703,12 @@ impl Repository {
     ///
     /// - If `timestamp` is `None`, the current time will be used
     /// instead.
-    /// - If one of the strings in `author` & `committer` contain
-    /// invalid characters (`<`, `>` or `\n`), this returns
+    /// - The timezone is set by [`Self::set_timezone`].
+    /// - If `author` or `committer` is rejected by the identity
+    /// policy (see [`Self::set_identity_policy`]), this returns
     /// `InvalidObject` immediately.
+    ///
+    /// See [`Self::commit_builder`] for more settings.
     pub fn commit(
         &mut self,
         message: &str,
@@ -312,52 +716,80 @@ impl Repository {
         committer: (&str, &str),
         timestamp: Option<u64>,
     ) -> Result<Hash> {
-        let timestamp = timestamp.unwrap_or_else(|| {
-            let now = SystemTime::now();
-            match now.duration_since(UNIX_EPOCH) {
-                Ok(duration) => duration.as_secs(),
-                _ => 0,
-            }
-        });
-
-        for string in [author.0, author.1, committer.0, committer.1] {
-            let has_newline = string.contains('\n');
-            let has_open = string.contains('<');
-            let has_close = string.contains('>');
-            if has_newline || has_open || has_close {
-                return Err(Error::InvalidObject);
-            }
-        }
-
-        let mut serialized = Vec::new();
+        let timestamp = timestamp.unwrap_or_else(now);
+        let author = Signature::new(author.0, author.1, timestamp).with_tz_offset(self.tz_offset);
+        let committer = Signature::new(committer.0, committer.1, timestamp).with_tz_offset(self.tz_offset);
 
-        if let Some(root) = self.root {
-            if Some(root) != self.get_commit_root(self.head).unwrap() {
-                self.commit_object(root);
-            }
-        }
+        self.commit_builder().message(message).author(author).committer(committer).commit()
+    }
 
-        let root = self.root.unwrap_or(Hash::zero());
-        write!(&mut serialized, "tree {}\n", root).unwrap();
+    /// Creates a new signed commit which saves staged files into
+    /// the repository.
+    ///
+    /// `signer` receives the serialized commit and must return an
+    /// armored signature, starting with `-----BEGIN SSH SIGNATURE-----`
+    /// or `-----BEGIN PGP SIGNATURE-----`, which is embedded in the
+    /// commit as a `gpgsig` header.
+    ///
+    /// See [`Self::commit`] for the other parameters.
+    pub fn commit_signed<S: FnOnce(&[u8]) -> Result<String>>(
+        &mut self,
+        message: &str,
+        author: (&str, &str),
+        committer: (&str, &str),
+        timestamp: Option<u64>,
+        signer: S,
+    ) -> Result<Hash> {
+        let timestamp = timestamp.unwrap_or_else(now);
+        let author = Signature::new(author.0, author.1, timestamp).with_tz_offset(self.tz_offset);
+        let committer = Signature::new(committer.0, committer.1, timestamp).with_tz_offset(self.tz_offset);
 
-        if !self.head.is_zero() {
-            write!(&mut serialized, "parent {}\n", self.head).unwrap();
-        }
+        self.commit_builder().message(message).author(author).committer(committer).commit_signed(signer)
+    }
 
-        write!(&mut serialized, "author {} <{}> {} +0000\n", author.0, author.1, timestamp).unwrap();
-        write!(&mut serialized, "committer {} <{}> {} +0000\n", committer.0, committer.1, timestamp).unwrap();
-        write!(&mut serialized, "\n{}\n", message).unwrap();
+    /// Verifies the signature of a commit object
+    ///
+    /// `verifier` receives the signed payload (the commit without
+    /// its `gpgsig` header) and the armored signature. Returns
+    /// `Ok(false)` if the commit isn't signed.
+    pub fn verify_commit_with<V: FnOnce(&[u8], &str) -> bool>(&self, commit: Hash, verifier: V) -> Result<bool> {
+        // replacements are ignored: the stored commit is checked
+        let commit = self.objects.get_as(commit, ObjectType::Commit).ok_or(Error::MissingObject)?;
+
+        Ok(match split_commit_signature(commit) {
+            Some((payload, signature)) => verifier(&payload, &signature),
+            None => false,
+        })
+    }
 
-        self.head = self.objects.insert(ObjectType::Commit, serialized.into(), None);
+    /// Verifies the SSH signature of a commit object
+    ///
+    /// `allowed_keys` are OpenSSH public keys (`ssh-ed25519 AAAA...`).
+    /// Returns `Ok(false)` if the commit isn't signed, or not by one
+    /// of these keys.
+    #[cfg(feature = "ssh-signing")]
+    pub fn verify_commit(&self, commit: Hash, allowed_keys: &[&str]) -> Result<bool> {
+        self.verify_commit_with(commit, |payload, signature| {
+            crate::ssh_verify(payload, signature, allowed_keys)
+        })
+    }
 
-        Ok(self.head)
+    /// Moves the staged tree to the committed objects
+    pub(crate) fn commit_staged(&mut self) -> Result<()> {
+        match self.root {
+            Some(root) if Some(root) != self.get_commit_root(self.head)? => self.commit_object(root),
+            _ => Ok(()),
+        }
     }
 
     /// Resets the current commit to the branch head in upstream
     ///
     /// Changes from the discarded commits are still present (staged).
+    ///
+    /// They can be recovered with [`Self::reset_to_reflog_entry`].
     pub fn discard_commits(&mut self) {
         self.head = self.upstream_head;
+        self.record_reflog("discard");
     }
 
     /// Discard changes that weren't commited
@@ -365,6 +797,7 @@ impl Repository {
         self.staged = ObjectStore::new();
         self.directories.get_mut().unwrap().clear();
         self.root = self.get_commit_root(self.head).unwrap();
+        self.journal_or_log(b"discard\n");
     }
 
     /// Resets the clone to the upstream state
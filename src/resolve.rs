@@ -0,0 +1,198 @@
+use super::internals::{Result, Error, Hash, Repository, Commit, ObjectType};
+
+/// Prefixes tried for short reference names, in order (like git)
+const REF_PREFIXES: [&str; 5] = ["", "refs/", "refs/tags/", "refs/heads/", "refs/remotes/"];
+
+impl Repository {
+    /// Resolves a revision expression, like `git rev-parse`
+    ///
+    /// Supported syntax:
+    /// - `HEAD` or `@`: the current commit
+    /// - `@{N}` or `HEAD@{N}`: the current commit N moves ago,
+    ///   see [`Self::reflog`]
+    /// - reference names: `main`, `tags/v1.0`, `refs/heads/main`...
+    /// - full or abbreviated hashes (4+ hexadecimal digits)
+    /// - `<rev>~N`: Nth first-parent ancestor (`~` alone is `~1`)
+    /// - `<rev>^N`: Nth parent (`^` alone is `^1`, `^0` is the commit itself)
+    ///
+    /// Suffixes can be chained (`HEAD~2^2`); annotated tags are
+    /// peeled to their commit when a suffix is applied.
+    ///
+    /// Returns `NoSuchReference` if the name cannot be resolved,
+    /// `AmbiguousPrefix` if an abbreviated hash matches several
+    /// objects and `MissingObject` if a parent doesn't exist.
+    pub fn resolve(&self, expr: &str) -> Result<Hash> {
+        let suffix_start = expr.find(['~', '^']).unwrap_or(expr.len());
+        let (base, mut suffixes) = expr.split_at(suffix_start);
+        let mut hash = self.resolve_base(base)?;
+
+        while let Some(op) = suffixes.chars().next() {
+            let rest = &suffixes[1..];
+            let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+            let n = match digits {
+                0 => 1,
+                _ => rest[..digits].parse().map_err(|_| Error::NoSuchReference)?,
+            };
+
+            suffixes = &rest[digits..];
+            hash = self.peel_to_commit(hash)?;
+
+            match op {
+                '~' => for _ in 0..n {
+                    hash = self.nth_parent(hash, 1)?;
+                },
+                _ => if n != 0 {
+                    hash = self.nth_parent(hash, n)?;
+                },
+            }
+        }
+
+        Ok(hash)
+    }
+
+    fn resolve_base(&self, base: &str) -> Result<Hash> {
+        if let Some((name, n)) = base.strip_suffix('}').and_then(|base| base.rsplit_once("@{")) {
+            // only the current commit has a reflog
+            let entry = match (name, n.parse::<usize>()) {
+                ("" | "@" | "HEAD", Ok(n)) => self.reflog().nth(n),
+                _ => None,
+            };
+
+            return match entry.map(|entry| entry.head).filter(|head| !head.is_zero()) {
+                Some(head) => Ok(head),
+                None => {
+                    log::error!("Unknown reflog entry: {:?}", base);
+                    Err(Error::NoSuchReference)
+                },
+            };
+        }
+
+        if base == "HEAD" || base == "@" {
+            return match self.head.is_zero() {
+                true => Err(Error::NoSuchReference),
+                false => Ok(self.head),
+            };
+        }
+
+        for prefix in REF_PREFIXES {
+            if let Some(hash) = self.get_ref(&format!("{}{}", prefix, base)) {
+                return Ok(hash);
+            }
+        }
+
+        if base.len() >= 4 && base.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return match self.resolve_prefix(base) {
+                Err(Error::MissingObject) => Err(Error::NoSuchReference),
+                result => result,
+            };
+        }
+
+        log::error!("Unknown revision: {:?}", base);
+        Err(Error::NoSuchReference)
+    }
+
+    /// Follows annotated tags until a non-tag object is found
+    fn peel_to_commit(&self, mut hash: Hash) -> Result<Hash> {
+        while let Some(tag) = self.objects.get_as(hash, ObjectType::Tag) {
+            let target = tag.strip_prefix(b"object ").and_then(|rest| rest.get(..40));
+            let target = target.and_then(|hex| core::str::from_utf8(hex).ok());
+            hash = target.and_then(Hash::from_hex).ok_or(Error::InvalidObject)?;
+        }
+
+        Ok(hash)
+    }
+
+    /// `n` starts at 1
+    fn nth_parent(&self, commit: Hash, n: usize) -> Result<Hash> {
+        let Some(object) = self.objects.get_as(commit, ObjectType::Commit) else {
+            log::error!("Commit {} isn't in the repository", commit);
+            return Err(Error::MissingObject);
+        };
+
+        match Commit::parse(object)?.parents.get(n - 1) {
+            Some(parent) => Ok(*parent),
+            None => {
+                log::error!("Commit {} has no parent #{}", commit, n);
+                Err(Error::MissingObject)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::{Repository, FileType, Error};
+    use crate::internals::ObjectType;
+
+    fn commit(repo: &mut Repository, name: &str) -> crate::Hash {
+        let signature = ("Test", "test@example.com");
+        repo.stage(name, Some((name.as_bytes().to_vec(), FileType::RegularFile))).unwrap();
+        repo.commit(name, signature, signature, Some(0)).unwrap()
+    }
+
+    #[test]
+    fn ancestors_and_parents() {
+        let mut repo = Repository::new();
+        let c0 = commit(&mut repo, "0");
+        let c1 = commit(&mut repo, "1");
+
+        // a side branch, merged into c2
+        let side = commit(&mut repo, "side");
+        repo.head = c1;
+        repo.root = repo.get_commit_root(c1).unwrap();
+        commit(&mut repo, "2");
+        let signature = crate::Signature::new("Test", "test@example.com", 0);
+        let merge = repo.commit_merge("merge", signature, &[repo.head(), side]).unwrap();
+        let c2 = repo.resolve("HEAD^1").unwrap();
+
+        assert_eq!(repo.resolve("HEAD").unwrap(), merge);
+        assert_eq!(repo.resolve("@").unwrap(), merge);
+        assert_eq!(repo.resolve("HEAD^0").unwrap(), merge);
+        assert_eq!(repo.resolve("HEAD^").unwrap(), c2);
+        assert_eq!(repo.resolve("HEAD^2").unwrap(), side);
+        assert_eq!(repo.resolve("HEAD~").unwrap(), c2);
+        assert_eq!(repo.resolve("HEAD~2").unwrap(), c1);
+        assert_eq!(repo.resolve("HEAD~3").unwrap(), c0);
+        assert_eq!(repo.resolve("HEAD^2~1").unwrap(), c1);
+        assert_eq!(repo.resolve("HEAD~2^").unwrap(), c0);
+
+        assert!(matches!(repo.resolve("HEAD^3"), Err(Error::MissingObject)));
+        assert!(matches!(repo.resolve("HEAD~4"), Err(Error::MissingObject)));
+        assert!(matches!(repo.resolve("nothing"), Err(Error::NoSuchReference)));
+    }
+
+    #[test]
+    fn reflog_entries() {
+        let mut repo = Repository::new();
+        let c0 = commit(&mut repo, "0");
+        let c1 = commit(&mut repo, "1");
+        let c2 = commit(&mut repo, "2");
+
+        assert_eq!(repo.resolve("@{0}").unwrap(), c2);
+        assert_eq!(repo.resolve("HEAD@{1}").unwrap(), c1);
+        assert_eq!(repo.resolve("@@{2}").unwrap(), c0);
+        assert_eq!(repo.resolve("@{1}~1").unwrap(), c0);
+
+        assert!(matches!(repo.resolve("@{3}"), Err(Error::NoSuchReference)));
+        assert!(matches!(repo.resolve("@{x}"), Err(Error::NoSuchReference)));
+        assert!(matches!(repo.resolve("main@{0}"), Err(Error::NoSuchReference)));
+    }
+
+    #[test]
+    fn ambiguous_short_hashes() {
+        let mut repo = Repository::new();
+        let mut seen = HashMap::new();
+
+        let (a, b) = (0..).find_map(|i| {
+            let hash = repo.objects.insert(ObjectType::Blob, format!("{}\n", i), None).unwrap();
+            seen.insert(hash.to_string()[..4].to_string(), hash).map(|other| (other, hash))
+        }).unwrap();
+
+        let prefix = &a.to_string()[..4];
+        assert!(matches!(repo.resolve(prefix), Err(Error::AmbiguousPrefix)));
+        assert_eq!(repo.resolve(&repo.abbreviate(a)).unwrap(), a);
+        assert_eq!(repo.resolve(&b.to_string()).unwrap(), b);
+        assert!(matches!(repo.resolve(&prefix[..3]), Err(Error::NoSuchReference)));
+    }
+}
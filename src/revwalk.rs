@@ -0,0 +1,278 @@
+use std::collections::{BinaryHeap, VecDeque, BTreeMap};
+use lmfu::HashSet;
+
+use super::internals::{Result, Error, Hash, Mode, Repository, Commit, Signature, ObjectType, TreeIter};
+
+/// Iterator on the history of a commit, see [`Repository::log`]
+///
+/// ```ignore
+/// let day_ago = now - 24 * 3600;
+/// for item in repo.log(repo.head()).since(day_ago).author("bot@example.com") {
+///     let (hash, commit) = item?;
+///     println!("{} {}", hash, commit.message);
+/// }
+/// ```
+pub struct RevWalk<'a> {
+    repo: &'a Repository,
+    /// Commits to visit, by committer timestamp
+    pending: BinaryHeap<(u64, Hash)>,
+    seen: HashSet<Hash>,
+    since: Option<u64>,
+    until: Option<u64>,
+    author: Option<&'a str>,
+    committer: Option<&'a str>,
+    /// Number of commits to prefetch, see [`Self::prefetch`]
+    prefetch: usize,
+    /// Commits which were prefetched, and the error which
+    /// stopped prefetching, if any
+    ready: VecDeque<(Hash, Commit<'a>)>,
+    error: Option<Error>,
+}
+
+impl Repository {
+    /// Iterates on the history of a commit, newest commits first
+    ///
+    /// Items are `(hash, commit)` pairs. Commits are ordered by
+    /// committer date; the walk stops at shallow boundaries (see
+    /// [`Self::is_shallow`]) and other missing commits are skipped.
+    pub fn log(&self, from: Hash) -> RevWalk<'_> {
+        let mut walk = RevWalk {
+            repo: self,
+            pending: BinaryHeap::new(),
+            seen: HashSet::new(),
+            since: None,
+            until: None,
+            author: None,
+            committer: None,
+            prefetch: 0,
+            ready: VecDeque::new(),
+            error: None,
+        };
+
+        if !from.is_zero() {
+            walk.push(from);
+        }
+
+        walk
+    }
+}
+
+/// Matches `Name <email>` like `git log --author`
+fn identity_matches(signature: &Signature, pattern: Option<&str>) -> bool {
+    match pattern {
+        Some(pattern) => signature.name.contains(pattern) || signature.email.contains(pattern),
+        None => true,
+    }
+}
+
+impl<'a> RevWalk<'a> {
+    /// Stops at commits older than a UNIX timestamp (committer date)
+    ///
+    /// The walk ends as soon as all pending commits are older, so
+    /// commits with a skewed clock can be missed.
+    pub fn since(mut self, timestamp: u64) -> Self {
+        self.since = Some(timestamp);
+        self
+    }
+
+    /// Skips commits newer than a UNIX timestamp (committer date)
+    pub fn until(mut self, timestamp: u64) -> Self {
+        self.until = Some(timestamp);
+        self
+    }
+
+    /// Only yields commits whose author name or email contains `pattern`
+    pub fn author(mut self, pattern: &'a str) -> Self {
+        self.author = Some(pattern);
+        self
+    }
+
+    /// Only yields commits whose committer name or email contains `pattern`
+    pub fn committer(mut self, pattern: &'a str) -> Self {
+        self.committer = Some(pattern);
+        self
+    }
+
+    /// Prefetches the objects changed by the next `commits` commits
+    ///
+    /// Commits are read `commits` at a time; trees & blobs which
+    /// differ from their first parent are requested in batches,
+    /// one tree level at a time (see [`ObjectStore::prefetch`]).
+    /// This hides the latency of remote object backends when
+    /// each commit is diffed, as in `git log --stat`.
+    ///
+    /// [`ObjectStore::prefetch`]: crate::internals::ObjectStore::prefetch
+    pub fn prefetch(mut self, commits: usize) -> Self {
+        self.prefetch = commits;
+        self
+    }
+
+    fn fill(&mut self) {
+        while self.ready.len() < self.prefetch {
+            match self.step() {
+                Ok(Some(item)) => self.ready.push_back(item),
+                Ok(None) => break,
+                Err(error) => {
+                    self.error = Some(error);
+                    break;
+                },
+            }
+        }
+
+        let commits = self.ready.iter().map(|(_, commit)| commit).collect::<Vec<_>>();
+        if let Err(error) = self.repo.prefetch_changes(&commits) {
+            log::warn!("Prefetching failed: {:?}", error);
+        }
+    }
+
+    /// Adds a commit to walk from
+    pub(crate) fn push(&mut self, hash: Hash) {
+        if self.seen.insert(hash, ()).is_some() {
+            return;
+        }
+
+        let Some(commit) = self.repo.objects.get_as(hash, ObjectType::Commit) else {
+            log::warn!("Commit {} isn't in the repository", hash);
+            return;
+        };
+
+        // invalid commits are reported when they're popped
+        let timestamp = Commit::parse(commit).map(|c| c.committer.timestamp).unwrap_or(u64::MAX);
+        self.pending.push((timestamp, hash));
+    }
+
+    fn step(&mut self) -> Result<Option<(Hash, Commit<'a>)>> {
+        while let Some((timestamp, hash)) = self.pending.pop() {
+            if self.since.is_some_and(|since| timestamp < since) {
+                self.pending.clear();
+                break;
+            }
+
+            let repo = self.repo;
+            let commit = Commit::parse(repo.objects.get_as(hash, ObjectType::Commit).unwrap())?;
+            if !repo.shallow.contains(&hash) {
+                for parent in &commit.parents {
+                    self.push(*parent);
+                }
+            }
+
+            let in_range = self.until.is_none_or(|until| timestamp <= until);
+            let matches = identity_matches(&commit.author, self.author)
+                && identity_matches(&commit.committer, self.committer);
+
+            if in_range && matches {
+                return Ok(Some((hash, commit)));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl<'a> Iterator for RevWalk<'a> {
+    type Item = Result<(Hash, Commit<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.prefetch == 0 {
+            return self.step().transpose();
+        }
+
+        if self.ready.is_empty() && self.error.is_none() {
+            self.fill();
+        }
+
+        match self.ready.pop_front() {
+            Some(item) => Some(Ok(item)),
+            None => self.error.take().map(Err),
+        }
+    }
+}
+
+impl Repository {
+    /// Entries of a tree, empty if it's missing
+    fn raw_tree_entries(&self, tree: Hash) -> Result<BTreeMap<&str, (Hash, Mode)>> {
+        let mut entries = BTreeMap::new();
+        if let Some(raw) = self.objects.get_as(tree, ObjectType::Tree) {
+            let mut iter = TreeIter::new(raw);
+            while let Some((name, hash, mode)) = iter.next()? {
+                entries.insert(name, (hash, mode));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Prefetches the trees & blobs which differ between
+    /// commits and their first parent
+    fn prefetch_changes(&self, commits: &[&Commit]) -> Result<()> {
+        let parents = commits.iter().filter_map(|commit| commit.parents.first().copied()).collect::<Vec<_>>();
+        self.objects.prefetch(&parents);
+
+        let parent_tree = |commit: &Commit| -> Option<Hash> {
+            let parent = commit.parents.first()?;
+            let raw = self.objects.get_as(*parent, ObjectType::Commit)?;
+            Some(Commit::parse(raw).ok()?.tree)
+        };
+
+        let is_dir = |mode| matches!(mode, Mode::Directory);
+        let pair = |commit: &Commit| (parent_tree(commit).unwrap_or(Hash::zero()), commit.tree);
+        let mut pairs = commits.iter().map(|commit| pair(commit)).collect::<Vec<_>>();
+        let mut blobs = Vec::new();
+
+        while !pairs.is_empty() {
+            let trees = pairs.iter().flat_map(|(old, new)| [*old, *new]).filter(|hash| !hash.is_zero());
+            self.objects.prefetch(&trees.collect::<Vec<_>>());
+
+            let mut subtrees = Vec::new();
+            for (old, new) in pairs {
+                let old = self.raw_tree_entries(old)?;
+                for (name, (hash, mode)) in self.raw_tree_entries(new)? {
+                    let previous = old.get(name).copied();
+                    if previous.is_some_and(|(old_hash, _)| old_hash == hash) {
+                        continue;
+                    }
+
+                    if is_dir(mode) {
+                        let old_tree = previous.filter(|(_, mode)| is_dir(*mode)).map(|(hash, _)| hash);
+                        subtrees.push((old_tree.unwrap_or(Hash::zero()), hash));
+                    } else if !matches!(mode, Mode::Gitlink) {
+                        blobs.push(hash);
+                        blobs.extend(previous.filter(|(_, mode)| !is_dir(*mode)).map(|(hash, _)| hash));
+                    }
+                }
+            }
+
+            pairs = subtrees;
+        }
+
+        self.objects.prefetch(&blobs);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Repository, FileType};
+
+    #[test]
+    fn since_cutoff() {
+        let mut repo = Repository::new();
+        let signature = ("Test", "test@example.com");
+        let mut commits = Vec::new();
+
+        for timestamp in [100, 200, 300, 400, 500] {
+            let content = format!("{}\n", timestamp).into_bytes();
+            repo.stage("file.txt", Some((content, FileType::RegularFile))).unwrap();
+            commits.push(repo.commit("commit", signature, signature, Some(timestamp)).unwrap());
+        }
+
+        let hashes = |walk: crate::RevWalk| walk.map(|item| item.unwrap().0).collect::<Vec<_>>();
+        let newest_first = commits.iter().rev().copied().collect::<Vec<_>>();
+
+        assert_eq!(hashes(repo.log(repo.head()).since(300)), newest_first[..3]);
+        assert_eq!(hashes(repo.log(repo.head()).since(301)), newest_first[..2]);
+        assert_eq!(hashes(repo.log(repo.head()).since(0)), newest_first);
+        assert_eq!(hashes(repo.log(repo.head()).since(501)), []);
+        assert_eq!(hashes(repo.log(repo.head()).since(200).until(400)), newest_first[1..4]);
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use super::internals::{Result, Error, Repository, GitProtocol, PacketLine, decode_line};
+
+/// Message sent by a remote server, see [`MessageHandler`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RemoteMessage<'a> {
+    /// Progress & hook output (side-band channel 2), such as
+    /// `Resolving deltas: 100% (12/12), done.`
+    Progress(&'a str),
+    /// Fatal error (side-band channel 3 or `ERR` line); clones &
+    /// fetches then fail with `RemoteError` or a more specific
+    /// error, pushes return it in [`PushReport::remote_error`]
+    ///
+    /// [`PushReport::remote_error`]: crate::PushReport::remote_error
+    Error(&'a str),
+}
+
+/// Receives the messages of remote servers, which are logged
+/// otherwise; see [`Repository::set_message_handler`]
+pub type MessageHandler = Arc<dyn Fn(RemoteMessage) + Send + Sync>;
+
+/// Receives the progress messages of remote servers, such as
+/// `Resolving deltas: 100% (12/12), done.`; see
+/// [`Repository::set_progress_handler`]
+pub type ProgressHandler = Box<dyn Fn(&str) + Send + Sync>;
+
+/// Sends a message to `handler`, line by line, or logs it
+pub(crate) fn report_message(handler: Option<&MessageHandler>, message: RemoteMessage) {
+    let (text, is_error) = match message {
+        RemoteMessage::Progress(text) => (text, false),
+        RemoteMessage::Error(text) => (text, true),
+    };
+
+    for line in text.split(['\r', '\n']).map(str::trim_end).filter(|line| !line.is_empty()) {
+        match (handler, is_error) {
+            (Some(handler), false) => handler(RemoteMessage::Progress(line)),
+            (Some(handler), true) => handler(RemoteMessage::Error(line)),
+            (None, false) => log::info!("Server Message: {}", line),
+            (None, true) => log::error!("Server Error: {}", line),
+        }
+    }
+}
+
+/// Reads a side-band multiplexed response, up to its flush packet
+///
+/// Returns the data of channel 1, and the error message of channel 3
+/// if the remote aborted. Messages of channels 2 & 3 are also
+/// reported, see [`GitProtocol::report`].
+pub(crate) fn read_side_band(protocol: &mut GitProtocol) -> Result<(Vec<u8>, Option<String>)> {
+    let mut data = Vec::new();
+
+    loop {
+        let (channel, message) = match protocol.read_line()? {
+            Some(packet) => {
+                let (channel, payload) = packet.split_first().ok_or(Error::GitProtocolError)?;
+                if *channel == 1 {
+                    data.extend_from_slice(payload);
+                    continue;
+                }
+
+                (*channel, String::from_utf8_lossy(payload).into_owned())
+            },
+            None => break Ok((data, None)),
+        };
+
+        match channel {
+            2 => protocol.report(RemoteMessage::Progress(&message)),
+            3 => {
+                protocol.report(RemoteMessage::Error(&message));
+                break Ok((data, Some(message.trim_end().into())));
+            },
+            _ => {
+                log::error!("Invalid side-band channel: {}", channel);
+                break Err(Error::GitProtocolError);
+            },
+        }
+    }
+}
+
+/// Splits pkt-lines carried by channel 1, up to a flush packet
+pub(crate) fn decode_lines(mut data: &[u8]) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+
+    while let Some((line, len)) = decode_line(data)? {
+        let PacketLine::Bytes(bytes) = line else { break };
+        let line = core::str::from_utf8(bytes).map_err(|_| Error::GitProtocolError)?;
+        lines.push(line.trim().into());
+        data = &data[len..];
+    }
+
+    Ok(lines)
+}
+
+impl Repository {
+    /// Receives the messages of remote servers during clones,
+    /// fetches & pushes, instead of logging them
+    ///
+    /// ```ignore
+    /// repo.set_message_handler(Arc::new(|message| match message {
+    ///     RemoteMessage::Progress(text) => eprintln!("remote: {}", text),
+    ///     RemoteMessage::Error(text) => eprintln!("remote error: {}", text),
+    /// }));
+    /// ```
+    pub fn set_message_handler(&mut self, handler: MessageHandler) {
+        self.message_handler = Some(handler);
+    }
+
+    /// Receives the progress messages of remote servers, instead
+    /// of logging them; error messages are still logged
+    ///
+    /// This replaces the handler of [`Self::set_message_handler`].
+    pub fn set_progress_handler(&mut self, handler: ProgressHandler) {
+        self.message_handler = Some(Arc::new(move |message| match message {
+            RemoteMessage::Progress(text) => handler(text),
+            RemoteMessage::Error(text) => log::error!("Server Error: {}", text),
+        }));
+    }
+}
@@ -0,0 +1,196 @@
+use std::io::{self, Read};
+
+use super::internals::{Result, Error, Hash, Write, Repository, ObjectStore, Object};
+use super::backend::{type_code, code_type};
+use std::sync::Arc;
+
+use super::compression::{Codec, Miniz, ZLIB_CODEC_ID, codec_for_id};
+
+/// First bytes of a snapshot, followed by the format version
+const SNAPSHOT_MAGIC: &[u8; 7] = b"RGSNAP\n";
+
+/// Version 2 added the codec id, version 3 added shallow commits
+const SNAPSHOT_VERSION: u8 = 3;
+
+/// Fast level: snapshots are usually written often
+const SNAPSHOT_COMPRESSION: u8 = 1;
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    match reader.read_exact(&mut bytes) {
+        Ok(()) => Ok(bytes),
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+            log::error!("Truncated snapshot");
+            Err(Error::InvalidObject)
+        },
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    Ok(u64::from_le_bytes(read_array(reader)?))
+}
+
+fn read_hash<R: Read>(reader: &mut R) -> Result<Hash> {
+    Ok(Hash::new(read_array(reader)?))
+}
+
+fn read_bytes<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    match bytes.len() as u64 == len {
+        true => Ok(bytes),
+        false => {
+            log::error!("Truncated snapshot");
+            Err(Error::InvalidObject)
+        },
+    }
+}
+
+/// Objects are `<type> <delta hint> <length> <compressed length> <compressed content>`
+fn write_objects<W: Write>(writer: &mut W, store: &ObjectStore, codec: &dyn Codec) -> Result<()> {
+    writer.write_all(&(store.len() as u64).to_le_bytes())?;
+    for (_, object) in store.iter() {
+        let compressed = codec.compress(object.content(), SNAPSHOT_COMPRESSION);
+        writer.write_all(&[type_code(object.obj_type())])?;
+        writer.write_all(&object.delta_hint().unwrap_or(Hash::zero()).to_bytes())?;
+        writer.write_all(&(object.content().len() as u64).to_le_bytes())?;
+        writer.write_all(&(compressed.len() as u64).to_le_bytes())?;
+        writer.write_all(&compressed)?;
+    }
+
+    Ok(())
+}
+
+fn read_objects<R: Read>(reader: &mut R, store: &mut ObjectStore, codec: &dyn Codec) -> Result<()> {
+    for _ in 0..read_u64(reader)? {
+        let [code] = read_array(reader)?;
+        let Some(obj_type) = code_type(code) else {
+            log::error!("Invalid object type in snapshot: {}", code);
+            return Err(Error::InvalidObject);
+        };
+
+        let delta_hint = Some(read_hash(reader)?).filter(|hint| !hint.is_zero());
+        let len = read_u64(reader)? as usize;
+        let compressed_len = read_u64(reader)?;
+
+        let compressed = read_bytes(reader, compressed_len)?;
+        let content = codec.decompress(&compressed, len)?;
+
+        if content.len() != len {
+            log::error!("Snapshot object size mismatch: {} != {}", content.len(), len);
+            return Err(Error::InvalidObject);
+        }
+
+        store.insert_entry(Object::new(obj_type, content, delta_hint))?;
+    }
+
+    Ok(())
+}
+
+impl Repository {
+    /// Writes objects, staged changes, references, shallow commits
+    /// and the current & upstream commits to `writer`
+    ///
+    /// Object contents are compressed (see [`Self::set_storage_codec`]);
+    /// settings, callbacks, the reflog and stashes aren't saved.
+    /// Use a buffered writer:
+    ///
+    /// ```ignore
+    /// let file = BufWriter::new(File::create("repo.snapshot")?);
+    /// repo.save_snapshot(file)?;
+    /// ```
+    ///
+    /// Returns `IoError` if writing fails.
+    pub fn save_snapshot<W: Write>(&self, mut writer: W) -> Result<()> {
+        let codec = &*self.storage_codec;
+        writer.write_all(SNAPSHOT_MAGIC)?;
+        writer.write_all(&[SNAPSHOT_VERSION, codec.id()])?;
+        writer.write_all(&self.head.to_bytes())?;
+        writer.write_all(&self.upstream_head.to_bytes())?;
+        writer.write_all(&self.root.unwrap_or(Hash::zero()).to_bytes())?;
+
+        writer.write_all(&(self.refs.len() as u64).to_le_bytes())?;
+        for (name, target) in self.refs.iter() {
+            writer.write_all(&(name.len() as u64).to_le_bytes())?;
+            writer.write_all(name.as_bytes())?;
+            writer.write_all(&target.to_bytes())?;
+        }
+
+        writer.write_all(&(self.shallow.len() as u64).to_le_bytes())?;
+        for commit in &self.shallow {
+            writer.write_all(&commit.to_bytes())?;
+        }
+
+        write_objects(&mut writer, &self.objects, codec)?;
+        write_objects(&mut writer, &self.staged, codec)?;
+        Ok(writer.flush()?)
+    }
+
+    /// Restores a repository written by [`Self::save_snapshot`]
+    ///
+    /// Object hashes are computed again, so a corrupted object
+    /// cannot be loaded under the hash of the original one.
+    ///
+    /// Returns `InvalidObject` if the snapshot is truncated, malformed
+    /// or compressed with an unknown codec, or `IoError` if reading fails.
+    pub fn load_snapshot<R: Read>(reader: R) -> Result<Self> {
+        Self::load_snapshot_with(reader, Arc::new(Miniz))
+    }
+
+    /// Same as [`Self::load_snapshot`], for snapshots compressed
+    /// with a custom codec
+    ///
+    /// `codec` is used if the snapshot names its id; the restored
+    /// repository keeps it as its storage codec.
+    pub fn load_snapshot_with<R: Read>(mut reader: R, codec: Arc<dyn Codec>) -> Result<Self> {
+        let [magic @ .., version] = read_array::<_, 8>(&mut reader)?;
+        if magic != *SNAPSHOT_MAGIC || !(1..=SNAPSHOT_VERSION).contains(&version) {
+            log::error!("Not a snapshot, or an unsupported version");
+            return Err(Error::InvalidObject);
+        }
+
+        // version 1 snapshots are always zlib streams
+        let codec_id = match version {
+            1 => ZLIB_CODEC_ID,
+            _ => read_array::<_, 1>(&mut reader)?[0],
+        };
+
+        let decoder = match codec.id() == codec_id {
+            true => Some(codec.clone()),
+            false => codec_for_id(codec_id),
+        };
+
+        let Some(decoder) = decoder else {
+            log::error!("Snapshot was compressed with unknown codec {}", codec_id);
+            return Err(Error::InvalidObject);
+        };
+
+        let mut repo = Self::new();
+        repo.storage_codec = codec;
+        repo.head = read_hash(&mut reader)?;
+        repo.upstream_head = read_hash(&mut reader)?;
+        repo.root = Some(read_hash(&mut reader)?).filter(|root| !root.is_zero());
+
+        for _ in 0..read_u64(&mut reader)? {
+            let len = read_u64(&mut reader)?;
+            let name = String::from_utf8(read_bytes(&mut reader, len)?).map_err(|_| {
+                log::error!("Invalid reference name in snapshot");
+                Error::InvalidObject
+            })?;
+
+            let target = read_hash(&mut reader)?;
+            repo.refs.insert(name.as_str().into(), target);
+        }
+
+        if version >= 3 {
+            for _ in 0..read_u64(&mut reader)? {
+                repo.shallow.insert(read_hash(&mut reader)?);
+            }
+        }
+
+        read_objects(&mut reader, &mut repo.objects, &*decoder)?;
+        read_objects(&mut reader, &mut repo.staged, &*decoder)?;
+        Ok(repo)
+    }
+}
@@ -0,0 +1,215 @@
+//! SSH signatures, as produced by `ssh-keygen -Y sign -n git`
+//!
+//! Enabled by the `ssh-signing` feature.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ed25519_dalek::{Signer, Keypair, Signature, PublicKey};
+use sha2::{Digest, Sha256, Sha512};
+
+use super::internals::{Result, Error};
+
+const MAGIC: &[u8] = b"SSHSIG";
+const NAMESPACE: &[u8] = b"git";
+const KEY_TYPE: &[u8] = b"ssh-ed25519";
+const HASH_ALGORITHM: &[u8] = b"sha512";
+const ARMOR_BEGIN: &str = "-----BEGIN SSH SIGNATURE-----";
+const ARMOR_END: &str = "-----END SSH SIGNATURE-----";
+
+fn put_string(dst: &mut Vec<u8>, data: &[u8]) {
+    dst.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    dst.extend_from_slice(data);
+}
+
+/// Reads a length-prefixed string
+fn get_string<'a>(src: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = u32::from_be_bytes(src.get(..4)?.try_into().unwrap()) as usize;
+    let string = src.get(4..4 + len)?;
+    *src = &src[4 + len..];
+    Some(string)
+}
+
+/// Data which is actually signed by the ed25519 key
+fn signed_data(payload: &[u8], hash_algorithm: &[u8]) -> Option<Vec<u8>> {
+    let mut data = MAGIC.to_vec();
+    put_string(&mut data, NAMESPACE);
+    put_string(&mut data, b"");
+    put_string(&mut data, hash_algorithm);
+
+    match hash_algorithm {
+        b"sha512" => put_string(&mut data, &Sha512::digest(payload)),
+        b"sha256" => put_string(&mut data, &Sha256::digest(payload)),
+        _ => return None,
+    }
+
+    Some(data)
+}
+
+fn parse_keypair(keypair_hex: &str) -> Result<Keypair> {
+    let mut bytes = [0; 64];
+    let valid = keypair_hex.len() == 128 && keypair_hex.is_ascii();
+
+    for (i, byte) in bytes.iter_mut().enumerate().filter(|_| valid) {
+        let hex = &keypair_hex[i * 2..][..2];
+        *byte = u8::from_str_radix(hex, 16).map_err(|_| Error::InvalidKeypair)?;
+    }
+
+    match valid {
+        true => Keypair::from_bytes(&bytes).map_err(|_| Error::InvalidKeypair),
+        false => Err(Error::InvalidKeypair),
+    }
+}
+
+/// Signs a payload using an ed25519 key pair
+///
+/// `keypair_hex` has the same format as [`Remote::keypair`](crate::Remote::keypair).
+/// The returned armored signature can be passed to
+/// [`Repository::commit_signed`](crate::Repository::commit_signed):
+///
+/// ```ignore
+/// let keypair = remote.keypair.clone();
+/// let signer = |payload: &[u8]| ssh_sign(&keypair, payload);
+/// repo.commit_signed("msg", author, author, None, signer)?;
+/// ```
+pub fn ssh_sign(keypair_hex: &str, payload: &[u8]) -> Result<String> {
+    let key = parse_keypair(keypair_hex)?;
+    let signature = key.sign(&signed_data(payload, HASH_ALGORITHM).unwrap());
+
+    let mut public_key = Vec::new();
+    put_string(&mut public_key, KEY_TYPE);
+    put_string(&mut public_key, key.public.as_bytes());
+
+    let mut signature_blob = Vec::new();
+    put_string(&mut signature_blob, KEY_TYPE);
+    put_string(&mut signature_blob, &signature.to_bytes());
+
+    let mut blob = MAGIC.to_vec();
+    blob.extend_from_slice(&1u32.to_be_bytes());
+    put_string(&mut blob, &public_key);
+    put_string(&mut blob, NAMESPACE);
+    put_string(&mut blob, b"");
+    put_string(&mut blob, HASH_ALGORITHM);
+    put_string(&mut blob, &signature_blob);
+
+    let encoded = BASE64.encode(blob);
+    let mut armored = format!("{}\n", ARMOR_BEGIN);
+
+    for line in encoded.as_bytes().chunks(70) {
+        armored += core::str::from_utf8(line).unwrap();
+        armored.push('\n');
+    }
+
+    armored += ARMOR_END;
+    armored.push('\n');
+
+    Ok(armored)
+}
+
+/// Decodes the blob of an `ssh-ed25519 AAAA... [comment]` public key
+fn parse_public_key(line: &str) -> Option<Vec<u8>> {
+    let mut parts = line.split_whitespace();
+    let key_type = parts.next()?;
+    let blob = BASE64.decode(parts.next()?).ok()?;
+
+    match key_type.as_bytes() == KEY_TYPE {
+        true => Some(blob),
+        false => None,
+    }
+}
+
+/// Verifies an armored SSH signature
+///
+/// Returns true if the signature is valid, has the `git` namespace
+/// and was made by one of `allowed_keys`, which are OpenSSH public
+/// keys (`ssh-ed25519 AAAA... [comment]`). Only ed25519 keys are
+/// supported.
+pub fn ssh_verify(payload: &[u8], signature: &str, allowed_keys: &[&str]) -> bool {
+    let check = || -> Option<bool> {
+        let body = signature.trim().strip_prefix(ARMOR_BEGIN)?.strip_suffix(ARMOR_END)?;
+        let body = body.split_whitespace().collect::<String>();
+        let blob = BASE64.decode(body).ok()?;
+
+        let mut blob = blob.strip_prefix(MAGIC)?;
+        let version = blob.get(..4)?;
+        blob = &blob[4..];
+
+        let public_key = get_string(&mut blob)?;
+        let namespace = get_string(&mut blob)?;
+        let _reserved = get_string(&mut blob)?;
+        let hash_algorithm = get_string(&mut blob)?;
+        let mut signature_blob = get_string(&mut blob)?;
+
+        if version != 1u32.to_be_bytes() || namespace != NAMESPACE {
+            return Some(false);
+        }
+
+        let allowed = allowed_keys.iter().filter_map(|key| parse_public_key(key));
+        if !allowed.into_iter().any(|key| key == public_key) {
+            log::warn!("SSH signature was made by a key which isn't allowed");
+            return Some(false);
+        }
+
+        let mut public_key = public_key;
+        let key_type = get_string(&mut public_key)?;
+        let key_bytes = get_string(&mut public_key)?;
+        let sig_type = get_string(&mut signature_blob)?;
+        let sig_bytes = get_string(&mut signature_blob)?;
+
+        if key_type != KEY_TYPE || sig_type != KEY_TYPE {
+            return Some(false);
+        }
+
+        let key = PublicKey::from_bytes(key_bytes).ok()?;
+        let signature = Signature::try_from(sig_bytes).ok()?;
+        let data = signed_data(payload, hash_algorithm)?;
+
+        Some(key.verify_strict(&data, &signature).is_ok())
+    };
+
+    check().unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Repository;
+
+    // generated with `ssh-keygen -t ed25519`, then signed
+    // with `ssh-keygen -Y sign -n git -f key payload`
+    const KEYPAIR: &str = "a8307f0100f45ed53a8f36d587e3edfc7a5641bd1c5333dfb6b6f7d0064fef4de8acab16191944b9d5d7c7ffa41fcee0d8a6263fbaa61bf671e41bb52c48b7f7";
+    const PUBLIC_KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIOisqxYZGUS51dfH/6QfzuDYpiY/uqYb9nHkG7UsSLf3 test";
+    const OTHER_KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAID6S7bDeiXqKyjGDHzKIHjFKQNEpzoq5Ds5xqCxuRukd other";
+    const PAYLOAD: &[u8] = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\nauthor A <a@b> 0 +0000\n\nsigned\n";
+    const SIGNATURE: &str = "\
+-----BEGIN SSH SIGNATURE-----
+U1NIU0lHAAAAAQAAADMAAAALc3NoLWVkMjU1MTkAAAAg6KyrFhkZRLnV18f/pB/O4NimJj
++6phv2ceQbtSxIt/cAAAADZ2l0AAAAAAAAAAZzaGE1MTIAAABTAAAAC3NzaC1lZDI1NTE5
+AAAAQMbcHxwAhgmKCaHXwDRylO2QZ9+lDSiEUKWfCGt6tEWY/5AJ3wBVU0fOd3s8XHdsyh
+G+6Rq+x0tWkAWBp4tEeQw=
+-----END SSH SIGNATURE-----
+";
+
+    #[test]
+    fn matches_ssh_keygen() {
+        assert_eq!(ssh_sign(KEYPAIR, PAYLOAD).unwrap(), SIGNATURE);
+        assert!(ssh_verify(PAYLOAD, SIGNATURE, &[OTHER_KEY, PUBLIC_KEY]));
+    }
+
+    #[test]
+    fn rejects_invalid_signatures() {
+        assert!(!ssh_verify(b"tampered", SIGNATURE, &[PUBLIC_KEY]));
+        assert!(!ssh_verify(PAYLOAD, SIGNATURE, &[OTHER_KEY]));
+        assert!(!ssh_verify(PAYLOAD, &SIGNATURE.replace("AAAAQMbc", "AAAAQMbd"), &[PUBLIC_KEY]));
+        assert!(matches!(ssh_sign(&KEYPAIR[2..], PAYLOAD), Err(Error::InvalidKeypair)));
+    }
+
+    #[test]
+    fn signed_commit_round_trip() {
+        let mut repo = Repository::new();
+        let signature = ("A", "a@b");
+        let signer = |payload: &[u8]| ssh_sign(KEYPAIR, payload);
+        let commit = repo.commit_signed("signed", signature, signature, Some(0), signer).unwrap();
+
+        assert!(repo.verify_commit(commit, &[PUBLIC_KEY]).unwrap());
+        assert!(!repo.verify_commit(commit, &[OTHER_KEY]).unwrap());
+    }
+}
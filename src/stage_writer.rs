@@ -0,0 +1,206 @@
+use core::mem::replace;
+use std::io;
+use sha1::Digest;
+
+use super::internals::{Result, Error, Hash, FileType, Repository, Write, ObjectType, PendingBlob};
+use super::objectstore::{Hasher, finalize};
+use super::repository::NewEntry;
+
+/// Incremental writer for a staged file, see [`Repository::stage_writer`]
+///
+/// The file is only staged by [`Self::finish`]; dropping
+/// the writer releases what was written.
+pub struct StageWriter<'a> {
+    repo: &'a mut Repository,
+    path: String,
+    file_type: FileType,
+    size: usize,
+    written: usize,
+    hasher: Hasher,
+    blob: Option<PendingBlob>,
+}
+
+impl Repository {
+    /// Stages a file of `size` bytes chunk by chunk, through [`std::io::Write`]
+    ///
+    /// ```ignore
+    /// let size = file.metadata()?.len() as usize;
+    /// let mut writer = repo.stage_writer("assets/video.mp4", FileType::RegularFile, size)?;
+    /// std::io::copy(&mut file, &mut writer)?;
+    /// let hash = writer.finish()?;
+    /// ```
+    ///
+    /// The blob is hashed as chunks come and is kept with other
+    /// staged objects until it is committed. When journaling (see
+    /// [`Self::open_journal`]), chunks are journaled as they are
+    /// written. Writing more than `size` bytes fails with
+    /// `InvalidInput`. Returns `IoError` if the blob cannot be
+    /// reserved or journaled.
+    pub fn stage_writer(&mut self, path: &str, file_type: FileType, size: usize) -> Result<StageWriter<'_>> {
+        let mut hasher = Hasher::new();
+        write!(&mut hasher, "{} {}\0", ObjectType::Blob, size).unwrap();
+        let blob = self.staged.begin_blob(size)?;
+
+        if self.journaling() {
+            if let Err(error) = self.journal_write(b"blob-start\n") {
+                self.staged.abort_blob(blob);
+                return Err(error);
+            }
+        }
+
+        Ok(StageWriter {
+            repo: self,
+            path: path.into(),
+            file_type,
+            size,
+            written: 0,
+            hasher,
+            blob: Some(blob),
+        })
+    }
+}
+
+impl<'a> StageWriter<'a> {
+    /// Number of bytes written so far
+    pub fn len(&self) -> usize {
+        self.written
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.written == 0
+    }
+
+    /// Stages the file, see [`Repository::stage`]
+    ///
+    /// Returns the hash of the new blob, and `InvalidObject` if
+    /// fewer bytes than declared were written. Like other staged
+    /// objects, the blob is dropped if staged changes are discarded.
+    pub fn finish(mut self) -> Result<Hash> {
+        if self.written != self.size {
+            log::error!("{} bytes were written to {}, instead of {}", self.written, self.path, self.size);
+            return Err(Error::InvalidObject);
+        }
+
+        let hash = Hash::new(finalize(replace(&mut self.hasher, Hasher::new()))?);
+        let blob = self.blob.take().ok_or(Error::InvalidObject)?;
+        self.repo.staged.end_blob(hash, blob)?;
+
+        if self.repo.journaling() {
+            self.repo.journal_write(format!("blob-end {}\n", hash).as_bytes())?;
+        }
+
+        self.repo.stage_entry(&self.path, Some(NewEntry::Existing(hash, self.file_type.into())))?;
+        Ok(hash)
+    }
+}
+
+impl<'a> Write for StageWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written + buf.len() > self.size {
+            let message = format!("{} is bigger than its declared size ({} bytes)", self.path, self.size);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+
+        let to_io = |error| match error {
+            Error::IoError(kind) => io::Error::from(kind),
+            error => io::Error::other(format!("{:?}", error)),
+        };
+
+        let blob = self.blob.as_mut().ok_or_else(|| to_io(Error::InvalidObject))?;
+        self.repo.staged.write_blob(blob, buf).map_err(to_io)?;
+
+        if self.repo.journaling() {
+            let mut event = format!("blob-chunk {}\n", buf.len()).into_bytes();
+            event.extend_from_slice(buf);
+            self.repo.journal_write(&event).map_err(to_io)?;
+        }
+
+        self.hasher.update(buf);
+        self.written += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> Drop for StageWriter<'a> {
+    fn drop(&mut self) {
+        if let Some(blob) = self.blob.take() {
+            self.repo.staged.abort_blob(blob);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write as _};
+    use crate::{Repository, FileType, Error};
+
+    #[test]
+    fn more_bytes_than_declared() {
+        let mut repo = Repository::new();
+        let mut writer = repo.stage_writer("file.txt", FileType::RegularFile, 4).unwrap();
+        writer.write_all(b"abc").unwrap();
+
+        let error = writer.write_all(b"de").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(writer.len(), 3);
+
+        drop(writer);
+        assert!(repo.read_file("file.txt").is_err());
+    }
+
+    #[test]
+    fn fewer_bytes_than_declared() {
+        let mut repo = Repository::new();
+        let mut writer = repo.stage_writer("file.txt", FileType::RegularFile, 4).unwrap();
+        writer.write_all(b"ab").unwrap();
+
+        assert!(matches!(writer.finish(), Err(Error::InvalidObject)));
+        assert!(repo.read_file("file.txt").is_err());
+        assert_eq!(repo.staged.len(), 0);
+    }
+
+    #[test]
+    fn dropped_unfinished() {
+        let mut repo = Repository::new();
+        let mut writer = repo.stage_writer("file.txt", FileType::RegularFile, usize::MAX).unwrap();
+        writer.write_all(b"abc").unwrap();
+        drop(writer);
+
+        assert!(repo.read_file("file.txt").is_err());
+        assert_eq!(repo.staged.len(), 0);
+    }
+
+    #[test]
+    fn journaled_and_discarded_like_staged_files() {
+        let path = std::env::temp_dir().join(format!("rustgit-stage-writer-{}.journal", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut repo = Repository::new();
+        repo.open_journal(&path).unwrap();
+
+        let mut writer = repo.stage_writer("aborted.txt", FileType::RegularFile, 8).unwrap();
+        writer.write_all(b"abc").unwrap();
+        drop(writer);
+
+        let mut writer = repo.stage_writer("file.txt", FileType::RegularFile, 6).unwrap();
+        writer.write_all(b"abc").unwrap();
+        writer.write_all(b"def").unwrap();
+        let hash = writer.finish().unwrap();
+        assert_eq!(repo.read_file("file.txt").unwrap(), b"abcdef");
+        repo.close_journal();
+
+        let mut replayed = Repository::new();
+        replayed.open_journal(&path).unwrap();
+        replayed.close_journal();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replayed.read_file("file.txt").unwrap(), b"abcdef");
+        assert!(replayed.read_file("aborted.txt").is_err());
+
+        repo.discard_changes();
+        assert!(!repo.contains(hash));
+    }
+}
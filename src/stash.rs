@@ -0,0 +1,110 @@
+use super::internals::{Result, Error, Hash, Mode, ObjectStore, ObjectType, Repository};
+use super::repository::NewEntry;
+
+/// Staged changes set aside by [`Repository::stash_save`]
+#[derive(Clone)]
+pub(crate) struct Stash {
+    /// Root of the current commit when the changes were stashed
+    base: Option<Hash>,
+    root: Option<Hash>,
+    staged: ObjectStore,
+}
+
+impl Stash {
+    pub(crate) fn root(&self) -> Option<Hash> {
+        self.root
+    }
+
+    pub(crate) fn objects(&self) -> &ObjectStore {
+        &self.staged
+    }
+}
+
+impl Repository {
+    /// Sets staged changes aside
+    ///
+    /// The staged objects and root are saved on a stack, then
+    /// the repository goes back to the current commit, like
+    /// [`Self::discard_changes`]. Returns false if there was
+    /// nothing to stash.
+    pub fn stash_save(&mut self) -> Result<bool> {
+        let base = self.get_commit_root(self.head)?;
+        if base == self.root {
+            return Ok(false);
+        }
+
+        self.stashes.push(Stash {
+            base,
+            root: self.root,
+            staged: core::mem::replace(&mut self.staged, ObjectStore::new()),
+        });
+
+        self.discard_changes();
+        Ok(true)
+    }
+
+    /// Stages changes set aside by the last [`Self::stash_save`]
+    ///
+    /// If the current commit changed in the meantime, stashed changes
+    /// are merged like in [`Self::cherry_pick`]. Returns the paths
+    /// which conflict; in that case, nothing is staged and the stash
+    /// is kept.
+    ///
+    /// Returns `NoSuchReference` if there's no stash and
+    /// `DirtyWorkspace` if there are staged changes.
+    pub fn stash_pop(&mut self) -> Result<Vec<String>> {
+        if self.get_commit_root(self.head)? != self.root {
+            return Err(Error::DirtyWorkspace);
+        }
+
+        let Some(stash) = self.stashes.pop() else {
+            log::error!("There are no stashed changes");
+            return Err(Error::NoSuchReference);
+        };
+
+        let result = self.apply_stash(&stash);
+        if !matches!(&result, Ok(conflicts) if conflicts.is_empty()) {
+            self.stashes.push(stash);
+        }
+
+        result
+    }
+
+    fn apply_stash(&mut self, stash: &Stash) -> Result<Vec<String>> {
+        for hash in stash.staged.hashes() {
+            self.staged.insert_entry(stash.staged.get(hash).unwrap().clone())?;
+        }
+
+        let (base, root) = (stash.base.unwrap_or(Hash::zero()), stash.root.unwrap_or(Hash::zero()));
+        let (entries, conflicts) = self.three_way(base, root)?;
+        if !conflicts.is_empty() {
+            self.discard_changes();
+            return Ok(conflicts);
+        }
+
+        // contents are staged again, so that they're journaled
+        let mut staged = Vec::with_capacity(entries.len());
+        for (path, entry) in entries {
+            let entry = match entry {
+                Some(NewEntry::Existing(hash, mode)) => match mode.file_type() {
+                    Some(file_type) if !matches!(mode, Mode::Gitlink) => {
+                        let data = self.any_store_get(hash, ObjectType::Blob).ok_or(Error::MissingObject)?;
+                        Some(NewEntry::Data(data.to_vec(), file_type))
+                    },
+                    _ => Some(NewEntry::Existing(hash, mode)),
+                },
+                entry => entry,
+            };
+
+            staged.push((path, entry));
+        }
+
+        self.stage_all(staged)?;
+        Ok(Vec::new())
+    }
+
+    /// Number of stashed change sets
+    pub fn stash_len(&self) -> usize {
+        self.stashes.len()
+    }
+}
@@ -0,0 +1,121 @@
+use std::{collections::BTreeSet, sync::RwLock};
+
+use super::internals::{Result, Hash, Repository};
+use super::drivers::Drivers;
+use super::identity::IdentityPolicy;
+use super::policy::CommitPolicy;
+
+/// Differences between two repositories, see [`Repository::diff_state`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateDiff {
+    /// Current commits, if they differ
+    pub head: Option<(Hash, Hash)>,
+    /// Last known upstream commits, if they differ
+    pub upstream_head: Option<(Hash, Hash)>,
+    /// Workspace roots (including staged changes), if they differ
+    pub root: Option<(Option<Hash>, Option<Hash>)>,
+    /// References which differ, by name
+    pub refs: Vec<(String, Option<Hash>, Option<Hash>)>,
+    /// Reachable objects missing from the other repository
+    pub only_here: Vec<Hash>,
+    /// Reachable objects missing from this repository
+    pub only_there: Vec<Hash>,
+}
+
+impl StateDiff {
+    /// True if both repositories are in the same state
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+fn differ<T: PartialEq>(a: T, b: T) -> Option<(T, T)> {
+    match a == b {
+        true => None,
+        false => Some((a, b)),
+    }
+}
+
+impl Repository {
+    /// Creates an independent copy of this repository
+    ///
+    /// Objects, staged changes, references and settings are copied;
+    /// diff & merge drivers, the identity policy and the commit
+    /// policy can hold callbacks, so the copy uses the defaults
+    /// instead.
+    pub fn duplicate(&self) -> Self {
+        Self {
+            directories: RwLock::new(self.directories.read().unwrap().clone()),
+            objects: self.objects.clone(),
+            staged: self.staged.clone(),
+            upstream_head: self.upstream_head,
+            head: self.head,
+            root: self.root,
+            replacements: self.replacements.clone(),
+            replace_objects: self.replace_objects,
+            drivers: Drivers::default(),
+            bitmaps: self.bitmaps.clone(),
+            tz_offset: self.tz_offset,
+            refs: self.refs.clone(),
+            identity_policy: IdentityPolicy::default(),
+            commit_policy: CommitPolicy::default(),
+            pack_reuse: self.pack_reuse,
+            pack_options: self.pack_options,
+            zlib_codec: self.zlib_codec.clone(),
+            storage_codec: self.storage_codec.clone(),
+            reflog: self.reflog.clone(),
+            journal: None,
+            stashes: self.stashes.clone(),
+            pins: self.pins.clone(),
+            shallow: self.shallow.clone(),
+            message_handler: self.message_handler.clone(),
+            path_separators: self.path_separators,
+        }
+    }
+
+    /// Objects reachable from the current & upstream commits
+    /// and from references
+    fn reachable_objects(&self) -> Result<BTreeSet<Hash>> {
+        let mut objects = BTreeSet::new();
+        let tips = self.refs.iter().map(|(_, hash)| hash).chain([&self.head, &self.upstream_head]);
+
+        for tip in tips.filter(|tip| !tip.is_zero()) {
+            self.for_each_reachable(*tip, |hash| {
+                objects.insert(hash);
+            })?;
+        }
+
+        Ok(objects)
+    }
+
+    /// Compares the state of two repositories
+    ///
+    /// Staged objects are only compared through the workspace root.
+    pub fn diff_state(&self, other: &Self) -> Result<StateDiff> {
+        let mut refs = Vec::new();
+        for (name, hash) in self.refs.iter() {
+            let theirs = other.refs.get(name).copied();
+            if theirs != Some(*hash) {
+                refs.push((name.to_string(), Some(*hash), theirs));
+            }
+        }
+
+        for (name, hash) in other.refs.iter() {
+            if !self.refs.contains_key(name) {
+                refs.push((name.to_string(), None, Some(*hash)));
+            }
+        }
+
+        let here = self.reachable_objects()?;
+        let there = other.reachable_objects()?;
+
+        Ok(StateDiff {
+            head: differ(self.head, other.head),
+            upstream_head: differ(self.upstream_head, other.upstream_head),
+            root: differ(self.root, other.root),
+            refs,
+            only_here: here.difference(&there).copied().collect(),
+            only_there: there.difference(&here).copied().collect(),
+        })
+    }
+}
@@ -0,0 +1,74 @@
+use super::internals::{Result, Repository, FileType};
+
+/// Shape of a repository generated by [`Repository::synthetic`]
+#[derive(Copy, Clone, Debug)]
+pub struct SyntheticParams {
+    pub commits: usize,
+    pub directories: usize,
+    pub files_per_directory: usize,
+    pub file_size: usize,
+    pub seed: u64,
+}
+
+impl Default for SyntheticParams {
+    fn default() -> Self {
+        Self {
+            commits: 10,
+            directories: 10,
+            files_per_directory: 10,
+            file_size: 1024,
+            seed: 0x5eed,
+        }
+    }
+}
+
+// xorshift64: cheap and deterministic
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+fn random_text(state: &mut u64, size: usize) -> Vec<u8> {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz      \n";
+    (0..size).map(|_| ALPHABET[next_random(state) as usize % ALPHABET.len()]).collect()
+}
+
+impl Repository {
+    /// Generates a repository with deterministic pseudo-random content
+    ///
+    /// The first commit creates every file; each following commit
+    /// rewrites one file per directory. Identical parameters always
+    /// lead to identical hashes, which makes this suitable for
+    /// benchmarks and tests. The result has no unpushed commits.
+    pub fn synthetic(params: &SyntheticParams) -> Result<Self> {
+        let mut repo = Self::new();
+        let mut state = params.seed | 1;
+        let signature = ("Synthetic", "synthetic@example.com");
+
+        for commit in 0..params.commits {
+            for dir in 0..params.directories {
+                let files = match commit {
+                    0 => 0..params.files_per_directory,
+                    _ => {
+                        let file = commit % params.files_per_directory.max(1);
+                        file..(file + 1).min(params.files_per_directory)
+                    },
+                };
+
+                for file in files {
+                    let path = format!("dir{}/file{}.txt", dir, file);
+                    let data = random_text(&mut state, params.file_size);
+                    repo.stage(&path, Some((data, FileType::RegularFile)))?;
+                }
+            }
+
+            let message = format!("synthetic commit {}", commit);
+            repo.commit(&message, signature, signature, Some(commit as u64))?;
+        }
+
+        repo.upstream_head = repo.head;
+        Ok(repo)
+    }
+}
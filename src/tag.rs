@@ -0,0 +1,126 @@
+use core::str::from_utf8;
+
+use super::internals::{Result, Error, Hash, Repository, ObjectType, Write, Signature};
+use super::refs::RefName;
+use super::repository::now;
+
+pub(crate) const SIGNATURE_HEADERS: [&str; 3] = [
+    "-----BEGIN PGP SIGNATURE-----",
+    "-----BEGIN SSH SIGNATURE-----",
+    "-----BEGIN SIGNED MESSAGE-----",
+];
+
+/// Splits a tag object into its signed payload and its armored signature
+///
+/// Returns `None` if the tag isn't signed.
+pub fn split_tag_signature(tag: &[u8]) -> Option<(&[u8], &str)> {
+    let mut found = None;
+
+    for i in 0..tag.len() {
+        let line_start = i == 0 || tag[i - 1] == b'\n';
+        if line_start && SIGNATURE_HEADERS.iter().any(|h| tag[i..].starts_with(h.as_bytes())) {
+            found = Some(i);
+        }
+    }
+
+    let (payload, signature) = tag.split_at(found?);
+    Some((payload, from_utf8(signature).ok()?))
+}
+
+impl Repository {
+    /// Creates an annotated tag object pointing to `target`
+    ///
+    /// - If `timestamp` is `None`, the current time will be used
+    ///   instead.
+    /// - The timezone is set by [`Self::set_timezone`].
+    /// - Returns `MissingObject` if `target` isn't in the repository,
+    ///   `InvalidRefName` if `name` isn't a valid tag name (see
+    ///   [`RefName`]) and `InvalidObject` if `tagger` is rejected by
+    ///   the identity policy.
+    ///
+    /// This doesn't create any reference: use the returned hash to
+    /// refer to the tag.
+    pub fn tag(
+        &mut self,
+        name: &str,
+        target: Hash,
+        tagger: (&str, &str),
+        message: &str,
+        timestamp: Option<u64>,
+    ) -> Result<Hash> {
+        let payload = self.tag_payload(name, target, tagger, message, timestamp)?;
+        self.insert_object(ObjectType::Tag, payload)
+    }
+
+    /// Creates a signed annotated tag object pointing to `target`
+    ///
+    /// `signer` receives the serialized tag and must return an
+    /// armored signature, starting with `-----BEGIN SSH SIGNATURE-----`
+    /// or `-----BEGIN PGP SIGNATURE-----`, which is appended to
+    /// the tag message.
+    ///
+    /// See [`Self::tag`] for the other parameters.
+    pub fn tag_signed<S: FnOnce(&[u8]) -> Result<String>>(
+        &mut self,
+        name: &str,
+        target: Hash,
+        tagger: (&str, &str),
+        message: &str,
+        timestamp: Option<u64>,
+        signer: S,
+    ) -> Result<Hash> {
+        let mut payload = self.tag_payload(name, target, tagger, message, timestamp)?;
+        let signature = signer(&payload)?;
+
+        if !SIGNATURE_HEADERS.iter().any(|h| signature.starts_with(h)) {
+            log::error!("Tag signature doesn't start with a known armor header");
+            return Err(Error::InvalidObject);
+        }
+
+        payload.extend_from_slice(signature.as_bytes());
+        if !signature.ends_with('\n') {
+            payload.push(b'\n');
+        }
+
+        self.insert_object(ObjectType::Tag, payload)
+    }
+
+    /// Verifies the signature of a tag object
+    ///
+    /// `verifier` receives the signed payload and the armored
+    /// signature. Returns `Ok(false)` if the tag isn't signed.
+    pub fn verify_tag<V: FnOnce(&[u8], &str) -> bool>(&self, tag: Hash, verifier: V) -> Result<bool> {
+        let tag = self.any_store_get(tag, ObjectType::Tag).ok_or(Error::MissingObject)?;
+
+        Ok(match split_tag_signature(tag) {
+            Some((payload, signature)) => verifier(payload, signature),
+            None => false,
+        })
+    }
+
+    fn tag_payload(
+        &self,
+        name: &str,
+        target: Hash,
+        tagger: (&str, &str),
+        message: &str,
+        timestamp: Option<u64>,
+    ) -> Result<Vec<u8>> {
+        let timestamp = timestamp.unwrap_or_else(now);
+        let tagger = Signature::new(tagger.0, tagger.1, timestamp).with_tz_offset(self.tz_offset);
+        tagger.check(&self.identity_policy)?;
+
+        RefName::new(&format!("refs/tags/{}", name))?;
+
+        let target_type = self.get_object(target).ok_or(Error::MissingObject)?.obj_type();
+
+        let mut serialized = Vec::new();
+        writeln!(&mut serialized, "object {}", target).unwrap();
+        writeln!(&mut serialized, "type {}", target_type).unwrap();
+        writeln!(&mut serialized, "tag {}", name).unwrap();
+        writeln!(&mut serialized, "tagger {}", tagger).unwrap();
+        write!(&mut serialized, "\n{}\n", message).unwrap();
+
+        Ok(serialized)
+    }
+}
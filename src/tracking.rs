@@ -0,0 +1,191 @@
+use super::internals::{Result, Error, Hash, Remote, Repository, ObjectType};
+use super::clone::ls_remote;
+use super::fetch::FetchRequest;
+use super::refs::RefName;
+
+/// Change of a local reference mirroring a remote one,
+/// see [`Repository::apply_fetched_refs`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RefUpdate {
+    Created(Hash),
+    Unchanged,
+    FastForward {
+        old: Hash,
+        new: Hash,
+    },
+    /// The remote history was rewritten and `force` was set:
+    /// the reference was updated anyway
+    Forced {
+        old: Hash,
+        new: Hash,
+    },
+    /// The remote history was rewritten: the reference
+    /// still points to `old`
+    Diverged {
+        old: Hash,
+        new: Hash,
+    },
+}
+
+impl RefUpdate {
+    /// Returns true for `Forced` and `Diverged` updates
+    pub fn is_rewrite(&self) -> bool {
+        matches!(self, Self::Forced { .. } | Self::Diverged { .. })
+    }
+}
+
+/// Position of the current commit relative to a remote-tracking
+/// branch, see [`Repository::upstream_status`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum UpstreamStatus {
+    UpToDate,
+    /// Local commits aren't upstream yet
+    Ahead(usize),
+    /// Upstream commits must be pulled
+    Behind(usize),
+    Diverged {
+        ahead: usize,
+        behind: usize,
+    },
+    /// The remote-tracking reference doesn't exist
+    Untracked,
+}
+
+impl UpstreamStatus {
+    /// Returns true for `Behind` and `Diverged`: new commits
+    /// would be based on an outdated commit
+    pub fn is_outdated(&self) -> bool {
+        matches!(self, Self::Behind(_) | Self::Diverged { .. })
+    }
+}
+
+/// Local name of a remote reference, `None` if it isn't tracked
+fn tracking_name(remote_name: &str, name: &str) -> Option<String> {
+    if let Some(branch) = name.strip_prefix("refs/heads/") {
+        Some(format!("refs/remotes/{}/{}", remote_name, branch))
+    } else if name.starts_with("refs/tags/") && !name.ends_with("^{}") {
+        Some(name.into())
+    } else {
+        None
+    }
+}
+
+impl Repository {
+    /// Compares the current commit with a remote-tracking branch
+    ///
+    /// `tracking_ref` is usually recorded by [`Self::fetch_refs`],
+    /// such as `refs/remotes/origin/main`. Missing commits
+    /// (in shallow clones) are silently skipped.
+    pub fn upstream_status(&self, tracking_ref: &str) -> Result<UpstreamStatus> {
+        let Some(tip) = self.get_ref(tracking_ref) else {
+            return Ok(UpstreamStatus::Untracked);
+        };
+
+        Ok(match self.ahead_behind_of(self.head, tip)? {
+            (0, 0) => UpstreamStatus::UpToDate,
+            (ahead, 0) => UpstreamStatus::Ahead(ahead),
+            (0, behind) => UpstreamStatus::Behind(behind),
+            (ahead, behind) => UpstreamStatus::Diverged { ahead, behind },
+        })
+    }
+
+    /// Records the references of a remote repository
+    ///
+    /// `advertised` are `(name, target)` pairs, such as the ones
+    /// returned by [`ls_remote`]: branches are saved as
+    /// `refs/remotes/<remote_name>/<branch>` and tags as is; other
+    /// references are ignored.
+    ///
+    /// Each update is checked against the previously recorded value:
+    /// if the new target doesn't descend from it (the remote history
+    /// was rewritten, or a tag was moved), the reference is kept and
+    /// reported as [`RefUpdate::Diverged`], unless `force` is set.
+    /// References are updated with [`Self::compare_and_set_ref`].
+    ///
+    /// Returns `MissingObject` if a target isn't in the repository;
+    /// references processed before that are updated.
+    pub fn apply_fetched_refs(
+        &mut self,
+        remote_name: &str,
+        advertised: &[(&str, Hash)],
+        force: bool,
+    ) -> Result<Vec<(String, RefUpdate)>> {
+        RefName::new(&format!("refs/remotes/{}", remote_name))?;
+        let mut updates = Vec::new();
+
+        for (name, new) in advertised {
+            let Some(local_name) = tracking_name(remote_name, name) else { continue };
+            let new = *new;
+            let previous = self.get_ref(&local_name);
+
+            let update = match previous {
+                None => RefUpdate::Created(new),
+                Some(old) if old == new => RefUpdate::Unchanged,
+                Some(old) => {
+                    let is_commit = self.objects.get_as(new, ObjectType::Commit).is_some();
+                    let is_branch = name.starts_with("refs/heads/");
+                    match is_branch && is_commit && self.is_ancestor(old, new)? {
+                        true => RefUpdate::FastForward { old, new },
+                        false if force => RefUpdate::Forced { old, new },
+                        false => {
+                            log::warn!("{} was rewritten upstream: {} => {}", name, old, new);
+                            RefUpdate::Diverged { old, new }
+                        },
+                    }
+                },
+            };
+
+            let apply = !matches!(update, RefUpdate::Unchanged | RefUpdate::Diverged { .. });
+            if apply {
+                // `previous` was just read: this cannot return `false`
+                self.compare_and_set_ref(&local_name, previous, new)?;
+            }
+
+            updates.push((local_name, update));
+        }
+
+        Ok(updates)
+    }
+
+    /// Fetches the branches & tags of a remote repository
+    ///
+    /// Missing commits are fetched, then references are updated
+    /// as described in [`Self::apply_fetched_refs`]; the current
+    /// commit doesn't change.
+    pub fn fetch_refs(&mut self, remote: &Remote, remote_name: &str, force: bool) -> Result<Vec<(String, RefUpdate)>> {
+        let advertised = ls_remote(remote)?;
+        let mut request = FetchRequest::new();
+        let mut wants = 0;
+
+        for (name, target) in &advertised {
+            let tracked = tracking_name(remote_name, name).is_some();
+            if tracked && self.get_object(*target).is_none() {
+                request = request.want(*target);
+                wants += 1;
+            }
+        }
+
+        if wants > 0 {
+            for (_, hash) in self.refs() {
+                if self.objects.get_as(hash, ObjectType::Commit).is_some() {
+                    request = request.have(hash);
+                }
+            }
+
+            if !self.head.is_zero() {
+                request = request.have(self.head);
+            }
+
+            self.fetch_objects(remote, &request)?;
+        }
+
+        let advertised = advertised.iter().map(|(name, hash)| (name.as_str(), *hash)).collect::<Vec<_>>();
+        match self.apply_fetched_refs(remote_name, &advertised, force) {
+            Err(Error::MissingObject) => {
+                log::error!("Remote server didn't send all advertised references");
+                Err(Error::GitProtocolError)
+            },
+            result => result,
+        }
+    }
+}
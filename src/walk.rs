@@ -0,0 +1,76 @@
+use super::internals::{Result, Error, Hash, Mode, EntryType, Repository};
+
+/// Iterator on all files under a directory, see [`Repository::walk`]
+pub struct Walk<'a> {
+    repo: &'a Repository,
+    /// Entries to visit, last one first
+    pending: Vec<(String, Hash, Mode)>,
+}
+
+impl Repository {
+    /// Iterates on all files under a directory, recursively
+    ///
+    /// Items are `(path, hash, mode)` triplets, where `path` is
+    /// relative to the root of the repository. Files are listed in
+    /// tree order; directories themselves aren't listed.
+    ///
+    /// Returns `PathError` if the path leads to nowhere.
+    ///
+    /// This can write-lock an internal RwLock for cache.
+    pub fn walk(&self, path: &str) -> Result<Walk<'_>> {
+        let path = self.path(path);
+        let mut current = self.root.ok_or(Error::PathError)?;
+
+        for subdir in path.all() {
+            current = self.find_in_dir(current, subdir, EntryType::Directory)?.0;
+        }
+
+        let prefix = path.all().collect::<Vec<_>>().join("/");
+        let mut walk = Walk {
+            repo: self,
+            pending: Vec::new(),
+        };
+
+        walk.push_dir(&prefix, current)?;
+        Ok(walk)
+    }
+}
+
+impl<'a> Walk<'a> {
+    fn push_dir(&mut self, prefix: &str, dir: Hash) -> Result<()> {
+        self.repo.fetch_dir(dir)?;
+        let dirs = self.repo.directories.read().unwrap();
+        let directory = dirs.get(&dir).unwrap(/* fetch_dir ensures it's there */);
+
+        let start = self.pending.len();
+        for (node, (hash, mode)) in directory.iter() {
+            let path = match prefix {
+                "" => node.to_string(),
+                prefix => format!("{}/{}", prefix, node),
+            };
+
+            self.pending.push((path, *hash, *mode));
+        }
+
+        // so that the first entry is popped first
+        self.pending[start..].reverse();
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = Result<(String, Hash, Mode)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (path, hash, mode) = self.pending.pop()?;
+
+            match mode {
+                Mode::Directory => if let Err(error) = self.push_dir(&path, hash) {
+                    return Some(Err(error));
+                },
+                _ => return Some(Ok((path, hash, mode))),
+            }
+        }
+    }
+}
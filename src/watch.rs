@@ -0,0 +1,62 @@
+use super::internals::{Result, Error, Hash, Mode, EntryType, Repository};
+
+/// Change detection handle, see [`Repository::watch`]
+#[derive(Clone, Debug)]
+pub struct Watch {
+    prefix: String,
+    /// Hash of the tree or file at `prefix`, if any
+    last: Option<Hash>,
+}
+
+impl Repository {
+    /// Hash of the tree or file at `path` in a tree
+    pub(crate) fn hash_at(&self, tree: Option<Hash>, path: &str) -> Result<Option<Hash>> {
+        let Some(mut current) = tree else { return Ok(None) };
+        let mut mode = Mode::Directory;
+
+        for node in self.path(path).all() {
+            if !matches!(mode, Mode::Directory) {
+                return Ok(None);
+            }
+
+            match self.find_in_dir(current, node, EntryType::All) {
+                Ok(entry) => (current, mode) = entry,
+                Err(Error::PathError) => return Ok(None),
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(Some(current))
+    }
+
+    /// Starts watching changes under a path prefix
+    ///
+    /// The returned handle compares the tree (or file) at `prefix`
+    /// in the current commit with the one seen previously, which is
+    /// cheap: call [`Watch::changed`] after each clone, fetch or
+    /// commit to know if the affected subsystem must be reloaded.
+    pub fn watch(&self, prefix: &str) -> Result<Watch> {
+        Ok(Watch {
+            prefix: prefix.into(),
+            last: self.hash_at(self.get_commit_root(self.head)?, prefix)?,
+        })
+    }
+}
+
+impl Watch {
+    /// Path prefix being watched
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Returns true if anything under the prefix changed since the
+    /// handle was created or since the last call
+    ///
+    /// This includes the creation or deletion of the prefix itself.
+    pub fn changed(&mut self, repo: &Repository) -> Result<bool> {
+        let current = repo.hash_at(repo.get_commit_root(repo.head)?, &self.prefix)?;
+        let changed = current != self.last;
+        self.last = current;
+        Ok(changed)
+    }
+}
@@ -0,0 +1,154 @@
+use super::internals::{Result, Error, Remote, Repository, Hash, FileType};
+use super::clone::{Reference, ls_remote};
+use super::fetch::FetchRequest;
+use super::push::{PushOptions, PushReport};
+
+/// A branch of a remote repository, for everyday tasks
+///
+/// This wraps a [`Repository`] and a [`Remote`] behind a small API
+/// which is kept stable across releases:
+///
+/// ```ignore
+/// let mut ws = Workspace::open(remote, "main", Some(1))?.identity("Jane Doe", "jane@example.com");
+/// ws.write("content.txt", "Hello World!")?;
+/// ws.commit("Say hello")?;
+/// assert!(ws.push()?.is_ok());
+/// ```
+///
+/// Use [`Self::repository`] for anything else.
+pub struct Workspace {
+    repo: Repository,
+    remote: Remote,
+    branch: String,
+    identity: Option<(String, String)>,
+    push_options: PushOptions,
+}
+
+impl Workspace {
+    /// Clones `branch` from `remote`
+    ///
+    /// `depth` limits the history, see [`Repository::clone`].
+    pub fn open(remote: Remote, branch: &str, depth: Option<usize>) -> Result<Self> {
+        let mut repo = Repository::new();
+        repo.clone(&remote, Reference::Branch(branch), depth)?;
+
+        Ok(Self {
+            repo,
+            remote,
+            branch: branch.into(),
+            identity: None,
+            push_options: PushOptions::default(),
+        })
+    }
+
+    /// Sets the author and committer of new commits
+    pub fn identity(mut self, name: &str, email: &str) -> Self {
+        self.identity = Some((name.into(), email.into()));
+        self
+    }
+
+    /// Sets the options used by [`Self::push`]
+    pub fn push_options(mut self, options: PushOptions) -> Self {
+        self.push_options = options;
+        self
+    }
+
+    pub fn branch(&self) -> &str {
+        &self.branch
+    }
+
+    pub fn remote(&self) -> &Remote {
+        &self.remote
+    }
+
+    /// Current commit of the branch
+    pub fn head(&self) -> Hash {
+        self.repo.head()
+    }
+
+    pub fn repository(&self) -> &Repository {
+        &self.repo
+    }
+
+    pub fn repository_mut(&mut self) -> &mut Repository {
+        &mut self.repo
+    }
+
+    /// Reads a file, including staged changes
+    pub fn read(&self, path: &str) -> Result<&[u8]> {
+        self.repo.read_file(path)
+    }
+
+    /// Stages a regular file
+    pub fn write<D: Into<Vec<u8>>>(&mut self, path: &str, data: D) -> Result<()> {
+        self.repo.stage(path, Some((data.into(), FileType::RegularFile)))
+    }
+
+    /// Stages the removal of a file or directory
+    pub fn remove(&mut self, path: &str) -> Result<()> {
+        self.repo.stage(path, None)
+    }
+
+    /// Returns true if there are staged changes or local commits
+    pub fn has_changes(&self) -> Result<bool> {
+        let head_root = self.repo.get_commit_root(self.repo.head)?;
+        Ok(self.repo.head != self.repo.upstream_head || head_root != self.repo.root)
+    }
+
+    /// Commits staged changes
+    ///
+    /// Returns `InvalidObject` if no identity was set,
+    /// see [`Self::identity`].
+    pub fn commit(&mut self, message: &str) -> Result<Hash> {
+        let Some((name, email)) = &self.identity else {
+            log::error!("Workspace identity wasn't set");
+            return Err(Error::InvalidObject);
+        };
+
+        let identity = (name.as_str(), email.as_str());
+        self.repo.commit(message, identity, identity, None)
+    }
+
+    /// Fast-forwards to the tip of the remote branch
+    ///
+    /// Returns false if the branch didn't change on the remote end,
+    /// and `DirtyWorkspace` if it did while there are local changes.
+    pub fn pull(&mut self) -> Result<bool> {
+        let name = format!("refs/heads/{}", self.branch);
+        let refs = ls_remote(&self.remote)?;
+        let Some((_, tip)) = refs.into_iter().find(|(ref_name, _)| *ref_name == name) else {
+            log::error!("Branch {} wasn't advertised by remote server", self.branch);
+            return Err(Error::NoSuchReference);
+        };
+
+        if tip == self.repo.upstream_head {
+            return Ok(false);
+        }
+
+        if self.has_changes()? {
+            return Err(Error::DirtyWorkspace);
+        }
+
+        if self.repo.get_object(tip).is_none() {
+            let mut request = FetchRequest::new().want(tip);
+            if !self.repo.upstream_head.is_zero() {
+                request = request.have(self.repo.upstream_head);
+            }
+
+            self.repo.fetch_objects(&self.remote, &request)?;
+        }
+
+        self.repo.head = tip;
+        self.repo.upstream_head = tip;
+        self.repo.root = self.repo.get_commit_root(tip)?;
+        self.repo.record_reflog("pull");
+
+        Ok(true)
+    }
+
+    /// Pushes local commits to the remote branch
+    pub fn push(&mut self) -> Result<PushReport> {
+        let head = self.repo.head;
+        self.repo.push_with(&self.remote, &[(&self.branch, head)], &self.push_options)
+    }
+}